@@ -0,0 +1,5 @@
+ALTER TABLE users
+ADD COLUMN email VARCHAR(255) UNIQUE,
+ADD COLUMN created_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+ADD COLUMN email_private BOOLEAN NOT NULL DEFAULT true,
+ADD COLUMN notify_by_email BOOLEAN NOT NULL DEFAULT true;
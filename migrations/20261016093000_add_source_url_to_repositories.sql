@@ -0,0 +1,3 @@
+ALTER TABLE repositories
+ADD COLUMN source_url TEXT,
+ADD COLUMN is_mirror BOOLEAN NOT NULL DEFAULT false;
@@ -0,0 +1,3 @@
+ALTER TABLE repositories
+ADD COLUMN last_synced_at TIMESTAMPTZ,
+ADD COLUMN last_sync_error TEXT;
@@ -0,0 +1,9 @@
+CREATE TABLE webhooks (
+    id SERIAL PRIMARY KEY,
+    repo_id INTEGER NOT NULL REFERENCES repositories(id) ON DELETE CASCADE,
+    url TEXT NOT NULL,
+    secret TEXT,
+    events TEXT[] NOT NULL DEFAULT '{push}',
+    active BOOLEAN NOT NULL DEFAULT true,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
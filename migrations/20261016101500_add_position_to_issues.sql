@@ -0,0 +1,4 @@
+ALTER TABLE issues
+ADD COLUMN position DOUBLE PRECISION;
+
+CREATE INDEX idx_issues_repo_position ON issues (repo_id, position);
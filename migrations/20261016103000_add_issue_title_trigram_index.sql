@@ -0,0 +1,3 @@
+CREATE EXTENSION IF NOT EXISTS pg_trgm;
+
+CREATE INDEX idx_issues_title_trgm ON issues USING gin (title gin_trgm_ops);
@@ -0,0 +1,7 @@
+ALTER TABLE issues
+ADD COLUMN updated_at TIMESTAMPTZ NOT NULL DEFAULT NOW();
+
+UPDATE issues SET updated_at = created_at;
+
+CREATE INDEX idx_issues_repo_updated_at ON issues (repo_id, updated_at);
+CREATE INDEX idx_pull_requests_repo_updated_at ON pull_requests (repo_id, updated_at);
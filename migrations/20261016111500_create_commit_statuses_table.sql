@@ -0,0 +1,13 @@
+CREATE TABLE commit_statuses (
+    id SERIAL PRIMARY KEY,
+    repo_id INTEGER NOT NULL REFERENCES repositories(id) ON DELETE CASCADE,
+    sha TEXT NOT NULL,
+    state VARCHAR(20) NOT NULL CHECK (state IN ('pending', 'success', 'failure', 'error')),
+    context TEXT NOT NULL DEFAULT 'default',
+    description TEXT,
+    target_url TEXT,
+    creator_id INTEGER REFERENCES users(id) ON DELETE SET NULL,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
+
+CREATE INDEX commit_statuses_repo_sha_idx ON commit_statuses (repo_id, sha, context, id);
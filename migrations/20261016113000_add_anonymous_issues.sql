@@ -0,0 +1,6 @@
+ALTER TABLE repositories ADD COLUMN allow_anonymous_issues BOOLEAN NOT NULL DEFAULT false;
+ALTER TABLE issues ADD COLUMN contact TEXT;
+
+-- Placeholder author for anonymous issues. '!' is never a valid bcrypt hash,
+-- so nobody can log in as this user.
+INSERT INTO users (username, password_hash) VALUES ('ghost', '!') ON CONFLICT (username) DO NOTHING;
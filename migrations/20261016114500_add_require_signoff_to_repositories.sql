@@ -0,0 +1 @@
+ALTER TABLE repositories ADD COLUMN require_signoff BOOLEAN NOT NULL DEFAULT false;
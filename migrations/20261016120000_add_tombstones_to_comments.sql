@@ -0,0 +1,7 @@
+ALTER TABLE issue_comments
+    ADD COLUMN deleted_at TIMESTAMPTZ,
+    ADD COLUMN deleted_by INTEGER REFERENCES users(id) ON DELETE SET NULL;
+
+ALTER TABLE pull_request_comments
+    ADD COLUMN deleted_at TIMESTAMPTZ,
+    ADD COLUMN deleted_by INTEGER REFERENCES users(id) ON DELETE SET NULL;
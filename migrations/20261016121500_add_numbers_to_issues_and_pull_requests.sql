@@ -0,0 +1,27 @@
+-- Issues and pull requests share one sequence of numbers per repository,
+-- handed out from `next_number`.
+ALTER TABLE repositories ADD COLUMN next_number INTEGER NOT NULL DEFAULT 1;
+ALTER TABLE issues ADD COLUMN number INTEGER;
+ALTER TABLE pull_requests ADD COLUMN number INTEGER;
+
+WITH items AS (
+    SELECT 'issue' AS kind, id, repo_id, created_at FROM issues
+    UNION ALL
+    SELECT 'pull' AS kind, id, repo_id, created_at FROM pull_requests
+), numbered AS (
+    SELECT kind, id, ROW_NUMBER() OVER (PARTITION BY repo_id ORDER BY created_at, id, kind)::INTEGER AS number
+    FROM items
+), issue_numbers AS (
+    UPDATE issues i SET number = n.number FROM numbered n WHERE n.kind = 'issue' AND n.id = i.id
+)
+UPDATE pull_requests pr SET number = n.number FROM numbered n WHERE n.kind = 'pull' AND n.id = pr.id;
+
+UPDATE repositories r SET next_number = 1 + GREATEST(
+    COALESCE((SELECT MAX(number) FROM issues WHERE repo_id = r.id), 0),
+    COALESCE((SELECT MAX(number) FROM pull_requests WHERE repo_id = r.id), 0)
+);
+
+ALTER TABLE issues ALTER COLUMN number SET NOT NULL;
+ALTER TABLE issues ADD CONSTRAINT issues_repo_id_number_key UNIQUE (repo_id, number);
+ALTER TABLE pull_requests ALTER COLUMN number SET NOT NULL;
+ALTER TABLE pull_requests ADD CONSTRAINT pull_requests_repo_id_number_key UNIQUE (repo_id, number);
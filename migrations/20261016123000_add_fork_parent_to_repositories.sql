@@ -0,0 +1,4 @@
+-- A fork outlives the repository it was forked from, becoming a standalone
+-- repository when the parent is deleted.
+ALTER TABLE repositories ADD COLUMN fork_parent_id INTEGER REFERENCES repositories(id) ON DELETE SET NULL;
+CREATE INDEX repositories_fork_parent_id_idx ON repositories (fork_parent_id);
@@ -0,0 +1,13 @@
+-- Users assigned to new issues, and asked to review new pull requests, when
+-- the request does not name anyone itself.
+CREATE TABLE repository_default_assignees (
+    repo_id INTEGER NOT NULL REFERENCES repositories(id) ON DELETE CASCADE,
+    user_id INTEGER NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    PRIMARY KEY (repo_id, user_id)
+);
+
+CREATE TABLE repository_default_reviewers (
+    repo_id INTEGER NOT NULL REFERENCES repositories(id) ON DELETE CASCADE,
+    user_id INTEGER NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    PRIMARY KEY (repo_id, user_id)
+);
@@ -0,0 +1 @@
+ALTER TABLE pull_requests ADD COLUMN draft BOOLEAN NOT NULL DEFAULT false;
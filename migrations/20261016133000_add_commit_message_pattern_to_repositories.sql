@@ -0,0 +1,3 @@
+-- A regular expression every commit pushed to a branch must match; NULL
+-- means any message is accepted.
+ALTER TABLE repositories ADD COLUMN commit_message_pattern TEXT;
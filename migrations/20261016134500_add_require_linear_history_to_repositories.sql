@@ -0,0 +1 @@
+ALTER TABLE repositories ADD COLUMN require_linear_history BOOLEAN NOT NULL DEFAULT false;
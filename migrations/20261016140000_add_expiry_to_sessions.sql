@@ -0,0 +1,8 @@
+-- Sessions created before expiry existed get the default lifetime from now.
+ALTER TABLE sessions
+ADD COLUMN created_at TIMESTAMPTZ NOT NULL DEFAULT now(),
+ADD COLUMN expires_at TIMESTAMPTZ NOT NULL DEFAULT now() + interval '7 days';
+
+ALTER TABLE sessions ALTER COLUMN expires_at DROP DEFAULT;
+
+CREATE INDEX sessions_expires_at_idx ON sessions (expires_at);
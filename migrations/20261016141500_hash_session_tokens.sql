@@ -0,0 +1,6 @@
+-- Tokens are stored as the hex SHA-256 of what clients send. Hashing the
+-- existing rows in place keeps current sessions working; deployments that
+-- would rather log everyone out can delete from sessions instead.
+ALTER TABLE sessions RENAME COLUMN token TO token_hash;
+
+UPDATE sessions SET token_hash = encode(sha256(convert_to(token_hash, 'UTF8')), 'hex');
@@ -0,0 +1,16 @@
+CREATE TABLE refresh_tokens (
+    token_hash TEXT PRIMARY KEY,
+    user_id INTEGER NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    -- Shared by every token rotated out of one login.
+    family TEXT NOT NULL,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT now(),
+    expires_at TIMESTAMPTZ NOT NULL,
+    -- Set once the token has been exchanged; presenting it again means it leaked.
+    used_at TIMESTAMPTZ
+);
+
+CREATE INDEX refresh_tokens_family_idx ON refresh_tokens (family);
+CREATE INDEX refresh_tokens_expires_at_idx ON refresh_tokens (expires_at);
+
+ALTER TABLE sessions ADD COLUMN refresh_family TEXT;
+CREATE INDEX sessions_refresh_family_idx ON sessions (refresh_family);
@@ -0,0 +1,3 @@
+-- Tokens created before scopes existed keep full access.
+ALTER TABLE api_tokens ADD COLUMN scopes TEXT[] NOT NULL DEFAULT ARRAY['repo:read', 'repo:write', 'issues:write'];
+ALTER TABLE api_tokens ALTER COLUMN scopes DROP DEFAULT;
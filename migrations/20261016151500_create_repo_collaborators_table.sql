@@ -0,0 +1,9 @@
+CREATE TABLE repo_collaborators (
+    repo_id INTEGER NOT NULL REFERENCES repositories(id) ON DELETE CASCADE,
+    user_id INTEGER NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    role TEXT NOT NULL CHECK (role IN ('read', 'write', 'admin')),
+    created_at TIMESTAMPTZ NOT NULL DEFAULT now(),
+    PRIMARY KEY (repo_id, user_id)
+);
+
+CREATE INDEX repo_collaborators_user_id_idx ON repo_collaborators (user_id);
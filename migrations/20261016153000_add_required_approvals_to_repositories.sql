@@ -0,0 +1 @@
+ALTER TABLE repositories ADD COLUMN required_approvals INTEGER NOT NULL DEFAULT 0 CHECK (required_approvals >= 0);
@@ -0,0 +1 @@
+ALTER TABLE pull_requests ADD COLUMN merge_method TEXT CHECK (merge_method IN ('merge', 'squash', 'rebase'));
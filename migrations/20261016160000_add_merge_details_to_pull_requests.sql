@@ -0,0 +1,4 @@
+ALTER TABLE pull_requests
+    ADD COLUMN merged_by INTEGER REFERENCES users(id) ON DELETE SET NULL,
+    ADD COLUMN merged_at TIMESTAMPTZ,
+    ADD COLUMN merge_commit_sha TEXT;
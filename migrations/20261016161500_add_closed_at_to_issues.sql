@@ -0,0 +1,2 @@
+ALTER TABLE issues ADD COLUMN closed_at TIMESTAMPTZ;
+UPDATE issues SET closed_at = updated_at WHERE status = 'closed';
@@ -0,0 +1 @@
+CREATE INDEX idx_issues_search ON issues USING GIN (to_tsvector('english', title || ' ' || COALESCE(body, '')));
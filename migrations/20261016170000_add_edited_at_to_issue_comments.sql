@@ -0,0 +1 @@
+ALTER TABLE issue_comments ADD COLUMN edited_at TIMESTAMPTZ;
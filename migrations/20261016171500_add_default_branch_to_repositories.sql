@@ -0,0 +1,3 @@
+-- Kept in step with the repository's HEAD. `main` is what repositories have
+-- always been initialized with.
+ALTER TABLE repositories ADD COLUMN default_branch VARCHAR(255) NOT NULL DEFAULT 'main';
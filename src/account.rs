@@ -0,0 +1,264 @@
+use axum::{extract::{Query, State}, http::StatusCode, response::IntoResponse, Json};
+use serde::{Deserialize, Serialize};
+use sqlx::{FromRow, PgPool};
+
+use crate::auth::{AuthUser, Scope};
+use crate::config::Config;
+use crate::db;
+use crate::issues::Issue;
+use crate::pagination::Pagination;
+use crate::pull_requests::{PullRequest, PullRequestStatus};
+use crate::validation::{Validate, ValidatedJson, Validator};
+use crate::AppState;
+
+#[derive(Serialize, FromRow)]
+pub struct Preferences {
+    pub email_private: bool,
+    pub notify_by_email: bool,
+}
+
+#[derive(Serialize, FromRow)]
+pub struct Account {
+    pub id: i32,
+    pub username: String,
+    pub email: Option<String>,
+    pub created_at: chrono::DateTime<chrono::Utc>,
+    #[sqlx(flatten)]
+    pub preferences: Preferences,
+}
+
+#[derive(Deserialize)]
+pub struct UpdatePreferences {
+    pub email_private: Option<bool>,
+    pub notify_by_email: Option<bool>,
+}
+
+#[derive(Deserialize)]
+pub struct UpdateAccount {
+    // An empty string clears the email address.
+    pub email: Option<String>,
+    pub preferences: Option<UpdatePreferences>,
+}
+
+impl Validate for UpdateAccount {
+    fn validate(&self, v: &mut Validator) {
+        let email = self.email.as_deref().map(str::trim).filter(|email| !email.is_empty());
+        v.check(email.is_none_or(|email| email.contains('@') && email.len() <= 255), "email", "must be a valid email address");
+    }
+}
+
+#[derive(Deserialize)]
+pub struct AssignedQuery {
+    // Closed issues and closed or merged pull requests are left out unless asked for.
+    #[serde(default)]
+    include_closed: bool,
+}
+
+#[derive(Deserialize)]
+pub struct AuthoredPullsQuery {
+    // Every status is listed when absent.
+    status: Option<PullRequestStatus>,
+}
+
+#[derive(Serialize, FromRow)]
+pub struct AssignedIssue {
+    repository: String,
+    #[serde(flatten)]
+    #[sqlx(flatten)]
+    issue: Issue,
+}
+
+#[derive(Serialize, FromRow)]
+pub struct AssignedPullRequest {
+    repository: String,
+    #[serde(flatten)]
+    #[sqlx(flatten)]
+    pull_request: PullRequest,
+}
+
+#[derive(Serialize)]
+pub struct AssignedGroup<T> {
+    total: i64,
+    items: Vec<T>,
+}
+
+#[derive(Serialize)]
+pub struct AssignedWork {
+    issues: AssignedGroup<AssignedIssue>,
+    pull_requests: AssignedGroup<AssignedPullRequest>,
+}
+
+const ASSIGNED_ISSUES: &str = r#"
+    FROM issues i
+    JOIN repositories r ON i.repo_id = r.id
+    JOIN issue_assignees ia ON ia.issue_id = i.id
+    WHERE ia.user_id = $1 AND (r.public OR r.user_id = $1 OR EXISTS (SELECT 1 FROM repo_collaborators c WHERE c.repo_id = r.id AND c.user_id = $1)) AND ($2 OR i.status = 'open')
+"#;
+
+const REVIEW_REQUESTED_PULLS: &str = r#"
+    FROM pull_requests pr
+    JOIN repositories r ON pr.repo_id = r.id
+    JOIN pull_request_reviewers prr ON prr.pull_request_id = pr.id
+    WHERE prr.user_id = $1 AND (r.public OR r.user_id = $1 OR EXISTS (SELECT 1 FROM repo_collaborators c WHERE c.repo_id = r.id AND c.user_id = $1)) AND ($2 OR pr.status = 'open')
+"#;
+
+const AUTHORED_PULLS: &str = r#"
+    FROM pull_requests pr
+    JOIN repositories r ON pr.repo_id = r.id
+    WHERE pr.author_id = $1 AND (r.public OR r.user_id = $1 OR EXISTS (SELECT 1 FROM repo_collaborators c WHERE c.repo_id = r.id AND c.user_id = $1)) AND ($2::text IS NULL OR pr.status = $2)
+"#;
+
+const ACCOUNT_COLUMNS: &str = "id, username, email, created_at, email_private, notify_by_email";
+
+// The address on commits the server writes for a user: their email when they
+// have made it public, otherwise a noreply address, so a private address never
+// ends up in history.
+pub(crate) async fn commit_email(pool: &PgPool, config: &Config, user_id: i32) -> Result<String, (StatusCode, String)> {
+    let (username, email, email_private) = sqlx::query_as::<_, (String, Option<String>, bool)>("SELECT username, email, email_private FROM users WHERE id = $1")
+        .bind(user_id)
+        .fetch_one(pool)
+        .await
+        .map_err(|e| db::error(e, "Failed to fetch account"))?;
+    Ok(match email {
+        Some(email) if !email_private => email,
+        _ => format!("{}@{}", username, config.noreply_email_domain),
+    })
+}
+
+#[axum::debug_handler]
+pub async fn get_account(
+    State(state): State<AppState>,
+    AuthUser(user): AuthUser,
+) -> Result<impl IntoResponse, (StatusCode, String)> {
+    let account = sqlx::query_as::<_, Account>(&format!("SELECT {} FROM users WHERE id = $1", ACCOUNT_COLUMNS))
+        .bind(user.id)
+        .fetch_one(&state.pool)
+        .await
+        .map_err(|e| db::error(e, "Failed to fetch account"))?;
+
+    Ok(Json(account))
+}
+
+#[axum::debug_handler]
+pub async fn update_account(
+    State(state): State<AppState>,
+    AuthUser(user): AuthUser,
+    ValidatedJson(update): ValidatedJson<UpdateAccount>,
+) -> Result<impl IntoResponse, (StatusCode, String)> {
+    user.require_scope(Scope::AccountWrite)?;
+    let current = sqlx::query_as::<_, Account>(&format!("SELECT {} FROM users WHERE id = $1", ACCOUNT_COLUMNS))
+        .bind(user.id)
+        .fetch_one(&state.pool)
+        .await
+        .map_err(|e| db::error(e, "Failed to fetch account"))?;
+
+    let email = match update.email {
+        Some(email) if email.trim().is_empty() => None,
+        Some(email) => Some(email.trim().to_string()),
+        None => current.email,
+    };
+    let preferences = update.preferences;
+    let email_private = preferences.as_ref().and_then(|p| p.email_private).unwrap_or(current.preferences.email_private);
+    let notify_by_email = preferences.as_ref().and_then(|p| p.notify_by_email).unwrap_or(current.preferences.notify_by_email);
+
+    let result = sqlx::query_as::<_, Account>(&format!(
+        "UPDATE users SET email = $1, email_private = $2, notify_by_email = $3 WHERE id = $4 RETURNING {}",
+        ACCOUNT_COLUMNS
+    ))
+    .bind(email)
+    .bind(email_private)
+    .bind(notify_by_email)
+    .bind(user.id)
+    .fetch_one(&state.pool)
+    .await;
+
+    match result {
+        Ok(account) => Ok(Json(account)),
+        Err(sqlx::Error::Database(db_err)) if db_err.is_unique_violation() => {
+            Err((StatusCode::CONFLICT, "Email is already in use".to_string()))
+        }
+        Err(e) => Err(db::error(e, "Failed to update account")),
+    }
+}
+
+#[axum::debug_handler]
+pub async fn list_assigned(
+    State(state): State<AppState>,
+    AuthUser(user): AuthUser,
+    Query(query): Query<AssignedQuery>,
+    pagination: Pagination,
+) -> Result<impl IntoResponse, (StatusCode, String)> {
+    let issues_total: i64 = sqlx::query_scalar(&format!("SELECT COUNT(*) {}", ASSIGNED_ISSUES))
+        .bind(user.id)
+        .bind(query.include_closed)
+        .fetch_one(&state.pool)
+        .await
+        .map_err(|e| db::error(e, "Failed to count assigned issues"))?;
+
+    let issues = sqlx::query_as::<_, AssignedIssue>(&format!(
+        "SELECT r.name AS repository, i.* {} ORDER BY i.created_at DESC, i.id DESC LIMIT $3 OFFSET $4",
+        ASSIGNED_ISSUES
+    ))
+    .bind(user.id)
+    .bind(query.include_closed)
+    .bind(pagination.limit())
+    .bind(pagination.offset())
+    .fetch_all(&state.pool)
+    .await
+    .map_err(|e| db::error(e, "Failed to list assigned issues"))?;
+
+    let pulls_total: i64 = sqlx::query_scalar(&format!("SELECT COUNT(*) {}", REVIEW_REQUESTED_PULLS))
+        .bind(user.id)
+        .bind(query.include_closed)
+        .fetch_one(&state.pool)
+        .await
+        .map_err(|e| db::error(e, "Failed to count requested reviews"))?;
+
+    let pull_requests = sqlx::query_as::<_, AssignedPullRequest>(&format!(
+        "SELECT r.name AS repository, pr.* {} ORDER BY pr.created_at DESC, pr.id DESC LIMIT $3 OFFSET $4",
+        REVIEW_REQUESTED_PULLS
+    ))
+    .bind(user.id)
+    .bind(query.include_closed)
+    .bind(pagination.limit())
+    .bind(pagination.offset())
+    .fetch_all(&state.pool)
+    .await
+    .map_err(|e| db::error(e, "Failed to list requested reviews"))?;
+
+    Ok(Json(AssignedWork {
+        issues: AssignedGroup { total: issues_total, items: issues },
+        pull_requests: AssignedGroup { total: pulls_total, items: pull_requests },
+    }))
+}
+
+#[axum::debug_handler]
+pub async fn list_authored_pulls(
+    State(state): State<AppState>,
+    AuthUser(user): AuthUser,
+    Query(query): Query<AuthoredPullsQuery>,
+    pagination: Pagination,
+) -> Result<impl IntoResponse, (StatusCode, String)> {
+    let status = query.status.map(|s| s.to_string());
+
+    let total: i64 = sqlx::query_scalar(&format!("SELECT COUNT(*) {}", AUTHORED_PULLS))
+        .bind(user.id)
+        .bind(&status)
+        .fetch_one(&state.pool)
+        .await
+        .map_err(|e| db::error(e, "Failed to count pull requests"))?;
+
+    let pull_requests = sqlx::query_as::<_, AssignedPullRequest>(&format!(
+        "SELECT r.name AS repository, pr.* {} ORDER BY pr.created_at DESC, pr.id DESC LIMIT $3 OFFSET $4",
+        AUTHORED_PULLS
+    ))
+    .bind(user.id)
+    .bind(&status)
+    .bind(pagination.limit())
+    .bind(pagination.offset())
+    .fetch_all(&state.pool)
+    .await
+    .map_err(|e| db::error(e, "Failed to list pull requests"))?;
+
+    Ok(pagination.respond(pull_requests, total))
+}
@@ -0,0 +1,175 @@
+use axum::{extract::{Path, State}, http::StatusCode, response::IntoResponse, Json};
+use serde::{Deserialize, Serialize};
+use sqlx::FromRow;
+
+use crate::auth::{generate_token, hash_token, AuthUser, Scope, API_TOKEN_PREFIX};
+use crate::db;
+use crate::validation::{ValidatedJson, Validate, Validator};
+use crate::AppState;
+
+const MAX_NAME_CHARS: usize = 100;
+const MAX_DESCRIPTION_CHARS: usize = 1024;
+
+#[derive(Serialize, FromRow)]
+pub struct ApiToken {
+    id: i32,
+    name: Option<String>,
+    description: Option<String>,
+    scopes: Vec<String>,
+    created_at: chrono::DateTime<chrono::Utc>,
+    last_used_at: Option<chrono::DateTime<chrono::Utc>>,
+}
+
+#[derive(Serialize)]
+pub struct CreatedApiToken {
+    #[serde(flatten)]
+    metadata: ApiToken,
+    // Only ever shown here; the server keeps just a hash.
+    token: String,
+}
+
+#[derive(Deserialize)]
+pub struct NewApiToken {
+    name: Option<String>,
+    description: Option<String>,
+    // When absent, every scope in `Scope::DEFAULT` the creating credential
+    // holds.
+    scopes: Option<Vec<Scope>>,
+}
+
+impl Validate for NewApiToken {
+    fn validate(&self, v: &mut Validator) {
+        v.max_chars("name", self.name.as_deref(), MAX_NAME_CHARS)
+            .max_chars("description", self.description.as_deref(), MAX_DESCRIPTION_CHARS);
+    }
+}
+
+const API_TOKEN_COLUMNS: &str = "id, name, description, scopes, created_at, last_used_at";
+
+// Personal access tokens authenticate like a login token but never expire;
+// they last until deleted. A token can only hand out scopes it holds itself.
+#[axum::debug_handler]
+pub async fn create_api_token(
+    State(state): State<AppState>,
+    AuthUser(user): AuthUser,
+    ValidatedJson(new_token): ValidatedJson<NewApiToken>,
+) -> Result<impl IntoResponse, (StatusCode, String)> {
+    user.require_scope(Scope::AccountWrite)?;
+    let mut scopes = new_token.scopes.unwrap_or_else(|| Scope::DEFAULT.into_iter().filter(|&scope| user.has_scope(scope)).collect());
+    scopes.sort_by_key(|scope| scope.as_str());
+    scopes.dedup();
+    for &scope in &scopes {
+        user.require_scope(scope)?;
+    }
+
+    let token = format!("{}{}", API_TOKEN_PREFIX, generate_token());
+    let metadata = sqlx::query_as::<_, ApiToken>(&format!(
+        "INSERT INTO api_tokens (user_id, token_hash, name, description, scopes) VALUES ($1, $2, $3, $4, $5) RETURNING {}",
+        API_TOKEN_COLUMNS
+    ))
+    .bind(user.id)
+    .bind(hash_token(&token))
+    .bind(new_token.name.filter(|name| !name.trim().is_empty()))
+    .bind(new_token.description.filter(|description| !description.trim().is_empty()))
+    .bind(scopes.iter().map(|scope| scope.as_str()).collect::<Vec<_>>())
+    .fetch_one(&state.pool)
+    .await
+    .map_err(|e| db::error(e, "Failed to create token"))?;
+
+    Ok((StatusCode::CREATED, Json(CreatedApiToken { metadata, token })))
+}
+
+#[axum::debug_handler]
+pub async fn list_api_tokens(
+    State(state): State<AppState>,
+    AuthUser(user): AuthUser,
+) -> Result<impl IntoResponse, (StatusCode, String)> {
+    let tokens = sqlx::query_as::<_, ApiToken>(&format!("SELECT {} FROM api_tokens WHERE user_id = $1 ORDER BY id", API_TOKEN_COLUMNS))
+        .bind(user.id)
+        .fetch_all(&state.pool)
+        .await
+        .map_err(|e| db::error(e, "Failed to list tokens"))?;
+
+    Ok(Json(tokens))
+}
+
+#[axum::debug_handler]
+pub async fn delete_api_token(
+    State(state): State<AppState>,
+    AuthUser(user): AuthUser,
+    Path(token_id): Path<i32>,
+) -> Result<impl IntoResponse, (StatusCode, String)> {
+    user.require_scope(Scope::AccountWrite)?;
+    let result = sqlx::query("DELETE FROM api_tokens WHERE id = $1 AND user_id = $2")
+        .bind(token_id)
+        .bind(user.id)
+        .execute(&state.pool)
+        .await
+        .map_err(|e| db::error(e, "Failed to delete token"))?;
+
+    if result.rows_affected() == 0 {
+        return Err((StatusCode::NOT_FOUND, "Token not found".to_string()));
+    }
+
+    Ok(StatusCode::NO_CONTENT)
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::http::StatusCode;
+    use serde_json::json;
+    use sqlx::PgPool;
+
+    use crate::test_support::{TestApp, PASSWORD};
+
+    async fn create_token(app: &TestApp, session: &str, scopes: serde_json::Value) -> (i64, String) {
+        let response = app.post("/me/tokens", Some(session), json!({ "scopes": scopes })).await;
+        assert_eq!(response.status, StatusCode::CREATED, "{}", response.body);
+        (response.body["id"].as_i64().unwrap(), response.body["token"].as_str().unwrap().to_string())
+    }
+
+    #[sqlx::test]
+    async fn read_only_token_can_list_but_not_create_repositories(pool: PgPool) {
+        let app = TestApp::new(pool);
+        let session = app.sign_up("alice").await;
+        let (_, token) = create_token(&app, &session, json!(["repo:read"])).await;
+
+        assert_eq!(app.get("/repos", Some(&token)).await.status, StatusCode::OK);
+        let response = app.post("/repos", Some(&token), json!({ "name": "scoped" })).await;
+        assert_eq!(response.status, StatusCode::FORBIDDEN);
+    }
+
+    #[sqlx::test]
+    async fn read_only_token_cannot_change_the_account(pool: PgPool) {
+        let app = TestApp::new(pool);
+        let session = app.sign_up("alice").await;
+        let (other_id, _) = create_token(&app, &session, json!(["repo:read"])).await;
+        let (_, token) = create_token(&app, &session, json!(["repo:read"])).await;
+
+        let response = app.patch("/account", Some(&token), json!({ "email": "attacker@evil.test" })).await;
+        assert_eq!(response.status, StatusCode::FORBIDDEN);
+        let response = app
+            .post("/me/password", Some(&token), json!({ "current_password": PASSWORD, "new_password": "another password" }))
+            .await;
+        assert_eq!(response.status, StatusCode::FORBIDDEN);
+        assert_eq!(app.post("/me/tokens", Some(&token), json!({})).await.status, StatusCode::FORBIDDEN);
+        assert_eq!(app.delete(&format!("/me/tokens/{}", other_id), Some(&token)).await.status, StatusCode::FORBIDDEN);
+
+        let account = app.get("/account", Some(&session)).await;
+        assert_eq!(account.body["email"], serde_json::Value::Null);
+        app.login("alice").await;
+    }
+
+    #[sqlx::test]
+    async fn account_write_is_only_granted_by_name(pool: PgPool) {
+        let app = TestApp::new(pool);
+        let session = app.sign_up("alice").await;
+
+        let response = app.post("/me/tokens", Some(&session), json!({})).await;
+        assert_eq!(response.body["scopes"], json!(["issues:write", "repo:read", "repo:write"]));
+
+        let (_, token) = create_token(&app, &session, json!(["account:write"])).await;
+        let response = app.patch("/account", Some(&token), json!({ "email": "alice@example.com" })).await;
+        assert_eq!(response.status, StatusCode::OK, "{}", response.body);
+    }
+}
@@ -1,15 +1,21 @@
 use axum::{
     extract::{FromRequestParts, State},
-    http::{request::Parts, HeaderMap, StatusCode},
+    http::{header, request::Parts, HeaderMap, StatusCode},
     response::{IntoResponse, Json, Response},
 };
 use async_trait::async_trait;
+use base64::{engine::general_purpose::STANDARD, Engine};
 use bcrypt::{hash, verify, DEFAULT_COST};
 use rand::distributions::Alphanumeric;
 use rand::{thread_rng, Rng};
 use serde::{Deserialize, Serialize};
-use sqlx::FromRow;
+use sha2::{Digest, Sha256};
+use sqlx::{FromRow, PgConnection, PgPool};
+use std::time::Duration;
 
+use crate::db;
+use crate::rate_limit::{self, ClientIp};
+use crate::validation::{ValidatedJson, Validate, Validator};
 use crate::AppState;
 
 #[derive(Debug, Serialize, FromRow, Clone)]
@@ -18,20 +24,82 @@ pub struct User {
     pub username: String,
     #[serde(skip_serializing)]
     password_hash: String,
+    // What the presented token may do; `None` for login sessions, which may
+    // do everything.
+    #[serde(skip_serializing)]
+    scopes: Option<Vec<String>>,
+}
+
+// What a personal access token can be limited to.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Scope {
+    #[serde(rename = "repo:read")]
+    RepoRead,
+    // Pushing, changing repositories and their settings, and pull requests.
+    #[serde(rename = "repo:write")]
+    RepoWrite,
+    #[serde(rename = "issues:write")]
+    IssuesWrite,
+    // The account itself: its settings, password and tokens.
+    #[serde(rename = "account:write")]
+    AccountWrite,
+}
+
+impl Scope {
+    // What a new token gets unless it names its scopes. `account:write` is
+    // left out so that a leaked token cannot take over the account.
+    pub const DEFAULT: [Scope; 3] = [Scope::RepoRead, Scope::RepoWrite, Scope::IssuesWrite];
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Scope::RepoRead => "repo:read",
+            Scope::RepoWrite => "repo:write",
+            Scope::IssuesWrite => "issues:write",
+            Scope::AccountWrite => "account:write",
+        }
+    }
+}
+
+impl User {
+    // Any scope implies `repo:read`: nothing can be written to a repository
+    // the token cannot see.
+    pub(crate) fn has_scope(&self, scope: Scope) -> bool {
+        match &self.scopes {
+            None => true,
+            Some(scopes) => scopes.iter().any(|granted| granted == scope.as_str()) || (scope == Scope::RepoRead && !scopes.is_empty()),
+        }
+    }
+
+    pub(crate) fn require_scope(&self, scope: Scope) -> Result<(), (StatusCode, String)> {
+        match self.has_scope(scope) {
+            true => Ok(()),
+            false => Err((StatusCode::FORBIDDEN, format!("This token lacks the '{}' scope", scope.as_str()))),
+        }
+    }
 }
 
 pub struct AuthUser(pub User);
 
+// Who a token belongs to, as `GET /me` reports it.
+#[derive(Serialize, FromRow)]
+pub struct UserProfile {
+    id: i32,
+    username: String,
+    created_at: chrono::DateTime<chrono::Utc>,
+}
+
 #[async_trait]
 impl FromRequestParts<AppState> for AuthUser {
     type Rejection = Response;
 
     async fn from_request_parts(parts: &mut Parts, state: &AppState) -> Result<Self, Self::Rejection> {
-        let token = get_token_from_header(&parts.headers).ok_or_else(|| {
-            (StatusCode::UNAUTHORIZED, "Missing or invalid authorization header").into_response()
-        })?;
+        let credentials = get_credentials(&parts.headers, state.config.allow_basic_auth)
+            .ok_or_else(|| unauthorized(state, "Missing or invalid authorization header"))?;
 
-        let user = validate_token(token, state).await.map_err(|e| e.into_response())?;
+        let user = authenticate(&credentials, state).await.map_err(|status| match status {
+            StatusCode::UNAUTHORIZED => unauthorized(state, "Invalid credentials"),
+            status => status.into_response(),
+        })?;
         Ok(AuthUser(user))
     }
 }
@@ -41,14 +109,20 @@ pub struct PermissiveAuthUser(pub Option<User>);
 
 #[async_trait]
 impl FromRequestParts<AppState> for PermissiveAuthUser {
-    type Rejection = Response; // This rejection is infallible, but the trait requires it.
+    type Rejection = Response; // Only rejects when the deployment disallows anonymous reads.
 
     async fn from_request_parts(parts: &mut Parts, state: &AppState) -> Result<Self, Self::Rejection> {
-        let user = if let Some(token) = get_token_from_header(&parts.headers) {
-            validate_token(token, state).await.ok()
-        } else {
-            None
+        // A token without `repo:read` reads only what anyone could.
+        let user = match get_credentials(&parts.headers, state.config.allow_basic_auth) {
+            Some(credentials) => authenticate(&credentials, state).await.ok().filter(|user| user.has_scope(Scope::RepoRead)),
+            None => None,
         };
+        if user.is_none() {
+            if !state.config.allow_anonymous_read {
+                return Err(unauthorized(state, "Authentication required"));
+            }
+            rate_limit::check_anonymous(parts, state).map_err(IntoResponse::into_response)?;
+        }
         Ok(PermissiveAuthUser(user))
     }
 }
@@ -59,30 +133,124 @@ pub struct CreateUser {
     password: String,
 }
 
+impl Validate for CreateUser {
+    fn validate(&self, v: &mut Validator) {
+        let username_ok = self.username.chars().all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_');
+        v.required("username", &self.username, 39)
+            .check(username_ok, "username", "may only contain letters, digits, '-' and '_'")
+            .check(!self.password.is_empty(), "password", "must not be empty")
+            .check(self.password.len() <= MAX_PASSWORD_BYTES, "password", format!("must be at most {} bytes", MAX_PASSWORD_BYTES));
+    }
+}
+
+// Only enforced when a password is changed; accounts registered before this
+// existed keep whatever they have until then.
+const MIN_PASSWORD_CHARS: usize = 8;
+// bcrypt ignores everything past 72 bytes.
+const MAX_PASSWORD_BYTES: usize = 72;
+
+const MIN_SESSION_TTL_SECS: u64 = 60;
+const MAX_SESSION_TTL_SECS: u64 = 90 * 24 * 60 * 60;
+const SESSION_SWEEP_INTERVAL: Duration = Duration::from_secs(60 * 60);
+
 #[derive(Debug, Deserialize)]
 pub struct LoginUser {
     username: String,
     password: String,
+    // Defaults to `SESSION_TTL_SECS`.
+    ttl_seconds: Option<u64>,
+}
+
+impl Validate for LoginUser {
+    fn validate(&self, v: &mut Validator) {
+        v.check(
+            self.ttl_seconds.is_none_or(|ttl| (MIN_SESSION_TTL_SECS..=MAX_SESSION_TTL_SECS).contains(&ttl)),
+            "ttl_seconds",
+            format!("must be between {} and {}", MIN_SESSION_TTL_SECS, MAX_SESSION_TTL_SECS),
+        );
+    }
 }
 
 #[derive(Debug, Serialize)]
 pub struct LoginResponse {
     token: String,
+    expires_at: chrono::DateTime<chrono::Utc>,
+    // Exchanged at `POST /refresh` for a new token without the password.
+    refresh_token: String,
+    refresh_token_expires_at: chrono::DateTime<chrono::Utc>,
+}
+
+#[derive(Deserialize)]
+pub struct ChangePassword {
+    current_password: String,
+    new_password: String,
+}
+
+impl Validate for ChangePassword {
+    fn validate(&self, v: &mut Validator) {
+        v.check(
+            self.new_password.chars().count() >= MIN_PASSWORD_CHARS,
+            "new_password",
+            format!("must be at least {} characters", MIN_PASSWORD_CHARS),
+        )
+        .check(self.new_password.len() <= MAX_PASSWORD_BYTES, "new_password", format!("must be at most {} bytes", MAX_PASSWORD_BYTES));
+    }
+}
+
+#[derive(Deserialize)]
+pub struct RefreshRequest {
+    refresh_token: String,
+}
+
+#[derive(FromRow)]
+struct StoredRefreshToken {
+    user_id: i32,
+    family: String,
+    expired: bool,
+    used: bool,
+}
+
+// Login failures are machine-readable so clients can tell bad credentials
+// from a lockout without parsing text.
+#[derive(Serialize)]
+pub struct LoginError {
+    error: &'static str,
+}
+
+fn invalid_credentials() -> Response {
+    (StatusCode::UNAUTHORIZED, Json(LoginError { error: "invalid_credentials" })).into_response()
+}
+
+#[derive(Serialize)]
+pub struct TokenInfo {
+    // `session` or `personal_access_token`.
+    #[serde(rename = "type")]
+    token_type: &'static str,
+    // Always absent for personal access tokens, which do not expire.
+    expires_at: Option<chrono::DateTime<chrono::Utc>>,
+}
+
+#[derive(Serialize)]
+pub struct Introspection {
+    user: User,
+    token: TokenInfo,
 }
 
 pub async fn register_handler(
     State(state): State<AppState>,
-    Json(payload): Json<CreateUser>,
+    ValidatedJson(payload): ValidatedJson<CreateUser>,
 ) -> impl IntoResponse {
-    let password_hash = match hash(payload.password, DEFAULT_COST) {
+    if !state.config.allow_registration {
+        return (StatusCode::FORBIDDEN, "Registration is disabled").into_response();
+    }
+
+    let password_hash = match hash_password(payload.password).await {
         Ok(h) => h,
-        Err(_) => {
-            return (StatusCode::INTERNAL_SERVER_ERROR, "Failed to hash password").into_response()
-        }
+        Err(e) => return e.into_response(),
     };
 
     let result = sqlx::query_as::<_, User>(
-        "INSERT INTO users (username, password_hash) VALUES ($1, $2) RETURNING id, username, password_hash",
+        "INSERT INTO users (username, password_hash) VALUES ($1, $2) RETURNING id, username, password_hash, NULL::text[] AS scopes",
     )
     .bind(&payload.username)
     .bind(&password_hash)
@@ -96,16 +264,26 @@ pub async fn register_handler(
         }
         Err(e) => {
             tracing::error!("Failed to register user: {}", e);
-            (StatusCode::INTERNAL_SERVER_ERROR, "Failed to register user").into_response()
+            (db::error_status(&e), "Failed to register user").into_response()
         }
     }
 }
 
 pub async fn login_handler(
     State(state): State<AppState>,
-    Json(payload): Json<LoginUser>,
+    ClientIp(ip): ClientIp,
+    ValidatedJson(payload): ValidatedJson<LoginUser>,
 ) -> impl IntoResponse {
-    let result = sqlx::query_as::<_, User>("SELECT id, username, password_hash FROM users WHERE username = $1")
+    if let Err(limited) = rate_limit::check_login(&state, &payload.username, ip) {
+        return (
+            StatusCode::TOO_MANY_REQUESTS,
+            [(header::RETRY_AFTER, limited.retry_after_secs.to_string())],
+            Json(LoginError { error: "too_many_attempts" }),
+        )
+            .into_response();
+    }
+
+    let result = sqlx::query_as::<_, User>("SELECT id, username, password_hash, NULL::text[] AS scopes FROM users WHERE username = $1")
         .bind(&payload.username)
         .fetch_one(&state.pool)
         .await;
@@ -113,58 +291,379 @@ pub async fn login_handler(
     let user = match result {
         Ok(user) => user,
         Err(sqlx::Error::RowNotFound) => {
-            return (StatusCode::UNAUTHORIZED, "Invalid username or password").into_response();
+            rate_limit::record_login_failure(&state, &payload.username, ip);
+            return invalid_credentials();
         }
         Err(e) => {
             tracing::error!("Failed to fetch user: {}", e);
-            return (StatusCode::INTERNAL_SERVER_ERROR, "Failed to login").into_response();
+            return (db::error_status(&e), "Failed to login").into_response();
+        }
+    };
+
+    if !password_matches(payload.password, user.password_hash.clone()).await {
+        rate_limit::record_login_failure(&state, &payload.username, ip);
+        return invalid_credentials();
+    }
+    rate_limit::clear_login_failures(&state, &payload.username, ip);
+
+    let ttl_secs = payload.ttl_seconds.unwrap_or(state.config.session_ttl_secs);
+    match issue_tokens(&state, user.id, ttl_secs, None).await {
+        Ok(response) => (StatusCode::OK, Json(response)).into_response(),
+        Err(e) => db::error(e, "Failed to create session").into_response(),
+    }
+}
+
+pub(crate) fn generate_token() -> String {
+    thread_rng().sample_iter(&Alphanumeric).take(32).map(char::from).collect()
+}
+
+// Creates a session and the refresh token that renews it. A refresh token
+// starts a new family at login and inherits its predecessor's on rotation.
+async fn issue_tokens(state: &AppState, user_id: i32, ttl_secs: u64, family: Option<String>) -> Result<LoginResponse, sqlx::Error> {
+    let mut tx = state.pool.begin().await?;
+    let response = insert_tokens(&mut tx, state, user_id, ttl_secs, family).await?;
+    tx.commit().await?;
+    Ok(response)
+}
+
+async fn insert_tokens(conn: &mut PgConnection, state: &AppState, user_id: i32, ttl_secs: u64, family: Option<String>) -> Result<LoginResponse, sqlx::Error> {
+    let (token, refresh_token) = (generate_token(), generate_token());
+    let family = family.unwrap_or_else(|| hash_token(&refresh_token));
+
+    let expires_at = sqlx::query_scalar::<_, chrono::DateTime<chrono::Utc>>(
+        "INSERT INTO sessions (token_hash, user_id, expires_at, refresh_family) VALUES ($1, $2, now() + make_interval(secs => $3), $4) RETURNING expires_at",
+    )
+    .bind(hash_token(&token))
+    .bind(user_id)
+    .bind(ttl_secs as f64)
+    .bind(&family)
+    .fetch_one(&mut *conn)
+    .await?;
+
+    let refresh_token_expires_at = sqlx::query_scalar::<_, chrono::DateTime<chrono::Utc>>(
+        "INSERT INTO refresh_tokens (token_hash, user_id, family, expires_at) VALUES ($1, $2, $3, now() + make_interval(secs => $4)) RETURNING expires_at",
+    )
+    .bind(hash_token(&refresh_token))
+    .bind(user_id)
+    .bind(&family)
+    .bind(state.config.refresh_token_ttl_secs as f64)
+    .fetch_one(&mut *conn)
+    .await?;
+
+    Ok(LoginResponse { token, expires_at, refresh_token, refresh_token_expires_at })
+}
+
+// Trades a refresh token for a new session token and a new refresh token.
+// Each refresh token works once: presenting a used one means it was copied,
+// so every session and refresh token of its family is revoked.
+pub async fn refresh_handler(State(state): State<AppState>, Json(payload): Json<RefreshRequest>) -> Response {
+    let invalid = || (StatusCode::UNAUTHORIZED, Json(LoginError { error: "invalid_refresh_token" })).into_response();
+
+    let result: Result<Option<LoginResponse>, sqlx::Error> = async {
+        let mut tx = state.pool.begin().await?;
+        let stored = sqlx::query_as::<_, StoredRefreshToken>(
+            "SELECT user_id, family, expires_at <= now() AS expired, used_at IS NOT NULL AS used FROM refresh_tokens WHERE token_hash = $1 FOR UPDATE",
+        )
+        .bind(hash_token(&payload.refresh_token))
+        .fetch_optional(&mut *tx)
+        .await?;
+
+        let Some(stored) = stored.filter(|stored| !stored.expired) else {
+            return Ok(None);
+        };
+        if stored.used {
+            tracing::warn!("Refresh token reused for user {}; revoking its family", stored.user_id);
+            revoke_family(&mut tx, &stored.family).await?;
+            tx.commit().await?;
+            return Ok(None);
+        }
+
+        sqlx::query("UPDATE refresh_tokens SET used_at = now() WHERE token_hash = $1")
+            .bind(hash_token(&payload.refresh_token))
+            .execute(&mut *tx)
+            .await?;
+        let response = insert_tokens(&mut tx, &state, stored.user_id, state.config.session_ttl_secs, Some(stored.family)).await?;
+        tx.commit().await?;
+        Ok(Some(response))
+    }
+    .await;
+
+    match result {
+        Ok(Some(response)) => (StatusCode::OK, Json(response)).into_response(),
+        Ok(None) => invalid(),
+        Err(e) => db::error(e, "Failed to refresh session").into_response(),
+    }
+}
+
+async fn revoke_family(conn: &mut PgConnection, family: &str) -> Result<(), sqlx::Error> {
+    sqlx::query("DELETE FROM sessions WHERE refresh_family = $1").bind(family).execute(&mut *conn).await?;
+    sqlx::query("DELETE FROM refresh_tokens WHERE family = $1").bind(family).execute(&mut *conn).await?;
+    Ok(())
+}
+
+// Ends the session of the presented token, along with the refresh tokens
+// that could renew it. Other sessions of the same user stay valid.
+pub async fn logout_handler(State(state): State<AppState>, AuthUser(_user): AuthUser, headers: HeaderMap) -> impl IntoResponse {
+    let Some(credentials) = get_credentials(&headers, state.config.allow_basic_auth) else {
+        return unauthorized(&state, "Missing or invalid authorization header");
+    };
+    let result: Result<(), sqlx::Error> = async {
+        let mut tx = state.pool.begin().await?;
+        let family = sqlx::query_scalar::<_, Option<String>>("DELETE FROM sessions WHERE token_hash = $1 RETURNING refresh_family")
+            .bind(hash_token(&credentials.token))
+            .fetch_optional(&mut *tx)
+            .await?
+            .flatten();
+        if let Some(family) = family {
+            revoke_family(&mut tx, &family).await?;
         }
+        tx.commit().await
+    }
+    .await;
+    match result {
+        Ok(()) => StatusCode::NO_CONTENT.into_response(),
+        Err(e) => db::error(e, "Failed to end session").into_response(),
+    }
+}
+
+// Lets a gateway check a token out of band: answers with the token's user,
+// or `401` exactly as any authenticated endpoint would.
+pub async fn introspect_handler(State(state): State<AppState>, AuthUser(user): AuthUser, headers: HeaderMap) -> Response {
+    let Some(credentials) = get_credentials(&headers, state.config.allow_basic_auth) else {
+        return unauthorized(&state, "Missing or invalid authorization header");
+    };
+    if is_api_token(&credentials.token) {
+        let introspection = Introspection { user, token: TokenInfo { token_type: "personal_access_token", expires_at: None } };
+        return ([(header::CACHE_CONTROL, "no-store")], Json(introspection)).into_response();
+    }
+    let expires_at = sqlx::query_scalar::<_, chrono::DateTime<chrono::Utc>>("SELECT expires_at FROM sessions WHERE token_hash = $1")
+        .bind(hash_token(&credentials.token))
+        .fetch_optional(&state.pool)
+        .await;
+    let expires_at = match expires_at {
+        Ok(expires_at) => expires_at,
+        Err(e) => return db::error(e, "Failed to fetch session").into_response(),
     };
+    let introspection = Introspection { user, token: TokenInfo { token_type: "session", expires_at } };
+    ([(header::CACHE_CONTROL, "no-store")], Json(introspection)).into_response()
+}
+
+// Lets a client confirm it is signed in, and as whom. `GET /account` has the
+// full account, email and preferences included.
+#[axum::debug_handler]
+pub async fn me_handler(State(state): State<AppState>, AuthUser(user): AuthUser) -> Result<impl IntoResponse, (StatusCode, String)> {
+    let profile = sqlx::query_as::<_, UserProfile>("SELECT id, username, created_at FROM users WHERE id = $1")
+        .bind(user.id)
+        .fetch_one(&state.pool)
+        .await
+        .map_err(|e| db::error(e, "Failed to fetch user"))?;
+    Ok(([(header::CACHE_CONTROL, "no-store")], Json(profile)))
+}
 
-    if let Ok(true) = verify(&payload.password, &user.password_hash) {
-        let token: String = thread_rng()
-            .sample_iter(&Alphanumeric)
-            .take(32)
-            .map(char::from)
-            .collect();
+// Changes the password and signs out every other session, so whoever may
+// have had the old password (or a token) loses access. The session making
+// the request stays signed in.
+#[axum::debug_handler]
+pub async fn change_password_handler(
+    State(state): State<AppState>,
+    AuthUser(user): AuthUser,
+    headers: HeaderMap,
+    ValidatedJson(payload): ValidatedJson<ChangePassword>,
+) -> Result<impl IntoResponse, (StatusCode, String)> {
+    user.require_scope(Scope::AccountWrite)?;
+    if !password_matches(payload.current_password, user.password_hash.clone()).await {
+        return Err((StatusCode::UNAUTHORIZED, "Current password is incorrect".to_string()));
+    }
+    let credentials = get_credentials(&headers, state.config.allow_basic_auth)
+        .ok_or_else(|| (StatusCode::UNAUTHORIZED, "Missing or invalid authorization header".to_string()))?;
+
+    let password_hash = hash_password(payload.new_password).await?;
 
-        let result = sqlx::query("INSERT INTO sessions (token, user_id) VALUES ($1, $2)")
-            .bind(&token)
+    let result: Result<(), sqlx::Error> = async {
+        let mut tx = state.pool.begin().await?;
+        sqlx::query("UPDATE users SET password_hash = $1 WHERE id = $2")
+            .bind(&password_hash)
+            .bind(user.id)
+            .execute(&mut *tx)
+            .await?;
+        sqlx::query("DELETE FROM sessions WHERE user_id = $1 AND token_hash <> $2")
+            .bind(user.id)
+            .bind(hash_token(&credentials.token))
+            .execute(&mut *tx)
+            .await?;
+        // Only the refresh tokens that renew the current session survive.
+        sqlx::query(
+            "DELETE FROM refresh_tokens WHERE user_id = $1 AND family IS DISTINCT FROM (SELECT refresh_family FROM sessions WHERE token_hash = $2)",
+        )
+        .bind(user.id)
+        .bind(hash_token(&credentials.token))
+        .execute(&mut *tx)
+        .await?;
+        // Tokens do not expire, so one minted with the old password would
+        // otherwise outlive the change for good.
+        sqlx::query("DELETE FROM api_tokens WHERE user_id = $1")
             .bind(user.id)
-            .execute(&state.pool)
-            .await;
-
-        match result {
-            Ok(_) => (StatusCode::OK, Json(LoginResponse { token })).into_response(),
-            Err(e) => {
-                tracing::error!("Failed to create session: {}", e);
-                (StatusCode::INTERNAL_SERVER_ERROR, "Failed to create session").into_response()
+            .execute(&mut *tx)
+            .await?;
+        tx.commit().await
+    }
+    .await;
+    result.map_err(|e| db::error(e, "Failed to change password"))?;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+// bcrypt is slow on purpose, so hashing and checking passwords stays off the
+// async workers.
+async fn hash_password(password: String) -> Result<String, (StatusCode, String)> {
+    tokio::task::spawn_blocking(move || hash(password, DEFAULT_COST))
+        .await
+        .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, format!("Task failed: {}", e)))?
+        .map_err(|_| (StatusCode::INTERNAL_SERVER_ERROR, "Failed to hash password".to_string()))
+}
+
+async fn password_matches(password: String, password_hash: String) -> bool {
+    tokio::task::spawn_blocking(move || verify(password, &password_hash).unwrap_or(false))
+        .await
+        .unwrap_or(false)
+}
+
+// Expired tokens are already refused; this only keeps the tables from
+// growing without bound. Used refresh tokens are kept until they expire so
+// their reuse is still recognised.
+pub fn spawn_session_sweeper(pool: PgPool) {
+    tokio::spawn(async move {
+        let mut ticker = tokio::time::interval(SESSION_SWEEP_INTERVAL);
+        loop {
+            ticker.tick().await;
+            for table in ["sessions", "refresh_tokens"] {
+                match sqlx::query(&format!("DELETE FROM {} WHERE expires_at <= now()", table)).execute(&pool).await {
+                    Ok(result) if result.rows_affected() > 0 => tracing::debug!("Removed {} expired {}", result.rows_affected(), table),
+                    Ok(_) => {}
+                    Err(e) => tracing::warn!("Failed to remove expired {}: {}", table, e),
+                }
             }
         }
+    });
+}
+
+struct Credentials {
+    token: String,
+    username: Option<String>,
+}
+
+// Bearer tokens are always accepted. When Basic auth is enabled (mainly for git
+// clients), the password is a session token and the username must match it.
+fn get_credentials(headers: &HeaderMap, allow_basic: bool) -> Option<Credentials> {
+    let auth_str = headers.get(header::AUTHORIZATION)?.to_str().ok()?;
+    if let Some(token) = auth_str.strip_prefix("Bearer ") {
+        return Some(Credentials { token: token.to_string(), username: None });
+    }
+
+    let encoded = auth_str.strip_prefix("Basic ").filter(|_| allow_basic)?;
+    let decoded = String::from_utf8(STANDARD.decode(encoded.trim()).ok()?).ok()?;
+    let (username, token) = decoded.split_once(':')?;
+    Some(Credentials { token: token.to_string(), username: Some(username.to_string()) })
+}
+
+async fn authenticate(credentials: &Credentials, state: &AppState) -> Result<User, StatusCode> {
+    let user = validate_token(&credentials.token, state).await?;
+    match &credentials.username {
+        Some(username) if *username != user.username => Err(StatusCode::UNAUTHORIZED),
+        _ => Ok(user),
+    }
+}
+
+pub(crate) fn unauthorized(state: &AppState, message: &'static str) -> Response {
+    if state.config.allow_basic_auth {
+        (StatusCode::UNAUTHORIZED, [(header::WWW_AUTHENTICATE, "Basic realm=\"git8\"")], message).into_response()
     } else {
-        (StatusCode::UNAUTHORIZED, "Invalid username or password").into_response()
+        (StatusCode::UNAUTHORIZED, message).into_response()
     }
 }
 
-fn get_token_from_header(headers: &HeaderMap) -> Option<&str> {
-    headers
-        .get("Authorization")
-        .and_then(|auth_header| auth_header.to_str().ok())
-        .and_then(|auth_str| auth_str.strip_prefix("Bearer "))
+// Only a hash of each token is stored, so a leaked `sessions` table cannot be
+// replayed. Tokens are random, which makes a fast unsalted hash sufficient.
+pub(crate) fn hash_token(token: &str) -> String {
+    format!("{:x}", Sha256::digest(token.as_bytes()))
+}
+
+// Personal access tokens carry this prefix so they can be told apart from
+// session tokens without a lookup, and recognised if they leak.
+pub(crate) const API_TOKEN_PREFIX: &str = "git8_pat_";
+
+fn is_api_token(token: &str) -> bool {
+    token.starts_with(API_TOKEN_PREFIX)
 }
 
+// Accepts a live session token or a personal access token. Using a personal
+// access token records when it was last used.
 async fn validate_token(token: &str, state: &AppState) -> Result<User, StatusCode> {
-    sqlx::query_as::<_, User>(
-        "SELECT u.id, u.username, u.password_hash FROM users u JOIN sessions s ON u.id = s.user_id WHERE s.token = $1",
-    )
-    .bind(token)
-    .fetch_one(&state.pool)
-    .await
-    .map_err(|e| match e {
-        sqlx::Error::RowNotFound => StatusCode::UNAUTHORIZED,
-        _ => {
-            tracing::error!("Token validation failed: {}", e);
-            StatusCode::INTERNAL_SERVER_ERROR
-        }
-    })
+    let query = match is_api_token(token) {
+        true => "UPDATE api_tokens t SET last_used_at = now() FROM users u WHERE t.token_hash = $1 AND u.id = t.user_id RETURNING u.id, u.username, u.password_hash, t.scopes",
+        false => "SELECT u.id, u.username, u.password_hash, NULL::text[] AS scopes FROM users u JOIN sessions s ON u.id = s.user_id WHERE s.token_hash = $1 AND s.expires_at > now()",
+    };
+    sqlx::query_as::<_, User>(query)
+        .bind(hash_token(token))
+        .fetch_one(&state.pool)
+        .await
+        .map_err(|e| match e {
+            sqlx::Error::RowNotFound => StatusCode::UNAUTHORIZED,
+            _ => {
+                tracing::error!("Token validation failed: {}", e);
+                db::error_status(&e)
+            }
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::http::StatusCode;
+    use serde_json::json;
+    use sqlx::PgPool;
+
+    use super::hash_token;
+    use crate::test_support::{TestApp, PASSWORD};
+
+    #[sqlx::test]
+    async fn sessions_are_stored_hashed(pool: PgPool) {
+        let app = TestApp::new(pool);
+        let token = app.sign_up("alice").await;
+        assert_eq!(app.get("/me", Some(&token)).await.status, StatusCode::OK);
+
+        let stored: Vec<String> = sqlx::query_scalar("SELECT token_hash FROM sessions").fetch_all(&app.pool).await.unwrap();
+        assert_eq!(stored, vec![hash_token(&token)]);
+        assert_ne!(stored[0], token);
+
+        // A row from before hashing holds the token itself, which no longer
+        // matches once the presented token is hashed.
+        let user_id: i32 = sqlx::query_scalar("SELECT id FROM users WHERE username = 'alice'").fetch_one(&app.pool).await.unwrap();
+        sqlx::query("INSERT INTO sessions (token_hash, user_id, expires_at) VALUES ('legacy-plaintext-token', $1, now() + interval '1 day')")
+            .bind(user_id)
+            .execute(&app.pool)
+            .await
+            .unwrap();
+        assert_eq!(app.get("/me", Some("legacy-plaintext-token")).await.status, StatusCode::UNAUTHORIZED);
+    }
+
+    #[sqlx::test]
+    async fn changing_the_password_signs_out_everything_else(pool: PgPool) {
+        let app = TestApp::new(pool);
+        let current = app.sign_up("alice").await;
+        let other = app.login("alice").await;
+        let token = app.post("/me/tokens", Some(&current), json!({})).await.body["token"].as_str().unwrap().to_string();
+        assert_eq!(app.get("/me", Some(&token)).await.status, StatusCode::OK);
+
+        let response = app.post("/me/password", Some(&current), json!({ "current_password": "wrong password", "new_password": "a new password" })).await;
+        assert_eq!(response.status, StatusCode::UNAUTHORIZED);
+        let response = app.post("/me/password", Some(&current), json!({ "current_password": PASSWORD, "new_password": "short" })).await;
+        assert_eq!(response.status, StatusCode::UNPROCESSABLE_ENTITY);
+        let response = app.post("/me/password", Some(&current), json!({ "current_password": PASSWORD, "new_password": "a new password" })).await;
+        assert_eq!(response.status, StatusCode::NO_CONTENT, "{}", response.body);
+
+        assert_eq!(app.get("/me", Some(&current)).await.status, StatusCode::OK);
+        assert_eq!(app.get("/me", Some(&other)).await.status, StatusCode::UNAUTHORIZED);
+        assert_eq!(app.get("/me", Some(&token)).await.status, StatusCode::UNAUTHORIZED);
+        let response = app.post("/login", None, json!({ "username": "alice", "password": "a new password" })).await;
+        assert_eq!(response.status, StatusCode::OK);
+    }
 }
@@ -0,0 +1,139 @@
+use axum::{
+    extract::{Path, Query, State},
+    http::{header, StatusCode},
+    response::IntoResponse,
+};
+use serde::Deserialize;
+
+use crate::auth::PermissiveAuthUser;
+use crate::git_api::{find_visible_repo, resolve_branch_commit};
+use crate::statuses::{combined_status, StatusState};
+use crate::AppState;
+
+const MAX_BADGE_TEXT_CHARS: usize = 100;
+
+const NAMED_COLORS: [(&str, &str); 10] = [
+    ("brightgreen", "#4c1"),
+    ("green", "#97ca00"),
+    ("yellowgreen", "#a4a61d"),
+    ("yellow", "#dfb317"),
+    ("orange", "#fe7d37"),
+    ("red", "#e05d44"),
+    ("blue", "#007ec6"),
+    ("lightgrey", "#9f9f9f"),
+    ("grey", "#555"),
+    ("inactive", "#9f9f9f"),
+];
+
+#[derive(Deserialize)]
+pub struct StatusBadgeQuery {
+    label: Option<String>,
+}
+
+fn escape_xml(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&apos;"),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+// Accepts the named palette or a 3/6 digit hex code without the `#`.
+fn parse_color(color: &str) -> Option<String> {
+    if let Some((_, hex)) = NAMED_COLORS.iter().find(|(name, _)| name.eq_ignore_ascii_case(color)) {
+        return Some(hex.to_string());
+    }
+    let is_hex = matches!(color.len(), 3 | 6) && color.bytes().all(|b| b.is_ascii_hexdigit());
+    is_hex.then(|| format!("#{}", color))
+}
+
+// A flat two-part badge. Text width is estimated from the character count
+// since no font metrics are available server side.
+fn render_badge(label: &str, message: &str, color: &str) -> String {
+    let text_width = |text: &str| text.chars().count() * 7 + 10;
+    let (label_width, message_width) = (text_width(label), text_width(message));
+    let width = label_width + message_width;
+    let (label, message) = (escape_xml(label), escape_xml(message));
+
+    format!(
+        r##"<svg xmlns="http://www.w3.org/2000/svg" width="{width}" height="20" role="img" aria-label="{label}: {message}"><title>{label}: {message}</title><linearGradient id="s" x2="0" y2="100%"><stop offset="0" stop-color="#bbb" stop-opacity=".1"/><stop offset="1" stop-opacity=".1"/></linearGradient><clipPath id="r"><rect width="{width}" height="20" rx="3" fill="#fff"/></clipPath><g clip-path="url(#r)"><rect width="{label_width}" height="20" fill="#555"/><rect x="{label_width}" width="{message_width}" height="20" fill="{color}"/><rect width="{width}" height="20" fill="url(#s)"/></g><g fill="#fff" text-anchor="middle" font-family="Verdana,Geneva,DejaVu Sans,sans-serif" font-size="11"><text x="{label_x}" y="14">{label}</text><text x="{message_x}" y="14">{message}</text></g></svg>"##,
+        label_x = label_width / 2,
+        message_x = label_width + message_width / 2,
+    )
+}
+
+fn svg_response(svg: String, cache_control: &'static str) -> impl IntoResponse {
+    ([(header::CONTENT_TYPE, "image/svg+xml; charset=utf-8"), (header::CACHE_CONTROL, cache_control)], svg)
+}
+
+// Splits `label-message-color` on single dashes. As in shields.io URLs, `--`
+// stands for a dash, `__` for an underscore and `_` for a space.
+fn parse_badge_spec(spec: &str) -> Option<(String, String, String)> {
+    let mut parts = vec![String::new()];
+    let mut chars = spec.chars().peekable();
+    while let Some(c) = chars.next() {
+        match (c, chars.peek()) {
+            ('-', Some('-')) | ('_', Some('_')) => {
+                chars.next();
+                parts.last_mut()?.push(c);
+            }
+            ('-', _) => parts.push(String::new()),
+            ('_', _) => parts.last_mut()?.push(' '),
+            (c, _) => parts.last_mut()?.push(c),
+        }
+    }
+    let [label, message, color]: [String; 3] = parts.try_into().ok()?;
+    Some((label, message, color))
+}
+
+#[axum::debug_handler]
+pub async fn generic_badge(Path(spec): Path<String>) -> Result<impl IntoResponse, (StatusCode, String)> {
+    let spec = spec.strip_suffix(".svg").unwrap_or(&spec);
+    let (label, message, color) = parse_badge_spec(spec)
+        .ok_or_else(|| (StatusCode::UNPROCESSABLE_ENTITY, "Badge must be given as label-message-color".to_string()))?;
+    if label.chars().count() > MAX_BADGE_TEXT_CHARS || message.chars().count() > MAX_BADGE_TEXT_CHARS {
+        return Err((StatusCode::UNPROCESSABLE_ENTITY, format!("Badge text must be at most {} characters", MAX_BADGE_TEXT_CHARS)));
+    }
+    let color = parse_color(&color).ok_or_else(|| (StatusCode::UNPROCESSABLE_ENTITY, format!("'{}' is not a badge color", color)))?;
+
+    // The output depends on nothing but the URL.
+    Ok(svg_response(render_badge(&label, &message, &color), "public, max-age=86400, immutable"))
+}
+
+#[axum::debug_handler]
+pub async fn status_badge(
+    State(state): State<AppState>,
+    PermissiveAuthUser(user): PermissiveAuthUser,
+    Path((repo_name, branch)): Path<(String, String)>,
+    Query(query): Query<StatusBadgeQuery>,
+) -> Result<impl IntoResponse, (StatusCode, String)> {
+    let repo = find_visible_repo(&state.pool, &repo_name, user.map(|u| u.id)).await?;
+
+    let git_repo = git2::Repository::open(format!("./repos/{}.git", repo.name))
+        .map_err(|_| (StatusCode::NOT_FOUND, "Repository not found on filesystem".to_string()))?;
+    let sha = resolve_branch_commit(&git_repo, &branch)?.id().to_string();
+    drop(git_repo);
+    let combined = combined_status(&state.pool, repo.id, sha).await?;
+
+    let (message, color) = match combined.state {
+        Some(StatusState::Success) => ("passing", "brightgreen"),
+        Some(StatusState::Failure | StatusState::Error) => ("failing", "red"),
+        Some(StatusState::Pending) => ("pending", "yellow"),
+        None => ("unknown", "lightgrey"),
+    };
+    let label = query.label.unwrap_or_else(|| "build".to_string());
+    let label: String = label.chars().take(MAX_BADGE_TEXT_CHARS).collect();
+    let color = parse_color(color).unwrap_or_default();
+
+    // Badges are embedded in READMEs behind caching image proxies, which
+    // must revalidate for the badge to follow new statuses.
+    let cache_control = if repo.public { "no-cache" } else { "private, no-cache" };
+    Ok(svg_response(render_badge(&label, message, &color), cache_control))
+}
@@ -0,0 +1,95 @@
+use axum::{
+    extract::{Path, State},
+    http::StatusCode,
+    response::{IntoResponse, Response},
+    Json,
+};
+use serde::Serialize;
+use std::path::Path as StdPath;
+
+use crate::auth::PermissiveAuthUser;
+use crate::blobs::resolve_commit;
+use crate::git_api::{check_repo_read_access, format_commit_time};
+use crate::AppState;
+
+// A run of consecutive lines last changed by the same commit.
+#[derive(Serialize)]
+pub struct BlameRange {
+    sha: String,
+    author: String,
+    // `null` for timestamps chrono cannot represent.
+    author_time: Option<String>,
+    start_line: usize,
+    lines: usize,
+    // Where the lines were in `sha`, which differs from `path` when the file
+    // has been renamed since.
+    original_start_line: usize,
+    original_path: Option<String>,
+}
+
+#[derive(Serialize)]
+pub struct Blame {
+    sha: String,
+    path: String,
+    ranges: Vec<BlameRange>,
+}
+
+fn blame_file(repo_name: &str, rev: &str, path: &str) -> Result<Blame, (StatusCode, String)> {
+    let repo = git2::Repository::open(format!("./repos/{}.git", repo_name))
+        .map_err(|_| (StatusCode::NOT_FOUND, "Repository not found".to_string()))?;
+    let commit = resolve_commit(&repo, rev)?;
+    let path = path.trim_matches('/');
+
+    let tree = commit
+        .tree()
+        .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, format!("Failed to get tree for commit: {}", e)))?;
+    let entry = tree
+        .get_path(StdPath::new(path))
+        .map_err(|_| (StatusCode::NOT_FOUND, "Path not found in repository".to_string()))?;
+    if entry.kind() != Some(git2::ObjectType::Blob) {
+        return Err((StatusCode::NOT_FOUND, "Path is not a file".to_string()));
+    }
+
+    let mut opts = git2::BlameOptions::new();
+    opts.newest_commit(commit.id());
+    let blame = repo
+        .blame_file(StdPath::new(path), Some(&mut opts))
+        .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, format!("Failed to blame file: {}", e)))?;
+
+    let ranges = blame
+        .iter()
+        .map(|hunk| {
+            let author = hunk.final_signature();
+            BlameRange {
+                sha: hunk.final_commit_id().to_string(),
+                author: String::from_utf8_lossy(author.name_bytes()).into_owned(),
+                author_time: format_commit_time(author.when().seconds()),
+                start_line: hunk.final_start_line(),
+                lines: hunk.lines_in_hunk(),
+                original_start_line: hunk.orig_start_line(),
+                original_path: hunk.path().map(|path| path.to_string_lossy().into_owned()),
+            }
+        })
+        .collect();
+
+    Ok(Blame { sha: commit.id().to_string(), path: path.to_string(), ranges })
+}
+
+#[axum::debug_handler]
+pub async fn blame_handler(
+    Path((name, rev, path)): Path<(String, String, String)>,
+    State(state): State<AppState>,
+    user: PermissiveAuthUser,
+) -> Response {
+    let repo_name = name.strip_suffix(".git").unwrap_or(&name).to_string();
+    if let Err(response) = check_repo_read_access(&repo_name, &state.pool, &user).await {
+        return response;
+    }
+
+    // Blame walks the file's whole history, so it stays off the async workers.
+    match tokio::task::spawn_blocking(move || blame_file(&repo_name, &rev, &path)).await {
+        Ok(Ok(blame)) => Json(blame).into_response(),
+        Ok(Err(e)) => e.into_response(),
+        Err(e) => (StatusCode::INTERNAL_SERVER_ERROR, format!("Task failed: {}", e)).into_response(),
+    }
+}
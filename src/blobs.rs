@@ -0,0 +1,394 @@
+use axum::{
+    body::{Body, Bytes},
+    extract::{Path, State},
+    http::{header, HeaderMap, StatusCode},
+    response::{IntoResponse, Response},
+    Json,
+};
+use serde::Serialize;
+use std::io::Read;
+use std::path::Path as StdPath;
+use std::process::{Child, ChildStdout, Command, Stdio};
+
+use crate::auth::PermissiveAuthUser;
+use crate::etag::none_match_hits;
+use crate::git_api::{check_repo_read_access, resolve_branch_commit};
+use crate::AppState;
+
+const CHUNK_BYTES: usize = 64 * 1024;
+// How much of a file is sniffed to tell text from binary, as in git.
+const SNIFF_BYTES: usize = 8000;
+
+#[derive(Debug, PartialEq)]
+enum ByteRange {
+    Full,
+    // Inclusive on both ends, as in `Content-Range`.
+    Partial(u64, u64),
+    Unsatisfiable,
+}
+
+// Understands a single `bytes=` range. Anything else, including multiple
+// ranges and malformed headers, is answered with the whole file, which
+// RFC 9110 allows.
+fn parse_range(range: Option<&str>, size: u64) -> ByteRange {
+    let Some(spec) = range.and_then(|range| range.trim().strip_prefix("bytes=")) else {
+        return ByteRange::Full;
+    };
+    if spec.contains(',') {
+        return ByteRange::Full;
+    }
+    let Some((start, end)) = spec.split_once('-') else {
+        return ByteRange::Full;
+    };
+    let (start, end) = (start.trim(), end.trim());
+
+    if start.is_empty() {
+        // `bytes=-N` asks for the last N bytes.
+        return match end.parse::<u64>() {
+            Ok(0) => ByteRange::Unsatisfiable,
+            Ok(_) if size == 0 => ByteRange::Unsatisfiable,
+            Ok(suffix) => ByteRange::Partial(size.saturating_sub(suffix), size - 1),
+            Err(_) => ByteRange::Full,
+        };
+    }
+
+    let Ok(start) = start.parse::<u64>() else {
+        return ByteRange::Full;
+    };
+    let end = match end {
+        "" => None,
+        end => match end.parse::<u64>() {
+            Ok(end) if end >= start => Some(end),
+            _ => return ByteRange::Full,
+        },
+    };
+    if start >= size {
+        return ByteRange::Unsatisfiable;
+    }
+    ByteRange::Partial(start, end.unwrap_or(u64::MAX).min(size - 1))
+}
+
+// `rev` names a branch or, failing that, a full or abbreviated commit sha,
+// so files can be fetched as they were at any point in history.
+pub(crate) fn resolve_commit<'r>(repo: &'r git2::Repository, rev: &str) -> Result<git2::Commit<'r>, (StatusCode, String)> {
+    let looks_like_sha = (4..=40).contains(&rev.len()) && rev.bytes().all(|b| b.is_ascii_hexdigit());
+    if !looks_like_sha || repo.find_reference(&format!("refs/heads/{}", rev)).is_ok() {
+        return resolve_branch_commit(repo, rev);
+    }
+    repo.revparse_single(rev)
+        .and_then(|object| object.peel_to_commit())
+        .map_err(|e| match e.code() {
+            git2::ErrorCode::Ambiguous => (StatusCode::UNPROCESSABLE_ENTITY, format!("'{}' matches more than one object", rev)),
+            _ => (StatusCode::NOT_FOUND, "Commit not found".to_string()),
+        })
+}
+
+struct BlobInfo {
+    oid: git2::Oid,
+    size: u64,
+    mode: i32,
+}
+
+#[derive(Serialize)]
+pub struct BlobMetadata {
+    sha: String,
+    size: u64,
+    // Octal, as git prints it: `100644`, `100755` or `120000` for symlinks.
+    mode: String,
+    is_binary: bool,
+    // `utf-8` for text, `binary` otherwise.
+    encoding: &'static str,
+}
+
+// Text has no NUL byte and is valid UTF-8, allowing for a character cut off
+// at the end of the sample.
+fn looks_like_text(head: &[u8]) -> bool {
+    !head.contains(&0) && std::str::from_utf8(head).map_or_else(|e| e.error_len().is_none(), |_| true)
+}
+
+// A packed blob streamed out of `git cat-file`. The process is stopped when
+// the reader is dropped, whether or not everything was read.
+struct CatFile {
+    child: Child,
+    stdout: ChildStdout,
+}
+
+impl Read for CatFile {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        self.stdout.read(buf)
+    }
+}
+
+impl Drop for CatFile {
+    fn drop(&mut self) {
+        let _ = self.child.kill();
+        let _ = self.child.wait();
+    }
+}
+
+// Streams a blob's content. libgit2 can only stream loose objects and would
+// load packed ones whole, so those are read through git instead, keeping
+// memory flat however large the file is.
+fn open_blob<'o>(repo: &git2::Repository, odb: &'o git2::Odb<'_>, oid: git2::Oid) -> Result<Box<dyn Read + 'o>, git2::Error> {
+    if let Ok((reader, _, _)) = odb.reader(oid) {
+        return Ok(Box::new(reader));
+    }
+    let mut child = Command::new("git")
+        .args(["cat-file", "blob", &oid.to_string()])
+        .current_dir(repo.path())
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::null())
+        .spawn()
+        .map_err(|e| git2::Error::from_str(&format!("Failed to run git cat-file: {}", e)))?;
+    let stdout = child.stdout.take().ok_or_else(|| git2::Error::from_str("git cat-file has no output"))?;
+    Ok(Box::new(CatFile { child, stdout }))
+}
+
+// Only the start of the blob is read.
+fn sniff_text(repo_name: &str, oid: git2::Oid) -> Result<bool, git2::Error> {
+    let repo = git2::Repository::open(format!("./repos/{}.git", repo_name))?;
+    let odb = repo.odb()?;
+    let mut head = Vec::with_capacity(SNIFF_BYTES);
+    open_blob(&repo, &odb, oid)?
+        .take(SNIFF_BYTES as u64)
+        .read_to_end(&mut head)
+        .map_err(|e| git2::Error::from_str(&e.to_string()))?;
+    Ok(looks_like_text(&head))
+}
+
+fn find_blob(repo_name: &str, rev: &str, path: &str) -> Result<BlobInfo, (StatusCode, String)> {
+    let repo = git2::Repository::open(format!("./repos/{}.git", repo_name))
+        .map_err(|_| (StatusCode::NOT_FOUND, "Repository not found".to_string()))?;
+    let tree = resolve_commit(&repo, rev)?
+        .tree()
+        .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, format!("Failed to get tree for commit: {}", e)))?;
+    let entry = tree
+        .get_path(StdPath::new(path.trim_matches('/')))
+        .map_err(|_| (StatusCode::NOT_FOUND, "Path not found in repository".to_string()))?;
+    if entry.kind() != Some(git2::ObjectType::Blob) {
+        return Err((StatusCode::NOT_FOUND, "Path is not a file".to_string()));
+    }
+
+    // Only the header is read, so the size is known without loading the blob.
+    let read_error = |e: git2::Error| (StatusCode::INTERNAL_SERVER_ERROR, format!("Failed to read blob: {}", e));
+    let odb = repo.odb().map_err(read_error)?;
+    let (size, _) = odb.read_header(entry.id()).map_err(read_error)?;
+    Ok(BlobInfo { oid: entry.id(), size: size as u64, mode: entry.filemode() })
+}
+
+// Sends `len` bytes of the blob starting at `start`.
+fn send_blob_range(repo_name: &str, oid: git2::Oid, start: u64, len: u64, tx: &tokio::sync::mpsc::Sender<Bytes>) -> Result<(), git2::Error> {
+    let repo = git2::Repository::open(format!("./repos/{}.git", repo_name))?;
+    let odb = repo.odb()?;
+
+    let mut reader = open_blob(&repo, &odb, oid)?.take(start + len);
+    std::io::copy(&mut (&mut reader).take(start), &mut std::io::sink()).map_err(|e| git2::Error::from_str(&e.to_string()))?;
+    let mut buf = vec![0; CHUNK_BYTES];
+    loop {
+        let read = reader.read(&mut buf).map_err(|e| git2::Error::from_str(&e.to_string()))?;
+        if read == 0 || tx.blocking_send(Bytes::copy_from_slice(&buf[..read])).is_err() {
+            return Ok(());
+        }
+    }
+}
+
+#[axum::debug_handler]
+pub async fn raw_file_handler(
+    Path((name, rev, path)): Path<(String, String, String)>,
+    State(state): State<AppState>,
+    user: PermissiveAuthUser,
+    headers: HeaderMap,
+) -> Response {
+    let repo_name = name.strip_suffix(".git").unwrap_or(&name).to_string();
+    if let Err(response) = check_repo_read_access(&repo_name, &state.pool, &user).await {
+        return response;
+    }
+
+    let lookup_repo = repo_name.clone();
+    let BlobInfo { oid, size, .. } = match tokio::task::spawn_blocking(move || find_blob(&lookup_repo, &rev, &path)).await {
+        Ok(Ok(blob)) => blob,
+        Ok(Err(e)) => return e.into_response(),
+        Err(e) => return (StatusCode::INTERNAL_SERVER_ERROR, format!("Task failed: {}", e)).into_response(),
+    };
+
+    // Blob ids change with the content, so they make a strong validator. The
+    // ETag middleware leaves responses that carry one alone, so conditional
+    // requests are answered here.
+    let etag = format!("\"{}\"", oid);
+    let if_none_match = headers.get(header::IF_NONE_MATCH).and_then(|value| value.to_str().ok());
+    if if_none_match.is_some_and(|candidates| none_match_hits(candidates, &etag)) {
+        return (StatusCode::NOT_MODIFIED, [(header::ETAG, etag)]).into_response();
+    }
+    let if_range_matches = headers
+        .get(header::IF_RANGE)
+        .is_none_or(|if_range| if_range.to_str().is_ok_and(|if_range| if_range == etag));
+    let range = match if_range_matches {
+        true => parse_range(headers.get(header::RANGE).and_then(|range| range.to_str().ok()), size),
+        false => ByteRange::Full,
+    };
+
+    let (status, start, len, content_range) = match range {
+        ByteRange::Full => (StatusCode::OK, 0, size, None),
+        ByteRange::Partial(start, end) => {
+            (StatusCode::PARTIAL_CONTENT, start, end - start + 1, Some(format!("bytes {}-{}/{}", start, end, size)))
+        }
+        ByteRange::Unsatisfiable => {
+            return (StatusCode::RANGE_NOT_SATISFIABLE, [(header::CONTENT_RANGE, format!("bytes */{}", size))]).into_response();
+        }
+    };
+    // Larger files can still be fetched a range at a time.
+    if len > state.config.max_raw_file_bytes {
+        return (
+            StatusCode::PAYLOAD_TOO_LARGE,
+            format!("File is larger than the {} byte limit; request a byte range", state.config.max_raw_file_bytes),
+        )
+            .into_response();
+    }
+
+    // Sniffed only once the request is known to be served.
+    let sniff_repo = repo_name.clone();
+    let content_type = match tokio::task::spawn_blocking(move || sniff_text(&sniff_repo, oid)).await {
+        Ok(Ok(true)) => "text/plain; charset=utf-8",
+        Ok(Ok(false)) => "application/octet-stream",
+        Ok(Err(e)) => return (StatusCode::INTERNAL_SERVER_ERROR, format!("Failed to read blob: {}", e)).into_response(),
+        Err(e) => return (StatusCode::INTERNAL_SERVER_ERROR, format!("Task failed: {}", e)).into_response(),
+    };
+
+    let (tx, rx) = tokio::sync::mpsc::channel::<Bytes>(16);
+    tokio::task::spawn_blocking(move || {
+        if let Err(e) = send_blob_range(&repo_name, oid, start, len, &tx) {
+            tracing::error!("Failed to read blob {} in {}: {}", oid, repo_name, e);
+        }
+    });
+    let stream = futures_util::stream::unfold(rx, |mut rx| async move {
+        rx.recv().await.map(|chunk| (Ok::<_, std::convert::Infallible>(chunk), rx))
+    });
+
+    let mut response = (
+        status,
+        [
+            (header::CONTENT_TYPE, content_type.to_string()),
+            (header::CONTENT_LENGTH, len.to_string()),
+            (header::ACCEPT_RANGES, "bytes".to_string()),
+            (header::ETAG, etag),
+            // Text is served as `text/plain` whatever it contains; browsers
+            // must not guess it is HTML.
+            (header::X_CONTENT_TYPE_OPTIONS, "nosniff".to_string()),
+        ],
+        Body::from_stream(stream),
+    )
+        .into_response();
+    if let Some(content_range) = content_range.and_then(|value| value.parse().ok()) {
+        response.headers_mut().insert(header::CONTENT_RANGE, content_range);
+    }
+    response
+}
+
+// What the raw endpoint would serve for a file, without its content, so
+// clients can decide whether to fetch it at all.
+#[axum::debug_handler]
+pub async fn blob_metadata_handler(
+    Path((name, rev, path)): Path<(String, String, String)>,
+    State(state): State<AppState>,
+    user: PermissiveAuthUser,
+) -> Response {
+    let repo_name = name.strip_suffix(".git").unwrap_or(&name).to_string();
+    if let Err(response) = check_repo_read_access(&repo_name, &state.pool, &user).await {
+        return response;
+    }
+
+    let lookup = move || {
+        let blob = find_blob(&repo_name, &rev, &path)?;
+        let is_text = sniff_text(&repo_name, blob.oid).map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, format!("Failed to read blob: {}", e)))?;
+        Ok::<_, (StatusCode, String)>((blob, is_text))
+    };
+    match tokio::task::spawn_blocking(lookup).await {
+        Ok(Ok((blob, is_text))) => Json(BlobMetadata {
+            sha: blob.oid.to_string(),
+            size: blob.size,
+            mode: format!("{:06o}", blob.mode),
+            is_binary: !is_text,
+            encoding: if is_text { "utf-8" } else { "binary" },
+        })
+        .into_response(),
+        Ok(Err(e)) => e.into_response(),
+        Err(e) => (StatusCode::INTERNAL_SERVER_ERROR, format!("Task failed: {}", e)).into_response(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::http::{header, Method, StatusCode};
+    use sqlx::PgPool;
+
+    use super::{parse_range, ByteRange};
+    use crate::test_support::{commit_file, TestApp};
+
+    #[test]
+    fn parses_single_byte_ranges() {
+        let range = |header: &str, size: u64| parse_range(Some(header), size);
+        use ByteRange::{Full, Partial, Unsatisfiable};
+
+        assert_eq!(range("bytes=0-9", 100), Partial(0, 9));
+        assert_eq!(range("bytes=90-200", 100), Partial(90, 99));
+        // Open-ended and suffix ranges.
+        assert_eq!(range("bytes=95-", 100), Partial(95, 99));
+        assert_eq!(range("bytes=-10", 100), Partial(90, 99));
+        assert_eq!(range("bytes=-500", 100), Partial(0, 99));
+        assert_eq!(range("bytes=-0", 100), Unsatisfiable);
+        // Starting at or past the end.
+        assert_eq!(range("bytes=100-", 100), Unsatisfiable);
+        assert_eq!(range("bytes=150-160", 100), Unsatisfiable);
+        // An empty file has no bytes to return.
+        assert_eq!(range("bytes=0-", 0), Unsatisfiable);
+        assert_eq!(range("bytes=-1", 0), Unsatisfiable);
+        // Multiple or malformed ranges get the whole file.
+        assert_eq!(range("bytes=0-1,5-6", 100), Full);
+        assert_eq!(range("bytes=5-1", 100), Full);
+        assert_eq!(range("items=0-1", 100), Full);
+        assert_eq!(range("bytes=x-", 100), Full);
+        assert_eq!(parse_range(None, 100), Full);
+    }
+
+    #[sqlx::test]
+    async fn packed_files_are_streamed(pool: PgPool) {
+        let mut app = TestApp::new(pool);
+        let token = app.sign_up("alice").await;
+        let repo = app.create_repo(&token, true).await;
+        commit_file(&repo, "main", "notes.txt", "packed content");
+        let output = std::process::Command::new("git").args(["gc", "--quiet"]).current_dir(format!("./repos/{}.git", repo)).output().unwrap();
+        assert!(output.status.success());
+
+        let uri = format!("/repos/{}/raw/main/notes.txt", repo);
+        let response = app.get(&uri, None).await;
+        assert_eq!(response.status, StatusCode::OK);
+        assert_eq!(response.headers[header::CONTENT_TYPE], "text/plain; charset=utf-8");
+        assert_eq!(response.body, "packed content");
+
+        let response = app.request_with_headers(Method::GET, &uri, None, &[("range", "bytes=7-13")], None).await;
+        assert_eq!(response.status, StatusCode::PARTIAL_CONTENT);
+        assert_eq!(response.body, "content");
+    }
+
+    #[sqlx::test]
+    async fn raw_files_answer_conditional_requests(pool: PgPool) {
+        let mut app = TestApp::new(pool);
+        let token = app.sign_up("alice").await;
+        let repo = app.create_repo(&token, true).await;
+        commit_file(&repo, "main", "page.html", "<script>alert(1)</script>");
+
+        let uri = format!("/repos/{}/raw/main/page.html", repo);
+        let response = app.get(&uri, None).await;
+        assert_eq!(response.status, StatusCode::OK);
+        assert_eq!(response.headers[header::X_CONTENT_TYPE_OPTIONS], "nosniff");
+        let etag = response.headers[header::ETAG].to_str().unwrap().to_string();
+
+        let response = app.request_with_headers(Method::GET, &uri, None, &[("if-none-match", &etag)], None).await;
+        assert_eq!(response.status, StatusCode::NOT_MODIFIED);
+        assert_eq!(response.headers[header::ETAG], etag.as_str());
+
+        let response = app.request_with_headers(Method::GET, &uri, None, &[("if-none-match", "\"0000\"")], None).await;
+        assert_eq!(response.status, StatusCode::OK);
+    }
+}
@@ -0,0 +1,103 @@
+use axum::{extract::{Path, State}, http::StatusCode, response::IntoResponse, Json};
+use serde::{Deserialize, Serialize};
+
+use crate::auth::{AuthUser, Scope};
+use crate::blobs::resolve_commit;
+use crate::git_api::{default_branch_reference, find_writable_repo, resolve_ref};
+use crate::validation::{ValidatedJson, Validate, Validator};
+use crate::AppState;
+
+const MAX_BRANCH_NAME_CHARS: usize = 255;
+
+#[derive(Deserialize)]
+pub struct NewBranch {
+    name: String,
+    // A branch, tag, full ref name or commit sha.
+    from_ref: String,
+}
+
+impl Validate for NewBranch {
+    fn validate(&self, v: &mut Validator) {
+        v.required("name", &self.name, MAX_BRANCH_NAME_CHARS)
+            .check(
+                git2::Branch::name_is_valid(&self.name).unwrap_or(false),
+                "name",
+                "is not a valid branch name",
+            )
+            .required("from_ref", &self.from_ref, MAX_BRANCH_NAME_CHARS);
+    }
+}
+
+#[derive(Serialize)]
+pub struct CreatedBranch {
+    name: String,
+    sha: String,
+}
+
+fn open_repository(repo_name: &str) -> Result<git2::Repository, (StatusCode, String)> {
+    git2::Repository::open(format!("./repos/{}.git", repo_name))
+        .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, format!("Failed to open repository: {}", e)))
+}
+
+fn create_branch_ref(repo_name: &str, name: &str, from_ref: &str) -> Result<CreatedBranch, (StatusCode, String)> {
+    let repo = open_repository(repo_name)?;
+    let commit = match resolve_ref(&repo, from_ref).and_then(|reference| reference.peel_to_commit().ok()) {
+        Some(commit) => commit,
+        None => resolve_commit(&repo, from_ref)
+            .map_err(|_| (StatusCode::UNPROCESSABLE_ENTITY, format!("'{}' does not name a commit in this repository", from_ref)))?,
+    };
+
+    repo.branch(name, &commit, false).map_err(|e| match e.code() {
+        git2::ErrorCode::Exists => (StatusCode::CONFLICT, format!("Branch '{}' already exists", name)),
+        _ => (StatusCode::INTERNAL_SERVER_ERROR, format!("Failed to create branch: {}", e)),
+    })?;
+    Ok(CreatedBranch { name: name.to_string(), sha: commit.id().to_string() })
+}
+
+fn delete_branch_ref(repo_name: &str, name: &str) -> Result<(), (StatusCode, String)> {
+    let repo = open_repository(repo_name)?;
+    let mut branch = repo
+        .find_branch(name, git2::BranchType::Local)
+        .map_err(|_| (StatusCode::NOT_FOUND, "Branch not found".to_string()))?;
+
+    let is_default = default_branch_reference(&repo).is_ok_and(|head| head.name() == branch.get().name());
+    if is_default {
+        return Err((StatusCode::CONFLICT, "The default branch cannot be deleted".to_string()));
+    }
+    branch
+        .delete()
+        .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, format!("Failed to delete branch: {}", e)))
+}
+
+#[axum::debug_handler]
+pub async fn create_branch(
+    State(state): State<AppState>,
+    AuthUser(user): AuthUser,
+    Path(repo_name): Path<String>,
+    ValidatedJson(new_branch): ValidatedJson<NewBranch>,
+) -> Result<impl IntoResponse, (StatusCode, String)> {
+    user.require_scope(Scope::RepoWrite)?;
+    let repo = find_writable_repo(&state.pool, &repo_name, user.id, "create branches in").await?;
+
+    let created = tokio::task::spawn_blocking(move || create_branch_ref(&repo.name, &new_branch.name, &new_branch.from_ref))
+        .await
+        .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, format!("Task failed: {}", e)))??;
+
+    Ok((StatusCode::CREATED, Json(created)))
+}
+
+#[axum::debug_handler]
+pub async fn delete_branch(
+    State(state): State<AppState>,
+    AuthUser(user): AuthUser,
+    Path((repo_name, branch)): Path<(String, String)>,
+) -> Result<impl IntoResponse, (StatusCode, String)> {
+    user.require_scope(Scope::RepoWrite)?;
+    let repo = find_writable_repo(&state.pool, &repo_name, user.id, "delete branches in").await?;
+
+    tokio::task::spawn_blocking(move || delete_branch_ref(&repo.name, &branch))
+        .await
+        .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, format!("Task failed: {}", e)))??;
+
+    Ok(StatusCode::NO_CONTENT)
+}
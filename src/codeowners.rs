@@ -0,0 +1,283 @@
+use axum::{
+    extract::{Path, Query, State},
+    http::StatusCode,
+    response::{IntoResponse, Response},
+    Json,
+};
+use serde::{Deserialize, Serialize};
+use std::path::Path as StdPath;
+
+use crate::auth::PermissiveAuthUser;
+use crate::git_api::{check_repo_read_access, default_branch_reference};
+use crate::AppState;
+
+const CODEOWNERS_LOCATIONS: [&str; 3] = [".github/CODEOWNERS", "CODEOWNERS", "docs/CODEOWNERS"];
+// Previewed paths deeper than this are refused; no real tree nests that far.
+const MAX_PATH_SEGMENTS: usize = 128;
+
+pub struct CodeOwnersRule {
+    pattern: String,
+    owners: Vec<String>,
+}
+
+#[derive(Deserialize)]
+pub struct CodeOwnersMatchQuery {
+    paths: String,
+    branch: Option<String>,
+}
+
+#[derive(Serialize)]
+pub struct CodeOwnersMatch {
+    path: String,
+    owners: Vec<String>,
+}
+
+pub fn parse_codeowners(content: &str) -> Vec<CodeOwnersRule> {
+    let mut rules = Vec::new();
+    for line in content.lines() {
+        let line = match line.find('#') {
+            Some(pos) => &line[..pos],
+            None => line,
+        };
+        let mut tokens = line.split_whitespace();
+        if let Some(pattern) = tokens.next() {
+            rules.push(CodeOwnersRule {
+                pattern: pattern.to_string(),
+                owners: tokens.map(str::to_string).collect(),
+            });
+        }
+    }
+    rules
+}
+
+pub fn load_codeowners(repo: &git2::Repository, tree: &git2::Tree<'_>) -> Vec<CodeOwnersRule> {
+    for location in CODEOWNERS_LOCATIONS {
+        let blob = tree
+            .get_path(StdPath::new(location))
+            .and_then(|entry| entry.to_object(repo))
+            .ok()
+            .and_then(|o| o.into_blob().ok());
+        if let Some(blob) = blob {
+            return parse_codeowners(&String::from_utf8_lossy(blob.content()));
+        }
+    }
+    Vec::new()
+}
+
+// The last matching rule wins, as on GitHub. A matching rule with no owners
+// explicitly leaves the path unowned.
+pub fn owners_for_path<'a>(rules: &'a [CodeOwnersRule], path: &str) -> &'a [String] {
+    rules
+        .iter()
+        .rev()
+        .find(|rule| pattern_matches(&rule.pattern, path))
+        .map(|rule| rule.owners.as_slice())
+        .unwrap_or(&[])
+}
+
+fn pattern_matches(pattern: &str, path: &str) -> bool {
+    let dir_only = pattern.ends_with('/');
+    let trimmed = pattern.trim_end_matches('/');
+    let anchored = trimmed.contains('/');
+    let trimmed = trimmed.trim_start_matches('/');
+    if trimmed.is_empty() {
+        return false;
+    }
+
+    let mut pattern_segments: Vec<&str> = trimmed.split('/').collect();
+    if !anchored {
+        pattern_segments.insert(0, "**");
+    }
+    // `**/**` matches exactly what `**` does.
+    pattern_segments.dedup_by(|a, b| *a == "**" && *b == "**");
+    let path_segments: Vec<&str> = path.trim_matches('/').split('/').collect();
+
+    let matched = matched_prefixes(&pattern_segments, &path_segments);
+    if !dir_only && matched[path_segments.len()] {
+        return true;
+    }
+
+    // A pattern naming a directory owns everything beneath it, except `dir/*`
+    // which only covers direct children.
+    if pattern_segments.last() == Some(&"*") {
+        return false;
+    }
+    matched[1..path_segments.len()].contains(&true)
+}
+
+// `matched[n]` is whether the pattern matches the first `n` path segments.
+// The path is walked once while tracking every pattern position reachable so
+// far, so no input makes this backtrack.
+fn matched_prefixes(pattern: &[&str], path: &[&str]) -> Vec<bool> {
+    // A `**` may match no segments, so reaching one also reaches what follows.
+    let close = |reachable: &mut Vec<bool>| {
+        for i in 0..pattern.len() {
+            if reachable[i] && pattern[i] == "**" {
+                reachable[i + 1] = true;
+            }
+        }
+    };
+
+    let mut reachable = vec![false; pattern.len() + 1];
+    reachable[0] = true;
+    close(&mut reachable);
+    let mut matched = Vec::with_capacity(path.len() + 1);
+    matched.push(reachable[pattern.len()]);
+
+    for segment in path {
+        let mut next = vec![false; pattern.len() + 1];
+        for (i, part) in pattern.iter().enumerate().filter(|&(i, _)| reachable[i]) {
+            if *part == "**" {
+                next[i] = true;
+            } else if wildcard_matches(part.as_bytes(), segment.as_bytes()) {
+                next[i + 1] = true;
+            }
+        }
+        close(&mut next);
+        reachable = next;
+        matched.push(reachable[pattern.len()]);
+    }
+    matched
+}
+
+// `*` and `?` within one segment. On a mismatch only the most recent `*` is
+// retried one byte further along, which is enough since each `*` can absorb
+// whatever an earlier one would have.
+fn wildcard_matches(pattern: &[u8], text: &[u8]) -> bool {
+    let (mut p, mut t) = (0, 0);
+    let mut last_star: Option<(usize, usize)> = None;
+    while t < text.len() {
+        match pattern.get(p) {
+            Some(b'*') => {
+                last_star = Some((p, t));
+                p += 1;
+            }
+            Some(&c) if c == b'?' || c == text[t] => {
+                p += 1;
+                t += 1;
+            }
+            _ => match last_star {
+                Some((star, from)) => {
+                    last_star = Some((star, from + 1));
+                    p = star + 1;
+                    t = from + 1;
+                }
+                None => return false,
+            },
+        }
+    }
+    pattern[p..].iter().all(|&c| c == b'*')
+}
+
+// Resolves the users to request review from for a pull request: the owners of
+// every file the head branch changed since it forked from base, per the base
+// branch's CODEOWNERS. Teams (`@org/team`) and email owners have no local
+// equivalent and are skipped.
+pub fn requested_owners(repo_name: &str, base_branch: &str, head_branch: &str) -> Result<Vec<String>, git2::Error> {
+    let repo = git2::Repository::open(format!("./repos/{}.git", repo_name))?;
+    let base = repo.find_reference(&format!("refs/heads/{}", base_branch))?.peel_to_commit()?;
+    let head = repo.find_reference(&format!("refs/heads/{}", head_branch))?.peel_to_commit()?;
+
+    let rules = load_codeowners(&repo, &base.tree()?);
+    if rules.is_empty() {
+        return Ok(Vec::new());
+    }
+
+    // Diffing from the merge base leaves out whatever landed on base since.
+    let fork_point = repo.find_commit(repo.merge_base(base.id(), head.id())?)?;
+    let mut usernames = Vec::new();
+    for path in crate::pull_requests::changed_files(&repo, &fork_point.tree()?, &head.tree()?)? {
+        for owner in owners_for_path(&rules, &path) {
+            if let Some(username) = owner.strip_prefix('@').filter(|u| !u.contains('/')) {
+                if !usernames.iter().any(|u| u == username) {
+                    usernames.push(username.to_string());
+                }
+            }
+        }
+    }
+    Ok(usernames)
+}
+
+#[axum::debug_handler]
+pub async fn match_codeowners_handler(
+    Path(name): Path<String>,
+    Query(query): Query<CodeOwnersMatchQuery>,
+    State(state): State<AppState>,
+    user: PermissiveAuthUser,
+) -> Response {
+    let repo_name = name.strip_suffix(".git").unwrap_or(&name).to_string();
+    if let Err(response) = check_repo_read_access(&repo_name, &state.pool, &user).await {
+        return response;
+    }
+
+    match tokio::task::spawn_blocking(move || match_paths(&repo_name, &query)).await {
+        Ok(Ok(matches)) => Json(matches).into_response(),
+        Ok(Err(e)) => e.into_response(),
+        Err(e) => (StatusCode::INTERNAL_SERVER_ERROR, format!("Task failed: {}", e)).into_response(),
+    }
+}
+
+fn match_paths(repo_name: &str, query: &CodeOwnersMatchQuery) -> Result<Vec<CodeOwnersMatch>, (StatusCode, String)> {
+    let paths: Vec<&str> = query.paths.split(',').map(str::trim).filter(|p| !p.is_empty()).collect();
+    if paths.iter().any(|path| path.split('/').count() > MAX_PATH_SEGMENTS) {
+        return Err((StatusCode::UNPROCESSABLE_ENTITY, format!("Paths may have at most {} segments", MAX_PATH_SEGMENTS)));
+    }
+
+    let repo_path = StdPath::new("./repos").join(format!("{}.git", repo_name));
+    let repo = git2::Repository::open(repo_path).map_err(|_| (StatusCode::NOT_FOUND, "Repository not found".to_string()))?;
+
+    let reference = match &query.branch {
+        Some(branch) => repo.find_reference(&format!("refs/heads/{}", branch)),
+        None => default_branch_reference(&repo),
+    };
+    let tree = reference
+        .and_then(|r| r.peel_to_tree())
+        .map_err(|_| (StatusCode::NOT_FOUND, "Branch not found".to_string()))?;
+
+    let rules = load_codeowners(&repo, &tree);
+    Ok(paths
+        .into_iter()
+        .map(|path| CodeOwnersMatch {
+            path: path.to_string(),
+            owners: owners_for_path(&rules, path).to_vec(),
+        })
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn owners(codeowners: &str, path: &str) -> Vec<String> {
+        owners_for_path(&parse_codeowners(codeowners), path).to_vec()
+    }
+
+    #[test]
+    fn matches_gitignore_style_patterns() {
+        assert!(pattern_matches("*.rs", "src/deep/main.rs"));
+        assert!(pattern_matches("/src/", "src/deep/main.rs"));
+        assert!(!pattern_matches("/src/", "lib/src"));
+        assert!(pattern_matches("docs/*", "docs/a.md"));
+        assert!(!pattern_matches("docs/*", "docs/sub/a.md"));
+        assert!(pattern_matches("a/**/b", "a/b"));
+        assert!(pattern_matches("a/**/b", "a/x/y/b"));
+        assert!(pattern_matches("ma?n.*s", "main.rs"));
+        assert!(!pattern_matches("*.rs", "main.rsx"));
+    }
+
+    #[test]
+    fn last_matching_rule_wins() {
+        let codeowners = "* @a\n/src/ @bob\n/src/vendor/\n";
+        assert_eq!(owners(codeowners, "README.md"), ["@a"]);
+        assert_eq!(owners(codeowners, "src/main.rs"), ["@bob"]);
+        assert!(owners(codeowners, "src/vendor/lib.rs").is_empty());
+    }
+
+    #[test]
+    fn repeated_double_stars_do_not_blow_up() {
+        let path = vec!["a"; MAX_PATH_SEGMENTS].join("/");
+        assert!(!pattern_matches("**/**/**/**/**/**/**/**/**/z", &path));
+        assert!(pattern_matches("**/**/**/**/**/**/**/**/**/a", &path));
+        assert!(!wildcard_matches(&[b'*'; 64].iter().chain(b"b").copied().collect::<Vec<_>>(), &[b'a'; 64]));
+    }
+}
@@ -0,0 +1,119 @@
+use axum::{extract::{Path, State}, http::StatusCode, response::IntoResponse, Json};
+use serde::{Deserialize, Serialize};
+use sqlx::FromRow;
+
+use crate::auth::{AuthUser, PermissiveAuthUser, Scope};
+use crate::db;
+use crate::git_api::{find_administered_repo, find_visible_repo};
+use crate::issues::DisplayUser;
+use crate::AppState;
+
+// Ordered from least to most access; each role includes the ones before it.
+#[derive(Serialize, Deserialize, sqlx::Type, Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+#[serde(rename_all = "lowercase")]
+#[sqlx(type_name = "text", rename_all = "lowercase")]
+pub enum CollaboratorRole {
+    // Sees the repository even when it is private.
+    Read,
+    // Pushes, merges pull requests and triages issues.
+    Write,
+    // Also changes settings and manages collaborators.
+    Admin,
+}
+
+#[derive(Serialize, FromRow)]
+pub struct Collaborator {
+    id: i32,
+    username: String,
+    role: CollaboratorRole,
+    created_at: chrono::DateTime<chrono::Utc>,
+}
+
+#[derive(Deserialize)]
+pub struct NewCollaborator {
+    username: String,
+    role: CollaboratorRole,
+}
+
+#[axum::debug_handler]
+pub async fn list_collaborators(
+    State(state): State<AppState>,
+    PermissiveAuthUser(user): PermissiveAuthUser,
+    Path(repo_name): Path<String>,
+) -> Result<impl IntoResponse, (StatusCode, String)> {
+    let repo = find_visible_repo(&state.pool, &repo_name, user.map(|u| u.id)).await?;
+
+    let collaborators = sqlx::query_as::<_, Collaborator>(
+        "SELECT u.id, u.username, c.role, c.created_at FROM repo_collaborators c JOIN users u ON u.id = c.user_id WHERE c.repo_id = $1 ORDER BY u.username",
+    )
+    .bind(repo.id)
+    .fetch_all(&state.pool)
+    .await
+    .map_err(|e| db::error(e, "Failed to list collaborators"))?;
+
+    Ok(Json(collaborators))
+}
+
+// Adds a collaborator, or changes the role of an existing one.
+#[axum::debug_handler]
+pub async fn add_collaborator(
+    State(state): State<AppState>,
+    AuthUser(user): AuthUser,
+    Path(repo_name): Path<String>,
+    Json(new_collaborator): Json<NewCollaborator>,
+) -> Result<impl IntoResponse, (StatusCode, String)> {
+    user.require_scope(Scope::RepoWrite)?;
+    let repo = find_administered_repo(&state.pool, &repo_name, user.id, "manage collaborators of").await?;
+
+    let collaborator = sqlx::query_as::<_, DisplayUser>("SELECT id, username FROM users WHERE username = $1")
+        .bind(&new_collaborator.username)
+        .fetch_optional(&state.pool)
+        .await
+        .map_err(|e| db::error(e, "Failed to fetch user"))?
+        .ok_or_else(|| (StatusCode::NOT_FOUND, "User not found".to_string()))?;
+    if collaborator.id == repo.user_id {
+        return Err((StatusCode::UNPROCESSABLE_ENTITY, "The repository owner cannot be added as a collaborator".to_string()));
+    }
+
+    let collaborator = sqlx::query_as::<_, Collaborator>(
+        r#"
+        WITH upserted AS (
+            INSERT INTO repo_collaborators (repo_id, user_id, role) VALUES ($1, $2, $3)
+            ON CONFLICT (repo_id, user_id) DO UPDATE SET role = EXCLUDED.role
+            RETURNING user_id, role, created_at
+        )
+        SELECT u.id, u.username, upserted.role, upserted.created_at FROM upserted JOIN users u ON u.id = upserted.user_id
+        "#,
+    )
+    .bind(repo.id)
+    .bind(collaborator.id)
+    .bind(new_collaborator.role)
+    .fetch_one(&state.pool)
+    .await
+    .map_err(|e| db::error(e, "Failed to add collaborator"))?;
+
+    Ok(Json(collaborator))
+}
+
+#[axum::debug_handler]
+pub async fn remove_collaborator(
+    State(state): State<AppState>,
+    AuthUser(user): AuthUser,
+    Path((repo_name, username)): Path<(String, String)>,
+) -> Result<impl IntoResponse, (StatusCode, String)> {
+    user.require_scope(Scope::RepoWrite)?;
+    let repo = find_administered_repo(&state.pool, &repo_name, user.id, "manage collaborators of").await?;
+
+    let result = sqlx::query("DELETE FROM repo_collaborators c USING users u WHERE c.user_id = u.id AND c.repo_id = $1 AND u.username = $2")
+        .bind(repo.id)
+        .bind(&username)
+        .execute(&state.pool)
+        .await
+        .map_err(|e| db::error(e, "Failed to remove collaborator"))?;
+
+    if result.rows_affected() == 0 {
+        return Err((StatusCode::NOT_FOUND, "Collaborator not found".to_string()));
+    }
+
+    Ok(StatusCode::NO_CONTENT)
+}
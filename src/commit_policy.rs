@@ -0,0 +1,273 @@
+use axum::{extract::{Path, State}, http::StatusCode, response::IntoResponse, Json};
+use regex::{Regex, RegexBuilder};
+use serde::{Deserialize, Serialize};
+use sqlx::{FromRow, PgPool};
+use std::collections::HashSet;
+use std::io::BufRead;
+use std::os::unix::fs::PermissionsExt;
+
+use crate::auth::{AuthUser, PermissiveAuthUser, Scope};
+use crate::db;
+use crate::git_api::{find_administered_repo, find_visible_repo};
+use crate::validation::{ValidatedJson, Validate, Validator};
+use crate::AppState;
+
+const MAX_PATTERN_CHARS: usize = 1024;
+// Keeps a pathological pattern from making every push slow.
+const MAX_COMPILED_BYTES: usize = 1 << 20;
+const MAX_REPORTED_COMMITS: usize = 10;
+
+const HOOKS_DIR: &str = "./hooks";
+const PATTERN_ENV: &str = "GIT8_COMMIT_PATTERN";
+const LINEAR_HISTORY_ENV: &str = "GIT8_REQUIRE_LINEAR_HISTORY";
+const HOOK_BIN_ENV: &str = "GIT8_HOOK_BIN";
+const PUSHED_REFS_ENV: &str = "GIT8_PUSHED_REFS";
+// `git receive-pack` runs this with the pushed refs on stdin; it hands them
+// back to the server binary, which does the actual checking. Pushes that
+// `enforce` did not set up pass.
+const PRE_RECEIVE_HOOK: &str = "#!/bin/sh\n[ -n \"$GIT8_HOOK_BIN\" ] || exit 0\nexec \"$GIT8_HOOK_BIN\" pre-receive\n";
+// Runs once the refs have moved and copies the updates receive-pack applied,
+// one `<old> <new> <ref>` line each, to where `record_pushed_refs` asked.
+const POST_RECEIVE_HOOK: &str = "#!/bin/sh\n[ -n \"$GIT8_PUSHED_REFS\" ] || exit 0\nexec cat > \"$GIT8_PUSHED_REFS\"\n";
+
+#[derive(Serialize, Deserialize)]
+pub struct CommitPolicy {
+    commit_message_pattern: Option<String>,
+}
+
+impl Validate for CommitPolicy {
+    fn validate(&self, v: &mut Validator) {
+        v.max_chars("commit_message_pattern", self.commit_message_pattern.as_deref(), MAX_PATTERN_CHARS);
+        if let Some(Err(e)) = self.commit_message_pattern.as_deref().map(compile) {
+            v.check(false, "commit_message_pattern", format!("is not a valid regular expression: {}", e));
+        }
+    }
+}
+
+fn compile(pattern: &str) -> Result<Regex, regex::Error> {
+    RegexBuilder::new(pattern).size_limit(MAX_COMPILED_BYTES).build()
+}
+
+#[axum::debug_handler]
+pub async fn get_commit_policy(
+    State(state): State<AppState>,
+    PermissiveAuthUser(user): PermissiveAuthUser,
+    Path(repo_name): Path<String>,
+) -> Result<impl IntoResponse, (StatusCode, String)> {
+    let repo = find_visible_repo(&state.pool, &repo_name, user.map(|u| u.id)).await?;
+    let commit_message_pattern = sqlx::query_scalar::<_, Option<String>>("SELECT commit_message_pattern FROM repositories WHERE id = $1")
+        .bind(repo.id)
+        .fetch_one(&state.pool)
+        .await
+        .map_err(|e| db::error(e, "Failed to fetch commit policy"))?;
+    Ok(Json(CommitPolicy { commit_message_pattern }))
+}
+
+// Replaces the policy. An empty or null pattern turns enforcement off.
+#[axum::debug_handler]
+pub async fn update_commit_policy(
+    State(state): State<AppState>,
+    AuthUser(user): AuthUser,
+    Path(repo_name): Path<String>,
+    ValidatedJson(policy): ValidatedJson<CommitPolicy>,
+) -> Result<impl IntoResponse, (StatusCode, String)> {
+    user.require_scope(Scope::RepoWrite)?;
+    let repo = find_administered_repo(&state.pool, &repo_name, user.id, "change the commit policy of").await?;
+    let pattern = policy.commit_message_pattern.filter(|pattern| !pattern.is_empty());
+
+    let commit_message_pattern = sqlx::query_scalar::<_, Option<String>>(
+        "UPDATE repositories SET commit_message_pattern = $1 WHERE id = $2 RETURNING commit_message_pattern",
+    )
+    .bind(pattern)
+    .bind(repo.id)
+    .fetch_one(&state.pool)
+    .await
+    .map_err(|e| db::error(e, "Failed to update commit policy"))?;
+
+    Ok(Json(CommitPolicy { commit_message_pattern }))
+}
+
+// What pushes to a repository are checked against by the pre-receive hook.
+#[derive(FromRow)]
+pub(crate) struct PushPolicy {
+    commit_message_pattern: Option<String>,
+    require_linear_history: bool,
+}
+
+impl PushPolicy {
+    pub(crate) fn is_enforced(&self) -> bool {
+        self.commit_message_pattern.is_some() || self.require_linear_history
+    }
+}
+
+pub(crate) async fn push_policy(pool: &PgPool, repo_name: &str) -> Result<Option<PushPolicy>, sqlx::Error> {
+    sqlx::query_as::<_, PushPolicy>("SELECT commit_message_pattern, require_linear_history FROM repositories WHERE name = $1")
+        .bind(repo_name)
+        .fetch_optional(pool)
+        .await
+}
+
+// Writes the hooks pushes run. They do nothing unless `enforce` or
+// `record_pushed_refs` pointed git at them, so they are safe to install once
+// at startup.
+pub fn install_hooks() -> std::io::Result<()> {
+    std::fs::create_dir_all(HOOKS_DIR)?;
+    for (name, script) in [("pre-receive", PRE_RECEIVE_HOOK), ("post-receive", POST_RECEIVE_HOOK)] {
+        let hook = std::path::Path::new(HOOKS_DIR).join(name);
+        if std::fs::read_to_string(&hook).ok().as_deref() != Some(script) {
+            std::fs::write(&hook, script)?;
+        }
+        std::fs::set_permissions(&hook, std::fs::Permissions::from_mode(0o755))?;
+    }
+    Ok(())
+}
+
+fn use_hooks(cmd: &mut std::process::Command) -> std::io::Result<()> {
+    let hooks_dir = std::fs::canonicalize(HOOKS_DIR)?;
+    cmd.env("GIT_CONFIG_COUNT", "1")
+        .env("GIT_CONFIG_KEY_0", "core.hooksPath")
+        .env("GIT_CONFIG_VALUE_0", hooks_dir);
+    Ok(())
+}
+
+// Makes the `git http-backend` about to handle a push run our pre-receive
+// hook with `policy`.
+pub(crate) fn enforce(cmd: &mut std::process::Command, policy: &PushPolicy) -> std::io::Result<()> {
+    use_hooks(cmd)?;
+    cmd.env(HOOK_BIN_ENV, std::env::current_exe()?);
+    if let Some(pattern) = &policy.commit_message_pattern {
+        cmd.env(PATTERN_ENV, pattern);
+    }
+    if policy.require_linear_history {
+        cmd.env(LINEAR_HISTORY_ENV, "1");
+    }
+    Ok(())
+}
+
+// Makes the push about to run leave the ref updates it applies in `path`,
+// which must be absolute since the hook runs inside the repository.
+pub(crate) fn record_pushed_refs(cmd: &mut std::process::Command, path: &std::path::Path) -> std::io::Result<()> {
+    use_hooks(cmd)?;
+    cmd.env(PUSHED_REFS_ENV, path);
+    Ok(())
+}
+
+// The body of the pre-receive hook: checks every commit the push adds to a
+// branch and rejects the whole push if any breaks the policy. Returns the
+// hook's exit code; whatever is printed reaches the pusher as `remote:` lines.
+pub fn run_pre_receive() -> i32 {
+    let pattern = std::env::var(PATTERN_ENV).ok();
+    let regex = match pattern.as_deref().map(compile).transpose() {
+        Ok(regex) => regex,
+        Err(e) => {
+            eprintln!("The repository's commit message pattern is invalid: {}", e);
+            return 1;
+        }
+    };
+    // Opening from the environment picks up the quarantine directory the
+    // pushed objects sit in until the hook accepts them.
+    let repo = match git2::Repository::open_from_env() {
+        Ok(repo) => repo,
+        Err(e) => {
+            eprintln!("Failed to open repository: {}", e);
+            return 1;
+        }
+    };
+    // The default branch is the one kept linear; see
+    // `default_branch_reference` for the `main` fallback.
+    let linear_branch = std::env::var_os(LINEAR_HISTORY_ENV).map(|_| match repo.head() {
+        Ok(head) => head.name().unwrap_or_default().to_string(),
+        Err(_) => "refs/heads/main".to_string(),
+    });
+
+    let mut checked = HashSet::new();
+    let mut unmatched = Vec::new();
+    let mut merges = Vec::new();
+    for line in std::io::stdin().lock().lines() {
+        let Ok(line) = line else {
+            eprintln!("Failed to read pushed refs");
+            return 1;
+        };
+        let mut fields = line.split_whitespace();
+        let (Some(old), Some(new), Some(refname)) = (fields.next(), fields.next(), fields.next()) else {
+            continue;
+        };
+        let (Ok(old), Ok(new)) = (git2::Oid::from_str(old), git2::Oid::from_str(new)) else { continue };
+        if new.is_zero() || !refname.starts_with("refs/heads/") {
+            continue;
+        }
+        let offending = |commit: &git2::Commit<'_>| (commit.id(), refname.to_string(), commit.summary().unwrap_or("").to_string());
+
+        if let Some(regex) = &regex {
+            let pushed = match new_commits(&repo, new) {
+                Ok(pushed) => pushed,
+                Err(e) => {
+                    eprintln!("Failed to list the commits pushed to {}: {}", refname, e);
+                    return 1;
+                }
+            };
+            for commit in pushed.iter().filter(|commit| checked.insert(commit.id())) {
+                if !regex.is_match(&String::from_utf8_lossy(commit.message_bytes())) {
+                    unmatched.push(offending(commit));
+                }
+            }
+        }
+
+        // Commits that already exist on another branch count too: merging
+        // one into the default branch is exactly what has to be refused.
+        if linear_branch.as_deref() == Some(refname) {
+            let added = match added_commits(&repo, old, new) {
+                Ok(added) => added,
+                Err(e) => {
+                    eprintln!("Failed to list the commits added to {}: {}", refname, e);
+                    return 1;
+                }
+            };
+            merges.extend(added.iter().filter(|commit| commit.parent_count() > 1).map(offending));
+        }
+    }
+
+    if unmatched.is_empty() && merges.is_empty() {
+        return 0;
+    }
+    eprintln!("Push rejected.");
+    if !unmatched.is_empty() {
+        eprintln!("Every commit message must match the pattern `{}`; these do not:", pattern.unwrap_or_default());
+        report(&unmatched);
+        eprintln!("Reword them (e.g. with `git rebase -i`) and push again.");
+    }
+    if !merges.is_empty() {
+        eprintln!("The default branch requires linear history; these are merge commits:");
+        report(&merges);
+        eprintln!("Rebase onto the branch instead of merging it, and push again.");
+    }
+    1
+}
+
+fn report(commits: &[(git2::Oid, String, String)]) {
+    for (oid, refname, summary) in commits.iter().take(MAX_REPORTED_COMMITS) {
+        eprintln!("  {:.7} ({}) {}", oid.to_string(), refname, summary);
+    }
+    if commits.len() > MAX_REPORTED_COMMITS {
+        eprintln!("  ...and {} more", commits.len() - MAX_REPORTED_COMMITS);
+    }
+}
+
+// Commits reachable from `new` that no ref reaches yet.
+fn new_commits(repo: &git2::Repository, new: git2::Oid) -> Result<Vec<git2::Commit<'_>>, git2::Error> {
+    let mut revwalk = repo.revwalk()?;
+    revwalk.push(new)?;
+    revwalk.hide_glob("*")?;
+    revwalk.map(|oid| repo.find_commit(oid?)).collect()
+}
+
+// Commits a ref moving from `old` to `new` gains, whether or not other refs
+// already have them.
+fn added_commits(repo: &git2::Repository, old: git2::Oid, new: git2::Oid) -> Result<Vec<git2::Commit<'_>>, git2::Error> {
+    let mut revwalk = repo.revwalk()?;
+    revwalk.push(new)?;
+    if !old.is_zero() {
+        revwalk.hide(old)?;
+    }
+    revwalk.map(|oid| repo.find_commit(oid?)).collect()
+}
@@ -0,0 +1,87 @@
+use std::env;
+
+#[derive(Debug)]
+pub struct Config {
+    pub max_commit_history: usize,
+    pub max_diff_bytes: usize,
+    pub max_raw_file_bytes: u64,
+    pub max_tree_entries: usize,
+    pub max_tree_depth: usize,
+    pub mirror_sync_interval_secs: u64,
+    pub db_keepalive_interval_secs: u64,
+    pub db_idle_timeout_secs: u64,
+    pub db_max_lifetime_secs: u64,
+    pub db_statement_timeout_secs: u64,
+    pub allow_basic_auth: bool,
+    pub allow_registration: bool,
+    pub allow_anonymous_read: bool,
+    pub gc_after_pushes: u64,
+    pub reserved_repo_names: Vec<String>,
+    pub public_base_url: Option<String>,
+    pub noreply_email_domain: String,
+    pub trust_proxy_headers: bool,
+    pub anonymous_rate_limit: u32,
+    pub session_ttl_secs: u64,
+    pub refresh_token_ttl_secs: u64,
+    pub login_max_failures: u32,
+    pub login_lockout_secs: u64,
+}
+
+impl Config {
+    pub fn from_env() -> Self {
+        Config {
+            max_commit_history: env_or("MAX_COMMIT_HISTORY", 10_000),
+            max_diff_bytes: env_or("MAX_DIFF_BYTES", 10 * 1024 * 1024),
+            max_raw_file_bytes: env_or("MAX_RAW_FILE_BYTES", 100 * 1024 * 1024),
+            max_tree_entries: env_or("MAX_TREE_ENTRIES", 10_000),
+            max_tree_depth: env_or("MAX_TREE_DEPTH", 32),
+            mirror_sync_interval_secs: env_or("MIRROR_SYNC_INTERVAL_SECS", 0),
+            db_keepalive_interval_secs: env_or("DB_KEEPALIVE_INTERVAL_SECS", 60),
+            db_idle_timeout_secs: env_or("DB_IDLE_TIMEOUT_SECS", 300),
+            db_max_lifetime_secs: env_or("DB_MAX_LIFETIME_SECS", 1800),
+            db_statement_timeout_secs: env_or("DB_STATEMENT_TIMEOUT_SECS", 30),
+            allow_basic_auth: env_or("ALLOW_BASIC_AUTH", false),
+            allow_registration: env_or("ALLOW_REGISTRATION", true),
+            allow_anonymous_read: env_or("ALLOW_ANONYMOUS_READ", true),
+            gc_after_pushes: env_or("GC_AFTER_PUSHES", 0),
+            reserved_repo_names: env_list("RESERVED_REPO_NAMES"),
+            public_base_url: env_base_url("PUBLIC_BASE_URL"),
+            noreply_email_domain: env_or("NOREPLY_EMAIL_DOMAIN", "users.noreply.localhost".to_string()),
+            trust_proxy_headers: env_or("TRUST_PROXY_HEADERS", false),
+            anonymous_rate_limit: env_or("ANONYMOUS_RATE_LIMIT", 0),
+            session_ttl_secs: env_or("SESSION_TTL_SECS", 7 * 24 * 60 * 60),
+            refresh_token_ttl_secs: env_or("REFRESH_TOKEN_TTL_SECS", 30 * 24 * 60 * 60),
+            login_max_failures: env_or("LOGIN_MAX_FAILURES", 5),
+            login_lockout_secs: env_or("LOGIN_LOCKOUT_SECS", 60),
+        }
+    }
+}
+
+fn env_list(key: &str) -> Vec<String> {
+    env::var(key)
+        .unwrap_or_default()
+        .split(',')
+        .map(str::trim)
+        .filter(|item| !item.is_empty())
+        .map(str::to_string)
+        .collect()
+}
+
+fn env_base_url(key: &str) -> Option<String> {
+    let value = env::var(key).ok()?;
+    let base_url = crate::urls::normalize_base_url(&value);
+    if base_url.is_none() {
+        tracing::warn!("Ignoring invalid value for {}: {}", key, value);
+    }
+    base_url
+}
+
+fn env_or<T: std::str::FromStr>(key: &str, default: T) -> T {
+    match env::var(key) {
+        Ok(value) => value.parse().unwrap_or_else(|_| {
+            tracing::warn!("Ignoring invalid value for {}: {}", key, value);
+            default
+        }),
+        Err(_) => default,
+    }
+}
@@ -0,0 +1,107 @@
+use axum::{extract::{Path, State}, http::StatusCode, response::IntoResponse, Json};
+use serde::Serialize;
+use sqlx::FromRow;
+
+use crate::auth::PermissiveAuthUser;
+use crate::db;
+use crate::git_api::{default_branch_reference, find_visible_repo};
+use crate::AppState;
+
+#[derive(FromRow)]
+struct Contributor {
+    id: i32,
+    username: String,
+    email: Option<String>,
+}
+
+#[derive(Serialize, FromRow)]
+pub struct ActivityCounts {
+    issues_opened: i64,
+    pull_requests_opened: i64,
+    pull_requests_merged: i64,
+    reviews: i64,
+}
+
+#[derive(Serialize)]
+pub struct ContributorStats {
+    username: String,
+    // Commits on the default branch authored under the user's username or
+    // email address.
+    commits: usize,
+    // Set when `MAX_COMMIT_HISTORY` stopped the count short.
+    commits_truncated: bool,
+    #[serde(flatten)]
+    activity: ActivityCounts,
+}
+
+const ACTIVITY_COUNTS: &str = r#"
+    SELECT
+        (SELECT COUNT(*) FROM issues WHERE repo_id = $1 AND author_id = $2) AS issues_opened,
+        (SELECT COUNT(*) FROM pull_requests WHERE repo_id = $1 AND author_id = $2) AS pull_requests_opened,
+        (SELECT COUNT(*) FROM pull_requests WHERE repo_id = $1 AND author_id = $2 AND status = 'merged') AS pull_requests_merged,
+        (SELECT COUNT(*) FROM reviews r JOIN pull_requests pr ON pr.id = r.pull_request_id WHERE pr.repo_id = $1 AND r.reviewer_id = $2) AS reviews
+"#;
+
+// Git identities are free-form, so a commit counts as the user's when its
+// author name is their username or its author email is their address.
+fn count_commits(repo_name: &str, username: &str, email: Option<&str>, max_commits: usize) -> Result<(usize, bool), git2::Error> {
+    let repo = git2::Repository::open(format!("./repos/{}.git", repo_name))?;
+    let Ok(head) = default_branch_reference(&repo).and_then(|head| head.peel_to_commit()) else {
+        return Ok((0, false));
+    };
+
+    let mut revwalk = repo.revwalk()?;
+    revwalk.push(head.id())?;
+    let (mut walked, mut commits) = (0, 0);
+    for oid in revwalk {
+        if walked == max_commits {
+            return Ok((commits, true));
+        }
+        walked += 1;
+        let commit = repo.find_commit(oid?)?;
+        let author = commit.author();
+        let by_name = author.name_bytes() == username.as_bytes();
+        let by_email = email.is_some_and(|email| author.email_bytes().eq_ignore_ascii_case(email.as_bytes()));
+        if by_name || by_email {
+            commits += 1;
+        }
+    }
+    Ok((commits, false))
+}
+
+#[axum::debug_handler]
+pub async fn get_contributor_stats(
+    State(state): State<AppState>,
+    PermissiveAuthUser(user): PermissiveAuthUser,
+    Path((repo_name, username)): Path<(String, String)>,
+) -> Result<impl IntoResponse, (StatusCode, String)> {
+    let repo = find_visible_repo(&state.pool, &repo_name, user.map(|u| u.id)).await?;
+
+    let Contributor { id, username, email } = sqlx::query_as::<_, Contributor>("SELECT id, username, email FROM users WHERE username = $1")
+        .bind(&username)
+        .fetch_optional(&state.pool)
+        .await
+        .map_err(|e| db::error(e, "Failed to fetch user"))?
+        .ok_or_else(|| (StatusCode::NOT_FOUND, "User not found".to_string()))?;
+
+    let activity = sqlx::query_as::<_, ActivityCounts>(ACTIVITY_COUNTS)
+        .bind(repo.id)
+        .bind(id)
+        .fetch_one(&state.pool)
+        .await
+        .map_err(|e| db::error(e, "Failed to count contributions"))?;
+
+    let max_commits = state.config.max_commit_history;
+    let name = username.clone();
+    let (commits, commits_truncated) = tokio::task::spawn_blocking(move || count_commits(&repo.name, &name, email.as_deref(), max_commits))
+        .await
+        .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, format!("Task failed: {}", e)))?
+        .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, format!("Failed to count commits: {}", e)))?;
+
+    Ok(Json(ContributorStats {
+        username,
+        commits,
+        commits_truncated,
+        activity,
+    }))
+}
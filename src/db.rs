@@ -1,14 +1,94 @@
-use sqlx::postgres::{PgPool, PgPoolOptions};
+use axum::http::StatusCode;
+use sqlx::postgres::{PgConnectOptions, PgPool, PgPoolOptions};
 use std::env;
+use std::time::Duration;
 
-pub async fn create_pool() -> Result<PgPool, sqlx::Error> {
+use crate::config::Config;
+
+pub async fn create_pool(config: &Config) -> Result<PgPool, sqlx::Error> {
     let db_url = env::var("DATABASE_URL").expect("DATABASE_URL must be set");
+    let mut connect_options: PgConnectOptions = db_url.parse()?;
+    // Postgres aborts any statement running longer than this, so one slow
+    // query cannot hold a pooled connection indefinitely.
+    if config.db_statement_timeout_secs > 0 {
+        let timeout_ms = (config.db_statement_timeout_secs * 1000).to_string();
+        connect_options = connect_options.options([("statement_timeout", timeout_ms.as_str())]);
+    }
+
     PgPoolOptions::new()
         .max_connections(5)
-        .connect(&db_url)
+        .idle_timeout(Duration::from_secs(config.db_idle_timeout_secs))
+        .max_lifetime(Duration::from_secs(config.db_max_lifetime_secs))
+        .connect_with(connect_options)
         .await
 }
 
+// Migrations may legitimately run longer than the statement timeout, so they
+// get a connection with it lifted.
 pub async fn run_migrations(pool: &PgPool) -> Result<(), sqlx::Error> {
-    sqlx::migrate!("./migrations").run(pool).await.map_err(|e| e.into())
+    let mut conn = pool.acquire().await?;
+    sqlx::query("SET statement_timeout = 0").execute(&mut *conn).await?;
+    let result = sqlx::migrate!("./migrations").run(&mut *conn).await;
+    sqlx::query("RESET statement_timeout").execute(&mut *conn).await?;
+    result.map_err(|e| e.into())
+}
+
+// Idle connections can be dropped by the server or a proxy under low traffic;
+// pinging the pool keeps them warm and evicts dead ones before a request does.
+pub fn spawn_keepalive(pool: PgPool, interval_secs: u64) {
+    if interval_secs == 0 {
+        return;
+    }
+
+    tokio::spawn(async move {
+        let mut ticker = tokio::time::interval(Duration::from_secs(interval_secs));
+        loop {
+            ticker.tick().await;
+            if let Err(e) = sqlx::query("SELECT 1").execute(&pool).await {
+                tracing::warn!("Database keepalive failed: {}", e);
+            }
+        }
+    });
+}
+
+// SQLSTATE for a statement cancelled by `statement_timeout`.
+const QUERY_CANCELED: &str = "57014";
+
+// Connection and pool failures are transient, so they surface as `503` to tell
+// clients to retry; statements that hit the timeout are `504`. Constraint
+// violations are the client's doing: a duplicate is a `409`, a reference to
+// something missing or a rejected value a `422`. Anything else is a server
+// error.
+pub fn error_status(e: &sqlx::Error) -> StatusCode {
+    match e {
+        sqlx::Error::PoolTimedOut | sqlx::Error::PoolClosed | sqlx::Error::Io(_) | sqlx::Error::Tls(_) | sqlx::Error::WorkerCrashed => {
+            StatusCode::SERVICE_UNAVAILABLE
+        }
+        sqlx::Error::Database(db_err) if db_err.code().as_deref() == Some(QUERY_CANCELED) => StatusCode::GATEWAY_TIMEOUT,
+        sqlx::Error::Database(db_err) if db_err.is_unique_violation() => StatusCode::CONFLICT,
+        sqlx::Error::Database(db_err) if db_err.is_foreign_key_violation() || db_err.is_check_violation() => {
+            StatusCode::UNPROCESSABLE_ENTITY
+        }
+        _ => StatusCode::INTERNAL_SERVER_ERROR,
+    }
+}
+
+// The response for a failed query. `context` says what was being done; the
+// database's own message can name tables, constraints and values, so it is
+// only logged.
+pub fn error(e: sqlx::Error, context: &str) -> (StatusCode, String) {
+    let status = error_status(&e);
+    let reason = match status {
+        StatusCode::SERVICE_UNAVAILABLE => "the database is unavailable, please retry",
+        StatusCode::GATEWAY_TIMEOUT => "the database took too long to respond",
+        StatusCode::CONFLICT => "it conflicts with an existing record",
+        StatusCode::UNPROCESSABLE_ENTITY => "it refers to a record that does not exist or has an invalid value",
+        _ => "internal error",
+    };
+    if status.is_server_error() {
+        tracing::error!("{}: {}", context, e);
+    } else {
+        tracing::debug!("{}: {}", context, e);
+    }
+    (status, format!("{}: {}", context, reason))
 }
@@ -0,0 +1,55 @@
+use axum::{
+    body::{Body, HttpBody},
+    extract::Request,
+    http::{header, HeaderValue, Method, StatusCode},
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use sha2::{Digest, Sha256};
+
+// Adds a strong `ETag` to successful GET/HEAD responses and answers matching
+// `If-None-Match` requests with `304`. Only fully buffered bodies are hashed,
+// so streamed responses pass through untouched.
+pub async fn etag(req: Request, next: Next) -> Response {
+    if req.method() != Method::GET && req.method() != Method::HEAD {
+        return next.run(req).await;
+    }
+
+    let if_none_match = req.headers().get(header::IF_NONE_MATCH).cloned();
+    let response = next.run(req).await;
+
+    if response.status() != StatusCode::OK
+        || response.headers().contains_key(header::ETAG)
+        || response.body().size_hint().exact().is_none()
+    {
+        return response;
+    }
+
+    let (mut parts, body) = response.into_parts();
+    let bytes = match axum::body::to_bytes(body, usize::MAX).await {
+        Ok(bytes) => bytes,
+        Err(e) => {
+            tracing::error!("Failed to buffer response body for ETag: {}", e);
+            return (StatusCode::INTERNAL_SERVER_ERROR, "Failed to read response body").into_response();
+        }
+    };
+
+    let tag = format!("\"{:x}\"", Sha256::digest(&bytes));
+    let tag_value = match HeaderValue::from_str(&tag) {
+        Ok(value) => value,
+        Err(_) => return Response::from_parts(parts, Body::from(bytes)),
+    };
+
+    if if_none_match.as_ref().and_then(|v| v.to_str().ok()).is_some_and(|candidates| none_match_hits(candidates, &tag)) {
+        return (StatusCode::NOT_MODIFIED, [(header::ETAG, tag_value)]).into_response();
+    }
+
+    parts.headers.insert(header::ETAG, tag_value);
+    Response::from_parts(parts, Body::from(bytes))
+}
+
+// Whether an `If-None-Match` value names `tag`, which makes the request
+// conditional GET a `304`. Weak comparison, as RFC 9110 asks for here.
+pub(crate) fn none_match_hits(candidates: &str, tag: &str) -> bool {
+    candidates.split(',').map(str::trim).any(|c| c == "*" || c == tag || c.strip_prefix("W/") == Some(tag))
+}
@@ -0,0 +1,188 @@
+use axum::{extract::{Path, State}, http::StatusCode, response::IntoResponse, Json};
+use serde::Serialize;
+use sqlx::FromRow;
+use std::collections::HashMap;
+
+use crate::auth::PermissiveAuthUser;
+use crate::db;
+use crate::git_api::{default_branch_reference, find_visible_repo};
+use crate::pagination::Pagination;
+use crate::AppState;
+
+// Each fork in a network costs a comparison against its parent.
+const MAX_NETWORK_FORKS: i64 = 500;
+
+#[derive(Serialize, FromRow)]
+pub struct Fork {
+    name: String,
+    owner: String,
+    public: bool,
+}
+
+#[derive(FromRow)]
+struct NetworkRow {
+    id: i32,
+    name: String,
+    owner: String,
+    fork_parent_id: Option<i32>,
+}
+
+// A fork's default branch compared with its parent's default branch.
+#[derive(Serialize)]
+pub struct Comparison {
+    default_branch: String,
+    ahead_by: usize,
+    behind_by: usize,
+}
+
+#[derive(Serialize)]
+pub struct NetworkNode {
+    name: String,
+    owner: String,
+    // Missing for the repository itself, and for forks where either side has
+    // no commits yet.
+    #[serde(flatten)]
+    comparison: Option<Comparison>,
+    forks: Vec<NetworkNode>,
+}
+
+#[derive(Serialize)]
+pub struct Network {
+    #[serde(flatten)]
+    root: NetworkNode,
+    truncated: bool,
+}
+
+const VISIBLE_FORKS: &str = "FROM repositories r JOIN users u ON u.id = r.user_id WHERE r.fork_parent_id = $1 AND (r.public OR r.user_id = $2 OR EXISTS (SELECT 1 FROM repo_collaborators c WHERE c.repo_id = r.id AND c.user_id = $2))";
+
+#[axum::debug_handler]
+pub async fn list_forks(
+    State(state): State<AppState>,
+    PermissiveAuthUser(user): PermissiveAuthUser,
+    Path(repo_name): Path<String>,
+    pagination: Pagination,
+) -> Result<impl IntoResponse, (StatusCode, String)> {
+    let user_id = user.map(|u| u.id);
+    let repo = find_visible_repo(&state.pool, &repo_name, user_id).await?;
+
+    let total: i64 = sqlx::query_scalar(&format!("SELECT COUNT(*) {}", VISIBLE_FORKS))
+        .bind(repo.id)
+        .bind(user_id)
+        .fetch_one(&state.pool)
+        .await
+        .map_err(|e| db::error(e, "Failed to count forks"))?;
+
+    let forks = sqlx::query_as::<_, Fork>(&format!(
+        "SELECT r.name, u.username AS owner, r.public {} ORDER BY r.id LIMIT $3 OFFSET $4",
+        VISIBLE_FORKS
+    ))
+    .bind(repo.id)
+    .bind(user_id)
+    .bind(pagination.limit())
+    .bind(pagination.offset())
+    .fetch_all(&state.pool)
+    .await
+    .map_err(|e| db::error(e, "Failed to list forks"))?;
+
+    Ok(pagination.respond(forks, total))
+}
+
+// Compares the default branches of a fork and its parent. The fork borrows
+// the parent's object database so commits only the parent has can be walked.
+fn compare_with_parent(parent_name: &str, fork_name: &str) -> Option<Comparison> {
+    let parent = git2::Repository::open(format!("./repos/{}.git", parent_name)).ok()?;
+    let fork = git2::Repository::open(format!("./repos/{}.git", fork_name)).ok()?;
+    let parent_tip = default_branch_reference(&parent).and_then(|r| r.peel_to_commit()).ok()?.id();
+    let fork_head = default_branch_reference(&fork).ok()?;
+    let default_branch = fork_head.shorthand()?.to_string();
+    let fork_tip = fork_head.peel_to_commit().ok()?.id();
+
+    fork.odb().and_then(|odb| odb.add_disk_alternate(&parent.path().join("objects").to_string_lossy())).ok()?;
+    let (ahead_by, behind_by) = fork.graph_ahead_behind(fork_tip, parent_tip).ok()?;
+    Some(Comparison { default_branch, ahead_by, behind_by })
+}
+
+fn build_network(
+    id: i32,
+    name: String,
+    owner: String,
+    comparison: Option<Comparison>,
+    children: &mut HashMap<i32, Vec<(NetworkRow, Option<Comparison>)>>,
+) -> NetworkNode {
+    let forks = children
+        .remove(&id)
+        .unwrap_or_default()
+        .into_iter()
+        .map(|(row, comparison)| build_network(row.id, row.name, row.owner, comparison, children))
+        .collect();
+    NetworkNode { name, owner, comparison, forks }
+}
+
+// The tree of forks below a repository. A fork the caller cannot see hides
+// the forks made from it as well.
+#[axum::debug_handler]
+pub async fn get_network(
+    State(state): State<AppState>,
+    PermissiveAuthUser(user): PermissiveAuthUser,
+    Path(repo_name): Path<String>,
+) -> Result<impl IntoResponse, (StatusCode, String)> {
+    let user_id = user.map(|u| u.id);
+    let repo = find_visible_repo(&state.pool, &repo_name, user_id).await?;
+
+    let owner: String = sqlx::query_scalar("SELECT username FROM users WHERE id = $1")
+        .bind(repo.user_id)
+        .fetch_one(&state.pool)
+        .await
+        .map_err(|e| db::error(e, "Failed to fetch repository owner"))?;
+
+    // Forks always have higher ids than their parents, so cutting the list
+    // off by id never leaves a fork without its parent.
+    let mut rows = sqlx::query_as::<_, NetworkRow>(
+        r#"
+        WITH RECURSIVE network AS (
+            SELECT id, name, user_id, fork_parent_id FROM repositories
+            WHERE fork_parent_id = $1 AND (public OR user_id = $2 OR EXISTS (SELECT 1 FROM repo_collaborators c WHERE c.repo_id = repositories.id AND c.user_id = $2))
+            UNION ALL
+            SELECT r.id, r.name, r.user_id, r.fork_parent_id FROM repositories r
+            JOIN network n ON r.fork_parent_id = n.id
+            WHERE r.public OR r.user_id = $2 OR EXISTS (SELECT 1 FROM repo_collaborators c WHERE c.repo_id = r.id AND c.user_id = $2)
+        )
+        SELECT n.id, n.name, u.username AS owner, n.fork_parent_id
+        FROM network n JOIN users u ON u.id = n.user_id
+        ORDER BY n.id
+        LIMIT $3
+        "#
+    )
+    .bind(repo.id)
+    .bind(user_id)
+    .bind(MAX_NETWORK_FORKS + 1)
+    .fetch_all(&state.pool)
+    .await
+    .map_err(|e| db::error(e, "Failed to fetch fork network"))?;
+
+    let truncated = rows.len() as i64 > MAX_NETWORK_FORKS;
+    rows.truncate(MAX_NETWORK_FORKS as usize);
+
+    let root_name = repo.name.clone();
+    let compared = tokio::task::spawn_blocking(move || {
+        let names: HashMap<i32, String> = rows.iter().map(|row| (row.id, row.name.clone())).chain([(repo.id, repo.name)]).collect();
+        rows.into_iter()
+            .map(|row| {
+                let comparison = row
+                    .fork_parent_id
+                    .and_then(|parent_id| names.get(&parent_id))
+                    .and_then(|parent_name| compare_with_parent(parent_name, &row.name));
+                (row, comparison)
+            })
+            .collect::<Vec<_>>()
+    })
+    .await
+    .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, format!("Task failed: {}", e)))?;
+
+    let mut children: HashMap<i32, Vec<_>> = HashMap::new();
+    for (row, comparison) in compared {
+        children.entry(row.fork_parent_id.unwrap_or_default()).or_default().push((row, comparison));
+    }
+
+    Ok(Json(Network { root: build_network(repo.id, root_name, owner, None, &mut children), truncated }))
+}
@@ -1,76 +1,480 @@
 use axum::{
-    extract::{Path, Json, State},
-    http::StatusCode,
+    body::{Body, Bytes},
+    extract::{Path, Json, Query, State},
+    http::{header, StatusCode},
     response::{IntoResponse, Response},
 };
 use serde::{Deserialize, Serialize};
 use std::path::Path as StdPath;
-use sqlx::{PgPool, FromRow};
+use sqlx::{PgConnection, PgExecutor, PgPool, FromRow};
 
-use crate::{auth::{AuthUser, PermissiveAuthUser}, AppState};
+use crate::{account, auth::{AuthUser, PermissiveAuthUser, Scope}, collaborators::CollaboratorRole, config::Config, db, gitignore::IgnoreRules, pagination::Pagination, patch::{self, PatchFormat}, templates, urls::BaseUrl, validation::{Validate, ValidatedJson, Validator}, AppState};
 
 
 #[derive(Serialize, FromRow)]
 pub struct Repo {
     name: String,
     public: bool,
+    default_branch: String,
+    #[sqlx(skip)]
+    clone_url: String,
 }
 
 #[derive(Deserialize)]
 pub struct CreateRepoRequest {
     name: String,
     public: Option<bool>,
+    // Start with a commit holding a README, instead of an unborn branch.
+    #[serde(default)]
+    initialize: bool,
+    gitignore_template: Option<String>,
+    license: Option<String>,
 }
 
-#[derive(Serialize)] pub struct Branch { name: String }
-#[derive(Serialize)] pub struct Commit { id: String, message: String, author: String, date: String }
-#[derive(Serialize)] pub struct TreeEntry { name: String, entry_type: String }
+#[derive(Deserialize)]
+pub struct UpdateRepoRequest {
+    name: Option<String>,
+    allow_anonymous_issues: Option<bool>,
+    require_signoff: Option<bool>,
+    require_linear_history: Option<bool>,
+    // Approving reviews a pull request needs before it can be merged.
+    required_approvals: Option<i32>,
+    // Usernames; each list replaces the current one.
+    default_assignees: Option<Vec<String>>,
+    default_reviewers: Option<Vec<String>>,
+    // Must be an existing branch.
+    default_branch: Option<String>,
+}
+
+impl Validate for UpdateRepoRequest {
+    fn validate(&self, v: &mut Validator) {
+        v.check(
+            self.required_approvals.is_none_or(|required| (0..=MAX_REQUIRED_APPROVALS).contains(&required)),
+            "required_approvals",
+            format!("must be between 0 and {}", MAX_REQUIRED_APPROVALS),
+        );
+    }
+}
+
+#[derive(Serialize, FromRow)]
+pub struct RepoSettings {
+    name: String,
+    public: bool,
+    allow_anonymous_issues: bool,
+    require_signoff: bool,
+    require_linear_history: bool,
+    required_approvals: i32,
+    default_branch: String,
+    #[sqlx(skip)]
+    default_assignees: Vec<String>,
+    #[sqlx(skip)]
+    default_reviewers: Vec<String>,
+}
+
+// Tables holding a repository's default issue assignees and pull request
+// reviewers.
+#[derive(Clone, Copy)]
+pub(crate) enum DefaultUsers {
+    Assignees,
+    Reviewers,
+}
+
+impl DefaultUsers {
+    fn table(self) -> &'static str {
+        match self {
+            DefaultUsers::Assignees => "repository_default_assignees",
+            DefaultUsers::Reviewers => "repository_default_reviewers",
+        }
+    }
+}
+
+#[derive(Serialize)]
+pub struct Branch {
+    name: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    merged: Option<bool>,
+}
+
+#[derive(Deserialize)]
+pub struct BranchListQuery {
+    merged_into: Option<String>,
+}
+
+#[derive(Serialize)]
+pub struct StaleBranch {
+    name: String,
+    sha: String,
+    last_commit_date: String,
+    author: String,
+    merged: bool,
+}
+
+#[derive(Deserialize)]
+pub struct StaleBranchQuery {
+    #[serde(default = "default_stale_days")]
+    days: i64,
+    merged_into: Option<String>,
+    merged: Option<bool>,
+}
+
+fn default_stale_days() -> i64 {
+    90
+}
+
+#[derive(Serialize)]
+pub struct Commit {
+    id: String,
+    short_id: String,
+    parents: Vec<String>,
+    message: String,
+    author: String,
+    // `null` when the commit's timestamp is out of chrono's range.
+    date: Option<String>,
+    // Identities from the message's `Signed-off-by:` and `Co-authored-by:` trailers.
+    signed_off_by: Vec<String>,
+    co_authors: Vec<String>,
+}
+#[derive(Serialize)]
+pub struct TreeEntry {
+    name: String,
+    entry_type: String,
+    // Set when the stored name is not valid UTF-8 and `name` is a lossy rendering of it.
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    invalid_utf8: bool,
+    // Whether `.gitignore` rules match the entry; only filled in on request.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    ignored: Option<bool>,
+}
+
+#[derive(Deserialize)]
+pub struct TreeListQuery {
+    #[serde(default)]
+    recursive: bool,
+    #[serde(default)]
+    ignored: bool,
+}
+
+#[derive(Serialize)]
+pub struct TreeListing {
+    entries: Vec<TreeEntry>,
+    // Set when `MAX_TREE_ENTRIES` or `MAX_TREE_DEPTH` cut the listing short.
+    truncated: bool,
+}
+
+#[derive(Deserialize)]
+pub struct CommitHistoryQuery {
+    limit: Option<usize>,
+    skip: Option<usize>,
+    path: Option<String>,
+    author: Option<String>,
+    #[serde(default)]
+    stream: bool,
+}
+
+#[derive(Serialize)]
+pub struct ResolvedRef {
+    #[serde(rename = "ref")]
+    name: String,
+    #[serde(rename = "type")]
+    kind: RefKind,
+    sha: String,
+}
+
+#[derive(Serialize)]
+pub enum RefKind {
+    #[serde(rename = "branch")]
+    Branch,
+    #[serde(rename = "tag")]
+    Tag,
+    #[serde(rename = "other")]
+    Other,
+}
+
+#[derive(Serialize)]
+pub struct IssueTemplate {
+    file_name: String,
+    name: Option<String>,
+    about: Option<String>,
+    title: Option<String>,
+    body: String,
+}
+
+#[derive(Deserialize)]
+pub struct IssueTemplateQuery {
+    branch: Option<String>,
+}
+
+// Access policy: a repository the caller cannot see is reported as `404`, the
+// same as one that does not exist, so private repositories are never revealed.
+// `403` is reserved for a visible repository where the caller lacks the
+// role an action requires. The owner holds every role; collaborators hold the
+// one they were given.
+#[derive(FromRow)]
+pub(crate) struct RepoAccess {
+    pub id: i32,
+    pub name: String,
+    pub user_id: i32,
+    pub public: bool,
+    // The caller's collaborator role, if any.
+    role: Option<CollaboratorRole>,
+}
+
+impl RepoAccess {
+    fn has_role(&self, user_id: Option<i32>, role: CollaboratorRole) -> bool {
+        user_id.is_some_and(|user_id| user_id == self.user_id) || self.role.is_some_and(|granted| granted >= role)
+    }
+
+    pub(crate) fn can_read(&self, user_id: Option<i32>) -> bool {
+        self.public || self.has_role(user_id, CollaboratorRole::Read)
+    }
+
+    pub(crate) fn can_write(&self, user_id: Option<i32>) -> bool {
+        self.has_role(user_id, CollaboratorRole::Write)
+    }
+}
+
+// Whether someone other than the caller can see the repository.
+pub(crate) async fn user_can_read<'e, E: PgExecutor<'e>>(executor: E, repo: &RepoAccess, user_id: i32) -> Result<bool, (StatusCode, String)> {
+    if repo.public || repo.user_id == user_id {
+        return Ok(true);
+    }
+    sqlx::query_scalar::<_, bool>("SELECT EXISTS (SELECT 1 FROM repo_collaborators WHERE repo_id = $1 AND user_id = $2)")
+        .bind(repo.id)
+        .bind(user_id)
+        .fetch_one(executor)
+        .await
+        .map_err(|e| db::error(e, "Failed to check repository access"))
+}
+
+pub(crate) fn repo_not_found() -> (StatusCode, String) {
+    (StatusCode::NOT_FOUND, "Repository not found".to_string())
+}
+
+pub(crate) async fn find_visible_repo<'e, E: PgExecutor<'e>>(
+    executor: E,
+    repo_name: &str,
+    user_id: Option<i32>,
+) -> Result<RepoAccess, (StatusCode, String)> {
+    let repo = sqlx::query_as::<_, RepoAccess>(
+        "SELECT r.id, r.name, r.user_id, r.public, c.role FROM repositories r LEFT JOIN repo_collaborators c ON c.repo_id = r.id AND c.user_id = $2 WHERE r.name = $1",
+    )
+    .bind(repo_name)
+    .bind(user_id)
+    .fetch_optional(executor)
+    .await
+    .map_err(|e| db::error(e, "Failed to get repo"))?
+    .ok_or_else(repo_not_found)?;
+
+    if repo.can_read(user_id) {
+        Ok(repo)
+    } else {
+        Err(repo_not_found())
+    }
+}
+
+// Hands out the next issue or pull request number of a repository. The row
+// lock taken by the UPDATE is held until the caller's transaction ends, so
+// concurrent creates queue up rather than reading the same counter, and a
+// create that rolls back gives its number back.
+pub(crate) async fn next_item_number(conn: &mut PgConnection, repo_id: i32) -> Result<i32, (StatusCode, String)> {
+    sqlx::query_scalar::<_, i32>("UPDATE repositories SET next_number = next_number + 1 WHERE id = $1 RETURNING next_number - 1")
+        .bind(repo_id)
+        .fetch_one(conn)
+        .await
+        .map_err(|e| db::error(e, "Failed to assign a number"))
+}
+
+async fn find_repo_with_role<'e, E: PgExecutor<'e>>(
+    executor: E,
+    repo_name: &str,
+    user_id: i32,
+    role: CollaboratorRole,
+    action: &str,
+) -> Result<RepoAccess, (StatusCode, String)> {
+    let repo = find_visible_repo(executor, repo_name, Some(user_id)).await?;
+    if !repo.has_role(Some(user_id), role) {
+        return Err((StatusCode::FORBIDDEN, format!("You do not have permission to {} this repository", action)));
+    }
+    Ok(repo)
+}
+
+// For changes to the repository's content and workflow: the owner and
+// collaborators with `write` or `admin`.
+pub(crate) async fn find_writable_repo<'e, E: PgExecutor<'e>>(
+    executor: E,
+    repo_name: &str,
+    user_id: i32,
+    action: &str,
+) -> Result<RepoAccess, (StatusCode, String)> {
+    find_repo_with_role(executor, repo_name, user_id, CollaboratorRole::Write, action).await
+}
+
+// For the repository's settings and who has access: the owner and `admin`
+// collaborators.
+pub(crate) async fn find_administered_repo<'e, E: PgExecutor<'e>>(
+    executor: E,
+    repo_name: &str,
+    user_id: i32,
+    action: &str,
+) -> Result<RepoAccess, (StatusCode, String)> {
+    find_repo_with_role(executor, repo_name, user_id, CollaboratorRole::Admin, action).await
+}
+
+// Only the owner may do what cannot be undone, such as deleting the repository.
+pub(crate) async fn find_owned_repo<'e, E: PgExecutor<'e>>(
+    executor: E,
+    repo_name: &str,
+    user_id: i32,
+    action: &str,
+) -> Result<RepoAccess, (StatusCode, String)> {
+    let repo = find_visible_repo(executor, repo_name, Some(user_id)).await?;
+    if repo.user_id != user_id {
+        return Err((StatusCode::FORBIDDEN, format!("You do not have permission to {} this repository", action)));
+    }
+    Ok(repo)
+}
 
 pub async fn check_repo_read_access(
     repo_name: &str,
     pool: &PgPool,
     user: &PermissiveAuthUser,
 ) -> Result<(), Response> {
-    let repo_info: Option<(i32, bool)> = sqlx::query_as("SELECT user_id, public FROM repositories WHERE name = $1")
-        .bind(repo_name)
-        .fetch_optional(pool)
+    find_visible_repo(pool, repo_name, user.0.as_ref().map(|u| u.id))
         .await
-        .map_err(|e| {
-            tracing::error!("Failed to query repository info: {}", e);
-            (StatusCode::INTERNAL_SERVER_ERROR, "Failed to check repository permissions").into_response()
-        })?;
+        .map(|_| ())
+        .map_err(|e| e.into_response())
+}
 
-    match repo_info {
-        Some((owner_id, is_public)) => {
-            if is_public {
-                return Ok(());
-            }
-            if let Some(u) = &user.0 {
-                if u.id == owner_id {
-                    return Ok(());
-                }
-            }
-            Err((StatusCode::FORBIDDEN, "You do not have permission to access this repository").into_response())
+// Returns the ref HEAD named before, for `restore_head`.
+fn point_head_at(repo_name: &str, branch: &str) -> Result<Option<String>, git2::Error> {
+    let repo = git2::Repository::open(format!("./repos/{}.git", repo_name))?;
+    let previous = repo.find_reference("HEAD")?.symbolic_target().map(str::to_string);
+    repo.set_head(&format!("refs/heads/{}", branch))?;
+    Ok(previous)
+}
+
+fn restore_head(repo_name: &str, previous: Option<&str>) {
+    let Some(previous) = previous else { return };
+    if let Err(e) = git2::Repository::open(format!("./repos/{}.git", repo_name)).and_then(|repo| repo.set_head(previous)) {
+        tracing::error!("Failed to point HEAD of {} back at {}: {}", repo_name, previous, e);
+    }
+}
+
+// After a push: while HEAD names a branch that does not exist, which is how
+// every repository created empty starts, the first branch to arrive (the
+// alphabetically first, if a push brings several) becomes the default.
+pub(crate) async fn adopt_pushed_default_branch(pool: &PgPool, repo_name: &str) {
+    let name = repo_name.to_string();
+    let adopted = tokio::task::spawn_blocking(move || {
+        let repo = git2::Repository::open(format!("./repos/{}.git", name))?;
+        if repo.head().is_ok() {
+            return Ok(None);
         }
-        None => Err((StatusCode::NOT_FOUND, "Repository not found").into_response()),
+        let mut branches: Vec<String> = repo
+            .branches(Some(git2::BranchType::Local))?
+            .flatten()
+            .filter_map(|(branch, _)| branch.name().ok().flatten().map(str::to_string))
+            .collect();
+        branches.sort();
+        let Some(branch) = branches.into_iter().next() else {
+            return Ok(None);
+        };
+        repo.set_head(&format!("refs/heads/{}", branch))?;
+        Ok::<_, git2::Error>(Some(branch))
+    })
+    .await;
+
+    let branch = match adopted {
+        Ok(Ok(Some(branch))) => branch,
+        Ok(Ok(None)) => return,
+        Ok(Err(e)) => return tracing::error!("Failed to set the default branch of {}: {}", repo_name, e),
+        Err(e) => return tracing::error!("Default branch task failed: {}", e),
+    };
+    if let Err(e) = sqlx::query("UPDATE repositories SET default_branch = $2 WHERE name = $1").bind(repo_name).bind(&branch).execute(pool).await {
+        tracing::error!("Failed to record {} as the default branch of {}: {}", branch, repo_name, e);
+    }
+}
+
+// Bare repos are initialised with HEAD pointing at `master`, which stays unborn
+// when only `main` is pushed, so fall back to `main` in that case.
+pub(crate) fn default_branch_reference(repo: &git2::Repository) -> Result<git2::Reference<'_>, git2::Error> {
+    repo.head().or_else(|_| repo.find_reference("refs/heads/main"))
+}
+
+const MAX_REQUIRED_APPROVALS: i32 = 10;
+// The branch HEAD names in a new repository, until a push or a setting changes it.
+const INITIAL_DEFAULT_BRANCH: &str = "main";
+
+// Names that would collide with top-level routes, or with `/repos/import`.
+const RESERVED_REPO_NAMES: [&str; 16] = ["register", "login", "logout", "refresh", "me", "auth", "repos", "account", "admin", "search", "users", "markdown", "healthz", "import", "badges", "templates"];
+
+pub(crate) fn validate_repo_name(name: &str, config: &Config) -> Result<(), (StatusCode, String)> {
+    if name.is_empty() || name.contains('/') || name.contains("..") {
+        return Err((StatusCode::BAD_REQUEST, "Invalid repository name".to_string()));
     }
+    let bare_name = name.strip_suffix(".git").unwrap_or(name);
+    let reserved = RESERVED_REPO_NAMES
+        .iter()
+        .copied()
+        .chain(config.reserved_repo_names.iter().map(String::as_str))
+        .any(|reserved| reserved.eq_ignore_ascii_case(bare_name));
+    if reserved {
+        return Err((StatusCode::UNPROCESSABLE_ENTITY, format!("'{}' is a reserved name", bare_name)));
+    }
+    Ok(())
 }
 
-pub async fn list_repos_handler(State(state): State<AppState>) -> Response {
-    match sqlx::query_as::<_, Repo>("SELECT name, public FROM repositories WHERE public = true")
+// Validates a client-supplied branch name and resolves it to its tip commit,
+// turning bad names and missing branches into actionable 422s.
+pub(crate) fn resolve_branch_commit<'r>(repo: &'r git2::Repository, branch: &str) -> Result<git2::Commit<'r>, (StatusCode, String)> {
+    let reference_name = format!("refs/heads/{}", branch);
+    if branch.is_empty() || !git2::Reference::is_valid_name(&reference_name) {
+        return Err((StatusCode::UNPROCESSABLE_ENTITY, format!("'{}' is not a valid branch name", branch)));
+    }
+    repo.find_reference(&reference_name)
+        .and_then(|r| r.peel_to_commit())
+        .map_err(|_| (StatusCode::UNPROCESSABLE_ENTITY, format!("branch '{}' does not exist", branch)))
+}
+
+pub async fn list_repos_handler(
+    State(state): State<AppState>,
+    PermissiveAuthUser(_user): PermissiveAuthUser,
+    base_url: BaseUrl,
+    pagination: Pagination,
+) -> Response {
+    let total = match sqlx::query_scalar::<_, i64>("SELECT COUNT(*) FROM repositories WHERE public = true")
+        .fetch_one(&state.pool)
+        .await
+    {
+        Ok(total) => total,
+        Err(e) => {
+            tracing::error!("Failed to count public repositories: {}", e);
+            return (db::error_status(&e), "Failed to list repositories").into_response();
+        }
+    };
+
+    match sqlx::query_as::<_, Repo>("SELECT name, public, default_branch FROM repositories WHERE public = true ORDER BY id LIMIT $1 OFFSET $2")
+        .bind(pagination.limit())
+        .bind(pagination.offset())
         .fetch_all(&state.pool)
         .await
     {
-        Ok(repos) => Json(repos).into_response(),
+        Ok(mut repos) => {
+            for repo in &mut repos {
+                repo.clone_url = base_url.clone_url(&repo.name);
+            }
+            pagination.respond(repos, total)
+        }
         Err(e) => {
             tracing::error!("Failed to list public repositories: {}", e);
-            (StatusCode::INTERNAL_SERVER_ERROR, "Failed to list repositories").into_response()
+            (db::error_status(&e), "Failed to list repositories").into_response()
         }
     }
 }
 
 pub async fn list_branches_handler(
     Path(name): Path<String>,
+    Query(query): Query<BranchListQuery>,
     State(state): State<AppState>,
     user: PermissiveAuthUser,
 ) -> Response {
@@ -85,11 +489,22 @@ pub async fn list_branches_handler(
         Err(_) => return (StatusCode::NOT_FOUND, "Repository not found on filesystem").into_response(),
     };
 
+    let merge_target = match &query.merged_into {
+        Some(target) => match repo.find_reference(&format!("refs/heads/{}", target)).and_then(|r| r.peel_to_commit()) {
+            Ok(commit) => Some(commit.id()),
+            Err(_) => return (StatusCode::NOT_FOUND, "Target branch not found").into_response(),
+        },
+        None => None,
+    };
+
     let mut branch_list = Vec::new();
     if let Ok(branches) = repo.branches(None) {
         for branch in branches.flatten() {
             if let Ok(Some(branch_name)) = branch.0.name() {
-                branch_list.push(Branch { name: branch_name.to_string() });
+                let merged = merge_target.map(|target| {
+                    branch.0.get().peel_to_commit().is_ok_and(|tip| is_merged(&repo, tip.id(), target))
+                });
+                branch_list.push(Branch { name: branch_name.to_string(), merged });
             }
         }
     }
@@ -97,17 +512,150 @@ pub async fn list_branches_handler(
     Json(branch_list).into_response()
 }
 
+fn is_merged(repo: &git2::Repository, tip: git2::Oid, target: git2::Oid) -> bool {
+    tip == target || repo.graph_descendant_of(target, tip).unwrap_or(false)
+}
+
+fn branches_containing(repo_name: &str, rev: &str) -> Result<Vec<Branch>, (StatusCode, String)> {
+    let repo = git2::Repository::open(StdPath::new("./repos").join(format!("{}.git", repo_name)))
+        .map_err(|_| (StatusCode::NOT_FOUND, "Repository not found on filesystem".to_string()))?;
+    let commit = repo
+        .revparse_single(rev)
+        .and_then(|object| object.peel_to_commit())
+        .map_err(|_| (StatusCode::NOT_FOUND, "Commit not found".to_string()))?;
+    let branches = repo
+        .branches(Some(git2::BranchType::Local))
+        .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, format!("Failed to list branches: {}", e)))?;
+
+    let mut containing = Vec::new();
+    for (branch, _) in branches.flatten() {
+        let (Ok(Some(name)), Ok(tip)) = (branch.name(), branch.get().peel_to_commit()) else { continue };
+        if is_merged(&repo, commit.id(), tip.id()) {
+            containing.push(Branch { name: name.to_string(), merged: None });
+        }
+    }
+    Ok(containing)
+}
+
+// Branches whose history includes the commit, e.g. to see which release
+// branches already carry a fix.
+pub async fn commit_branches_handler(
+    Path((name, rev)): Path<(String, String)>,
+    State(state): State<AppState>,
+    user: PermissiveAuthUser,
+) -> Response {
+    let repo_name = name.strip_suffix(".git").unwrap_or(&name).to_string();
+    if let Err(response) = check_repo_read_access(&repo_name, &state.pool, &user).await {
+        return response;
+    }
+
+    match tokio::task::spawn_blocking(move || branches_containing(&repo_name, &rev)).await {
+        Ok(Ok(branches)) => Json(branches).into_response(),
+        Ok(Err(e)) => e.into_response(),
+        Err(e) => (StatusCode::INTERNAL_SERVER_ERROR, format!("Task failed: {}", e)).into_response(),
+    }
+}
+
+// Local branches whose tip commit is older than `days`, excluding the default
+// branch. `merged` is relative to `merged_into`, or the default branch.
+pub async fn stale_branches_handler(
+    Path(name): Path<String>,
+    Query(query): Query<StaleBranchQuery>,
+    State(state): State<AppState>,
+    user: PermissiveAuthUser,
+) -> Response {
+    let repo_name = name.strip_suffix(".git").unwrap_or(&name);
+    if let Err(response) = check_repo_read_access(repo_name, &state.pool, &user).await {
+        return response;
+    }
+
+    let repo_path = StdPath::new("./repos").join(format!("{}.git", name));
+    let repo = match git2::Repository::open(repo_path) {
+        Ok(repo) => repo,
+        Err(_) => return (StatusCode::NOT_FOUND, "Repository not found on filesystem").into_response(),
+    };
+
+    let default_branch = default_branch_reference(&repo).ok();
+    let default_name = default_branch.as_ref().and_then(|r| r.name()).map(str::to_string);
+    let merge_target = match &query.merged_into {
+        Some(target) => match repo.find_reference(&format!("refs/heads/{}", target)).and_then(|r| r.peel_to_commit()) {
+            Ok(commit) => Some(commit.id()),
+            Err(_) => return (StatusCode::NOT_FOUND, "Target branch not found").into_response(),
+        },
+        None => default_branch.and_then(|r| r.peel_to_commit().ok()).map(|commit| commit.id()),
+    };
+
+    let cutoff = chrono::Utc::now().timestamp() - query.days.max(0) * 24 * 60 * 60;
+    let mut stale = Vec::new();
+    if let Ok(branches) = repo.branches(Some(git2::BranchType::Local)) {
+        for (branch, _) in branches.flatten() {
+            let reference = branch.get();
+            if reference.name().map(str::to_string) == default_name {
+                continue;
+            }
+            let (Ok(Some(branch_name)), Ok(tip)) = (branch.name(), reference.peel_to_commit()) else { continue };
+            if tip.time().seconds() >= cutoff {
+                continue;
+            }
+
+            let merged = merge_target.is_some_and(|target| is_merged(&repo, tip.id(), target));
+            if query.merged.is_some_and(|wanted| wanted != merged) {
+                continue;
+            }
+
+            let date = chrono::DateTime::from_timestamp(tip.time().seconds(), 0).unwrap_or_default().to_rfc3339();
+            stale.push(StaleBranch {
+                name: branch_name.to_string(),
+                sha: tip.id().to_string(),
+                last_commit_date: date,
+                author: String::from_utf8_lossy(tip.author().name_bytes()).into_owned(),
+                merged,
+            });
+        }
+    }
+    stale.sort_by(|a, b| a.last_commit_date.cmp(&b.last_commit_date));
+
+    Json(stale).into_response()
+}
+
 #[axum::debug_handler]
 pub async fn create_repo_handler(
     State(state): State<AppState>,
     user: AuthUser,
+    base_url: BaseUrl,
     Json(payload): Json<CreateRepoRequest>,
 ) -> Response {
+    if let Err(e) = user.0.require_scope(Scope::RepoWrite) {
+        return e.into_response();
+    }
     let name = &payload.name;
-    if name.is_empty() || name.contains('/') || name.contains("..") {
-        return (StatusCode::BAD_REQUEST, "Invalid repository name").into_response();
+    if let Err(response) = validate_repo_name(name, &state.config) {
+        return response.into_response();
     }
 
+    let license = match payload.license.as_deref().map(|key| templates::find_license(key).ok_or(key)) {
+        Some(Err(key)) => return (StatusCode::UNPROCESSABLE_ENTITY, format!("Unknown license '{}'", key)).into_response(),
+        Some(Ok(license)) => Some(license),
+        None => None,
+    };
+    let gitignore = match payload.gitignore_template.as_deref().map(|name| templates::find_gitignore(name).ok_or(name)) {
+        Some(Err(name)) => return (StatusCode::UNPROCESSABLE_ENTITY, format!("Unknown gitignore template '{}'", name)).into_response(),
+        Some(Ok(gitignore)) => Some(gitignore),
+        None => None,
+    };
+    if !payload.initialize && (license.is_some() || gitignore.is_some()) {
+        return (StatusCode::UNPROCESSABLE_ENTITY, "license and gitignore_template require initialize").into_response();
+    }
+
+    let initial_commit_email = if payload.initialize {
+        match account::commit_email(&state.pool, &state.config, user.0.id).await {
+            Ok(email) => Some(email),
+            Err(e) => return e.into_response(),
+        }
+    } else {
+        None
+    };
+
     let repo_name_git = format!("{}.git", name);
     let path = StdPath::new("./repos").join(&repo_name_git);
 
@@ -115,11 +663,14 @@ pub async fn create_repo_handler(
         return (StatusCode::CONFLICT, "Repository already exists on filesystem").into_response();
     }
 
-    let existing_repo: Option<(i32,)> = sqlx::query_as("SELECT id FROM repositories WHERE name = $1")
+    let existing_repo: Option<(i32,)> = match sqlx::query_as("SELECT id FROM repositories WHERE name = $1")
         .bind(name)
         .fetch_optional(&state.pool)
         .await
-        .unwrap_or(None);
+    {
+        Ok(existing_repo) => existing_repo,
+        Err(e) => return db::error(e, "Failed to check repository").into_response(),
+    };
 
     if existing_repo.is_some() {
         return (StatusCode::CONFLICT, "Repository already exists in database").into_response();
@@ -130,6 +681,21 @@ pub async fn create_repo_handler(
             if let Ok(mut config) = repo.config() {
                 let _ = config.set_bool("http.receivepack", true);
             }
+            // libgit2 points HEAD at `master`; agree with the `default_branch`
+            // the row starts with.
+            if let Err(e) = repo.set_head(&format!("refs/heads/{}", INITIAL_DEFAULT_BRANCH)) {
+                tracing::warn!("Failed to point HEAD of {} at {}: {}", repo_name_git, INITIAL_DEFAULT_BRANCH, e);
+            }
+
+            if let Some(email) = &initial_commit_email {
+                if let Err(e) = initialize_repository(&repo, name, &user.0.username, email, license, gitignore) {
+                    tracing::error!("Failed to create the initial commit: {}. Cleaning up filesystem.", e);
+                    if let Err(fs_err) = std::fs::remove_dir_all(&path) {
+                        tracing::error!("Failed to cleanup repository filesystem: {}", fs_err);
+                    }
+                    return (StatusCode::INTERNAL_SERVER_ERROR, "Failed to create repository").into_response();
+                }
+            }
 
             let repo_name_db = name.to_string();
             let is_public = payload.public.unwrap_or(false);
@@ -144,7 +710,13 @@ pub async fn create_repo_handler(
             match result {
                 Ok(_) => {
                     tracing::info!("Created new repository: {}", repo_name_git);
-                    (StatusCode::CREATED, Json(Repo { name: repo_name_db, public: is_public })).into_response()
+                    let created = Repo {
+                        clone_url: base_url.clone_url(&repo_name_db),
+                        name: repo_name_db,
+                        public: is_public,
+                        default_branch: INITIAL_DEFAULT_BRANCH.to_string(),
+                    };
+                    (StatusCode::CREATED, Json(created)).into_response()
                 }
                 Err(e) => {
                     tracing::error!("Failed to record repository ownership: {}. Cleaning up filesystem.", e);
@@ -162,35 +734,55 @@ pub async fn create_repo_handler(
     }
 }
 
+// Commits a README, plus the chosen license and .gitignore, to `main`, which
+// HEAD already points at, so the repository can be cloned and browsed right
+// away.
+fn initialize_repository(
+    repo: &git2::Repository,
+    name: &str,
+    owner: &str,
+    email: &str,
+    license: Option<&templates::License>,
+    gitignore: Option<&templates::Gitignore>,
+) -> Result<(), git2::Error> {
+    let mut files = vec![("README.md", format!("# {}\n", name))];
+    if let Some(license) = license {
+        files.push(("LICENSE", license.fill(chrono::Datelike::year(&chrono::Utc::now()), owner)));
+    }
+    if let Some(gitignore) = gitignore {
+        files.push((".gitignore", gitignore.text.to_string()));
+    }
+
+    let mut builder = repo.treebuilder(None)?;
+    for (file_name, content) in &files {
+        let blob = repo.blob(content.as_bytes())?;
+        builder.insert(file_name, blob, i32::from(git2::FileMode::Blob))?;
+    }
+    let tree = repo.find_tree(builder.write()?)?;
+
+    let signature = git2::Signature::now(owner, email)?;
+    repo.commit(Some(&format!("refs/heads/{}", INITIAL_DEFAULT_BRANCH)), &signature, &signature, "Initial commit", &tree, &[])?;
+    Ok(())
+}
+
 #[axum::debug_handler]
 pub async fn delete_repo_handler(
     Path(name): Path<String>,
     State(state): State<AppState>,
     user: AuthUser,
 ) -> Response {
+    if let Err(e) = user.0.require_scope(Scope::RepoWrite) {
+        return e.into_response();
+    }
     let repo_name = name.strip_suffix(".git").unwrap_or(&name).to_string();
 
-    let owner_result: Result<Option<(i32,)>, _> = sqlx::query_as("SELECT user_id FROM repositories WHERE name = $1")
-        .bind(&repo_name)
-        .fetch_optional(&state.pool)
-        .await;
-
-    match owner_result {
-        Ok(Some((owner_id,))) => {
-            if owner_id != user.0.id {
-                return (StatusCode::FORBIDDEN, "You do not have permission to delete this repository").into_response();
-            }
-        }
-        Ok(None) => return (StatusCode::NOT_FOUND, "Repository not found").into_response(),
-        Err(e) => {
-            tracing::error!("Failed to query repository ownership: {}", e);
-            return (StatusCode::INTERNAL_SERVER_ERROR, "Failed to check ownership").into_response();
-        }
+    if let Err(e) = find_owned_repo(&state.pool, &repo_name, user.0.id, "delete").await {
+        return e.into_response();
     }
 
     if let Err(e) = sqlx::query("DELETE FROM repositories WHERE name = $1").bind(&repo_name).execute(&state.pool).await {
         tracing::error!("Failed to delete repository record: {}", e);
-        return (StatusCode::INTERNAL_SERVER_ERROR, "Failed to delete repository").into_response();
+        return (db::error_status(&e), "Failed to delete repository").into_response();
     }
 
     let repo_name_git = format!("{}.git", repo_name);
@@ -206,16 +798,278 @@ pub async fn delete_repo_handler(
 }
 
 #[axum::debug_handler]
-pub async fn list_files_root_handler(Path((name, branch)): Path<(String, String)>, State(state): State<AppState>, user: PermissiveAuthUser) -> Response {
-    list_files_implementation(name, branch, None, user, state).await
+pub async fn update_repo_handler(
+    Path(name): Path<String>,
+    State(state): State<AppState>,
+    user: AuthUser,
+    ValidatedJson(payload): ValidatedJson<UpdateRepoRequest>,
+) -> Response {
+    if let Err(e) = user.0.require_scope(Scope::RepoWrite) {
+        return e.into_response();
+    }
+    let repo_name = name.strip_suffix(".git").unwrap_or(&name);
+    match update_repo_settings(&state, repo_name, user.0.id, payload).await {
+        Ok(settings) => Json(settings).into_response(),
+        Err(e) => e.into_response(),
+    }
+}
+
+async fn update_repo_settings(state: &AppState, repo_name: &str, user_id: i32, payload: UpdateRepoRequest) -> Result<RepoSettings, (StatusCode, String)> {
+    let mut tx = state.pool.begin().await.map_err(|e| db::error(e, "Failed to start transaction"))?;
+    let repo = find_administered_repo(&mut *tx, repo_name, user_id, "change the settings of").await?;
+
+    let new_name = match payload.name.as_deref().map(|name| name.strip_suffix(".git").unwrap_or(name)) {
+        Some(name) if name != repo.name => {
+            validate_repo_name(name, &state.config)?;
+            Some(name.to_string())
+        }
+        _ => None,
+    };
+    if let Some(branch) = &payload.default_branch {
+        let (repo_name, branch) = (repo.name.clone(), branch.clone());
+        let exists = tokio::task::spawn_blocking(move || {
+            git2::Repository::open(format!("./repos/{}.git", repo_name)).is_ok_and(|git| git.find_branch(&branch, git2::BranchType::Local).is_ok())
+        })
+        .await
+        .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, format!("Task failed: {}", e)))?;
+        if !exists {
+            return Err((StatusCode::UNPROCESSABLE_ENTITY, "default_branch must be an existing branch".to_string()));
+        }
+    }
+
+    let mut settings = sqlx::query_as::<_, RepoSettings>(
+        r#"
+        UPDATE repositories
+        SET name = COALESCE($4, name),
+            allow_anonymous_issues = COALESCE($2, allow_anonymous_issues),
+            require_signoff = COALESCE($3, require_signoff),
+            require_linear_history = COALESCE($5, require_linear_history),
+            required_approvals = COALESCE($6, required_approvals),
+            default_branch = COALESCE($7, default_branch)
+        WHERE id = $1
+        RETURNING name, public, allow_anonymous_issues, require_signoff, require_linear_history, required_approvals, default_branch
+        "#
+    )
+    .bind(repo.id)
+    .bind(payload.allow_anonymous_issues)
+    .bind(payload.require_signoff)
+    .bind(&new_name)
+    .bind(payload.require_linear_history)
+    .bind(payload.required_approvals)
+    .bind(&payload.default_branch)
+    .fetch_one(&mut *tx)
+    .await
+    .map_err(|e| match e {
+        sqlx::Error::Database(db_err) if db_err.is_unique_violation() => {
+            (StatusCode::CONFLICT, "A repository with that name already exists".to_string())
+        }
+        e => db::error(e, "Failed to update repository"),
+    })?;
+
+    if let Some(usernames) = &payload.default_assignees {
+        replace_default_users(&mut tx, &repo, DefaultUsers::Assignees, usernames).await?;
+    }
+    if let Some(usernames) = &payload.default_reviewers {
+        replace_default_users(&mut tx, &repo, DefaultUsers::Reviewers, usernames).await?;
+    }
+    settings.default_assignees = fetch_default_usernames(&mut *tx, repo.id, DefaultUsers::Assignees).await?;
+    settings.default_reviewers = fetch_default_usernames(&mut *tx, repo.id, DefaultUsers::Reviewers).await?;
+
+    // Clones check out whatever HEAD names, so it moves with the setting,
+    // and back again if the change is not saved.
+    let previous_head = match &payload.default_branch {
+        Some(branch) => {
+            point_head_at(&repo.name, branch).map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, format!("Failed to change the default branch: {}", e)))?
+        }
+        None => None,
+    };
+
+    // The directory moves last, once nothing else can fail before the commit.
+    // The new name's row stays locked until then, so no concurrent rename or
+    // create can claim it in between.
+    if let Some(new_name) = &new_name {
+        if let Err(e) = move_repository_storage(&repo.name, new_name) {
+            restore_head(&repo.name, previous_head.as_deref());
+            return Err(e);
+        }
+    }
+    if let Err(e) = tx.commit().await {
+        if let Some(new_name) = &new_name {
+            if let Err(fs_err) = move_repository_storage(new_name, &repo.name) {
+                tracing::error!("Failed to move repository {} back to {}: {}", new_name, repo.name, fs_err.1);
+            }
+        }
+        restore_head(&repo.name, previous_head.as_deref());
+        return Err(db::error(e, "Failed to commit transaction"));
+    }
+
+    if let Some(new_name) = new_name {
+        forget_repository_name(state, &repo.name, &new_name);
+        tracing::info!("Renamed repository {} to {}", repo.name, new_name);
+    }
+    Ok(settings)
+}
+
+fn move_repository_storage(from: &str, to: &str) -> Result<(), (StatusCode, String)> {
+    let (from_path, to_path) = (StdPath::new("./repos").join(format!("{}.git", from)), StdPath::new("./repos").join(format!("{}.git", to)));
+    if to_path.exists() {
+        return Err((StatusCode::CONFLICT, "Repository directory already exists".to_string()));
+    }
+    // A rename within one filesystem is atomic, so the repository is never
+    // visible half-moved.
+    std::fs::rename(&from_path, &to_path).map_err(|e| {
+        tracing::error!("Failed to move {} to {}: {}", from_path.display(), to_path.display(), e);
+        (StatusCode::INTERNAL_SERVER_ERROR, "Failed to move repository storage".to_string())
+    })
+}
+
+// Drops in-memory state keyed by the old name.
+fn forget_repository_name(state: &AppState, old_name: &str, new_name: &str) {
+    if let Ok(mut cache) = state.diff_stats_cache.lock() {
+        cache.retain(|(repo_name, _, _), _| repo_name != old_name);
+    }
+    if let Ok(mut counts) = state.push_counts.lock() {
+        if let Some(count) = counts.remove(old_name) {
+            counts.insert(new_name.to_string(), count);
+        }
+    }
+}
+
+async fn replace_default_users(conn: &mut PgConnection, repo: &RepoAccess, kind: DefaultUsers, usernames: &[String]) -> Result<(), (StatusCode, String)> {
+    let users = sqlx::query_as::<_, (i32, String)>("SELECT id, username FROM users WHERE username = ANY($1)")
+        .bind(usernames)
+        .fetch_all(&mut *conn)
+        .await
+        .map_err(|e| db::error(e, "Failed to find users"))?;
+    if let Some(unknown) = usernames.iter().find(|name| !users.iter().any(|(_, username)| username == *name)) {
+        return Err((StatusCode::UNPROCESSABLE_ENTITY, format!("User '{}' does not exist", unknown)));
+    }
+    for (id, username) in &users {
+        if !user_can_read(&mut *conn, repo, *id).await? {
+            return Err((StatusCode::UNPROCESSABLE_ENTITY, format!("User '{}' cannot access this repository", username)));
+        }
+    }
+
+    sqlx::query(&format!("DELETE FROM {} WHERE repo_id = $1", kind.table()))
+        .bind(repo.id)
+        .execute(&mut *conn)
+        .await
+        .map_err(|e| db::error(e, "Failed to update default users"))?;
+    sqlx::query(&format!("INSERT INTO {} (repo_id, user_id) SELECT $1, unnest($2::int[])", kind.table()))
+        .bind(repo.id)
+        .bind(users.iter().map(|(id, _)| *id).collect::<Vec<_>>())
+        .execute(&mut *conn)
+        .await
+        .map_err(|e| db::error(e, "Failed to update default users"))?;
+    Ok(())
+}
+
+async fn fetch_default_usernames<'e, E: PgExecutor<'e>>(executor: E, repo_id: i32, kind: DefaultUsers) -> Result<Vec<String>, (StatusCode, String)> {
+    sqlx::query_scalar::<_, String>(&format!(
+        "SELECT u.username FROM {} d JOIN users u ON u.id = d.user_id WHERE d.repo_id = $1 ORDER BY u.username",
+        kind.table()
+    ))
+    .bind(repo_id)
+    .fetch_all(executor)
+    .await
+    .map_err(|e| db::error(e, "Failed to fetch default users"))
+}
+
+// The repository's default assignees or reviewers who can still see it.
+// Access is checked now rather than when the defaults were configured,
+// since the repository may have gone private in between.
+pub(crate) async fn default_user_ids(conn: &mut PgConnection, repo_id: i32, kind: DefaultUsers) -> Result<Vec<i32>, (StatusCode, String)> {
+    sqlx::query_scalar::<_, i32>(&format!(
+        "SELECT d.user_id FROM {} d JOIN repositories r ON r.id = d.repo_id WHERE d.repo_id = $1 AND (r.public OR r.user_id = d.user_id OR EXISTS (SELECT 1 FROM repo_collaborators c WHERE c.repo_id = r.id AND c.user_id = d.user_id))",
+        kind.table()
+    ))
+    .bind(repo_id)
+    .fetch_all(conn)
+    .await
+    .map_err(|e| db::error(e, "Failed to fetch default users"))
+}
+
+// The root of the repository's default branch. Repositories the caller
+// cannot see get the same `404` from `list_files_implementation`.
+#[axum::debug_handler]
+pub async fn list_files_default_handler(Path(name): Path<String>, Query(query): Query<TreeListQuery>, State(state): State<AppState>, user: PermissiveAuthUser) -> Response {
+    let repo_name = name.strip_suffix(".git").unwrap_or(&name);
+    let default_branch = sqlx::query_scalar::<_, String>("SELECT default_branch FROM repositories WHERE name = $1")
+        .bind(repo_name)
+        .fetch_optional(&state.pool)
+        .await;
+    let branch = match default_branch {
+        Ok(branch) => branch.unwrap_or_else(|| INITIAL_DEFAULT_BRANCH.to_string()),
+        Err(e) => return db::error(e, "Failed to fetch repository").into_response(),
+    };
+    list_files_implementation(name, branch, None, query, user, state).await
+}
+
+#[axum::debug_handler]
+pub async fn list_files_root_handler(Path((name, branch)): Path<(String, String)>, Query(query): Query<TreeListQuery>, State(state): State<AppState>, user: PermissiveAuthUser) -> Response {
+    list_files_implementation(name, branch, None, query, user, state).await
 }
 
 #[axum::debug_handler]
-pub async fn list_files_subdirectory_handler(Path((name, branch, path)): Path<(String, String, String)>, State(state): State<AppState>, user: PermissiveAuthUser) -> Response {
-    list_files_implementation(name, branch, Some(path), user, state).await
+pub async fn list_files_subdirectory_handler(Path((name, branch, path)): Path<(String, String, String)>, Query(query): Query<TreeListQuery>, State(state): State<AppState>, user: PermissiveAuthUser) -> Response {
+    list_files_implementation(name, branch, Some(path), query, user, state).await
+}
+
+// Appends the entries of `tree` to `files`, named by their path below the
+// listed directory. `depth` is how many more directory levels a recursive
+// listing may descend into, or `None` for a flat listing. Returns whether the
+// entry or depth limit left anything out.
+fn collect_tree_entries(
+    repo: &git2::Repository,
+    tree: &git2::Tree<'_>,
+    prefix: &str,
+    prefix_invalid_utf8: bool,
+    depth: Option<usize>,
+    max_entries: usize,
+    files: &mut Vec<TreeEntry>,
+) -> bool {
+    let mut truncated = false;
+    for entry in tree.iter() {
+        if files.len() >= max_entries {
+            return true;
+        }
+        let entry_type = match entry.kind() {
+            Some(git2::ObjectType::Blob) => "blob",
+            Some(git2::ObjectType::Tree) => "tree",
+            _ => "unknown",
+        };
+        let (name, invalid_utf8) = match entry.name() {
+            Some(name) => (name.to_string(), false),
+            None => (String::from_utf8_lossy(entry.name_bytes()).into_owned(), true),
+        };
+        let name = format!("{}{}", prefix, name);
+        let invalid_utf8 = invalid_utf8 || prefix_invalid_utf8;
+        files.push(TreeEntry {
+            name: name.clone(),
+            entry_type: entry_type.to_string(),
+            invalid_utf8,
+            ignored: None,
+        });
+
+        let depth = match depth {
+            Some(depth) if entry.kind() == Some(git2::ObjectType::Tree) => depth,
+            _ => continue,
+        };
+        // Git does not store empty trees, so a directory at the depth limit
+        // always has contents the listing leaves out.
+        if depth == 0 {
+            truncated = true;
+            continue;
+        }
+        let Ok(subtree) = entry.to_object(repo).and_then(|object| object.peel_to_tree()) else {
+            continue;
+        };
+        truncated |= collect_tree_entries(repo, &subtree, &format!("{}/", name), invalid_utf8, Some(depth - 1), max_entries, files);
+    }
+    truncated
 }
 
-async fn list_files_implementation(name: String, branch: String, path: Option<String>, user: PermissiveAuthUser, state: AppState) -> Response {
+async fn list_files_implementation(name: String, branch: String, path: Option<String>, query: TreeListQuery, user: PermissiveAuthUser, state: AppState) -> Response {
     let repo_name = name.strip_suffix(".git").unwrap_or(&name);
     if let Err(response) = check_repo_read_access(repo_name, &state.pool, &user).await {
         return response;
@@ -246,99 +1100,609 @@ async fn list_files_implementation(name: String, branch: String, path: Option<St
         Err(_) => return (StatusCode::INTERNAL_SERVER_ERROR, "Failed to get tree for commit").into_response(),
     };
 
-    let target_tree = if let Some(p) = path {
-        let p = p.strip_prefix("/").unwrap_or(&p);
-        let p = p.strip_suffix("/").unwrap_or(&p);
-        if p.is_empty() {
-            tree
-        } else {
-            match tree.get_path(StdPath::new(p)) {
-                Ok(entry) => match entry.to_object(&repo) {
-                    Ok(object) => match object.into_tree() {
-                        Ok(tree) => tree,
-                        Err(_) => return (StatusCode::NOT_FOUND, "Path is not a directory").into_response(),
-                    },
-                    Err(_) => return (StatusCode::INTERNAL_SERVER_ERROR, "Failed to resolve path object").into_response(),
+    let dir = path.as_deref().map_or("", |p| p.trim_start_matches('/').trim_end_matches('/'));
+    let target_tree = if dir.is_empty() {
+        tree.clone()
+    } else {
+        match tree.get_path(StdPath::new(dir)) {
+            Ok(entry) => match entry.to_object(&repo) {
+                Ok(object) => match object.into_tree() {
+                    Ok(tree) => tree,
+                    Err(_) => return (StatusCode::NOT_FOUND, "Path is not a directory").into_response(),
                 },
-                Err(_) => return (StatusCode::NOT_FOUND, "Path not found in repository").into_response(),
-            }
+                Err(_) => return (StatusCode::INTERNAL_SERVER_ERROR, "Failed to resolve path object").into_response(),
+            },
+            Err(_) => return (StatusCode::NOT_FOUND, "Path not found in repository").into_response(),
         }
-    } else {
-        tree
     };
 
-    let mut files = Vec::new();
-    for entry in target_tree.iter() {
-        let entry_type = match entry.kind() {
-            Some(git2::ObjectType::Blob) => "blob",
-            Some(git2::ObjectType::Tree) => "tree",
-            _ => "unknown",
+    let depth = query.recursive.then_some(state.config.max_tree_depth);
+    let mut entries = Vec::new();
+    let truncated = collect_tree_entries(&repo, &target_tree, "", false, depth, state.config.max_tree_entries, &mut entries);
+
+    if query.ignored {
+        let full_path = |name: &str| if dir.is_empty() { name.to_string() } else { format!("{}/{}", dir, name) };
+        // Rules come from the listed directory, its ancestors, and any
+        // directory the listing descends into.
+        let ancestors = std::iter::once("").chain(dir.match_indices('/').map(|(i, _)| &dir[..i])).chain((!dir.is_empty()).then_some(dir));
+        let subdirs: Vec<String> = entries.iter().filter(|e| e.entry_type == "tree").map(|e| full_path(&e.name)).collect();
+        let rules = match IgnoreRules::load(&repo, &tree, ancestors.chain(subdirs.iter().map(String::as_str))) {
+            Ok(rules) => rules,
+            Err(e) => {
+                tracing::error!("Failed to load ignore rules for {}: {}", repo_name, e);
+                return (StatusCode::INTERNAL_SERVER_ERROR, "Failed to load ignore rules").into_response();
+            }
         };
-        if let Some(name) = entry.name() {
-            files.push(TreeEntry {
-                name: name.to_string(),
-                entry_type: entry_type.to_string(),
-            });
+        for entry in &mut entries {
+            entry.ignored = Some(rules.is_ignored(&full_path(&entry.name), entry.entry_type == "tree"));
         }
     }
 
-    Json(files).into_response()
+    Json(TreeListing { entries, truncated }).into_response()
 }
 
 #[axum::debug_handler]
-pub async fn commit_history_handler(Path((name, branch_name)): Path<(String, String)>, State(state): State<AppState>, user: PermissiveAuthUser) -> Response {
+pub async fn commit_history_handler(
+    Path((name, branch_name)): Path<(String, String)>,
+    Query(query): Query<CommitHistoryQuery>,
+    pagination: Pagination,
+    State(state): State<AppState>,
+    user: PermissiveAuthUser,
+) -> Response {
     let repo_name = name.strip_suffix(".git").unwrap_or(&name);
     if let Err(response) = check_repo_read_access(repo_name, &state.pool, &user).await {
         return response;
     }
 
+    // `/commits/<sha>.patch` and `/commits/<sha>.diff` share this route.
+    if let Some((rev, format)) = PatchFormat::from_path(&branch_name) {
+        return patch::commit_patch_response(repo_name, rev, format, state.config.max_diff_bytes).await;
+    }
+
+    // A page has its own offset, so a `skip` alongside it would be ignored.
+    let mut validator = Validator::default();
+    validator.check(query.skip.is_none() || !pagination.is_requested(), "skip", "cannot be combined with page or per_page");
+    if let Err(e) = validator.finish() {
+        return e.into_response();
+    }
+
     let repo_path = StdPath::new("./repos").join(format!("{}.git", name));
     let repo = match git2::Repository::open(repo_path) {
         Ok(repo) => repo,
         Err(_) => return (StatusCode::NOT_FOUND, "Repository not found").into_response(),
     };
 
-    let branch = match repo.find_branch(&branch_name, git2::BranchType::Local) {
-        Ok(branch) => branch,
-        Err(_) => {
-            match repo.find_branch(&format!("origin/{}", branch_name), git2::BranchType::Remote) {
-                Ok(branch) => branch,
-                Err(_) => return (StatusCode::NOT_FOUND, "Branch not found").into_response(),
+    let head = {
+        let branch = match repo.find_branch(&branch_name, git2::BranchType::Local) {
+            Ok(branch) => branch,
+            Err(_) => {
+                match repo.find_branch(&format!("origin/{}", branch_name), git2::BranchType::Remote) {
+                    Ok(branch) => branch,
+                    Err(_) => return (StatusCode::NOT_FOUND, "Branch not found").into_response(),
+                }
             }
+        };
+        match branch.get().peel_to_commit() {
+            Ok(commit) => commit.id(),
+            Err(_) => return (StatusCode::INTERNAL_SERVER_ERROR, "Failed to get commit for branch").into_response(),
         }
     };
 
-    let commit = match branch.get().peel_to_commit() {
-        Ok(commit) => commit,
-        Err(_) => return (StatusCode::INTERNAL_SERVER_ERROR, "Failed to get commit for branch").into_response(),
+    let max = state.config.max_commit_history;
+    let limit = query.limit.unwrap_or(max).min(max);
+    // History has its own `?limit=` and `?skip=` and can be streamed, so it
+    // is only paged when the client asks for a page.
+    let (skip, take) = match pagination.is_requested() {
+        true => {
+            let skip = pagination.offset() as usize;
+            (skip, (pagination.limit() as usize).min(limit.saturating_sub(skip)))
+        }
+        false => (query.skip.unwrap_or(0), limit),
+    };
+    let filter = HistoryFilter {
+        path: query.path.map(|path| path.trim_matches('/').to_string()).filter(|path| !path.is_empty()),
+        author: query.author.map(|author| author.to_lowercase()).filter(|author| !author.is_empty()),
     };
 
-    let mut revwalk = match repo.revwalk() {
-        Ok(walk) => walk,
-        Err(_) => return (StatusCode::INTERNAL_SERVER_ERROR, "Failed to create revision walker").into_response(),
+    if query.stream {
+        return stream_commit_history(repo, head, filter, skip, take);
+    }
+
+    // Without a page or an envelope there is nothing to count or link to,
+    // and counting means walking the rest of the history.
+    let count = pagination.envelope || pagination.is_requested();
+    let walk = move || {
+        let mut commits = Vec::new();
+        walk_commits(&repo, head, &filter, skip, take, |commit| {
+            commits.push(commit);
+            true
+        })?;
+        let total = match count {
+            true => Some(history(&repo, head, &filter)?.take(limit).try_fold(0, |n, commit| commit.map(|_| n + 1))?),
+            false => None,
+        };
+        Ok::<_, git2::Error>((commits, total))
     };
-    
-    if let Err(_) = revwalk.push(commit.id()) {
-        return (StatusCode::INTERNAL_SERVER_ERROR, "Failed to push commit to revision walker").into_response();
-    }
-
-    let mut commits = Vec::new();
-    for oid in revwalk {
-        if let Ok(oid) = oid {
-            if let Ok(commit) = repo.find_commit(oid) {
-                let author = commit.author();
-                let author_name = author.name().unwrap_or("Unknown");
-                let date = chrono::DateTime::from_timestamp(commit.time().seconds(), 0).unwrap().to_rfc2822();
-
-                commits.push(Commit {
-                    id: oid.to_string(),
-                    message: commit.message().unwrap_or("").to_string(),
-                    author: author_name.to_string(),
-                    date,
-                });
+
+    match tokio::task::spawn_blocking(walk).await {
+        Ok(Ok((commits, Some(total)))) => pagination.respond(commits, total),
+        Ok(Ok((commits, None))) => Json(commits).into_response(),
+        Ok(Err(_)) => (StatusCode::INTERNAL_SERVER_ERROR, "Failed to walk commit history").into_response(),
+        Err(e) => (StatusCode::INTERNAL_SERVER_ERROR, format!("Task failed: {}", e)).into_response(),
+    }
+}
+
+// Writes the history as a chunked JSON array so large logs reach the client as
+// the revwalk produces them instead of after the whole walk. The status is
+// sent before the walk starts, so a failure part way aborts the body instead
+// of closing the array, and the client cannot mistake a cut-off log for the
+// whole history.
+fn stream_commit_history(repo: git2::Repository, head: git2::Oid, filter: HistoryFilter, skip: usize, limit: usize) -> Response {
+    let (tx, rx) = tokio::sync::mpsc::channel::<std::io::Result<Bytes>>(64);
+
+    tokio::task::spawn_blocking(move || {
+        if tx.blocking_send(Ok(Bytes::from_static(b"["))).is_err() {
+            return;
+        }
+        let mut first = true;
+        let walked = walk_commits(&repo, head, &filter, skip, limit, |commit| {
+            let mut chunk = if first { Vec::new() } else { b",".to_vec() };
+            first = false;
+            serde_json::to_writer(&mut chunk, &commit).is_ok() && tx.blocking_send(Ok(Bytes::from(chunk))).is_ok()
+        });
+        let last = match walked {
+            Ok(()) => Ok(Bytes::from_static(b"]")),
+            Err(e) => {
+                tracing::error!("Failed to walk commit history: {}", e);
+                Err(std::io::Error::other(format!("Failed to walk commit history: {}", e)))
             }
+        };
+        let _ = tx.blocking_send(last);
+    });
+
+    let stream = futures_util::stream::unfold(rx, |mut rx| async move { rx.recv().await.map(|chunk| (chunk, rx)) });
+
+    ([(header::CONTENT_TYPE, "application/json")], Body::from_stream(stream)).into_response()
+}
+
+// Narrows commit history like `git log --author=<author> -- <path>`.
+struct HistoryFilter {
+    path: Option<String>,
+    // Lowercased; matched against any part of the author's name or email.
+    author: Option<String>,
+}
+
+impl HistoryFilter {
+    fn matches(&self, commit: &git2::Commit<'_>) -> bool {
+        let by_author = self.author.as_deref().is_none_or(|author| {
+            let signature = commit.author();
+            [signature.name_bytes(), signature.email_bytes()]
+                .iter()
+                .any(|field| String::from_utf8_lossy(field).to_lowercase().contains(author))
+        });
+        by_author && self.path.as_deref().is_none_or(|path| changes_path(commit, path))
+    }
+}
+
+// Commits reachable from `head` that pass `filter`, newest first. The walk is
+// lazy, so taking a page stops it once the page is full. Missing or corrupt
+// objects come out as errors rather than gaps in the history.
+fn history<'r>(
+    repo: &'r git2::Repository,
+    head: git2::Oid,
+    filter: &'r HistoryFilter,
+) -> Result<impl Iterator<Item = Result<git2::Commit<'r>, git2::Error>> + 'r, git2::Error> {
+    let mut revwalk = repo.revwalk()?;
+    revwalk.push(head)?;
+    Ok(revwalk
+        .map(|oid| repo.find_commit(oid?))
+        .filter(move |commit| commit.as_ref().map_or(true, |commit| filter.matches(commit))))
+}
+
+// Whether the file or directory at `path` differs from every parent's, so a
+// merge that took it unchanged from one side does not count.
+fn changes_path(commit: &git2::Commit<'_>, path: &str) -> bool {
+    let entry_id = |commit: &git2::Commit<'_>| commit.tree().ok()?.get_path(StdPath::new(path)).ok().map(|entry| entry.id());
+    let own = entry_id(commit);
+    match commit.parent_count() {
+        0 => own.is_some(),
+        _ => commit.parents().all(|parent| entry_id(&parent) != own),
+    }
+}
+
+// Walks history from `head`, handing each commit to `emit` until `limit` is
+// reached or `emit` returns false.
+fn walk_commits(
+    repo: &git2::Repository,
+    head: git2::Oid,
+    filter: &HistoryFilter,
+    skip: usize,
+    limit: usize,
+    mut emit: impl FnMut(Commit) -> bool,
+) -> Result<(), git2::Error> {
+    let mut commits = history(repo, head, filter)?;
+    for commit in commits.by_ref().take(skip) {
+        commit?;
+    }
+    for commit in commits.take(limit) {
+        if !emit(Commit::from_git(&commit?)) {
+            break;
+        }
+    }
+    Ok(())
+}
+
+// RFC 3339 in UTC, or `None` for times chrono cannot represent; git accepts
+// any 64-bit timestamp.
+pub(crate) fn format_commit_time(seconds: i64) -> Option<String> {
+    chrono::DateTime::from_timestamp(seconds, 0).map(|date| date.to_rfc3339())
+}
+
+impl Commit {
+    pub(crate) fn from_git(commit: &git2::Commit<'_>) -> Self {
+        let author = commit.author();
+        let date = format_commit_time(commit.time().seconds());
+
+        // Messages and names in legacy encodings are converted lossily rather than dropped.
+        let message = String::from_utf8_lossy(commit.message_bytes()).into_owned();
+        let signed_off_by = message_trailers(&message, "Signed-off-by");
+        let co_authors = message_trailers(&message, "Co-authored-by");
+        let id = commit.id().to_string();
+        Commit {
+            short_id: id[..7].to_string(),
+            id,
+            parents: commit.parent_ids().map(|parent| parent.to_string()).collect(),
+            message,
+            author: String::from_utf8_lossy(author.name_bytes()).into_owned(),
+            date,
+            signed_off_by,
+            co_authors,
         }
     }
 
-    Json(commits).into_response()
-}
\ No newline at end of file
+    pub(crate) fn id(&self) -> &str {
+        &self.id
+    }
+
+    pub(crate) fn is_signed_off(&self) -> bool {
+        !self.signed_off_by.is_empty()
+    }
+}
+
+// Values of the trailers named `key` (compared case-insensitively, as git
+// does) in the message's final trailer block.
+fn message_trailers(message: &str, key: &str) -> Vec<String> {
+    let Ok(trailers) = git2::message_trailers_strs(message) else {
+        return Vec::new();
+    };
+    trailers
+        .iter()
+        .filter(|(k, _)| k.eq_ignore_ascii_case(key))
+        .map(|(_, value)| value.trim().to_string())
+        .collect()
+}
+
+// Accepts `HEAD`, a full ref name (`refs/tags/v1`), or a short branch or tag
+// name, preferring branches like `git rev-parse` does.
+pub(crate) fn resolve_ref<'r>(repo: &'r git2::Repository, reference: &str) -> Option<git2::Reference<'r>> {
+    if reference == "HEAD" {
+        return default_branch_reference(repo).ok();
+    }
+    if reference.starts_with("refs/") {
+        return repo.find_reference(reference).ok();
+    }
+    repo.find_reference(&format!("refs/heads/{}", reference))
+        .or_else(|_| repo.find_reference(&format!("refs/tags/{}", reference)))
+        .ok()
+}
+
+#[axum::debug_handler]
+pub async fn resolve_ref_handler(
+    Path((name, reference)): Path<(String, String)>,
+    State(state): State<AppState>,
+    user: PermissiveAuthUser,
+) -> Response {
+    let repo_name = name.strip_suffix(".git").unwrap_or(&name);
+    if let Err(response) = check_repo_read_access(repo_name, &state.pool, &user).await {
+        return response;
+    }
+
+    let repo_path = StdPath::new("./repos").join(format!("{}.git", repo_name));
+    let repo = match git2::Repository::open(repo_path) {
+        Ok(repo) => repo,
+        Err(_) => return (StatusCode::NOT_FOUND, "Repository not found").into_response(),
+    };
+
+    let resolved = resolve_ref(&repo, &reference).and_then(|r| {
+        let name = r.name()?.to_string();
+        let commit = r.peel_to_commit().ok()?;
+        Some((name, commit.id()))
+    });
+    let (ref_name, sha) = match resolved {
+        Some(resolved) => resolved,
+        None => return (StatusCode::NOT_FOUND, "Ref not found").into_response(),
+    };
+
+    let kind = if ref_name.starts_with("refs/heads/") {
+        RefKind::Branch
+    } else if ref_name.starts_with("refs/tags/") {
+        RefKind::Tag
+    } else {
+        RefKind::Other
+    };
+
+    Json(ResolvedRef { name: ref_name, kind, sha: sha.to_string() }).into_response()
+}
+
+#[axum::debug_handler]
+pub async fn list_issue_templates_handler(
+    Path(name): Path<String>,
+    Query(query): Query<IssueTemplateQuery>,
+    State(state): State<AppState>,
+    user: PermissiveAuthUser,
+) -> Response {
+    let repo_name = name.strip_suffix(".git").unwrap_or(&name);
+    if let Err(response) = check_repo_read_access(repo_name, &state.pool, &user).await {
+        return response;
+    }
+
+    let repo_path = StdPath::new("./repos").join(format!("{}.git", repo_name));
+    let repo = match git2::Repository::open(repo_path) {
+        Ok(repo) => repo,
+        Err(_) => return (StatusCode::NOT_FOUND, "Repository not found").into_response(),
+    };
+
+    let reference = match &query.branch {
+        Some(branch) => repo.find_reference(&format!("refs/heads/{}", branch)),
+        None => default_branch_reference(&repo),
+    };
+    let tree = match reference.and_then(|r| r.peel_to_tree()) {
+        Ok(tree) => tree,
+        Err(_) => return (StatusCode::NOT_FOUND, "Branch not found").into_response(),
+    };
+
+    let mut templates = Vec::new();
+    if let Ok(entry) = tree.get_path(StdPath::new(".github/ISSUE_TEMPLATE")) {
+        if let Ok(dir) = entry.to_object(&repo).and_then(|o| o.peel_to_tree()) {
+            for entry in dir.iter() {
+                let file_name = match entry.name() {
+                    Some(n) if n.ends_with(".md") => n.to_string(),
+                    _ => continue,
+                };
+                if let Some(content) = entry.to_object(&repo).ok().and_then(|o| o.into_blob().ok()) {
+                    templates.push(parse_issue_template(file_name, &String::from_utf8_lossy(content.content())));
+                }
+            }
+        }
+    }
+
+    if templates.is_empty() {
+        for candidate in [".github/ISSUE_TEMPLATE.md", "ISSUE_TEMPLATE.md", "docs/ISSUE_TEMPLATE.md"] {
+            let blob = tree
+                .get_path(StdPath::new(candidate))
+                .and_then(|entry| entry.to_object(&repo))
+                .ok()
+                .and_then(|o| o.into_blob().ok());
+            if let Some(blob) = blob {
+                templates.push(parse_issue_template("ISSUE_TEMPLATE.md".to_string(), &String::from_utf8_lossy(blob.content())));
+                break;
+            }
+        }
+    }
+
+    Json(templates).into_response()
+}
+
+fn parse_issue_template(file_name: String, content: &str) -> IssueTemplate {
+    let mut template = IssueTemplate { file_name, name: None, about: None, title: None, body: content.to_string() };
+
+    let rest = match content.strip_prefix("---\n").or_else(|| content.strip_prefix("---\r\n")) {
+        Some(rest) => rest,
+        None => return template,
+    };
+
+    let mut offset = 0;
+    for line in rest.split_inclusive('\n') {
+        offset += line.len();
+        let line = line.trim_end();
+        if line == "---" {
+            template.body = rest[offset..].trim_start_matches(['\r', '\n']).to_string();
+            return template;
+        }
+        if let Some((key, value)) = line.split_once(':') {
+            let value = value.trim().trim_matches(|c| c == '"' || c == '\'').to_string();
+            match key.trim() {
+                "name" => template.name = Some(value),
+                "about" => template.about = Some(value),
+                "title" => template.title = Some(value),
+                _ => {}
+            }
+        }
+    }
+
+    // No closing delimiter: treat the whole file as body.
+    IssueTemplate { name: None, about: None, title: None, ..template }
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::http::StatusCode;
+    use serde_json::json;
+    use sqlx::PgPool;
+
+    use super::{format_commit_time, Commit};
+    use crate::test_support::{commit_file, TestApp};
+
+    #[test]
+    fn commit_dates_are_rfc3339_in_utc() {
+        let path = std::env::temp_dir().join(format!("git8-dates-{}", std::process::id()));
+        let repo = git2::Repository::init_bare(&path).unwrap();
+        let tree = repo.find_tree(repo.treebuilder(None).unwrap().write().unwrap()).unwrap();
+        let commit_at = |seconds: i64, offset_minutes: i32| {
+            let signature = git2::Signature::new("Test", "test@example.com", &git2::Time::new(seconds, offset_minutes)).unwrap();
+            let oid = repo.commit(None, &signature, &signature, "Dated", &tree, &[]).unwrap();
+            Commit::from_git(&repo.find_commit(oid).unwrap())
+        };
+
+        // The offset the commit was made in does not change the instant.
+        assert_eq!(commit_at(1_700_000_000, 330).date.as_deref(), Some("2023-11-14T22:13:20+00:00"));
+        assert_eq!(commit_at(1_700_000_000, -480).date.as_deref(), Some("2023-11-14T22:13:20+00:00"));
+        // Times near the ends of i64 are listed rather than panicking.
+        commit_at(i64::MAX / 1000, 0);
+        commit_at(i64::MIN / 1000, 0);
+
+        std::fs::remove_dir_all(path).unwrap();
+
+        assert_eq!(format_commit_time(0).as_deref(), Some("1970-01-01T00:00:00+00:00"));
+        assert_eq!(format_commit_time(i64::MAX), None);
+        assert_eq!(format_commit_time(i64::MIN), None);
+    }
+
+    #[sqlx::test]
+    async fn history_pages_and_skips_do_not_mix(pool: PgPool) {
+        let mut app = TestApp::new(pool);
+        let token = app.sign_up("alice").await;
+        let repo = app.create_repo(&token, true).await;
+        for file in ["a.txt", "b.txt", "c.txt"] {
+            commit_file(&repo, "main", file, file);
+        }
+
+        let response = app.get(&format!("/repos/{}/commits/main?page=1&skip=1", repo), None).await;
+        assert_eq!(response.status, StatusCode::UNPROCESSABLE_ENTITY);
+        assert_eq!(response.body["errors"][0]["field"], "skip");
+
+        let response = app.get(&format!("/repos/{}/commits/main?page=2&per_page=2&envelope=true", repo), None).await;
+        assert_eq!(response.status, StatusCode::OK, "{}", response.body);
+        assert_eq!(response.body["total"], 4);
+        assert_eq!(response.body["data"].as_array().unwrap().len(), 2);
+
+        let response = app.get(&format!("/repos/{}/commits/main?skip=3", repo), None).await;
+        assert_eq!(response.status, StatusCode::OK, "{}", response.body);
+        assert_eq!(response.body[0]["message"], "Initial commit");
+    }
+
+    #[sqlx::test]
+    async fn renames_move_the_storage_with_the_name(pool: PgPool) {
+        let mut app = TestApp::new(pool);
+        let token = app.sign_up("alice").await;
+        let repo = app.create_repo(&token, true).await;
+        let new_name = format!("{}-renamed", repo);
+        app.remove_on_drop(&new_name);
+
+        let response = app.patch(&format!("/repos/{}", repo), Some(&token), json!({ "name": new_name })).await;
+        assert_eq!(response.status, StatusCode::OK, "{}", response.body);
+        assert_eq!(response.body["name"], new_name.as_str());
+        assert!(!std::path::Path::new(&format!("./repos/{}.git", repo)).exists());
+        assert!(std::path::Path::new(&format!("./repos/{}.git", new_name)).exists());
+
+        assert_eq!(app.get(&format!("/repos/{}/branches", repo), Some(&token)).await.status, StatusCode::NOT_FOUND);
+        let branches = app.get(&format!("/repos/{}/branches", new_name), Some(&token)).await;
+        assert_eq!(branches.status, StatusCode::OK, "{}", branches.body);
+    }
+
+    #[sqlx::test]
+    async fn renames_onto_a_taken_name_conflict(pool: PgPool) {
+        let mut app = TestApp::new(pool);
+        let token = app.sign_up("alice").await;
+        let repo = app.create_repo(&token, true).await;
+        let other = app.create_repo(&token, true).await;
+
+        let response = app.patch(&format!("/repos/{}", repo), Some(&token), json!({ "name": other })).await;
+        assert_eq!(response.status, StatusCode::CONFLICT);
+        for name in [&repo, &other] {
+            assert!(std::path::Path::new(&format!("./repos/{}.git", name)).exists());
+            assert_eq!(app.get(&format!("/repos/{}/branches", name), Some(&token)).await.status, StatusCode::OK);
+        }
+    }
+
+    #[sqlx::test]
+    async fn renames_roll_back_when_the_storage_cannot_move(pool: PgPool) {
+        let mut app = TestApp::new(pool);
+        let token = app.sign_up("alice").await;
+        let repo = app.create_repo(&token, true).await;
+        // A directory with no repository row, so only the move fails.
+        let new_name = format!("{}-orphan", repo);
+        app.remove_on_drop(&new_name);
+        std::fs::create_dir_all(format!("./repos/{}.git", new_name)).unwrap();
+
+        let response = app.patch(&format!("/repos/{}", repo), Some(&token), json!({ "name": new_name })).await;
+        assert_eq!(response.status, StatusCode::CONFLICT);
+        assert_eq!(app.get(&format!("/repos/{}/branches", repo), Some(&token)).await.status, StatusCode::OK);
+        assert_eq!(app.get(&format!("/repos/{}/branches", new_name), Some(&token)).await.status, StatusCode::NOT_FOUND);
+    }
+
+    #[sqlx::test]
+    async fn renames_roll_back_when_the_update_fails(pool: PgPool) {
+        let mut app = TestApp::new(pool);
+        let token = app.sign_up("alice").await;
+        let repo = app.create_repo(&token, true).await;
+        let new_name = format!("{}-renamed", repo);
+        app.remove_on_drop(&new_name);
+
+        // The row is renamed before the unknown assignee is found.
+        let response = app.patch(&format!("/repos/{}", repo), Some(&token), json!({ "name": new_name, "default_assignees": ["nobody"] })).await;
+        assert_eq!(response.status, StatusCode::UNPROCESSABLE_ENTITY);
+        assert!(std::path::Path::new(&format!("./repos/{}.git", repo)).exists());
+        assert!(!std::path::Path::new(&format!("./repos/{}.git", new_name)).exists());
+        assert_eq!(app.get(&format!("/repos/{}/branches", repo), Some(&token)).await.status, StatusCode::OK);
+    }
+
+    #[sqlx::test]
+    async fn failed_updates_leave_head_alone(pool: PgPool) {
+        let mut app = TestApp::new(pool);
+        let token = app.sign_up("alice").await;
+        let repo = app.create_repo(&token, true).await;
+        commit_file(&repo, "feature", "feature.txt", "feature\n");
+        let new_name = format!("{}-orphan", repo);
+        app.remove_on_drop(&new_name);
+        std::fs::create_dir_all(format!("./repos/{}.git", new_name)).unwrap();
+
+        let response = app.patch(&format!("/repos/{}", repo), Some(&token), json!({ "name": new_name, "default_branch": "feature" })).await;
+        assert_eq!(response.status, StatusCode::CONFLICT);
+        let git = git2::Repository::open(format!("./repos/{}.git", repo)).unwrap();
+        assert_eq!(git.find_reference("HEAD").unwrap().symbolic_target(), Some("refs/heads/main"));
+    }
+
+    #[sqlx::test]
+    async fn initial_commits_use_the_owners_public_email(pool: PgPool) {
+        let mut app = TestApp::new(pool);
+        let token = app.sign_up("alice").await;
+        let author_email = |repo: &str| {
+            let git = git2::Repository::open(format!("./repos/{}.git", repo)).unwrap();
+            let commit = git.head().unwrap().peel_to_commit().unwrap();
+            let email = commit.author().email().unwrap().to_string();
+            email
+        };
+
+        let response = app.patch("/account", Some(&token), json!({ "email": "alice@example.org" })).await;
+        assert_eq!(response.status, StatusCode::OK, "{}", response.body);
+        let repo = app.create_repo(&token, true).await;
+        assert_eq!(author_email(&repo), "alice@users.noreply.localhost");
+
+        let response = app.patch("/account", Some(&token), json!({ "preferences": { "email_private": false } })).await;
+        assert_eq!(response.status, StatusCode::OK, "{}", response.body);
+        let repo = app.create_repo(&token, true).await;
+        assert_eq!(author_email(&repo), "alice@example.org");
+    }
+
+    #[sqlx::test]
+    async fn broken_history_is_not_passed_off_as_complete(pool: PgPool) {
+        let mut app = TestApp::new(pool);
+        let token = app.sign_up("alice").await;
+        let repo = app.create_repo(&token, true).await;
+        commit_file(&repo, "main", "a.txt", "a");
+        let head = commit_file(&repo, "main", "b.txt", "b");
+
+        // Lose the oldest commit, as a corrupt repository would.
+        let git = git2::Repository::open(format!("./repos/{}.git", repo)).unwrap();
+        let root = git.find_commit(head).unwrap().parent(0).unwrap().parent_id(0).unwrap().to_string();
+        std::fs::remove_file(format!("./repos/{}.git/objects/{}/{}", repo, &root[..2], &root[2..])).unwrap();
+
+        let response = app.get(&format!("/repos/{}/commits/main", repo), None).await;
+        assert_eq!(response.status, StatusCode::INTERNAL_SERVER_ERROR);
+
+        let response = app.get_response(&format!("/repos/{}/commits/main?stream=true", repo)).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        assert!(axum::body::to_bytes(response.into_body(), usize::MAX).await.is_err());
+    }
+}
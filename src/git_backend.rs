@@ -1,12 +1,111 @@
 use axum::{
     body::Body,
-    http::{Request, Response, StatusCode},
+    extract::State,
+    http::{Method, Request, Response, StatusCode},
+    response::IntoResponse,
 };
+use rand::distributions::Alphanumeric;
+use rand::{thread_rng, Rng};
+use std::path::{Path as StdPath, PathBuf};
 use std::process::Stdio;
-use std::str::FromStr;
 use tokio::io::AsyncWriteExt;
 
-pub async fn handler(req: Request<Body>) -> Response<Body> {
+use crate::auth::{unauthorized, PermissiveAuthUser, Scope, User};
+use crate::commit_policy;
+use crate::db;
+use crate::git_api::{self, find_visible_repo};
+use crate::maintenance;
+use crate::urls::BaseUrl;
+use crate::webhooks::{self, PushCommit, PushCommitAuthor, PushEvent, PushRepository, PushUser};
+use crate::AppState;
+
+const MAX_PUSH_COMMITS: usize = 20;
+
+// A ref update receive-pack applied, as its post-receive hook reported it.
+struct RefUpdate {
+    name: String,
+    old: git2::Oid,
+    new: git2::Oid,
+}
+
+// The suffixes `git http-backend` serves: smart HTTP first, then the dumb
+// protocol's static files.
+const GIT_SERVICE_SUFFIXES: [&str; 7] = [
+    "/info/refs",
+    "/git-upload-pack",
+    "/git-receive-pack",
+    "/HEAD",
+    "/objects/info/alternates",
+    "/objects/info/http-alternates",
+    "/objects/info/packs",
+];
+
+// Returns the repository part of a path `git http-backend` would serve, so
+// stray requests that reach the fallback never spawn a process.
+fn git_repo_path(path: &str) -> Option<&str> {
+    let is_hex = |s: &str, len: usize| s.len() == len && s.bytes().all(|b| b.is_ascii_hexdigit());
+
+    let repo = GIT_SERVICE_SUFFIXES.iter().find_map(|suffix| path.strip_suffix(suffix)).or_else(|| {
+        let (rest, file) = path.rsplit_once('/')?;
+        let (rest, dir) = rest.rsplit_once('/')?;
+        let (repo, objects) = rest.rsplit_once('/')?;
+        let loose = is_hex(dir, 2) && is_hex(file, 38);
+        let packed = dir == "pack"
+            && [".pack", ".idx"].iter().any(|ext| {
+                file.strip_prefix("pack-").and_then(|f| f.strip_suffix(ext)).is_some_and(|sha| is_hex(sha, 40))
+            });
+        (objects == "objects" && (loose || packed)).then_some(repo)
+    })?;
+
+    let repo = repo.trim_start_matches('/');
+    (!repo.is_empty()).then_some(repo)
+}
+
+fn not_found() -> Response<Body> {
+    Response::builder()
+        .status(StatusCode::NOT_FOUND)
+        .header("Content-Type", "application/json")
+        .body(Body::from(r#"{"message":"Not Found"}"#))
+        .unwrap()
+}
+
+// Resolves the repository before any process is spawned. Anonymous callers
+// get a `401` challenge for anything they cannot see, so git prompts for
+// credentials without learning whether a private repository exists.
+async fn authorize(state: &AppState, user: Option<&User>, repo_path: &str, push: bool) -> Result<(), Response<Body>> {
+    let repo_name = repo_path.strip_suffix(".git").unwrap_or(repo_path);
+    let user_id = user.map(|u| u.id);
+
+    let repo = match find_visible_repo(&state.pool, repo_name, user_id).await {
+        Ok(repo) => repo,
+        Err((StatusCode::NOT_FOUND, _)) if user.is_none() => return Err(unauthorized(state, "Authentication required")),
+        Err(e) => return Err(e.into_response()),
+    };
+    if push && user.is_none() {
+        return Err(unauthorized(state, "Authentication required"));
+    }
+    if push && !repo.can_write(user_id) {
+        return Err((StatusCode::FORBIDDEN, "You do not have permission to push to this repository").into_response());
+    }
+    if let Some(user) = user.filter(|_| push) {
+        user.require_scope(Scope::RepoWrite).map_err(IntoResponse::into_response)?;
+    }
+    if !StdPath::new("./repos").join(format!("{}.git", repo.name)).is_dir() {
+        return Err(not_found());
+    }
+    Ok(())
+}
+
+pub async fn handler(State(state): State<AppState>, user: PermissiveAuthUser, req: Request<Body>) -> Response<Body> {
+    let Some(repo_path) = git_repo_path(req.uri().path()) else {
+        return not_found();
+    };
+    let push = req.uri().path().ends_with("/git-receive-pack")
+        || req.uri().query().is_some_and(|query| query.split('&').any(|param| param == "service=git-receive-pack"));
+    if let Err(response) = authorize(&state, user.0.as_ref(), repo_path, push).await {
+        return response;
+    }
+
     let (parts, body) = req.into_parts();
     let body_bytes = match axum::body::to_bytes(body, usize::MAX).await {
         Ok(bytes) => bytes,
@@ -18,6 +117,11 @@ pub async fn handler(req: Request<Body>) -> Response<Body> {
         }
     };
 
+    let pushed_repo = (parts.method == Method::POST)
+        .then(|| parts.uri.path().strip_suffix("/git-receive-pack"))
+        .flatten()
+        .map(|path| path.trim_start_matches('/').trim_end_matches(".git").to_string());
+
     let mut cmd = tokio::process::Command::new("git");
     cmd.arg("http-backend");
 
@@ -32,6 +136,40 @@ pub async fn handler(req: Request<Body>) -> Response<Body> {
         }
     }
 
+    if let Some(repo_name) = &pushed_repo {
+        let policy = match commit_policy::push_policy(&state.pool, repo_name).await {
+            Ok(policy) => policy,
+            Err(e) => {
+                tracing::error!("Failed to fetch push policy for {}: {}", repo_name, e);
+                return Response::builder()
+                    .status(db::error_status(&e))
+                    .body(Body::from("Failed to fetch push policy"))
+                    .unwrap();
+            }
+        };
+        if let Some(policy) = policy.filter(|policy| policy.is_enforced()) {
+            if let Err(e) = commit_policy::enforce(cmd.as_std_mut(), &policy) {
+                tracing::error!("Failed to set up the pre-receive hook: {}", e);
+                return Response::builder()
+                    .status(StatusCode::INTERNAL_SERVER_ERROR)
+                    .body(Body::from("Failed to set up the pre-receive hook"))
+                    .unwrap();
+            }
+        }
+    }
+    // Receive-pack's own report of the refs it moved, so a concurrent push
+    // to the same repository is never mistaken for this one.
+    let pushed_refs = pushed_repo.as_ref().map(|_| pushed_refs_path());
+    if let Some(path) = &pushed_refs {
+        if let Err(e) = commit_policy::record_pushed_refs(cmd.as_std_mut(), path) {
+            tracing::error!("Failed to set up the post-receive hook: {}", e);
+            return Response::builder()
+                .status(StatusCode::INTERNAL_SERVER_ERROR)
+                .body(Body::from("Failed to set up the post-receive hook"))
+                .unwrap();
+        }
+    }
+
     cmd.stdout(Stdio::piped());
     cmd.stdin(Stdio::piped());
     cmd.stderr(Stdio::piped());
@@ -53,7 +191,9 @@ pub async fn handler(req: Request<Body>) -> Response<Body> {
         }
     }
 
-    let output = match child.wait_with_output().await {
+    let output = child.wait_with_output().await;
+    let ref_updates = pushed_refs.as_deref().map(take_pushed_refs).unwrap_or_default();
+    let output = match output {
         Ok(output) => output,
         Err(e) => {
             eprintln!("Failed to wait for git http-backend: {}", e);
@@ -69,6 +209,12 @@ pub async fn handler(req: Request<Body>) -> Response<Body> {
             "git http-backend exited with error: {}",
             String::from_utf8_lossy(&output.stderr)
         );
+    } else if let Some(repo_name) = pushed_repo {
+        let pusher = user.0.map(|u| PushUser { name: u.username });
+        maintenance::record_push(&state, &repo_name);
+        git_api::adopt_pushed_default_branch(&state.pool, &repo_name).await;
+        let base_url = BaseUrl::from_headers(&parts.headers, &state.config);
+        notify_push(&state, repo_name, ref_updates, pusher, base_url).await;
     }
 
     let mut headers_end = 0;
@@ -102,17 +248,14 @@ pub async fn handler(req: Request<Body>) -> Response<Body> {
             continue;
         }
 
-        if line.to_lowercase().starts_with("status:") {
-            if let Some(status_str) = line.split_whitespace().nth(1) {
-                if let Ok(status_code) = StatusCode::from_str(status_str) {
-                    response = response.status(status_code);
-                }
-            }
-        } else if let Some((key, value)) = line.split_once(':') {
-            let value = value.trim();
-            if !key.is_empty() && !value.is_empty() {
-                response = response.header(key, value);
+        let Some((key, value)) = line.split_once(':') else { continue };
+        let value = value.trim();
+        if key.trim().eq_ignore_ascii_case("status") {
+            if let Some(status_code) = parse_cgi_status(value) {
+                response = response.status(status_code);
             }
+        } else if !key.is_empty() && !value.is_empty() {
+            response = response.header(key, value);
         }
     }
 
@@ -124,3 +267,220 @@ pub async fn handler(req: Request<Body>) -> Response<Body> {
             .unwrap()
     })
 }
+
+// The value of a CGI `Status:` header is a code optionally followed by a
+// reason phrase (`404`, `404 Not Found`). An empty value leaves the status as
+// it is; anything that is not a valid HTTP status code becomes a 500 rather
+// than letting an error body go out as a 200.
+fn parse_cgi_status(value: &str) -> Option<StatusCode> {
+    let code = value.split_whitespace().next()?;
+    let status = code
+        .parse::<u16>()
+        .ok()
+        .filter(|code| (100..=599).contains(code))
+        .and_then(|code| StatusCode::from_u16(code).ok());
+    Some(status.unwrap_or(StatusCode::INTERNAL_SERVER_ERROR))
+}
+
+fn pushed_refs_path() -> PathBuf {
+    let name: String = thread_rng().sample_iter(&Alphanumeric).take(16).map(char::from).collect();
+    std::env::temp_dir().join(format!("git8-pushed-refs-{}", name))
+}
+
+// Reads and removes what the post-receive hook left at `path`. A push that
+// moved nothing leaves no file.
+fn take_pushed_refs(path: &StdPath) -> Vec<RefUpdate> {
+    let Ok(contents) = std::fs::read_to_string(path) else { return Vec::new() };
+    if let Err(e) = std::fs::remove_file(path) {
+        tracing::warn!("Failed to remove {}: {}", path.display(), e);
+    }
+    contents
+        .lines()
+        .filter_map(|line| {
+            let mut fields = line.split_whitespace();
+            let (old, new, name) = (fields.next()?, fields.next()?, fields.next()?);
+            Some(RefUpdate { name: name.to_string(), old: git2::Oid::from_str(old).ok()?, new: git2::Oid::from_str(new).ok()? })
+        })
+        .collect()
+}
+
+async fn notify_push(state: &AppState, repo_name: String, ref_updates: Vec<RefUpdate>, pusher: Option<PushUser>, base_url: BaseUrl) {
+    let events_repo = repo_name.clone();
+    let events = tokio::task::spawn_blocking(move || push_events(&events_repo, &ref_updates, pusher, &base_url)).await;
+
+    match events {
+        Ok(Ok(events)) => {
+            for event in &events {
+                webhooks::dispatch(state, &repo_name, "push", event);
+            }
+        }
+        Ok(Err(e)) => tracing::error!("Failed to build push events for {}: {}", repo_name, e),
+        Err(e) => tracing::error!("Push event task failed: {}", e),
+    }
+}
+
+fn push_events(repo_name: &str, updates: &[RefUpdate], pusher: Option<PushUser>, base_url: &BaseUrl) -> Result<Vec<PushEvent>, git2::Error> {
+    let repo = git2::Repository::open(format!("./repos/{}.git", repo_name))?;
+
+    let mut events = Vec::new();
+    for update in updates.iter().filter(|update| update.old != update.new) {
+        let (old, new) = (update.old, update.new);
+        let commits = if new.is_zero() { Vec::new() } else { pushed_commits(&repo, repo_name, new, updates, base_url)? };
+        let head_commit = if new.is_zero() { None } else { Some(push_commit(&repo.find_commit(new)?, repo_name, base_url)) };
+
+        events.push(PushEvent {
+            git_ref: update.name.clone(),
+            before: old.to_string(),
+            after: new.to_string(),
+            created: old.is_zero(),
+            deleted: new.is_zero(),
+            repository: PushRepository {
+                name: repo_name.to_string(),
+                url: base_url.repo_url(repo_name),
+                clone_url: base_url.clone_url(repo_name),
+            },
+            pusher: pusher.clone(),
+            commits,
+            head_commit,
+        });
+    }
+    Ok(events)
+}
+
+// The commits a push introduced: reachable from the new tip but neither from
+// where the pushed refs were before nor from any ref the push left alone,
+// oldest first.
+fn pushed_commits(
+    repo: &git2::Repository,
+    repo_name: &str,
+    new: git2::Oid,
+    updates: &[RefUpdate],
+    base_url: &BaseUrl,
+) -> Result<Vec<PushCommit>, git2::Error> {
+    let mut revwalk = repo.revwalk()?;
+    revwalk.set_sorting(git2::Sort::TOPOLOGICAL | git2::Sort::REVERSE)?;
+    revwalk.push(new)?;
+    for update in updates.iter().filter(|update| !update.old.is_zero()) {
+        let _ = revwalk.hide(update.old);
+    }
+    for reference in repo.references()?.flatten() {
+        if let (Some(name), Some(oid)) = (reference.name(), reference.target()) {
+            if !updates.iter().any(|update| update.name == name) {
+                let _ = revwalk.hide(oid);
+            }
+        }
+    }
+
+    let oids: Vec<git2::Oid> = revwalk.flatten().collect();
+    oids.iter()
+        .skip(oids.len().saturating_sub(MAX_PUSH_COMMITS))
+        .map(|oid| repo.find_commit(*oid).map(|commit| push_commit(&commit, repo_name, base_url)))
+        .collect()
+}
+
+fn push_commit(commit: &git2::Commit<'_>, repo_name: &str, base_url: &BaseUrl) -> PushCommit {
+    let author = commit.author();
+    PushCommit {
+        id: commit.id().to_string(),
+        url: base_url.join(&format!("/repos/{}/commits/{}.diff", repo_name, commit.id())),
+        message: String::from_utf8_lossy(commit.message_bytes()).into_owned(),
+        timestamp: chrono::DateTime::from_timestamp(commit.time().seconds(), 0).map(|t| t.to_rfc3339()),
+        author: PushCommitAuthor {
+            name: String::from_utf8_lossy(author.name_bytes()).into_owned(),
+            email: String::from_utf8_lossy(author.email_bytes()).into_owned(),
+        },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::Config;
+    use crate::test_support::{commit_file, TestApp};
+    use axum::http::HeaderMap;
+    use sqlx::PgPool;
+
+    fn git(dir: &StdPath, args: &[&str]) {
+        let status = std::process::Command::new("git")
+            .args(["-c", "user.name=Test", "-c", "user.email=test@example.com"])
+            .args(args)
+            .current_dir(dir)
+            .status()
+            .unwrap();
+        assert!(status.success(), "git {:?}", args);
+    }
+
+    #[sqlx::test]
+    async fn push_events_describe_the_refs_receive_pack_moved(pool: PgPool) {
+        let mut app = TestApp::new(pool);
+        let token = app.sign_up("alice").await;
+        let repo = app.create_repo(&token, true).await;
+        let old = commit_file(&repo, "old", "old.txt", "old\n");
+        let bare = std::fs::canonicalize(format!("./repos/{}.git", repo)).unwrap();
+        let main_before = git2::Repository::open(&bare).unwrap().refname_to_id("refs/heads/main").unwrap();
+
+        let work = std::env::temp_dir().join(format!("git8-push-{}", repo));
+        git(&std::env::temp_dir(), &["clone", "--quiet", bare.to_str().unwrap(), work.to_str().unwrap()]);
+        for file in ["a.txt", "b.txt"] {
+            std::fs::write(work.join(file), file).unwrap();
+            git(&work, &["add", file]);
+            git(&work, &["commit", "--quiet", "-m", &format!("Add {}", file)]);
+        }
+        git(&work, &["checkout", "--quiet", "-b", "feature"]);
+        std::fs::write(work.join("c.txt"), "c").unwrap();
+        git(&work, &["add", "c.txt"]);
+        git(&work, &["commit", "--quiet", "-m", "Add c.txt"]);
+
+        // Pushes to a local path clear git's config variables from
+        // receive-pack's environment, so the hooks go on its command line.
+        commit_policy::install_hooks().unwrap();
+        let receive_pack = format!("git -c core.hooksPath={} receive-pack", std::fs::canonicalize("./hooks").unwrap().display());
+        let pushed_refs = pushed_refs_path();
+        let mut push = std::process::Command::new("git");
+        push.args(["push", "--quiet", "--receive-pack", &receive_pack, "origin", "main", "feature", ":old"]).current_dir(&work);
+        commit_policy::record_pushed_refs(&mut push, &pushed_refs).unwrap();
+        assert!(push.status().unwrap().success());
+        let _ = std::fs::remove_dir_all(&work);
+
+        let updates = take_pushed_refs(&pushed_refs);
+        assert!(!pushed_refs.exists());
+        let base_url = BaseUrl::from_headers(&HeaderMap::new(), &Config::from_env());
+        let events = push_events(&repo, &updates, None, &base_url).unwrap();
+        let event = |name: &str| {
+            let event = events.iter().find(|event| event.git_ref == name).unwrap();
+            serde_json::to_value(event).unwrap()
+        };
+        let messages = |event: &serde_json::Value| -> Vec<String> {
+            event["commits"].as_array().unwrap().iter().map(|commit| commit["message"].as_str().unwrap().trim().to_string()).collect()
+        };
+        assert_eq!(events.len(), 3);
+
+        let main = event("refs/heads/main");
+        assert_eq!(main["ref"], "refs/heads/main");
+        assert_eq!(main["before"], main_before.to_string());
+        assert_eq!((main["created"].as_bool(), main["deleted"].as_bool()), (Some(false), Some(false)));
+        assert_eq!(messages(&main), ["Add a.txt", "Add b.txt"]);
+        assert_eq!(main["head_commit"]["id"], main["after"]);
+
+        let feature = event("refs/heads/feature");
+        assert_eq!(feature["before"], git2::Oid::zero().to_string());
+        assert_eq!((feature["created"].as_bool(), feature["deleted"].as_bool()), (Some(true), Some(false)));
+        assert_eq!(messages(&feature), ["Add a.txt", "Add b.txt", "Add c.txt"]);
+
+        let deleted = event("refs/heads/old");
+        assert_eq!(deleted["before"], old.to_string());
+        assert_eq!(deleted["after"], git2::Oid::zero().to_string());
+        assert_eq!((deleted["created"].as_bool(), deleted["deleted"].as_bool()), (Some(false), Some(true)));
+        assert!(messages(&deleted).is_empty());
+        assert!(deleted["head_commit"].is_null());
+    }
+
+    #[test]
+    fn parses_cgi_status_values() {
+        assert_eq!(parse_cgi_status("404"), Some(StatusCode::NOT_FOUND));
+        assert_eq!(parse_cgi_status("404 Not Found"), Some(StatusCode::NOT_FOUND));
+        assert_eq!(parse_cgi_status(""), None);
+        assert_eq!(parse_cgi_status("abc"), Some(StatusCode::INTERNAL_SERVER_ERROR));
+        assert_eq!(parse_cgi_status("999"), Some(StatusCode::INTERNAL_SERVER_ERROR));
+    }
+}
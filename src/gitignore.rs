@@ -0,0 +1,60 @@
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+
+static NEXT_SCRATCH_DIR: AtomicU64 = AtomicU64::new(0);
+
+// The `.gitignore` rules of one commit. libgit2 only evaluates ignore rules
+// against a working directory, which bare repositories do not have, so the
+// relevant `.gitignore` files are laid out in a scratch directory that the
+// repository is pointed at. The directory is removed on drop.
+pub(crate) struct IgnoreRules<'r> {
+    repo: &'r git2::Repository,
+    scratch: PathBuf,
+}
+
+impl<'r> IgnoreRules<'r> {
+    // Loads the `.gitignore` of each directory in `dirs` (`""` is the root).
+    // A path's rules come from its ancestors, so `dirs` must cover every
+    // directory above the paths that will be asked about.
+    pub(crate) fn load<'d>(repo: &'r git2::Repository, root: &git2::Tree<'_>, dirs: impl IntoIterator<Item = &'d str>) -> std::io::Result<Self> {
+        let scratch = std::env::temp_dir().join(format!(
+            "git8-ignore-{}-{}",
+            std::process::id(),
+            NEXT_SCRATCH_DIR.fetch_add(1, Ordering::Relaxed)
+        ));
+        std::fs::create_dir_all(&scratch)?;
+        let rules = IgnoreRules { repo, scratch };
+
+        for dir in dirs {
+            let path = Path::new(dir).join(".gitignore");
+            let Ok(entry) = root.get_path(&path) else { continue };
+            // Git does not follow a `.gitignore` that is a symlink.
+            if entry.kind() != Some(git2::ObjectType::Blob) || entry.filemode() == 0o120000 {
+                continue;
+            }
+            let Ok(blob) = repo.find_blob(entry.id()) else { continue };
+            let target = rules.scratch.join(&path);
+            if let Some(parent) = target.parent() {
+                std::fs::create_dir_all(parent)?;
+            }
+            std::fs::write(target, blob.content())?;
+        }
+
+        repo.set_workdir(&rules.scratch, false).map_err(std::io::Error::other)?;
+        Ok(rules)
+    }
+
+    pub(crate) fn is_ignored(&self, path: &str, is_dir: bool) -> bool {
+        // The trailing slash lets directory-only patterns such as `build/` match.
+        let path = if is_dir { format!("{}/", path) } else { path.to_string() };
+        self.repo.is_path_ignored(path).unwrap_or(false)
+    }
+}
+
+impl Drop for IgnoreRules<'_> {
+    fn drop(&mut self) {
+        if let Err(e) = std::fs::remove_dir_all(&self.scratch) {
+            tracing::warn!("Failed to remove {}: {}", self.scratch.display(), e);
+        }
+    }
+}
@@ -0,0 +1,154 @@
+use axum::{
+    body::Body,
+    extract::{FromRequestParts, Request, State},
+    http::{header, HeaderValue, Method, StatusCode},
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use sqlx::{FromRow, PgPool};
+
+use crate::auth::PermissiveAuthUser;
+use crate::db;
+use crate::AppState;
+
+const IDEMPOTENCY_KEY: &str = "idempotency-key";
+const MAX_KEY_CHARS: usize = 255;
+// Long enough to cover any sensible retry loop.
+const KEY_LIFETIME_HOURS: i32 = 24;
+
+#[derive(FromRow)]
+struct StoredResponse {
+    request_path: String,
+    status_code: Option<i16>,
+    content_type: Option<String>,
+    response_body: Option<Vec<u8>>,
+}
+
+// Makes POSTs carrying an `Idempotency-Key` header safe to retry. The first
+// request with a key runs normally and its successful response is stored;
+// repeats within `KEY_LIFETIME_HOURS` get that response back instead of
+// creating the resource again. Keys belong to the authenticated user, so
+// anonymous requests and requests without the header pass straight through.
+pub async fn idempotent(State(state): State<AppState>, req: Request, next: Next) -> Response {
+    if req.method() != Method::POST || !req.headers().contains_key(IDEMPOTENCY_KEY) {
+        return next.run(req).await;
+    }
+
+    let key = match req.headers().get(IDEMPOTENCY_KEY).and_then(|v| v.to_str().ok()).map(str::trim) {
+        Some(key) if !key.is_empty() && key.chars().count() <= MAX_KEY_CHARS => key.to_string(),
+        _ => {
+            let message = format!("Idempotency-Key must be 1 to {} characters", MAX_KEY_CHARS);
+            return (StatusCode::UNPROCESSABLE_ENTITY, message).into_response();
+        }
+    };
+
+    let (mut parts, body) = req.into_parts();
+    let user = match PermissiveAuthUser::from_request_parts(&mut parts, &state).await {
+        Ok(PermissiveAuthUser(Some(user))) => user,
+        Ok(PermissiveAuthUser(None)) => return next.run(Request::from_parts(parts, body)).await,
+        Err(rejection) => return rejection,
+    };
+    let path = parts.uri.path().to_string();
+
+    match claim_key(&state.pool, user.id, &key, &path).await {
+        Ok(None) => {}
+        Ok(Some(stored)) => return replay(stored, &path),
+        Err(e) => {
+            tracing::error!("Failed to claim idempotency key: {}", e);
+            return (db::error_status(&e), "Failed to check idempotency key").into_response();
+        }
+    }
+
+    let response = next.run(Request::from_parts(parts, body)).await;
+    if !response.status().is_success() {
+        // Nothing was created, so the client may retry with the same key.
+        if let Err(e) = release_key(&state.pool, user.id, &key).await {
+            tracing::error!("Failed to release idempotency key: {}", e);
+        }
+        return response;
+    }
+
+    let (parts, body) = response.into_parts();
+    let bytes = match axum::body::to_bytes(body, usize::MAX).await {
+        Ok(bytes) => bytes,
+        Err(e) => {
+            tracing::error!("Failed to buffer response body for idempotency key: {}", e);
+            return (StatusCode::INTERNAL_SERVER_ERROR, "Failed to read response body").into_response();
+        }
+    };
+    let content_type = parts.headers.get(header::CONTENT_TYPE).and_then(|v| v.to_str().ok());
+    let stored = sqlx::query(
+        "UPDATE idempotency_keys SET status_code = $1, content_type = $2, response_body = $3 WHERE user_id = $4 AND key = $5",
+    )
+    .bind(parts.status.as_u16() as i16)
+    .bind(content_type)
+    .bind(bytes.as_ref())
+    .bind(user.id)
+    .bind(&key)
+    .execute(&state.pool)
+    .await;
+    if let Err(e) = stored {
+        tracing::error!("Failed to store response for idempotency key: {}", e);
+    }
+
+    Response::from_parts(parts, Body::from(bytes))
+}
+
+// Reserves the key for this request. Returns what is already stored under it
+// when another request got there first.
+async fn claim_key(pool: &PgPool, user_id: i32, key: &str, path: &str) -> Result<Option<StoredResponse>, sqlx::Error> {
+    sqlx::query(&format!(
+        "DELETE FROM idempotency_keys WHERE user_id = $1 AND key = $2 AND created_at < now() - interval '{} hours'",
+        KEY_LIFETIME_HOURS
+    ))
+    .bind(user_id)
+    .bind(key)
+    .execute(pool)
+    .await?;
+
+    let claim = sqlx::query(
+        "INSERT INTO idempotency_keys (user_id, key, request_path) VALUES ($1, $2, $3) ON CONFLICT (user_id, key) DO NOTHING",
+    )
+    .bind(user_id)
+    .bind(key)
+    .bind(path)
+    .execute(pool)
+    .await?;
+    if claim.rows_affected() == 1 {
+        return Ok(None);
+    }
+
+    sqlx::query_as::<_, StoredResponse>(
+        "SELECT request_path, status_code, content_type, response_body FROM idempotency_keys WHERE user_id = $1 AND key = $2",
+    )
+    .bind(user_id)
+    .bind(key)
+    .fetch_optional(pool)
+    .await
+}
+
+async fn release_key(pool: &PgPool, user_id: i32, key: &str) -> Result<(), sqlx::Error> {
+    sqlx::query("DELETE FROM idempotency_keys WHERE user_id = $1 AND key = $2 AND status_code IS NULL")
+        .bind(user_id)
+        .bind(key)
+        .execute(pool)
+        .await
+        .map(|_| ())
+}
+
+fn replay(stored: StoredResponse, path: &str) -> Response {
+    if stored.request_path != path {
+        return (StatusCode::UNPROCESSABLE_ENTITY, "Idempotency-Key was already used for a different request").into_response();
+    }
+    let (Some(status), Some(body)) = (stored.status_code, stored.response_body) else {
+        return (StatusCode::CONFLICT, "A request with this Idempotency-Key is still in progress").into_response();
+    };
+
+    let mut response = Response::new(Body::from(body));
+    *response.status_mut() = StatusCode::from_u16(status as u16).unwrap_or(StatusCode::OK);
+    if let Some(content_type) = stored.content_type.and_then(|v| HeaderValue::from_str(&v).ok()) {
+        response.headers_mut().insert(header::CONTENT_TYPE, content_type);
+    }
+    response.headers_mut().insert("idempotent-replayed", HeaderValue::from_static("true"));
+    response
+}
@@ -1,10 +1,20 @@
-use axum::{extract::{Path, State}, http::StatusCode, response::IntoResponse, Json};
+use axum::{extract::{Path, Query, State}, http::StatusCode, response::IntoResponse, Json};
 use serde::{Deserialize, Serialize};
-use sqlx::FromRow;
+use sqlx::{FromRow, PgConnection};
+use std::collections::HashMap;
+use tokio::task;
 
-use crate::auth::{AuthUser, PermissiveAuthUser};
+use crate::auth::{AuthUser, PermissiveAuthUser, Scope};
+use crate::db;
+use crate::git_api::{default_user_ids, find_visible_repo, find_writable_repo, next_item_number, DefaultUsers};
+use crate::markdown::{self, RenderOptions, RenderQuery};
+use crate::pagination::{Pagination, SortDirection};
+use crate::validation::{ValidatedJson, Validate, Validator, MAX_BODY_CHARS, MAX_TITLE_CHARS};
 use crate::AppState;
 
+pub mod position;
+pub mod reactions;
+
 #[derive(Serialize, FromRow, Clone)]
 pub struct Label {
     pub id: i32,
@@ -22,12 +32,17 @@ pub struct DisplayUser {
 #[derive(Serialize, FromRow)]
 pub struct Issue {
     pub id: i32,
+    pub number: i32,
     pub repo_id: i32,
     pub title: String,
     pub body: Option<String>,
     pub author_id: i32,
     pub status: String,
     pub created_at: chrono::DateTime<chrono::Utc>,
+    pub position: Option<f64>,
+    pub updated_at: chrono::DateTime<chrono::Utc>,
+    // When the issue was last closed; null while it is open.
+    pub closed_at: Option<chrono::DateTime<chrono::Utc>>,
 }
 
 #[derive(Serialize)]
@@ -37,6 +52,13 @@ pub struct FullIssue {
     pub labels: Vec<Label>,
     pub assignees: Vec<DisplayUser>,
     pub author: DisplayUser,
+    pub reactions: reactions::ReactionCounts,
+    // How to reach the reporter of an anonymous issue; only shown to the
+    // repository owner.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub contact: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub body_html: Option<String>,
 }
 
 #[derive(Deserialize)]
@@ -47,8 +69,21 @@ pub struct NewIssue {
     pub labels: Vec<String>,
     #[serde(default)]
     pub assignees: Vec<String>,
+    pub contact: Option<String>,
+}
+
+impl Validate for NewIssue {
+    fn validate(&self, v: &mut Validator) {
+        v.required("title", &self.title, MAX_TITLE_CHARS)
+            .max_chars("body", self.body.as_deref(), MAX_BODY_CHARS)
+            .max_chars("contact", self.contact.as_deref(), MAX_TITLE_CHARS);
+    }
 }
 
+// Deleted comments keep their row, so replies that quote or link them still
+// have context, but their body is replaced with this marker.
+pub(crate) const DELETED_COMMENT_BODY: &str = "This comment was deleted.";
+
 #[derive(Serialize, FromRow)]
 pub struct IssueComment {
     id: i32,
@@ -56,6 +91,12 @@ pub struct IssueComment {
     body: String,
     author_id: i32,
     created_at: chrono::DateTime<chrono::Utc>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    edited_at: Option<chrono::DateTime<chrono::Utc>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    deleted_at: Option<chrono::DateTime<chrono::Utc>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    deleted_by: Option<i32>,
 }
 
 #[derive(Deserialize)]
@@ -63,42 +104,179 @@ pub struct NewComment {
     pub body: String,
 }
 
+impl Validate for NewComment {
+    fn validate(&self, v: &mut Validator) {
+        v.required("body", &self.body, MAX_BODY_CHARS);
+    }
+}
+
 #[derive(Deserialize)]
 pub struct NewLabel {
     pub name: String,
     pub color: String,
 }
 
+impl Validate for NewLabel {
+    fn validate(&self, v: &mut Validator) {
+        let color = self.color.strip_prefix('#').unwrap_or(&self.color);
+        v.required("name", &self.name, 50)
+            .check(color.len() == 6 && color.chars().all(|c| c.is_ascii_hexdigit()), "color", "must be a 6-digit hex color");
+    }
+}
+
+#[derive(Deserialize, Clone, Copy, PartialEq)]
+pub enum IssueStatus {
+    #[serde(rename = "open")]
+    Open,
+    #[serde(rename = "closed")]
+    Closed,
+}
+
+impl IssueStatus {
+    fn as_str(self) -> &'static str {
+        match self {
+            IssueStatus::Open => "open",
+            IssueStatus::Closed => "closed",
+        }
+    }
+}
+
+#[derive(Deserialize)]
+pub struct UpdateIssue {
+    pub status: Option<IssueStatus>,
+    pub title: Option<String>,
+    pub body: Option<String>,
+}
+
+impl Validate for UpdateIssue {
+    fn validate(&self, v: &mut Validator) {
+        if let Some(title) = &self.title {
+            v.required("title", title, MAX_TITLE_CHARS);
+        }
+        v.max_chars("body", self.body.as_deref(), MAX_BODY_CHARS);
+    }
+}
+
+#[derive(Deserialize, Clone, Copy, PartialEq)]
+pub enum BulkIssueAction {
+    #[serde(rename = "close")]
+    Close,
+    #[serde(rename = "reopen")]
+    Reopen,
+    #[serde(rename = "add_label")]
+    AddLabel,
+    #[serde(rename = "remove_label")]
+    RemoveLabel,
+}
+
+// `position` follows the manual backlog order; issues that were never
+// positioned come last, oldest first.
+#[derive(Deserialize, Clone, Copy, Default)]
+pub enum IssueSort {
+    #[default]
+    #[serde(rename = "created")]
+    Created,
+    #[serde(rename = "position")]
+    Position,
+}
+
+// `since` narrows the list to issues created or updated at or after that
+// time, oldest change first, for incremental polling. `label` takes a
+// comma-separated list of names, all of which an issue must carry.
+#[derive(Deserialize)]
+pub struct IssueListQuery {
+    #[serde(default)]
+    pub sort: IssueSort,
+    #[serde(default)]
+    pub direction: SortDirection,
+    pub since: Option<chrono::DateTime<chrono::Utc>>,
+    pub status: Option<IssueStatus>,
+    pub label: Option<String>,
+    pub author: Option<String>,
+    pub assignee: Option<String>,
+}
+
+const ISSUE_FILTERS: &str = r#"
+    FROM issues i
+    WHERE i.repo_id = $1
+      AND ($2::timestamptz IS NULL OR i.updated_at >= $2)
+      AND ($3::text IS NULL OR i.status = $3)
+      AND (cardinality($4::text[]) = 0 OR (
+          SELECT COUNT(DISTINCT l.name) FROM issue_labels il JOIN labels l ON l.id = il.label_id
+          WHERE il.issue_id = i.id AND l.name = ANY($4)
+      ) = cardinality($4::text[]))
+      AND ($5::text IS NULL OR i.author_id = (SELECT id FROM users WHERE username = $5))
+      AND ($6::text IS NULL OR EXISTS (
+          SELECT 1 FROM issue_assignees ia JOIN users u ON u.id = ia.user_id
+          WHERE ia.issue_id = i.id AND u.username = $6
+      ))
+"#;
+
+#[derive(Deserialize)]
+pub struct BulkIssueUpdate {
+    pub numbers: Vec<i32>,
+    pub action: BulkIssueAction,
+    pub label: Option<String>,
+}
+
+impl Validate for BulkIssueUpdate {
+    fn validate(&self, v: &mut Validator) {
+        let needs_label = matches!(self.action, BulkIssueAction::AddLabel | BulkIssueAction::RemoveLabel);
+        v.check((1..=MAX_BULK_ISSUES).contains(&self.numbers.len()), "numbers", format!("must list between 1 and {} issues", MAX_BULK_ISSUES))
+            .check(!needs_label || self.label.is_some(), "label", "is required for this action");
+    }
+}
+
+#[derive(Serialize)]
+pub struct BulkIssueResult {
+    pub number: i32,
+    pub success: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+const MAX_BULK_ISSUES: usize = 100;
+
+#[derive(Deserialize)]
+pub struct SimilarIssuesQuery {
+    pub title: String,
+}
+
+#[derive(Serialize, FromRow)]
+pub struct SimilarIssue {
+    pub id: i32,
+    pub number: i32,
+    pub title: String,
+    pub status: String,
+    pub similarity: f32,
+}
+
+const MAX_SIMILAR_ISSUES: i64 = 5;
+
+#[derive(Deserialize)]
+pub struct IssueSearchQuery {
+    pub q: String,
+}
+
 #[axum::debug_handler]
 pub async fn create_label(
     State(state): State<AppState>,
     AuthUser(user): AuthUser,
     Path(repo_name): Path<String>,
-    Json(new_label): Json<NewLabel>,
+    ValidatedJson(new_label): ValidatedJson<NewLabel>,
 ) -> Result<impl IntoResponse, (StatusCode, String)> {
+    user.require_scope(Scope::IssuesWrite)?;
+    let repo = find_writable_repo(&state.pool, &repo_name, user.id, "create labels in").await?;
     let label = sqlx::query_as!(
         Label,
-        r#"
-        WITH repo AS (
-            SELECT id FROM repositories WHERE name = $1 AND user_id = $2
-        )
-        INSERT INTO labels (repo_id, name, color)
-        SELECT id, $3, $4 FROM repo
-        RETURNING id, repo_id, name, color
-        "#,
-        repo_name,
-        user.id,
+        "INSERT INTO labels (repo_id, name, color) VALUES ($1, $2, $3) RETURNING id, repo_id, name, color",
+        repo.id,
         new_label.name,
         new_label.color
     )
     .fetch_one(&state.pool)
     .await
-    .map_err(|e| {
-        (
-            StatusCode::INTERNAL_SERVER_ERROR,
-            format!("Failed to create label: {}", e),
-        )
-    })?;
+    .map_err(|e| db::error(e, "Failed to create label"))?;
 
     Ok((StatusCode::CREATED, Json(label)))
 }
@@ -117,19 +295,14 @@ pub async fn list_labels(
         SELECT l.id, l.repo_id, l.name, l.color
         FROM labels l
         JOIN repositories r ON l.repo_id = r.id
-        WHERE r.name = $1 AND (r.public OR r.user_id = $2)
+        WHERE r.name = $1 AND (r.public OR r.user_id = $2 OR EXISTS (SELECT 1 FROM repo_collaborators c WHERE c.repo_id = r.id AND c.user_id = $2))
         "#,
         repo_name,
         user_id
     )
     .fetch_all(&state.pool)
     .await
-    .map_err(|e| {
-        (
-            StatusCode::INTERNAL_SERVER_ERROR,
-            format!("Failed to list labels: {}", e),
-        )
-    })?;
+    .map_err(|e| db::error(e, "Failed to list labels"))?;
 
     Ok(Json(labels))
 }
@@ -137,107 +310,289 @@ pub async fn list_labels(
 #[axum::debug_handler]
 pub async fn create_issue(
     State(state): State<AppState>,
-    AuthUser(user): AuthUser,
+    PermissiveAuthUser(user): PermissiveAuthUser,
     Path(repo_name): Path<String>,
-    Json(new_issue): Json<NewIssue>,
+    ValidatedJson(new_issue): ValidatedJson<NewIssue>,
 ) -> Result<impl IntoResponse, (StatusCode, String)> {
-    let mut tx = state.pool.begin().await.map_err(|e| {
-        (
-            StatusCode::INTERNAL_SERVER_ERROR,
-            format!("Failed to start transaction: {}", e),
-        )
-    })?;
+    if let Some(user) = &user {
+        user.require_scope(Scope::IssuesWrite)?;
+    }
+    let mut tx = state.pool.begin().await.map_err(|e| db::error(e, "Failed to start transaction"))?;
 
-    let repo_id_result = sqlx::query!(r#"SELECT id FROM repositories WHERE name = $1 AND (public OR user_id = $2)"#, repo_name, user.id)
-        .fetch_optional(&mut *tx)
-        .await
-        .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, format!("Failed to get repo: {}", e)))?;
+    let user_id = user.map(|u| u.id);
+    let repo = find_visible_repo(&mut *tx, &repo_name, user_id).await?;
+    let repo_id = repo.id;
 
-    let repo_id = match repo_id_result {
-        Some(repo) => repo.id,
-        None => return Err((StatusCode::FORBIDDEN, "Repository not found or you don't have permission to create an issue here.".to_string())),
+    // Opening issues takes the `write` role, unless the owner turned on
+    // `allow_anonymous_issues` to take them from anyone who can see the
+    // repository. Labels and assignees are left to those who can triage it.
+    let (author_id, contact, labels, assignees) = match user_id {
+        Some(user_id) if repo.can_write(Some(user_id)) => (user_id, None, new_issue.labels, new_issue.assignees),
+        Some(user_id) => {
+            if !accepts_outside_issues(&mut tx, repo_id).await? {
+                return Err((StatusCode::FORBIDDEN, "You do not have permission to open issues in this repository".to_string()));
+            }
+            (user_id, None, Vec::new(), Vec::new())
+        }
+        None => (anonymous_author(&mut tx, repo_id).await?, new_issue.contact, Vec::new(), Vec::new()),
     };
+    let number = next_item_number(&mut tx, repo_id).await?;
 
     let issue = sqlx::query_as!(
         Issue,
         r#"
-        INSERT INTO issues (repo_id, title, body, author_id)
-        VALUES ($1, $2, $3, $4)
-        RETURNING id, repo_id, title, body, author_id, status, created_at
+        INSERT INTO issues (repo_id, number, title, body, author_id, contact)
+        VALUES ($1, $2, $3, $4, $5, $6)
+        RETURNING id, number, repo_id, title, body, author_id, status, created_at, position, updated_at, closed_at
         "#,
         repo_id,
+        number,
         new_issue.title,
         new_issue.body,
-        user.id
+        author_id,
+        contact,
     )
     .fetch_one(&mut *tx)
     .await
-    .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, format!("Failed to create issue: {}", e)))?;
+    .map_err(|e| db::error(e, "Failed to create issue"))?;
 
-    if !new_issue.labels.is_empty() {
-        let labels_to_add = sqlx::query_as!(Label, "SELECT id, repo_id, name, color FROM labels WHERE repo_id = $1 AND name = ANY($2)", repo_id, &new_issue.labels)
+    if !labels.is_empty() {
+        let labels_to_add = sqlx::query_as!(Label, "SELECT id, repo_id, name, color FROM labels WHERE repo_id = $1 AND name = ANY($2)", repo_id, &labels)
             .fetch_all(&mut *tx)
             .await
-            .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, format!("Failed to find labels: {}", e)))?;
+            .map_err(|e| db::error(e, "Failed to find labels"))?;
         
         for label in labels_to_add {
             sqlx::query!(r#"INSERT INTO issue_labels (issue_id, label_id) VALUES ($1, $2)"#, issue.id, label.id)
                 .execute(&mut *tx)
                 .await
-                .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, format!("Failed to add label to issue: {}", e)))?;
+                .map_err(|e| db::error(e, "Failed to add label to issue"))?;
         }
     }
 
-    if !new_issue.assignees.is_empty() {
-        let users_to_add = sqlx::query_as!(DisplayUser, "SELECT id, username FROM users WHERE username = ANY($1)", &new_issue.assignees)
+    if !assignees.is_empty() {
+        let users_to_add = sqlx::query_as!(DisplayUser, "SELECT id, username FROM users WHERE username = ANY($1)", &assignees)
             .fetch_all(&mut *tx)
             .await
-            .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, format!("Failed to find users: {}", e)))?;
+            .map_err(|e| db::error(e, "Failed to find users"))?;
 
         for assignee in users_to_add {
             sqlx::query!(r#"INSERT INTO issue_assignees (issue_id, user_id) VALUES ($1, $2)"#, issue.id, assignee.id)
                 .execute(&mut *tx)
                 .await
-                .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, format!("Failed to add assignee to issue: {}", e)))?;
+                .map_err(|e| db::error(e, "Failed to add assignee to issue"))?;
         }
+    } else {
+        let default_assignees = default_user_ids(&mut tx, repo_id, DefaultUsers::Assignees).await?;
+        sqlx::query!(
+            "INSERT INTO issue_assignees (issue_id, user_id) SELECT $1, unnest($2::int[])",
+            issue.id,
+            &default_assignees,
+        )
+        .execute(&mut *tx)
+        .await
+        .map_err(|e| db::error(e, "Failed to add default assignees to issue"))?;
     }
 
-    tx.commit().await.map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, format!("Failed to commit transaction: {}", e)))?;
+    tx.commit().await.map_err(|e| db::error(e, "Failed to commit transaction"))?;
 
-    let full_issue = get_full_issue(&state, repo_name, issue.id, Some(user.id)).await?.1;
+    let full_issue = get_full_issue(&state, repo_name, issue.number, user_id).await?.1;
 
     Ok((StatusCode::CREATED, Json(full_issue)))
 }
 
+async fn accepts_outside_issues(conn: &mut PgConnection, repo_id: i32) -> Result<bool, (StatusCode, String)> {
+    sqlx::query_scalar!("SELECT allow_anonymous_issues FROM repositories WHERE id = $1", repo_id)
+        .fetch_one(conn)
+        .await
+        .map_err(|e| db::error(e, "Failed to fetch repository settings"))
+}
+
+// Anonymous issues are filed as the placeholder `ghost` user, and only in
+// repositories whose owner turned on `allow_anonymous_issues`.
+async fn anonymous_author(conn: &mut PgConnection, repo_id: i32) -> Result<i32, (StatusCode, String)> {
+    if !accepts_outside_issues(&mut *conn, repo_id).await? {
+        return Err((StatusCode::UNAUTHORIZED, "Authentication required".to_string()));
+    }
+
+    sqlx::query_scalar!("SELECT id FROM users WHERE username = 'ghost' AND password_hash = '!'")
+        .fetch_optional(&mut *conn)
+        .await
+        .map_err(|e| db::error(e, "Failed to fetch the anonymous author"))?
+        .ok_or_else(|| (StatusCode::INTERNAL_SERVER_ERROR, "The placeholder author for anonymous issues is missing".to_string()))
+}
+
+// Pre-create lookup for likely duplicates: open issues whose titles are
+// trigram-similar to the one being typed, best match first.
+#[axum::debug_handler]
+pub async fn list_similar_issues(
+    State(state): State<AppState>,
+    PermissiveAuthUser(user): PermissiveAuthUser,
+    Path(repo_name): Path<String>,
+    Query(query): Query<SimilarIssuesQuery>,
+) -> Result<impl IntoResponse, (StatusCode, String)> {
+    let repo_id = find_visible_repo(&state.pool, &repo_name, user.map(|u| u.id)).await?.id;
+
+    let similar = sqlx::query_as!(
+        SimilarIssue,
+        r#"
+        SELECT id, number, title, status, similarity(title, $2) as "similarity!"
+        FROM issues
+        WHERE repo_id = $1 AND status = 'open' AND title % $2
+        ORDER BY similarity(title, $2) DESC, id
+        LIMIT $3
+        "#,
+        repo_id,
+        query.title.trim(),
+        MAX_SIMILAR_ISSUES,
+    )
+    .fetch_all(&state.pool)
+    .await
+    .map_err(|e| db::error(e, "Failed to find similar issues"))?;
+
+    Ok(Json(similar))
+}
+
+// Full-text search over titles and bodies, best match first. The tsvector
+// expression must stay in step with `idx_issues_search` for the index to be
+// used.
+#[axum::debug_handler]
+pub async fn search_issues(
+    State(state): State<AppState>,
+    PermissiveAuthUser(user): PermissiveAuthUser,
+    Path(repo_name): Path<String>,
+    Query(query): Query<IssueSearchQuery>,
+    pagination: Pagination,
+) -> Result<impl IntoResponse, (StatusCode, String)> {
+    let terms = query.q.trim();
+    if terms.is_empty() {
+        return Err((StatusCode::BAD_REQUEST, "The search query `q` must not be empty".to_string()));
+    }
+    let user_id = user.map(|u| u.id);
+    let repo = find_visible_repo(&state.pool, &repo_name, user_id).await?;
+
+    let total = sqlx::query_scalar!(
+        r#"
+        SELECT COUNT(*) as "count!" FROM issues
+        WHERE repo_id = $1 AND to_tsvector('english', title || ' ' || COALESCE(body, '')) @@ plainto_tsquery('english', $2)
+        "#,
+        repo.id,
+        terms,
+    )
+    .fetch_one(&state.pool)
+    .await
+    .map_err(|e| db::error(e, "Failed to count matching issues"))?;
+
+    let issues = sqlx::query_as!(
+        Issue,
+        r#"
+        SELECT id, number, repo_id, title, body, author_id, status, created_at, position, updated_at, closed_at
+        FROM issues
+        WHERE repo_id = $1 AND to_tsvector('english', title || ' ' || COALESCE(body, '')) @@ plainto_tsquery('english', $2)
+        ORDER BY ts_rank(to_tsvector('english', title || ' ' || COALESCE(body, '')), plainto_tsquery('english', $2)) DESC, id
+        LIMIT $3 OFFSET $4
+        "#,
+        repo.id,
+        terms,
+        pagination.limit(),
+        pagination.offset(),
+    )
+    .fetch_all(&state.pool)
+    .await
+    .map_err(|e| db::error(e, "Failed to search issues"))?;
+
+    let full_issues = with_details(&state.pool, issues, repo.can_write(user_id)).await?;
+
+    Ok(pagination.respond(full_issues, total))
+}
+
 #[axum::debug_handler]
 pub async fn get_issue(
     State(state): State<AppState>,
     PermissiveAuthUser(user): PermissiveAuthUser,
-    Path((repo_name, issue_id)): Path<(String, i32)>,
+    Path((repo_name, issue_number)): Path<(String, i32)>,
+    Query(render): Query<RenderQuery>,
 ) -> Result<impl IntoResponse, (StatusCode, String)> {
     let user_id = user.map(|u| u.id);
-    let (_status, full_issue) = get_full_issue(&state, repo_name, issue_id, user_id).await?;
+    let (_status, mut full_issue) = get_full_issue(&state, repo_name.clone(), issue_number, user_id).await?;
+
+    if render.render {
+        let body = full_issue.issue.body.clone().unwrap_or_default();
+        let (mentions, references) = markdown::resolve_mentions_and_references(&state.pool, &body, Some(full_issue.issue.repo_id))
+            .await
+            .map_err(|e| db::error(e, "Failed to resolve references"))?;
+        let html = task::spawn_blocking(move || {
+            let options = RenderOptions { repo: Some(&repo_name), link_commits: render.link_commits, mentions, references };
+            markdown::render(&body, &options)
+        })
+        .await
+        .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, format!("Task failed: {}", e)))?;
+        full_issue.body_html = Some(html);
+    }
+
+    Ok(Json(full_issue))
+}
+
+// Closes, reopens or edits an issue. `closed_at` is set when an open issue is
+// closed and cleared when it is reopened.
+#[axum::debug_handler]
+pub async fn update_issue(
+    State(state): State<AppState>,
+    AuthUser(user): AuthUser,
+    Path((repo_name, issue_number)): Path<(String, i32)>,
+    ValidatedJson(update): ValidatedJson<UpdateIssue>,
+) -> Result<impl IntoResponse, (StatusCode, String)> {
+    user.require_scope(Scope::IssuesWrite)?;
+    let repo = find_writable_repo(&state.pool, &repo_name, user.id, "update issues in").await?;
+
+    sqlx::query_scalar!(
+        r#"
+        UPDATE issues
+        SET status = COALESCE($3, status),
+            title = COALESCE($4, title),
+            body = COALESCE($5, body),
+            closed_at = CASE
+                WHEN $3 = 'open' THEN NULL
+                WHEN $3 = 'closed' AND status <> 'closed' THEN NOW()
+                ELSE closed_at
+            END,
+            updated_at = NOW()
+        WHERE number = $1 AND repo_id = $2
+        RETURNING id
+        "#,
+        issue_number,
+        repo.id,
+        update.status.map(IssueStatus::as_str),
+        update.title,
+        update.body,
+    )
+    .fetch_optional(&state.pool)
+    .await
+    .map_err(|e| db::error(e, "Failed to update issue"))?
+    .ok_or_else(|| (StatusCode::NOT_FOUND, "Issue not found".to_string()))?;
+
+    let full_issue = get_full_issue(&state, repo_name, issue_number, Some(user.id)).await?.1;
     Ok(Json(full_issue))
 }
 
-async fn get_full_issue(state: &AppState, repo_name: String, issue_id: i32, user_id: Option<i32>) -> Result<(StatusCode, FullIssue), (StatusCode, String)> {
+async fn get_full_issue(state: &AppState, repo_name: String, issue_number: i32, user_id: Option<i32>) -> Result<(StatusCode, FullIssue), (StatusCode, String)> {
     let issue = sqlx::query_as!(
         Issue,
         r#"
-        SELECT i.id, i.repo_id, i.title, i.body, i.author_id, i.status, i.created_at
+        SELECT i.id, i.number, i.repo_id, i.title, i.body, i.author_id, i.status, i.created_at, i.position, i.updated_at, i.closed_at
         FROM issues i
         JOIN repositories r ON i.repo_id = r.id
-        WHERE r.name = $1 AND i.id = $2 AND (r.public OR r.user_id = $3)
+        WHERE r.name = $1 AND i.number = $2 AND (r.public OR r.user_id = $3 OR EXISTS (SELECT 1 FROM repo_collaborators c WHERE c.repo_id = r.id AND c.user_id = $3))
         "#,
         repo_name,
-        issue_id,
+        issue_number,
         user_id,
     )
     .fetch_one(&state.pool)
     .await
     .map_err(|e| match e {
         sqlx::Error::RowNotFound => (StatusCode::NOT_FOUND, "Issue not found".to_string()),
-        _ => (StatusCode::INTERNAL_SERVER_ERROR, format!("Failed to fetch issue: {}", e)),
+        _ => db::error(e, "Failed to fetch issue"),
     })?;
 
     let labels = sqlx::query_as!(
@@ -247,7 +602,7 @@ async fn get_full_issue(state: &AppState, repo_name: String, issue_id: i32, user
     )
     .fetch_all(&state.pool)
     .await
-    .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, format!("Failed to fetch labels: {}", e)))?;
+    .map_err(|e| db::error(e, "Failed to fetch labels"))?;
 
     let assignees = sqlx::query_as!(
         DisplayUser,
@@ -256,7 +611,7 @@ async fn get_full_issue(state: &AppState, repo_name: String, issue_id: i32, user
     )
     .fetch_all(&state.pool)
     .await
-    .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, format!("Failed to fetch assignees: {}", e)))?;
+    .map_err(|e| db::error(e, "Failed to fetch assignees"))?;
 
     let author = sqlx::query_as!(
         DisplayUser,
@@ -265,9 +620,28 @@ async fn get_full_issue(state: &AppState, repo_name: String, issue_id: i32, user
     )
     .fetch_one(&state.pool)
     .await
-    .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, format!("Failed to fetch author: {}", e)))?;
+    .map_err(|e| db::error(e, "Failed to fetch author"))?;
+
+    let contact = match user_id {
+        Some(user_id) => sqlx::query_scalar!(
+            "SELECT i.contact FROM issues i JOIN repositories r ON i.repo_id = r.id WHERE i.id = $1 AND (r.user_id = $2 OR EXISTS (SELECT 1 FROM repo_collaborators c WHERE c.repo_id = r.id AND c.user_id = $2 AND c.role <> 'read'))",
+            issue.id,
+            user_id
+        )
+        .fetch_optional(&state.pool)
+        .await
+        .map_err(|e| db::error(e, "Failed to fetch contact"))?
+        .flatten(),
+        None => None,
+    };
+
+    let reactions = reactions::reaction_counts(&state.pool, &[issue.id])
+        .await
+        .map_err(|e| db::error(e, "Failed to count reactions"))?
+        .remove(&issue.id)
+        .unwrap_or_default();
 
-    Ok((StatusCode::OK, FullIssue { issue, labels, assignees, author }))
+    Ok((StatusCode::OK, FullIssue { issue, labels, assignees, author, reactions, contact, body_html: None }))
 }
 
 #[axum::debug_handler]
@@ -275,45 +649,154 @@ pub async fn list_issues(
     State(state): State<AppState>,
     PermissiveAuthUser(user): PermissiveAuthUser,
     Path(repo_name): Path<String>,
+    Query(query): Query<IssueListQuery>,
+    pagination: Pagination,
 ) -> Result<impl IntoResponse, (StatusCode, String)> {
     let user_id = user.map(|u| u.id);
+    let repo = find_visible_repo(&state.pool, &repo_name, user_id).await?;
+    let repo_id = repo.id;
 
-    let issues = sqlx::query_as!(
-        Issue,
-        r#"
-        SELECT i.id, i.repo_id, i.title, i.body, i.author_id, i.status, i.created_at
-        FROM issues i
-        JOIN repositories r ON i.repo_id = r.id
-        WHERE r.name = $1 AND (r.public OR r.user_id = $2)
-        "#,
-        repo_name,
-        user_id,
-    )
+    let mut labels: Vec<String> = query
+        .label
+        .as_deref()
+        .unwrap_or("")
+        .split(',')
+        .map(str::trim)
+        .filter(|label| !label.is_empty())
+        .map(str::to_string)
+        .collect();
+    // The filter compares the number of distinct matching labels with the
+    // number asked for, so a repeated label must only be counted once.
+    labels.sort();
+    labels.dedup();
+    let status = query.status.map(IssueStatus::as_str);
+
+    let total: i64 = sqlx::query_scalar(&format!("SELECT COUNT(*) {}", ISSUE_FILTERS))
+        .bind(repo_id)
+        .bind(query.since)
+        .bind(status)
+        .bind(&labels)
+        .bind(&query.author)
+        .bind(&query.assignee)
+        .fetch_one(&state.pool)
+        .await
+        .map_err(|e| db::error(e, "Failed to count issues"))?;
+
+    let direction = query.direction.as_sql();
+    let mut order = Vec::new();
+    if query.since.is_some() {
+        order.push("i.updated_at".to_string());
+    }
+    if matches!(query.sort, IssueSort::Position) {
+        order.push(format!("i.position {} NULLS LAST", direction));
+    }
+    order.push(format!("i.id {}", direction));
+
+    let issues = sqlx::query_as::<_, Issue>(&format!(
+        "SELECT i.id, i.number, i.repo_id, i.title, i.body, i.author_id, i.status, i.created_at, i.position, i.updated_at, i.closed_at {} ORDER BY {} LIMIT $7 OFFSET $8",
+        ISSUE_FILTERS,
+        order.join(", "),
+    ))
+    .bind(repo_id)
+    .bind(query.since)
+    .bind(status)
+    .bind(&labels)
+    .bind(&query.author)
+    .bind(&query.assignee)
+    .bind(pagination.limit())
+    .bind(pagination.offset())
     .fetch_all(&state.pool)
     .await
-    .map_err(|e| {
-        (
-            StatusCode::INTERNAL_SERVER_ERROR,
-            format!("Failed to list issues: {}", e),
-        )
-    })?;
+    .map_err(|e| db::error(e, "Failed to list issues"))?;
+
+    let full_issues = with_details(&state.pool, issues, repo.can_write(user_id)).await?;
 
-    let mut full_issues = Vec::new();
-    for issue in issues {
-        let (_, full_issue) = get_full_issue(&state, repo_name.clone(), issue.id, user_id).await?;
-        full_issues.push(full_issue);
+    Ok(pagination.respond(full_issues, total))
+}
+
+// Fills in the labels, assignees, authors, reactions and, for `show_contact`,
+// the reporter contacts of `issues` with one query each rather than one per
+// issue.
+async fn with_details(pool: &sqlx::PgPool, issues: Vec<Issue>, show_contact: bool) -> Result<Vec<FullIssue>, (StatusCode, String)> {
+    let issue_ids: Vec<i32> = issues.iter().map(|issue| issue.id).collect();
+    let author_ids: Vec<i32> = issues.iter().map(|issue| issue.author_id).collect();
+
+    let mut labels: HashMap<i32, Vec<Label>> = HashMap::new();
+    for row in sqlx::query!(
+        "SELECT il.issue_id, l.id, l.repo_id, l.name, l.color FROM labels l JOIN issue_labels il ON l.id = il.label_id WHERE il.issue_id = ANY($1)",
+        &issue_ids
+    )
+    .fetch_all(pool)
+    .await
+    .map_err(|e| db::error(e, "Failed to fetch labels"))?
+    {
+        labels.entry(row.issue_id).or_default().push(Label { id: row.id, repo_id: row.repo_id, name: row.name, color: row.color });
     }
-    
-    Ok(Json(full_issues))
+
+    let mut assignees: HashMap<i32, Vec<DisplayUser>> = HashMap::new();
+    for row in sqlx::query!(
+        "SELECT ia.issue_id, u.id, u.username FROM users u JOIN issue_assignees ia ON u.id = ia.user_id WHERE ia.issue_id = ANY($1)",
+        &issue_ids
+    )
+    .fetch_all(pool)
+    .await
+    .map_err(|e| db::error(e, "Failed to fetch assignees"))?
+    {
+        assignees.entry(row.issue_id).or_default().push(DisplayUser { id: row.id, username: row.username });
+    }
+
+    let authors: HashMap<i32, DisplayUser> = sqlx::query_as!(DisplayUser, "SELECT id, username FROM users WHERE id = ANY($1)", &author_ids)
+        .fetch_all(pool)
+        .await
+        .map_err(|e| db::error(e, "Failed to fetch authors"))?
+        .into_iter()
+        .map(|author| (author.id, author))
+        .collect();
+
+    let mut reactions = reactions::reaction_counts(pool, &issue_ids)
+        .await
+        .map_err(|e| db::error(e, "Failed to count reactions"))?;
+
+    let mut contacts: HashMap<i32, String> = HashMap::new();
+    if show_contact {
+        for row in sqlx::query!("SELECT id, contact AS \"contact!\" FROM issues WHERE id = ANY($1) AND contact IS NOT NULL", &issue_ids)
+            .fetch_all(pool)
+            .await
+            .map_err(|e| db::error(e, "Failed to fetch contacts"))?
+        {
+            contacts.insert(row.id, row.contact);
+        }
+    }
+
+    issues
+        .into_iter()
+        .map(|issue| {
+            let author = authors
+                .get(&issue.author_id)
+                .cloned()
+                .ok_or_else(|| (StatusCode::INTERNAL_SERVER_ERROR, "Failed to fetch author".to_string()))?;
+            Ok(FullIssue {
+                labels: labels.remove(&issue.id).unwrap_or_default(),
+                assignees: assignees.remove(&issue.id).unwrap_or_default(),
+                reactions: reactions.remove(&issue.id).unwrap_or_default(),
+                contact: contacts.remove(&issue.id),
+                author,
+                body_html: None,
+                issue,
+            })
+        })
+        .collect()
 }
 
 #[axum::debug_handler]
 pub async fn add_label_to_issue(
     State(state): State<AppState>,
     AuthUser(user): AuthUser,
-    Path((repo_name, issue_id, label_name)): Path<(String, i32, String)>,
+    Path((repo_name, issue_number, label_name)): Path<(String, i32, String)>,
 ) -> Result<impl IntoResponse, (StatusCode, String)> {
-    let mut tx = state.pool.begin().await.map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, format!("Failed to start transaction: {}", e)))?;
+    user.require_scope(Scope::IssuesWrite)?;
+    let mut tx = state.pool.begin().await.map_err(|e| db::error(e, "Failed to start transaction"))?;
+    find_writable_repo(&mut *tx, &repo_name, user.id, "label issues in").await?;
     
     let issue_repo_label = sqlx::query!(
         r#"
@@ -324,16 +807,16 @@ pub async fn add_label_to_issue(
         FROM issues i
         JOIN repositories r ON i.repo_id = r.id
         CROSS JOIN labels l
-        WHERE r.name = $1 AND i.id = $2 AND l.name = $3 AND l.repo_id = r.id AND (r.public OR r.user_id = $4)
+        WHERE r.name = $1 AND i.number = $2 AND l.name = $3 AND l.repo_id = r.id AND (r.public OR r.user_id = $4 OR EXISTS (SELECT 1 FROM repo_collaborators c WHERE c.repo_id = r.id AND c.user_id = $4))
         "#,
         repo_name,
-        issue_id,
+        issue_number,
         label_name,
         user.id
     )
     .fetch_optional(&mut *tx)
     .await
-    .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, format!("Failed to validate resources: {}", e)))?
+    .map_err(|e| db::error(e, "Failed to validate resources"))?
     .ok_or((StatusCode::NOT_FOUND, "Issue, repository, or label not found, or you don't have permission.".to_string()))?;
 
     sqlx::query!(
@@ -343,9 +826,11 @@ pub async fn add_label_to_issue(
     )
     .execute(&mut *tx)
     .await
-    .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, format!("Failed to add label to issue: {}", e)))?;
+    .map_err(|e| db::error(e, "Failed to add label to issue"))?;
+
+    touch_issues(&mut tx, &[issue_repo_label.issue_id]).await?;
 
-    tx.commit().await.map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, format!("Failed to commit transaction: {}", e)))?;
+    tx.commit().await.map_err(|e| db::error(e, "Failed to commit transaction"))?;
 
     Ok(StatusCode::OK)
 }
@@ -354,9 +839,11 @@ pub async fn add_label_to_issue(
 pub async fn remove_label_from_issue(
     State(state): State<AppState>,
     AuthUser(user): AuthUser,
-    Path((repo_name, issue_id, label_name)): Path<(String, i32, String)>,
+    Path((repo_name, issue_number, label_name)): Path<(String, i32, String)>,
 ) -> Result<impl IntoResponse, (StatusCode, String)> {
-    let mut tx = state.pool.begin().await.map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, format!("Failed to start transaction: {}", e)))?;
+    user.require_scope(Scope::IssuesWrite)?;
+    let mut tx = state.pool.begin().await.map_err(|e| db::error(e, "Failed to start transaction"))?;
+    find_writable_repo(&mut *tx, &repo_name, user.id, "label issues in").await?;
     
     let issue_repo_label = sqlx::query!(
         r#"
@@ -366,16 +853,16 @@ pub async fn remove_label_from_issue(
         FROM issues i
         JOIN repositories r ON i.repo_id = r.id
         CROSS JOIN labels l
-        WHERE r.name = $1 AND i.id = $2 AND l.name = $3 AND l.repo_id = r.id AND (r.public OR r.user_id = $4)
+        WHERE r.name = $1 AND i.number = $2 AND l.name = $3 AND l.repo_id = r.id AND (r.public OR r.user_id = $4 OR EXISTS (SELECT 1 FROM repo_collaborators c WHERE c.repo_id = r.id AND c.user_id = $4))
         "#,
         repo_name,
-        issue_id,
+        issue_number,
         label_name,
         user.id
     )
     .fetch_optional(&mut *tx)
     .await
-    .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, format!("Failed to validate resources: {}", e)))?
+    .map_err(|e| db::error(e, "Failed to validate resources"))?
     .ok_or((StatusCode::NOT_FOUND, "Issue, repository, or label not found, or you don't have permission.".to_string()))?;
 
     sqlx::query!(
@@ -385,9 +872,11 @@ pub async fn remove_label_from_issue(
     )
     .execute(&mut *tx)
     .await
-    .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, format!("Failed to remove label from issue: {}", e)))?;
+    .map_err(|e| db::error(e, "Failed to remove label from issue"))?;
 
-    tx.commit().await.map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, format!("Failed to commit transaction: {}", e)))?;
+    touch_issues(&mut tx, &[issue_repo_label.issue_id]).await?;
+
+    tx.commit().await.map_err(|e| db::error(e, "Failed to commit transaction"))?;
 
     Ok(StatusCode::OK)
 }
@@ -396,9 +885,11 @@ pub async fn remove_label_from_issue(
 pub async fn add_assignee_to_issue(
     State(state): State<AppState>,
     AuthUser(user): AuthUser,
-    Path((repo_name, issue_id, assignee_username)): Path<(String, i32, String)>,
+    Path((repo_name, issue_number, assignee_username)): Path<(String, i32, String)>,
 ) -> Result<impl IntoResponse, (StatusCode, String)> {
-    let mut tx = state.pool.begin().await.map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, format!("Failed to start transaction: {}", e)))?;
+    user.require_scope(Scope::IssuesWrite)?;
+    let mut tx = state.pool.begin().await.map_err(|e| db::error(e, "Failed to start transaction"))?;
+    find_writable_repo(&mut *tx, &repo_name, user.id, "assign issues in").await?;
     
     let issue_repo_assignee = sqlx::query!(
         r#"
@@ -408,16 +899,16 @@ pub async fn add_assignee_to_issue(
         FROM issues i
         JOIN repositories r ON i.repo_id = r.id
         CROSS JOIN users u
-        WHERE r.name = $1 AND i.id = $2 AND u.username = $3 AND (r.public OR r.user_id = $4)
+        WHERE r.name = $1 AND i.number = $2 AND u.username = $3 AND (r.public OR r.user_id = $4 OR EXISTS (SELECT 1 FROM repo_collaborators c WHERE c.repo_id = r.id AND c.user_id = $4))
         "#,
         repo_name,
-        issue_id,
+        issue_number,
         assignee_username,
         user.id,
     )
     .fetch_optional(&mut *tx)
     .await
-    .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, format!("Failed to validate resources: {}", e)))?
+    .map_err(|e| db::error(e, "Failed to validate resources"))?
     .ok_or((StatusCode::NOT_FOUND, "Issue, repository, or user to assign not found, or you don't have permission.".to_string()))?;
 
     sqlx::query!(
@@ -427,9 +918,11 @@ pub async fn add_assignee_to_issue(
     )
     .execute(&mut *tx)
     .await
-    .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, format!("Failed to add assignee to issue: {}", e)))?;
+    .map_err(|e| db::error(e, "Failed to add assignee to issue"))?;
+
+    touch_issues(&mut tx, &[issue_repo_assignee.issue_id]).await?;
 
-    tx.commit().await.map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, format!("Failed to commit transaction: {}", e)))?;
+    tx.commit().await.map_err(|e| db::error(e, "Failed to commit transaction"))?;
 
     Ok(StatusCode::OK)
 }
@@ -438,9 +931,11 @@ pub async fn add_assignee_to_issue(
 pub async fn remove_assignee_from_issue(
     State(state): State<AppState>,
     AuthUser(user): AuthUser,
-    Path((repo_name, issue_id, assignee_username)): Path<(String, i32, String)>,
+    Path((repo_name, issue_number, assignee_username)): Path<(String, i32, String)>,
 ) -> Result<impl IntoResponse, (StatusCode, String)> {
-    let mut tx = state.pool.begin().await.map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, format!("Failed to start transaction: {}", e)))?;
+    user.require_scope(Scope::IssuesWrite)?;
+    let mut tx = state.pool.begin().await.map_err(|e| db::error(e, "Failed to start transaction"))?;
+    find_writable_repo(&mut *tx, &repo_name, user.id, "assign issues in").await?;
     
     let issue_repo_assignee = sqlx::query!(
         r#"
@@ -450,16 +945,16 @@ pub async fn remove_assignee_from_issue(
         FROM issues i
         JOIN repositories r ON i.repo_id = r.id
         CROSS JOIN users u
-        WHERE r.name = $1 AND i.id = $2 AND u.username = $3 AND (r.public OR r.user_id = $4)
+        WHERE r.name = $1 AND i.number = $2 AND u.username = $3 AND (r.public OR r.user_id = $4 OR EXISTS (SELECT 1 FROM repo_collaborators c WHERE c.repo_id = r.id AND c.user_id = $4))
         "#,
         repo_name,
-        issue_id,
+        issue_number,
         assignee_username,
         user.id
     )
     .fetch_optional(&mut *tx)
     .await
-    .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, format!("Failed to validate resources: {}", e)))?
+    .map_err(|e| db::error(e, "Failed to validate resources"))?
     .ok_or((StatusCode::NOT_FOUND, "Issue, repository, or user to remove not found, or you don't have permission.".to_string()))?;
 
     sqlx::query!(
@@ -469,101 +964,512 @@ pub async fn remove_assignee_from_issue(
     )
     .execute(&mut *tx)
     .await
-    .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, format!("Failed to remove assignee from issue: {}", e)))?;
+    .map_err(|e| db::error(e, "Failed to remove assignee from issue"))?;
+
+    touch_issues(&mut tx, &[issue_repo_assignee.issue_id]).await?;
 
-    tx.commit().await.map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, format!("Failed to commit transaction: {}", e)))?;
+    tx.commit().await.map_err(|e| db::error(e, "Failed to commit transaction"))?;
 
     Ok(StatusCode::OK)
 }
 
+// Bumps `updated_at` for changes that live outside the issues row, so
+// `?since=` polling picks them up.
+async fn touch_issues(conn: &mut PgConnection, ids: &[i32]) -> Result<(), (StatusCode, String)> {
+    sqlx::query!("UPDATE issues SET updated_at = NOW() WHERE id = ANY($1)", ids)
+        .execute(conn)
+        .await
+        .map_err(|e| db::error(e, "Failed to update issues"))?;
+    Ok(())
+}
 
 #[axum::debug_handler]
 pub async fn create_comment(
     State(state): State<AppState>,
     AuthUser(user): AuthUser,
-    Path((repo_name, issue_id)): Path<(String, i32)>,
-    Json(new_comment): Json<NewComment>,
+    Path((repo_name, issue_number)): Path<(String, i32)>,
+    ValidatedJson(new_comment): ValidatedJson<NewComment>,
 ) -> Result<impl IntoResponse, (StatusCode, String)> {
-    let comment_result = sqlx::query_as!(
+    user.require_scope(Scope::IssuesWrite)?;
+    let repo = find_visible_repo(&state.pool, &repo_name, Some(user.id)).await?;
+    let issue = sqlx::query!("SELECT id, author_id FROM issues WHERE repo_id = $1 AND number = $2", repo.id, issue_number)
+        .fetch_optional(&state.pool)
+        .await
+        .map_err(|e| db::error(e, "Failed to fetch issue"))?
+        .ok_or_else(|| (StatusCode::NOT_FOUND, "Issue not found".to_string()))?;
+    // Besides those who can triage, an issue's author may follow up on it.
+    if issue.author_id != user.id && !repo.can_write(Some(user.id)) {
+        return Err((StatusCode::FORBIDDEN, "You do not have permission to comment on this issue".to_string()));
+    }
+
+    let comment = sqlx::query_as!(
         IssueComment,
         r#"
-        WITH issue_repo AS (
-            SELECT repo_id FROM issues WHERE id = $1
-        ), repo_access AS (
-            SELECT r.id
-            FROM repositories r
-            JOIN issue_repo ir ON r.id = ir.repo_id
-            WHERE r.name = $4 AND (r.public OR r.user_id = $3)
+        WITH touched AS (
+            UPDATE issues SET updated_at = NOW() WHERE id = $1
         )
         INSERT INTO issue_comments (issue_id, body, author_id)
-        SELECT $1, $2, $3
-        FROM repo_access
-        RETURNING id, issue_id, body, author_id, created_at
+        VALUES ($1, $2, $3)
+        RETURNING id, issue_id, body, author_id, created_at, edited_at, deleted_at, deleted_by
         "#,
-        issue_id,
+        issue.id,
         new_comment.body,
         user.id,
-        repo_name
     )
     .fetch_one(&state.pool)
-    .await;
+    .await
+    .map_err(|e| db::error(e, "Failed to create comment"))?;
 
-    match comment_result {
-        Ok(comment) => Ok((StatusCode::CREATED, Json(comment))),
-        Err(sqlx::Error::RowNotFound) => Err((
-            StatusCode::FORBIDDEN,
-            "Issue not found, repository not found, or you don't have permission to comment.".to_string(),
-        )),
-        Err(e) => Err((
-            StatusCode::INTERNAL_SERVER_ERROR,
-            format!("Failed to create comment: {}", e),
-        )),
-    }
+    Ok((StatusCode::CREATED, Json(comment)))
 }
 
 #[axum::debug_handler]
 pub async fn list_comments(
     State(state): State<AppState>,
     PermissiveAuthUser(user): PermissiveAuthUser,
-    Path((repo_name, issue_id)): Path<(String, i32)>,
+    Path((repo_name, issue_number)): Path<(String, i32)>,
 ) -> Result<impl IntoResponse, (StatusCode, String)> {
     let user_id = user.map(|u| u.id);
 
-    let repo_exists_and_has_access = sqlx::query!(
-        "SELECT EXISTS(SELECT 1 FROM repositories WHERE name = $1 AND (public OR user_id = $2))",
-        repo_name,
-        user_id
-    )
-    .fetch_one(&state.pool)
-    .await
-    .map(|r| r.exists.unwrap_or(false))
-    .unwrap_or(false);
-
-    if !repo_exists_and_has_access {
-        return Err((StatusCode::NOT_FOUND, "Repository not found or you don't have permission to view it.".to_string()));
-    }
+    find_visible_repo(&state.pool, &repo_name, user_id).await?;
 
     let comments = sqlx::query_as!(
         IssueComment,
         r#"
-        SELECT ic.id, ic.issue_id, ic.body, ic.author_id, ic.created_at
+        SELECT ic.id, ic.issue_id, ic.body, ic.author_id, ic.created_at, ic.edited_at, ic.deleted_at, ic.deleted_by
         FROM issue_comments ic
         JOIN issues i ON ic.issue_id = i.id
         JOIN repositories r ON i.repo_id = r.id
-        WHERE r.name = $1 AND i.id = $2 AND (r.public OR r.user_id = $3)
+        WHERE r.name = $1 AND i.number = $2 AND (r.public OR r.user_id = $3 OR EXISTS (SELECT 1 FROM repo_collaborators c WHERE c.repo_id = r.id AND c.user_id = $3))
+        ORDER BY ic.id
         "#,
         repo_name,
-        issue_id,
+        issue_number,
         user_id,
     )
     .fetch_all(&state.pool)
     .await
-    .map_err(|e| {
-        (
-            StatusCode::INTERNAL_SERVER_ERROR,
-            format!("Failed to list comments: {}", e),
-        )
-    })?;
+    .map_err(|e| db::error(e, "Failed to list comments"))?;
 
     Ok(Json(comments))
 }
+
+struct ManagedComment {
+    issue_id: i32,
+    deleted: bool,
+}
+
+// Comments are managed by their author and anyone with write access, who
+// moderate them. `action` completes the `403` message.
+async fn find_comment_to_manage(
+    conn: &mut PgConnection,
+    repo_name: &str,
+    issue_number: i32,
+    comment_id: i32,
+    user_id: i32,
+    action: &str,
+) -> Result<ManagedComment, (StatusCode, String)> {
+    let repo = find_visible_repo(&mut *conn, repo_name, Some(user_id)).await?;
+
+    let comment = sqlx::query!(
+        "SELECT ic.author_id, ic.issue_id, ic.deleted_at FROM issue_comments ic JOIN issues i ON ic.issue_id = i.id WHERE ic.id = $1 AND i.number = $2 AND i.repo_id = $3",
+        comment_id,
+        issue_number,
+        repo.id,
+    )
+    .fetch_optional(&mut *conn)
+    .await
+    .map_err(|e| db::error(e, "Failed to fetch comment"))?
+    .ok_or_else(|| (StatusCode::NOT_FOUND, "Comment not found".to_string()))?;
+
+    if comment.author_id != user_id && !repo.can_write(Some(user_id)) {
+        return Err((StatusCode::FORBIDDEN, format!("Only the comment's author or someone with write access can {} it", action)));
+    }
+    Ok(ManagedComment { issue_id: comment.issue_id, deleted: comment.deleted_at.is_some() })
+}
+
+// Replaces the comment's body and stamps `edited_at`. Deleted comments stay
+// deleted.
+#[axum::debug_handler]
+pub async fn update_comment(
+    State(state): State<AppState>,
+    AuthUser(user): AuthUser,
+    Path((repo_name, issue_number, comment_id)): Path<(String, i32, i32)>,
+    ValidatedJson(edit): ValidatedJson<NewComment>,
+) -> Result<impl IntoResponse, (StatusCode, String)> {
+    user.require_scope(Scope::IssuesWrite)?;
+    let mut tx = state.pool.begin().await.map_err(|e| db::error(e, "Failed to start transaction"))?;
+
+    let comment = find_comment_to_manage(&mut tx, &repo_name, issue_number, comment_id, user.id, "edit").await?;
+    if comment.deleted {
+        return Err((StatusCode::CONFLICT, "Deleted comments cannot be edited".to_string()));
+    }
+
+    let updated = sqlx::query_as!(
+        IssueComment,
+        r#"
+        UPDATE issue_comments SET body = $2, edited_at = NOW()
+        WHERE id = $1
+        RETURNING id, issue_id, body, author_id, created_at, edited_at, deleted_at, deleted_by
+        "#,
+        comment_id,
+        edit.body,
+    )
+    .fetch_one(&mut *tx)
+    .await
+    .map_err(|e| db::error(e, "Failed to update comment"))?;
+
+    touch_issues(&mut tx, &[comment.issue_id]).await?;
+
+    tx.commit().await.map_err(|e| db::error(e, "Failed to commit transaction"))?;
+
+    Ok(Json(updated))
+}
+
+// Tombstones the comment rather than deleting the row.
+#[axum::debug_handler]
+pub async fn delete_comment(
+    State(state): State<AppState>,
+    AuthUser(user): AuthUser,
+    Path((repo_name, issue_number, comment_id)): Path<(String, i32, i32)>,
+) -> Result<impl IntoResponse, (StatusCode, String)> {
+    user.require_scope(Scope::IssuesWrite)?;
+    let mut tx = state.pool.begin().await.map_err(|e| db::error(e, "Failed to start transaction"))?;
+
+    let comment = find_comment_to_manage(&mut tx, &repo_name, issue_number, comment_id, user.id, "delete").await?;
+
+    sqlx::query!(
+        "UPDATE issue_comments SET body = $2, deleted_at = NOW(), deleted_by = $3 WHERE id = $1 AND deleted_at IS NULL",
+        comment_id,
+        DELETED_COMMENT_BODY,
+        user.id,
+    )
+    .execute(&mut *tx)
+    .await
+    .map_err(|e| db::error(e, "Failed to delete comment"))?;
+
+    touch_issues(&mut tx, &[comment.issue_id]).await?;
+
+    tx.commit().await.map_err(|e| db::error(e, "Failed to commit transaction"))?;
+
+    Ok(StatusCode::NO_CONTENT)
+}
+
+#[axum::debug_handler]
+pub async fn bulk_update_issues(
+    State(state): State<AppState>,
+    AuthUser(user): AuthUser,
+    Path(repo_name): Path<String>,
+    ValidatedJson(update): ValidatedJson<BulkIssueUpdate>,
+) -> Result<impl IntoResponse, (StatusCode, String)> {
+    user.require_scope(Scope::IssuesWrite)?;
+    let mut tx = state.pool.begin().await.map_err(|e| db::error(e, "Failed to start transaction"))?;
+
+    let repo = find_writable_repo(&mut *tx, &repo_name, user.id, "bulk update issues in").await?;
+
+    let label_id = match (update.action, update.label.as_deref()) {
+        (BulkIssueAction::AddLabel | BulkIssueAction::RemoveLabel, Some(label_name)) => {
+            let label = sqlx::query!("SELECT id FROM labels WHERE repo_id = $1 AND name = $2", repo.id, label_name)
+                .fetch_optional(&mut *tx)
+                .await
+                .map_err(|e| db::error(e, "Failed to find label"))?
+                .ok_or((StatusCode::UNPROCESSABLE_ENTITY, format!("Label '{}' does not exist in this repository.", label_name)))?;
+            Some(label.id)
+        }
+        _ => None,
+    };
+
+    let (found, found_numbers): (Vec<i32>, Vec<i32>) = sqlx::query!("SELECT id, number FROM issues WHERE repo_id = $1 AND number = ANY($2)", repo.id, &update.numbers)
+        .fetch_all(&mut *tx)
+        .await
+        .map_err(|e| db::error(e, "Failed to find issues"))?
+        .into_iter()
+        .map(|issue| (issue.id, issue.number))
+        .unzip();
+
+    match (update.action, label_id) {
+        (BulkIssueAction::Close, _) | (BulkIssueAction::Reopen, _) => {
+            let status = if update.action == BulkIssueAction::Close { "closed" } else { "open" };
+            sqlx::query!(
+                r#"
+                UPDATE issues
+                SET status = $1,
+                    closed_at = CASE WHEN $1::varchar = 'open' THEN NULL WHEN status <> 'closed' THEN NOW() ELSE closed_at END,
+                    updated_at = NOW()
+                WHERE id = ANY($2)
+                "#,
+                status,
+                &found
+            )
+                .execute(&mut *tx)
+                .await
+                .map_err(|e| db::error(e, "Failed to update issues"))?;
+        }
+        (BulkIssueAction::AddLabel, Some(label_id)) => {
+            sqlx::query!(
+                "INSERT INTO issue_labels (issue_id, label_id) SELECT unnest($1::int[]), $2 ON CONFLICT DO NOTHING",
+                &found,
+                label_id
+            )
+            .execute(&mut *tx)
+            .await
+            .map_err(|e| db::error(e, "Failed to add label to issues"))?;
+            touch_issues(&mut tx, &found).await?;
+        }
+        (BulkIssueAction::RemoveLabel, Some(label_id)) => {
+            sqlx::query!("DELETE FROM issue_labels WHERE label_id = $1 AND issue_id = ANY($2)", label_id, &found)
+                .execute(&mut *tx)
+                .await
+                .map_err(|e| db::error(e, "Failed to remove label from issues"))?;
+            touch_issues(&mut tx, &found).await?;
+        }
+        _ => {}
+    }
+
+    tx.commit().await.map_err(|e| db::error(e, "Failed to commit transaction"))?;
+
+    let results: Vec<BulkIssueResult> = update
+        .numbers
+        .iter()
+        .map(|&number| {
+            let success = found_numbers.contains(&number);
+            BulkIssueResult {
+                number,
+                success,
+                error: (!success).then(|| "Issue not found in this repository.".to_string()),
+            }
+        })
+        .collect();
+
+    Ok(Json(results))
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::http::StatusCode;
+    use serde_json::json;
+    use sqlx::PgPool;
+
+    use crate::test_support::TestApp;
+
+    // Opens an issue and returns its number.
+    async fn open_issue(app: &TestApp, token: &str, repo: &str, body: serde_json::Value) -> i64 {
+        let response = app.post(&format!("/repos/{}/issues", repo), Some(token), body).await;
+        assert_eq!(response.status, StatusCode::CREATED, "{}", response.body);
+        response.body["number"].as_i64().unwrap()
+    }
+
+    // The numbers of the issues a list request returns, in list order.
+    async fn ordered_numbers(app: &TestApp, uri: &str) -> Vec<i64> {
+        let response = app.get(uri, None).await;
+        assert_eq!(response.status, StatusCode::OK, "{}", response.body);
+        response.body.as_array().unwrap().iter().map(|issue| issue["number"].as_i64().unwrap()).collect()
+    }
+
+    // The numbers of the issues a list request returns, in ascending order.
+    async fn listed_numbers(app: &TestApp, uri: &str) -> Vec<i64> {
+        let mut numbers = ordered_numbers(app, uri).await;
+        numbers.sort();
+        numbers
+    }
+
+    #[sqlx::test]
+    async fn concurrent_issues_get_distinct_numbers(pool: PgPool) {
+        let mut app = TestApp::new(pool);
+        let owner = app.sign_up("owner").await;
+        let repo = app.create_repo(&owner, true).await;
+
+        let uri = format!("/repos/{}/issues", repo);
+        let responses = futures_util::future::join_all((0..10).map(|i| app.post(&uri, Some(&owner), json!({ "title": format!("Issue {}", i) })))).await;
+        let mut numbers: Vec<i64> = responses
+            .iter()
+            .map(|response| {
+                assert_eq!(response.status, StatusCode::CREATED, "{}", response.body);
+                response.body["number"].as_i64().unwrap()
+            })
+            .collect();
+        numbers.sort();
+        assert_eq!(numbers, (1..=10).collect::<Vec<_>>());
+    }
+
+    #[sqlx::test]
+    async fn closing_an_issue_records_when(pool: PgPool) {
+        let mut app = TestApp::new(pool);
+        let owner = app.sign_up("owner").await;
+        let repo = app.create_repo(&owner, true).await;
+        let number = open_issue(&app, &owner, &repo, json!({ "title": "Broken" })).await;
+        let uri = format!("/repos/{}/issues/{}", repo, number);
+        assert!(app.get(&uri, None).await.body["closed_at"].is_null());
+
+        let response = app.patch(&uri, Some(&owner), json!({ "status": "closed" })).await;
+        assert_eq!(response.status, StatusCode::OK, "{}", response.body);
+        assert_eq!(response.body["status"], "closed");
+        let closed_at = response.body["closed_at"].clone();
+        assert!(closed_at.is_string(), "{}", response.body);
+        assert_eq!(app.get(&uri, None).await.body["closed_at"], closed_at);
+
+        let response = app.patch(&uri, Some(&owner), json!({ "status": "open" })).await;
+        assert_eq!(response.status, StatusCode::OK, "{}", response.body);
+        assert!(response.body["closed_at"].is_null());
+    }
+
+    #[sqlx::test]
+    async fn listed_issues_match_the_issue_endpoint(pool: PgPool) {
+        let mut app = TestApp::new(pool);
+        let owner = app.sign_up("owner").await;
+        let repo = app.create_repo(&owner, true).await;
+        let helper = app.sign_up("helper").await;
+        app.add_collaborator(&owner, &repo, "helper", "write").await;
+        let response = app.post(&format!("/repos/{}/labels", repo), Some(&owner), json!({ "name": "bug", "color": "ff0000" })).await;
+        assert_eq!(response.status, StatusCode::CREATED, "{}", response.body);
+
+        open_issue(&app, &owner, &repo, json!({ "title": "Labelled", "labels": ["bug"] })).await;
+        open_issue(&app, &helper, &repo, json!({ "title": "Assigned", "assignees": ["owner", "helper"] })).await;
+        open_issue(&app, &owner, &repo, json!({ "title": "Both", "labels": ["bug"], "assignees": ["helper"] })).await;
+        open_issue(&app, &owner, &repo, json!({ "title": "Plain" })).await;
+
+        for token in [Some(owner.as_str()), None] {
+            let listed = app.get(&format!("/repos/{}/issues", repo), token).await;
+            assert_eq!(listed.status, StatusCode::OK, "{}", listed.body);
+            let listed = listed.body.as_array().unwrap();
+            assert_eq!(listed.len(), 4);
+            let filled = |issue: &serde_json::Value, field: &str| !issue[field].as_array().unwrap().is_empty();
+            assert!(listed.iter().any(|issue| filled(issue, "labels") && filled(issue, "assignees")));
+            for issue in listed {
+                let single = app.get(&format!("/repos/{}/issues/{}", repo, issue["number"]), token).await;
+                assert_eq!(single.status, StatusCode::OK, "{}", single.body);
+                assert_eq!(issue, &single.body);
+            }
+        }
+    }
+
+    #[sqlx::test]
+    async fn issues_filter_by_status_author_and_assignee(pool: PgPool) {
+        let mut app = TestApp::new(pool);
+        let owner = app.sign_up("owner").await;
+        let repo = app.create_repo(&owner, true).await;
+        let helper = app.sign_up("helper").await;
+        app.add_collaborator(&owner, &repo, "helper", "write").await;
+
+        let mine = open_issue(&app, &owner, &repo, json!({ "title": "Mine" })).await;
+        let theirs = open_issue(&app, &helper, &repo, json!({ "title": "Theirs", "assignees": ["owner"] })).await;
+        let closed = open_issue(&app, &helper, &repo, json!({ "title": "Done", "assignees": ["helper"] })).await;
+        let response = app.patch(&format!("/repos/{}/issues/{}", repo, closed), Some(&owner), json!({ "status": "closed" })).await;
+        assert_eq!(response.status, StatusCode::OK, "{}", response.body);
+
+        let list = |query: &str| format!("/repos/{}/issues?{}", repo, query);
+        assert_eq!(listed_numbers(&app, &list("status=open")).await, vec![mine, theirs]);
+        assert_eq!(listed_numbers(&app, &list("status=closed")).await, vec![closed]);
+        assert_eq!(listed_numbers(&app, &list("author=helper")).await, vec![theirs, closed]);
+        assert_eq!(listed_numbers(&app, &list("assignee=owner")).await, vec![theirs]);
+        assert_eq!(listed_numbers(&app, &list("assignee=helper")).await, vec![closed]);
+        assert!(listed_numbers(&app, &list("author=nobody")).await.is_empty());
+    }
+
+    #[sqlx::test]
+    async fn issues_sort_by_creation_or_position(pool: PgPool) {
+        let mut app = TestApp::new(pool);
+        let owner = app.sign_up("owner").await;
+        let repo = app.create_repo(&owner, true).await;
+        let mut numbers = Vec::new();
+        for title in ["First", "Second", "Third"] {
+            numbers.push(open_issue(&app, &owner, &repo, json!({ "title": title })).await);
+        }
+
+        let list = |query: &str| format!("/repos/{}/issues?{}", repo, query);
+        assert_eq!(ordered_numbers(&app, &list("sort=created")).await, numbers);
+        assert_eq!(ordered_numbers(&app, &list("sort=created&direction=desc")).await, numbers.iter().rev().copied().collect::<Vec<_>>());
+
+        let response = app.patch(&format!("/repos/{}/issues/{}/position", repo, numbers[2]), Some(&owner), json!({ "position": 1.0 })).await;
+        assert_eq!(response.status, StatusCode::OK, "{}", response.body);
+        assert_eq!(ordered_numbers(&app, &list("sort=position")).await, vec![numbers[2], numbers[0], numbers[1]]);
+    }
+
+    #[sqlx::test]
+    async fn issue_filters_combine(pool: PgPool) {
+        let mut app = TestApp::new(pool);
+        let owner = app.sign_up("owner").await;
+        let repo = app.create_repo(&owner, true).await;
+        let helper = app.sign_up("helper").await;
+        app.add_collaborator(&owner, &repo, "helper", "write").await;
+        let response = app.post(&format!("/repos/{}/labels", repo), Some(&owner), json!({ "name": "bug", "color": "ff0000" })).await;
+        assert_eq!(response.status, StatusCode::CREATED, "{}", response.body);
+
+        let wanted = open_issue(&app, &helper, &repo, json!({ "title": "Crash", "labels": ["bug"] })).await;
+        open_issue(&app, &owner, &repo, json!({ "title": "Other author", "labels": ["bug"] })).await;
+        open_issue(&app, &helper, &repo, json!({ "title": "Unlabelled" })).await;
+        let closed = open_issue(&app, &helper, &repo, json!({ "title": "Fixed crash", "labels": ["bug"] })).await;
+        let response = app.patch(&format!("/repos/{}/issues/{}", repo, closed), Some(&owner), json!({ "status": "closed" })).await;
+        assert_eq!(response.status, StatusCode::OK, "{}", response.body);
+
+        let uri = format!("/repos/{}/issues?status=open&label=bug&author=helper", repo);
+        assert_eq!(listed_numbers(&app, &uri).await, vec![wanted]);
+    }
+
+    #[sqlx::test]
+    async fn similar_issues_carry_their_number(pool: PgPool) {
+        let mut app = TestApp::new(pool);
+        let owner = app.sign_up("owner").await;
+        let repo = app.create_repo(&owner, true).await;
+        let other = app.create_repo(&owner, true).await;
+        // Issues in another repository take the low ids.
+        open_issue(&app, &owner, &other, json!({ "title": "Unrelated" })).await;
+        let number = open_issue(&app, &owner, &repo, json!({ "title": "Crash when saving files" })).await;
+
+        let similar = app.get(&format!("/repos/{}/issues/similar?title=crash%20when%20saving", repo), None).await;
+        assert_eq!(similar.status, StatusCode::OK, "{}", similar.body);
+        assert_eq!(similar.body[0]["number"], number);
+        let issue = app.get(&format!("/repos/{}/issues/{}", repo, similar.body[0]["number"]), None).await;
+        assert_eq!(issue.body["title"], "Crash when saving files");
+    }
+
+    #[sqlx::test]
+    async fn label_filters_require_every_label(pool: PgPool) {
+        let mut app = TestApp::new(pool);
+        let owner = app.sign_up("owner").await;
+        let repo = app.create_repo(&owner, true).await;
+        for label in ["bug", "ui"] {
+            let response = app.post(&format!("/repos/{}/labels", repo), Some(&owner), json!({ "name": label, "color": "ff0000" })).await;
+            assert_eq!(response.status, StatusCode::CREATED, "{}", response.body);
+        }
+        let bug = open_issue(&app, &owner, &repo, json!({ "title": "Crash", "labels": ["bug"] })).await;
+        let ui_bug = open_issue(&app, &owner, &repo, json!({ "title": "Button", "labels": ["bug", "ui"] })).await;
+        open_issue(&app, &owner, &repo, json!({ "title": "Idea" })).await;
+
+        let labeled = |labels: &str| format!("/repos/{}/issues?label={}", repo, labels);
+        assert_eq!(listed_numbers(&app, &labeled("bug")).await, vec![bug, ui_bug]);
+        assert_eq!(listed_numbers(&app, &labeled("bug,bug")).await, vec![bug, ui_bug]);
+        assert_eq!(listed_numbers(&app, &labeled("bug,ui")).await, vec![ui_bug]);
+        assert_eq!(listed_numbers(&app, &labeled("ui,bug,ui")).await, vec![ui_bug]);
+        assert!(listed_numbers(&app, &labeled("bug,missing")).await.is_empty());
+    }
+
+    #[sqlx::test]
+    async fn only_authors_and_writers_manage_comments(pool: PgPool) {
+        let mut app = TestApp::new(pool);
+        let owner = app.sign_up("owner").await;
+        let repo = app.create_repo(&owner, true).await;
+        let number = open_issue(&app, &owner, &repo, json!({ "title": "Broken" })).await;
+        let comments = format!("/{}/issues/{}/comments", repo, number);
+        let comment = app.post(&comments, Some(&owner), json!({ "body": "First" })).await;
+        assert_eq!(comment.status, StatusCode::CREATED, "{}", comment.body);
+        let comment_uri = format!("{}/{}", comments, comment.body["id"]);
+
+        let stranger = app.sign_up("stranger").await;
+        let response = app.patch(&comment_uri, Some(&stranger), json!({ "body": "Mine now" })).await;
+        assert_eq!(response.status, StatusCode::FORBIDDEN);
+        let response = app.delete(&comment_uri, Some(&stranger)).await;
+        assert_eq!(response.status, StatusCode::FORBIDDEN);
+
+        let missing = format!("{}/999999", comments);
+        let response = app.patch(&missing, Some(&owner), json!({ "body": "Edited" })).await;
+        assert_eq!(response.status, StatusCode::NOT_FOUND);
+        let response = app.delete(&missing, Some(&owner)).await;
+        assert_eq!(response.status, StatusCode::NOT_FOUND);
+
+        let response = app.patch(&comment_uri, Some(&owner), json!({ "body": "Edited" })).await;
+        assert_eq!(response.status, StatusCode::OK, "{}", response.body);
+        assert_eq!(response.body["body"], "Edited");
+        assert!(!response.body["edited_at"].is_null());
+    }
+}
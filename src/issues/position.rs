@@ -0,0 +1,177 @@
+use axum::{extract::{Path, State}, http::StatusCode, response::IntoResponse, Json};
+use serde::Deserialize;
+use sqlx::PgConnection;
+
+use crate::auth::{AuthUser, Scope};
+use crate::db;
+use crate::git_api::find_writable_repo;
+use crate::validation::{ValidatedJson, Validate, Validator};
+use crate::AppState;
+
+use super::get_full_issue;
+
+// Spacing between positions handed out at the ends of the backlog. Moves
+// between two issues take the midpoint, so only the moved row is written
+// until the gap runs out and the repository is renumbered.
+const POSITION_GAP: f64 = 1024.0;
+
+#[derive(Deserialize)]
+pub struct MoveIssue {
+    pub position: Option<f64>,
+    pub before: Option<i32>,
+    pub after: Option<i32>,
+}
+
+impl Validate for MoveIssue {
+    fn validate(&self, v: &mut Validator) {
+        let targets = [self.position.is_some(), self.before.is_some(), self.after.is_some()];
+        v.check(targets.iter().filter(|t| **t).count() == 1, "position", "exactly one of position, before or after is required")
+            .check(self.position.is_none_or(f64::is_finite), "position", "must be a finite number");
+    }
+}
+
+#[derive(Clone, Copy)]
+enum Placement {
+    Before(i32),
+    After(i32),
+}
+
+// Returns the anchor's position, first appending it to the ordered part of
+// the backlog if it has never been positioned.
+async fn anchor_position(conn: &mut PgConnection, repo_id: i32, anchor: i32) -> Result<f64, (StatusCode, String)> {
+    let position = sqlx::query_scalar!("SELECT position FROM issues WHERE number = $1 AND repo_id = $2", anchor, repo_id)
+        .fetch_optional(&mut *conn)
+        .await
+        .map_err(|e| db::error(e, "Failed to fetch issue"))?
+        .ok_or_else(|| (StatusCode::UNPROCESSABLE_ENTITY, format!("Issue {} is not in this repository", anchor)))?;
+
+    if let Some(position) = position {
+        return Ok(position);
+    }
+
+    sqlx::query_scalar!(
+        r#"
+        UPDATE issues SET position = (SELECT COALESCE(MAX(position), 0) + $3 FROM issues WHERE repo_id = $2)
+        WHERE number = $1 AND repo_id = $2
+        RETURNING position as "position!"
+        "#,
+        anchor,
+        repo_id,
+        POSITION_GAP,
+    )
+    .fetch_one(&mut *conn)
+    .await
+    .map_err(|e| db::error(e, "Failed to position issue"))
+}
+
+// The position halfway between the anchor and its neighbour on the requested
+// side, or `None` when the two are too close to fit anything in between.
+async fn position_next_to(conn: &mut PgConnection, repo_id: i32, issue_number: i32, placement: Placement) -> Result<Option<f64>, (StatusCode, String)> {
+    let (anchor, before) = match placement {
+        Placement::Before(anchor) => (anchor, true),
+        Placement::After(anchor) => (anchor, false),
+    };
+    let anchor_position = anchor_position(conn, repo_id, anchor).await?;
+
+    let neighbour = sqlx::query_scalar!(
+        r#"
+        SELECT CASE WHEN $4 THEN MAX(position) ELSE MIN(position) END
+        FROM issues
+        WHERE repo_id = $1 AND number <> $2 AND number <> $3
+          AND CASE WHEN $4 THEN position < $5 ELSE position > $5 END
+        "#,
+        repo_id,
+        issue_number,
+        anchor,
+        before,
+        anchor_position,
+    )
+    .fetch_one(&mut *conn)
+    .await
+    .map_err(|e| db::error(e, "Failed to fetch neighbouring issue"))?;
+
+    let Some(neighbour) = neighbour else {
+        return Ok(Some(if before { anchor_position - POSITION_GAP } else { anchor_position + POSITION_GAP }));
+    };
+    let midpoint = (anchor_position + neighbour) / 2.0;
+    Ok((midpoint != anchor_position && midpoint != neighbour).then_some(midpoint))
+}
+
+async fn renumber_positions(conn: &mut PgConnection, repo_id: i32) -> Result<(), (StatusCode, String)> {
+    sqlx::query!(
+        r#"
+        UPDATE issues i SET position = o.rank::float8 * $2
+        FROM (
+            SELECT id, ROW_NUMBER() OVER (ORDER BY position, id) AS rank
+            FROM issues
+            WHERE repo_id = $1 AND position IS NOT NULL
+        ) o
+        WHERE i.id = o.id
+        "#,
+        repo_id,
+        POSITION_GAP,
+    )
+    .execute(&mut *conn)
+    .await
+    .map_err(|e| db::error(e, "Failed to renumber issues"))?;
+    Ok(())
+}
+
+#[axum::debug_handler]
+pub async fn move_issue(
+    State(state): State<AppState>,
+    AuthUser(user): AuthUser,
+    Path((repo_name, issue_number)): Path<(String, i32)>,
+    ValidatedJson(target): ValidatedJson<MoveIssue>,
+) -> Result<impl IntoResponse, (StatusCode, String)> {
+    user.require_scope(Scope::IssuesWrite)?;
+    let mut tx = state.pool.begin().await.map_err(|e| db::error(e, "Failed to start transaction"))?;
+
+    let repo = find_writable_repo(&mut *tx, &repo_name, user.id, "reorder issues in").await?;
+
+    // Serialise moves within a repository so two concurrent drags cannot pick
+    // the same midpoint.
+    sqlx::query!("SELECT id FROM repositories WHERE id = $1 FOR UPDATE", repo.id)
+        .fetch_one(&mut *tx)
+        .await
+        .map_err(|e| db::error(e, "Failed to lock repository"))?;
+
+    sqlx::query_scalar!("SELECT id FROM issues WHERE number = $1 AND repo_id = $2", issue_number, repo.id)
+        .fetch_optional(&mut *tx)
+        .await
+        .map_err(|e| db::error(e, "Failed to fetch issue"))?
+        .ok_or_else(|| (StatusCode::NOT_FOUND, "Issue not found".to_string()))?;
+
+    let placement = match (target.before, target.after) {
+        (Some(anchor), _) => Some(Placement::Before(anchor)),
+        (_, Some(anchor)) => Some(Placement::After(anchor)),
+        _ => None,
+    };
+
+    let position = match (target.position, placement) {
+        (Some(position), _) => position,
+        (None, Some(Placement::Before(anchor) | Placement::After(anchor))) if anchor == issue_number => {
+            return Err((StatusCode::UNPROCESSABLE_ENTITY, "An issue cannot be moved relative to itself".to_string()));
+        }
+        (None, Some(placement)) => match position_next_to(&mut tx, repo.id, issue_number, placement).await? {
+            Some(position) => position,
+            None => {
+                renumber_positions(&mut tx, repo.id).await?;
+                position_next_to(&mut tx, repo.id, issue_number, placement)
+                    .await?
+                    .ok_or_else(|| (StatusCode::INTERNAL_SERVER_ERROR, "Failed to find a free position".to_string()))?
+            }
+        },
+        (None, None) => unreachable!("validated to have exactly one target"),
+    };
+
+    sqlx::query!("UPDATE issues SET position = $1 WHERE number = $2 AND repo_id = $3", position, issue_number, repo.id)
+        .execute(&mut *tx)
+        .await
+        .map_err(|e| db::error(e, "Failed to move issue"))?;
+
+    tx.commit().await.map_err(|e| db::error(e, "Failed to commit transaction"))?;
+
+    let (_, full_issue) = get_full_issue(&state, repo_name, issue_number, Some(user.id)).await?;
+    Ok(Json(full_issue))
+}
@@ -0,0 +1,108 @@
+use axum::{extract::{Path, State}, http::StatusCode, response::IntoResponse, Json};
+use serde::Deserialize;
+use std::collections::{BTreeMap, HashMap};
+
+use crate::auth::{AuthUser, Scope};
+use crate::db;
+use crate::git_api::find_visible_repo;
+use crate::AppState;
+
+// The reactions anyone may leave, by the names clients send.
+const REACTIONS: [&str; 8] = ["+1", "-1", "laugh", "confused", "heart", "hooray", "rocket", "eyes"];
+
+pub type ReactionCounts = BTreeMap<String, i64>;
+
+#[derive(Deserialize)]
+pub struct NewReaction {
+    pub content: String,
+}
+
+fn check_content(content: &str) -> Result<(), (StatusCode, String)> {
+    match REACTIONS.contains(&content) {
+        true => Ok(()),
+        false => Err((StatusCode::BAD_REQUEST, format!("Unknown reaction '{}'; use one of {}", content, REACTIONS.join(", ")))),
+    }
+}
+
+// Reaction counts for each of `issue_ids` that has any.
+pub(crate) async fn reaction_counts(pool: &sqlx::PgPool, issue_ids: &[i32]) -> Result<HashMap<i32, ReactionCounts>, sqlx::Error> {
+    let mut counts: HashMap<i32, ReactionCounts> = HashMap::new();
+    for row in sqlx::query!(
+        r#"SELECT issue_id, content, COUNT(*) AS "count!" FROM issue_reactions WHERE issue_id = ANY($1) GROUP BY issue_id, content"#,
+        issue_ids
+    )
+    .fetch_all(pool)
+    .await?
+    {
+        counts.entry(row.issue_id).or_default().insert(row.content, row.count);
+    }
+    Ok(counts)
+}
+
+// Returns the id of issue `issue_number` if the user can see it.
+async fn find_visible_issue(state: &AppState, repo_name: &str, issue_number: i32, user_id: i32) -> Result<i32, (StatusCode, String)> {
+    let repo = find_visible_repo(&state.pool, repo_name, Some(user_id)).await?;
+    sqlx::query_scalar!("SELECT id FROM issues WHERE number = $1 AND repo_id = $2", issue_number, repo.id)
+        .fetch_optional(&state.pool)
+        .await
+        .map_err(|e| db::error(e, "Failed to fetch issue"))?
+        .ok_or_else(|| (StatusCode::NOT_FOUND, "Issue not found".to_string()))
+}
+
+// Reacting twice with the same content changes nothing; the response says
+// whether the reaction is new (`201`) or was already there (`200`), and
+// carries the issue's updated counts.
+#[axum::debug_handler]
+pub async fn add_reaction(
+    State(state): State<AppState>,
+    AuthUser(user): AuthUser,
+    Path((repo_name, issue_number)): Path<(String, i32)>,
+    Json(reaction): Json<NewReaction>,
+) -> Result<impl IntoResponse, (StatusCode, String)> {
+    user.require_scope(Scope::IssuesWrite)?;
+    check_content(&reaction.content)?;
+    let issue_id = find_visible_issue(&state, &repo_name, issue_number, user.id).await?;
+
+    let inserted = sqlx::query!(
+        "INSERT INTO issue_reactions (issue_id, user_id, content) VALUES ($1, $2, $3) ON CONFLICT DO NOTHING",
+        issue_id,
+        user.id,
+        reaction.content,
+    )
+    .execute(&state.pool)
+    .await
+    .map_err(|e| db::error(e, "Failed to add reaction"))?
+    .rows_affected()
+        > 0;
+
+    let counts = reaction_counts(&state.pool, &[issue_id])
+        .await
+        .map_err(|e| db::error(e, "Failed to count reactions"))?
+        .remove(&issue_id)
+        .unwrap_or_default();
+    let status = if inserted { StatusCode::CREATED } else { StatusCode::OK };
+    Ok((status, Json(counts)))
+}
+
+#[axum::debug_handler]
+pub async fn remove_reaction(
+    State(state): State<AppState>,
+    AuthUser(user): AuthUser,
+    Path((repo_name, issue_number, content)): Path<(String, i32, String)>,
+) -> Result<impl IntoResponse, (StatusCode, String)> {
+    user.require_scope(Scope::IssuesWrite)?;
+    check_content(&content)?;
+    let issue_id = find_visible_issue(&state, &repo_name, issue_number, user.id).await?;
+
+    sqlx::query!(
+        "DELETE FROM issue_reactions WHERE issue_id = $1 AND user_id = $2 AND content = $3",
+        issue_id,
+        user.id,
+        content,
+    )
+    .execute(&state.pool)
+    .await
+    .map_err(|e| db::error(e, "Failed to remove reaction"))?;
+
+    Ok(StatusCode::NO_CONTENT)
+}
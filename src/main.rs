@@ -1,26 +1,75 @@
 use axum::{
-    routing::{any, get, post, delete, Router},
+    middleware,
+    routing::{any, get, patch, post, delete, Router},
 };
 use std::net::SocketAddr;
+use std::sync::Arc;
 use std::path::Path as StdPath;
+use tower_http::compression::{
+    predicate::{NotForContentType, Predicate},
+    CompressionLayer, DefaultPredicate,
+};
 use tower_http::trace::TraceLayer;
 use tracing_subscriber::{layer::SubscriberExt, util::SubscriberInitExt};
 use sqlx::PgPool;
 
+mod account;
+mod api_tokens;
 mod git_backend;
 mod git_api;
 mod db;
+mod etag;
+mod forks;
+mod gitignore;
+mod idempotency;
 mod auth;
+mod badges;
+mod blame;
+mod blobs;
+mod branches;
+mod codeowners;
+mod collaborators;
+mod commit_policy;
+mod config;
+mod contributors;
 mod issues;
+mod maintenance;
+mod markdown;
+mod pagination;
+mod patch;
 mod pull_requests;
+mod rate_limit;
+mod repo_import;
+mod statuses;
+mod tags;
+mod templates;
+#[cfg(test)]
+mod test_support;
+mod urls;
+mod users;
+mod validation;
+mod webhooks;
 
 #[derive(Clone)]
 pub struct AppState {
     pool: PgPool,
+    config: Arc<config::Config>,
+    diff_stats_cache: pull_requests::DiffStatsCache,
+    import_jobs: repo_import::ImportJobs,
+    http_client: reqwest::Client,
+    push_counts: maintenance::PushCounts,
+    rate_limits: rate_limit::RateLimits,
+    login_failures: rate_limit::LoginFailures,
 }
 
 #[tokio::main]
 async fn main() {
+    // Pushes to repositories with a commit policy run this binary again as
+    // their pre-receive hook.
+    if std::env::args().nth(1).as_deref() == Some("pre-receive") {
+        std::process::exit(commit_policy::run_pre_receive());
+    }
+
     dotenv::dotenv().ok();
 
     tracing_subscriber::registry()
@@ -38,7 +87,14 @@ async fn main() {
         }
     }
 
-    let pool = match db::create_pool().await {
+    if let Err(e) = commit_policy::install_hooks() {
+        tracing::error!("Failed to install the git hooks: {}", e);
+        return;
+    }
+
+    let config = Arc::new(config::Config::from_env());
+
+    let pool = match db::create_pool(&config).await {
         Ok(pool) => pool,
         Err(e) => {
             tracing::error!("Failed to create database pool: {}", e);
@@ -50,39 +106,153 @@ async fn main() {
         tracing::error!("Failed to run database migrations: {}", e);
         return;
     }
-    
+
+    db::spawn_keepalive(pool.clone(), config.db_keepalive_interval_secs);
+    auth::spawn_session_sweeper(pool.clone());
+
+    // Redirects are not followed, so a webhook cannot bounce its delivery
+    // to an address its URL was checked against.
+    let http_client = match reqwest::Client::builder()
+        .timeout(std::time::Duration::from_secs(10))
+        .redirect(reqwest::redirect::Policy::none())
+        .build()
+    {
+        Ok(client) => client,
+        Err(e) => {
+            tracing::error!("Failed to create HTTP client: {}", e);
+            return;
+        }
+    };
+
     let state = AppState {
         pool,
+        config,
+        diff_stats_cache: Default::default(),
+        import_jobs: Default::default(),
+        http_client,
+        push_counts: Default::default(),
+        rate_limits: Default::default(),
+        login_failures: Default::default(),
     };
 
-    let app = Router::new()
-        .route("/register", post(auth::register_handler))
-        .route("/login", post(auth::login_handler))
-        .route("/repos", get(git_api::list_repos_handler).post(git_api::create_repo_handler))
-        .route("/repos/:name", delete(git_api::delete_repo_handler))
+    repo_import::spawn_mirror_sync_scheduler(state.clone());
+
+    let app = app(state);
+
+    let addr = SocketAddr::from(([0, 0, 0, 0], 3000));
+    tracing::debug!("listening on {}", addr);
+    let listener = tokio::net::TcpListener::bind(addr).await.unwrap();
+    axum::serve(listener, app.into_make_service_with_connect_info::<SocketAddr>()).await.unwrap();
+}
+
+// Every route the server answers; the tests drive the same router.
+fn app(state: AppState) -> Router {
+    // GET routes also answer HEAD; the ETag layer sits inside axum's body stripping
+    // so HEAD responses carry the same ETag and Content-Length as GET.
+    let read_routes = Router::new()
+        .route("/repos", get(git_api::list_repos_handler))
         .route("/repos/:name/branches", get(git_api::list_branches_handler))
+        .route("/repos/:name/stale_branches", get(git_api::stale_branches_handler))
+        .route("/repos/:name/tags", get(tags::list_tags_handler))
+        .route("/repos/:name/tags/*tag", get(tags::get_tag_handler))
+        .route("/repos/:name/tree", get(git_api::list_files_default_handler))
         .route("/repos/:name/tree/:branch", get(git_api::list_files_root_handler))
         .route("/repos/:name/tree/:branch/", get(git_api::list_files_root_handler))
         .route("/repos/:name/tree/:branch/*path", get(git_api::list_files_subdirectory_handler))
+        .route("/repos/:name/raw/:rev/*path", get(blobs::raw_file_handler))
+        .route("/repos/:name/blob/:rev/*path", get(blobs::blob_metadata_handler))
+        .route("/repos/:name/blame/:rev/*path", get(blame::blame_handler))
         .route("/repos/:name/commits/:branch", get(git_api::commit_history_handler))
+        .route("/repos/:name/commits/:branch/branches", get(git_api::commit_branches_handler))
+        .route("/repos/:name/commits/:branch/status", get(statuses::get_combined_status))
+        .route("/repos/:name/refs/*ref", get(git_api::resolve_ref_handler))
+        .route("/repos/:name/forks", get(forks::list_forks))
+        .route("/repos/:name/network", get(forks::get_network))
+        .route("/repos/:name/contributors/:username/stats", get(contributors::get_contributor_stats))
+        .route_layer(middleware::from_fn(etag::etag));
+
+    // Creation endpoints that honour an `Idempotency-Key` header on POST.
+    let idempotent_routes = Router::new()
         .route("/repos/:name/issues", post(issues::create_issue).get(issues::list_issues))
-        .route("/repos/:name/issues/:issue_id", get(issues::get_issue))
-        .route("/:name/issues/:issue_id/comments", post(issues::create_comment).get(issues::list_comments))
-        .route("/repos/:name/labels", post(issues::create_label).get(issues::list_labels))
-        .route("/repos/:name/issues/:issue_id/labels/:label_name", post(issues::add_label_to_issue).delete(issues::remove_label_from_issue))
-        .route("/repos/:name/issues/:issue_id/assignees/:assignee_username", post(issues::add_assignee_to_issue).delete(issues::remove_assignee_from_issue))
+        .route("/:name/issues/:issue_number/comments", post(issues::create_comment).get(issues::list_comments))
         .route("/repos/:name/pulls", post(pull_requests::create_pull_request).get(pull_requests::list_pull_requests))
-        .route("/repos/:name/pulls/:pull_id", get(pull_requests::get_pull_request).patch(pull_requests::update_pull_request))
-        .route("/repos/:name/pulls/:pull_id/diff", get(pull_requests::get_pull_request_diff))
-        .route("/repos/:name/pulls/:pull_id/comments", post(pull_requests::comments::create_comment).get(pull_requests::comments::list_comments))
-        .route("/repos/:name/pulls/:pull_id/reviews", post(pull_requests::reviews::create_review).get(pull_requests::reviews::list_reviews))
-        .route("/repos/:name/pulls/:pull_id/reviews/:review_id", get(pull_requests::reviews::get_review).patch(pull_requests::reviews::update_review).delete(pull_requests::reviews::delete_review))
+        .route("/repos/:name/pulls/:pull_number/comments", post(pull_requests::comments::create_comment).get(pull_requests::comments::list_comments))
+        .route_layer(middleware::from_fn_with_state(state.clone(), idempotency::idempotent));
+
+    Router::new()
+        .route("/register", post(auth::register_handler))
+        .route("/login", post(auth::login_handler))
+        .route("/logout", post(auth::logout_handler))
+        .route("/refresh", post(auth::refresh_handler))
+        .route("/auth/introspect", get(auth::introspect_handler))
+        .route("/me", get(auth::me_handler))
+        .route("/me/password", post(auth::change_password_handler))
+        .route("/me/tokens", post(api_tokens::create_api_token).get(api_tokens::list_api_tokens))
+        .route("/me/tokens/:token_id", delete(api_tokens::delete_api_token))
+        .route("/markdown", post(markdown::preview_handler))
+        .route("/users/resolve", post(users::resolve_users))
+        .route("/badges/:badge", get(badges::generic_badge))
+        .route("/templates/licenses", get(templates::list_licenses))
+        .route("/templates/licenses/:key", get(templates::get_license))
+        .route("/templates/gitignore", get(templates::list_gitignores))
+        .route("/templates/gitignore/:name", get(templates::get_gitignore))
+        .route("/account", get(account::get_account).patch(account::update_account))
+        .route("/account/assigned", get(account::list_assigned))
+        .route("/account/pulls", get(account::list_authored_pulls))
+        .route("/repos", post(git_api::create_repo_handler))
+        .route("/repos/import", post(repo_import::import_repo_handler))
+        .route("/repos/import/:job_id", get(repo_import::get_import_job_handler))
+        .route("/repos/:name", delete(git_api::delete_repo_handler).patch(git_api::update_repo_handler))
+        .route("/repos/:name/branches", post(branches::create_branch))
+        .route("/repos/:name/branches/*branch", delete(branches::delete_branch))
+        .route("/repos/:name/sync", post(repo_import::sync_mirror_handler))
+        .route("/repos/:name/gc", post(maintenance::gc_repo_handler))
+        .route("/repos/:name/statuses/:sha", post(statuses::create_status))
+        .route("/repos/:name/badges/:branch/status.svg", get(badges::status_badge))
+        .route("/repos/:name/hooks", post(webhooks::create_webhook).get(webhooks::list_webhooks))
+        .route("/repos/:name/hooks/:hook_id", delete(webhooks::delete_webhook))
+        .route("/repos/:name/collaborators", post(collaborators::add_collaborator).get(collaborators::list_collaborators))
+        .route("/repos/:name/collaborators/:username", delete(collaborators::remove_collaborator))
+        .route("/repos/:name/commit_policy", get(commit_policy::get_commit_policy).put(commit_policy::update_commit_policy))
+        .route("/repos/:name/codeowners/match", get(codeowners::match_codeowners_handler))
+        .route("/repos/:name/issue_templates", get(git_api::list_issue_templates_handler))
+        .route("/repos/:name/issues/bulk", post(issues::bulk_update_issues))
+        .route("/repos/:name/issues/similar", get(issues::list_similar_issues))
+        .route("/repos/:name/issues/search", get(issues::search_issues))
+        .route("/repos/:name/issues/:issue_number", get(issues::get_issue).patch(issues::update_issue))
+        .route("/repos/:name/issues/:issue_number/position", patch(issues::position::move_issue))
+        .route("/repos/:name/issues/:issue_number/reactions", post(issues::reactions::add_reaction))
+        .route("/repos/:name/issues/:issue_number/reactions/:content", delete(issues::reactions::remove_reaction))
+        .route("/:name/issues/:issue_number/comments/:comment_id", patch(issues::update_comment).delete(issues::delete_comment))
+        .route("/repos/:name/labels", post(issues::create_label).get(issues::list_labels))
+        .route("/repos/:name/issues/:issue_number/labels/:label_name", post(issues::add_label_to_issue).delete(issues::remove_label_from_issue))
+        .route("/repos/:name/issues/:issue_number/assignees/:assignee_username", post(issues::add_assignee_to_issue).delete(issues::remove_assignee_from_issue))
+        .route("/repos/:name/pulls/:pull_number", get(pull_requests::get_pull_request).patch(pull_requests::update_pull_request))
+        .route("/repos/:name/pulls/:pull_number/diff", get(pull_requests::get_pull_request_diff))
+        .route("/repos/:name/pulls/:pull_number/base_drift", get(pull_requests::base_drift::get_base_drift))
+        .route("/repos/:name/pulls/:pull_number/apply_closes", post(pull_requests::closes::apply_closes))
+        .route("/repos/:name/pulls/:pull_number/commits", get(pull_requests::commits::list_commits))
+        .route("/repos/:name/pulls/:pull_number/comments/:comment_id", delete(pull_requests::comments::delete_comment))
+        .route("/repos/:name/pulls/:pull_number/requested_reviewers", get(pull_requests::requested_reviewers::list_requested_reviewers))
+        .route("/repos/:name/pulls/:pull_number/requested_reviewers/:username", post(pull_requests::requested_reviewers::request_reviewer).delete(pull_requests::requested_reviewers::remove_requested_reviewer))
+        .route("/repos/:name/pulls/:pull_number/ready_for_review", post(pull_requests::drafts::mark_ready_for_review))
+        .route("/repos/:name/pulls/:pull_number/reviews", post(pull_requests::reviews::create_review).get(pull_requests::reviews::list_reviews))
+        .route("/repos/:name/pulls/:pull_number/reviews/:review_id", get(pull_requests::reviews::get_review).patch(pull_requests::reviews::update_review).delete(pull_requests::reviews::delete_review))
+        .merge(read_routes)
+        .merge(idempotent_routes)
         .fallback(any(git_backend::handler))
         .with_state(state)
-        .layer(TraceLayer::new_for_http());
+        .layer(CompressionLayer::new().compress_when(compression_predicate()))
+        .layer(TraceLayer::new_for_http())
+}
 
-    let addr = SocketAddr::from(([0, 0, 0, 0], 3000));
-    tracing::debug!("listening on {}", addr);
-    let listener = tokio::net::TcpListener::bind(addr).await.unwrap();
-    axum::serve(listener, app.into_make_service()).await.unwrap();
+// Git's smart-HTTP responses are already zlib-compressed packfiles, and binary
+// downloads gain nothing from a second pass, so only the REST text is compressed.
+fn compression_predicate() -> impl Predicate {
+    DefaultPredicate::new()
+        .and(NotForContentType::const_new("application/x-git-"))
+        .and(NotForContentType::const_new("application/octet-stream"))
+        .and(NotForContentType::const_new("application/zip"))
+        .and(NotForContentType::const_new("application/gzip"))
+        .and(NotForContentType::const_new("application/x-tar"))
 }
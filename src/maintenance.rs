@@ -0,0 +1,100 @@
+use axum::{extract::{Path, State}, http::StatusCode, response::IntoResponse, Json};
+use serde::Serialize;
+use std::collections::HashMap;
+use std::path::{Path as StdPath, PathBuf};
+use std::sync::{Arc, Mutex};
+
+use crate::auth::{AuthUser, Scope};
+use crate::git_api::find_administered_repo;
+use crate::AppState;
+
+pub type PushCounts = Arc<Mutex<HashMap<String, u64>>>;
+
+#[derive(Serialize)]
+pub struct GcReport {
+    size_before: u64,
+    size_after: u64,
+}
+
+fn dir_size(path: &StdPath) -> std::io::Result<u64> {
+    let mut size = 0;
+    for entry in std::fs::read_dir(path)? {
+        let entry = entry?;
+        let metadata = entry.metadata()?;
+        size += if metadata.is_dir() { dir_size(&entry.path())? } else { metadata.len() };
+    }
+    Ok(size)
+}
+
+fn gc(path: PathBuf) -> Result<GcReport, String> {
+    let size_before = dir_size(&path).map_err(|e| format!("Failed to measure repository: {}", e))?;
+
+    let output = std::process::Command::new("git")
+        .args(["gc", "--quiet"])
+        .current_dir(&path)
+        .output()
+        .map_err(|e| format!("Failed to run git gc: {}", e))?;
+    if !output.status.success() {
+        return Err(format!("git gc failed: {}", String::from_utf8_lossy(&output.stderr).trim()));
+    }
+
+    let size_after = dir_size(&path).map_err(|e| format!("Failed to measure repository: {}", e))?;
+    Ok(GcReport { size_before, size_after })
+}
+
+async fn run_gc(repo_name: &str) -> Result<GcReport, String> {
+    let path = StdPath::new("./repos").join(format!("{}.git", repo_name));
+    tokio::task::spawn_blocking(move || gc(path))
+        .await
+        .map_err(|e| format!("Task failed: {}", e))?
+}
+
+#[axum::debug_handler]
+pub async fn gc_repo_handler(
+    State(state): State<AppState>,
+    AuthUser(user): AuthUser,
+    Path(repo_name): Path<String>,
+) -> Result<impl IntoResponse, (StatusCode, String)> {
+    user.require_scope(Scope::RepoWrite)?;
+    let repo = find_administered_repo(&state.pool, &repo_name, user.id, "maintain").await?;
+
+    let report = run_gc(&repo.name).await.map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e))?;
+    if let Ok(mut counts) = state.push_counts.lock() {
+        counts.remove(&repo.name);
+    }
+
+    Ok(Json(report))
+}
+
+// Counts successful pushes and runs gc in the background every
+// `GC_AFTER_PUSHES` pushes, when that is configured.
+pub fn record_push(state: &AppState, repo_name: &str) {
+    let threshold = state.config.gc_after_pushes;
+    if threshold == 0 {
+        return;
+    }
+
+    let due = match state.push_counts.lock() {
+        Ok(mut counts) => {
+            let count = counts.entry(repo_name.to_string()).or_insert(0);
+            *count += 1;
+            if *count >= threshold {
+                *count = 0;
+                true
+            } else {
+                false
+            }
+        }
+        Err(_) => false,
+    };
+
+    if due {
+        let repo_name = repo_name.to_string();
+        tokio::spawn(async move {
+            match run_gc(&repo_name).await {
+                Ok(report) => tracing::info!("Garbage collected {}: {} -> {} bytes", repo_name, report.size_before, report.size_after),
+                Err(e) => tracing::warn!("Automatic gc of {} failed: {}", repo_name, e),
+            }
+        });
+    }
+}
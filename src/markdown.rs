@@ -0,0 +1,274 @@
+use axum::{extract::State, http::{header, StatusCode}, response::IntoResponse};
+use pulldown_cmark::{html, CowStr, Event, Options, Parser, Tag, TagEnd};
+use serde::Deserialize;
+use sqlx::PgPool;
+use std::collections::{HashMap, HashSet};
+
+use crate::auth::PermissiveAuthUser;
+use crate::db;
+use crate::git_api::find_visible_repo;
+use crate::validation::{ValidatedJson, Validate, Validator, MAX_BODY_CHARS};
+use crate::AppState;
+
+// Every candidate costs an object-database lookup, so stop linking after
+// this many per render.
+const MAX_SHA_LOOKUPS: usize = 50;
+
+// `?render=true` asks for rendered HTML next to the raw markdown;
+// `link_commits=true` additionally turns commit shas into links.
+#[derive(Deserialize, Clone, Copy)]
+pub struct RenderQuery {
+    #[serde(default)]
+    pub render: bool,
+    #[serde(default)]
+    pub link_commits: bool,
+}
+
+#[derive(Deserialize)]
+pub struct MarkdownRequest {
+    pub text: String,
+    pub repo: Option<String>,
+    #[serde(default)]
+    pub link_commits: bool,
+}
+
+impl Validate for MarkdownRequest {
+    fn validate(&self, v: &mut Validator) {
+        v.max_chars("text", Some(&self.text), MAX_BODY_CHARS);
+    }
+}
+
+#[derive(Clone, Copy)]
+pub enum Reference {
+    Issue,
+    PullRequest,
+}
+
+#[derive(Default)]
+pub struct RenderOptions<'a> {
+    // Repository the text belongs to; `#N` and commit shas are resolved against it.
+    pub repo: Option<&'a str>,
+    pub link_commits: bool,
+    pub mentions: HashSet<String>,
+    pub references: HashMap<i32, Reference>,
+}
+
+// Looks up which `@user`s exist and which `#N`s are issues or pull requests
+// of the repository, so rendering only decorates real ones.
+pub async fn resolve_mentions_and_references(
+    pool: &PgPool,
+    text: &str,
+    repo_id: Option<i32>,
+) -> Result<(HashSet<String>, HashMap<i32, Reference>), sqlx::Error> {
+    let (usernames, numbers) = mention_and_reference_candidates(text);
+
+    let mut mentions = HashSet::new();
+    if !usernames.is_empty() {
+        mentions = sqlx::query_scalar::<_, String>("SELECT username FROM users WHERE username = ANY($1)")
+            .bind(&usernames)
+            .fetch_all(pool)
+            .await?
+            .into_iter()
+            .collect();
+    }
+
+    let mut references = HashMap::new();
+    if let (Some(repo_id), false) = (repo_id, numbers.is_empty()) {
+        let issues = sqlx::query_scalar::<_, i32>("SELECT number FROM issues WHERE repo_id = $1 AND number = ANY($2)")
+            .bind(repo_id)
+            .bind(&numbers)
+            .fetch_all(pool)
+            .await?;
+        let pulls = sqlx::query_scalar::<_, i32>("SELECT number FROM pull_requests WHERE repo_id = $1 AND number = ANY($2)")
+            .bind(repo_id)
+            .bind(&numbers)
+            .fetch_all(pool)
+            .await?;
+        references.extend(pulls.into_iter().map(|number| (number, Reference::PullRequest)));
+        references.extend(issues.into_iter().map(|number| (number, Reference::Issue)));
+    }
+
+    Ok((mentions, references))
+}
+
+// Renders user-supplied markdown to sanitized HTML. Does blocking git work
+// when commit linking is on.
+pub fn render(text: &str, options: &RenderOptions<'_>) -> String {
+    let parser = Parser::new_ext(text, Options::ENABLE_TABLES | Options::ENABLE_STRIKETHROUGH | Options::ENABLE_TASKLISTS);
+
+    let commits = match (options.link_commits, options.repo) {
+        (true, Some(repo_name)) => git2::Repository::open(format!("./repos/{}.git", repo_name)).ok(),
+        _ => None,
+    };
+    let mut linker = Linker { options, commits, lookups: 0 };
+
+    // Text inside links and code is left alone.
+    let mut plain_depth = 0usize;
+    let events = parser.flat_map(|event| {
+        match &event {
+            Event::Start(Tag::Link { .. } | Tag::Image { .. } | Tag::CodeBlock(_)) => plain_depth += 1,
+            Event::End(TagEnd::Link | TagEnd::Image | TagEnd::CodeBlock) => plain_depth -= 1,
+            Event::Text(text) if plain_depth == 0 => return linker.link(text),
+            _ => {}
+        }
+        vec![event]
+    });
+
+    let mut unsafe_html = String::new();
+    html::push_html(&mut unsafe_html, events);
+
+    ammonia::Builder::default()
+        .add_allowed_classes("a", ["issue-link", "commit-link"])
+        .add_allowed_classes("span", ["user-mention"])
+        .clean(&unsafe_html)
+        .to_string()
+}
+
+#[axum::debug_handler]
+pub async fn preview_handler(
+    State(state): State<AppState>,
+    PermissiveAuthUser(user): PermissiveAuthUser,
+    ValidatedJson(request): ValidatedJson<MarkdownRequest>,
+) -> Result<impl IntoResponse, (StatusCode, String)> {
+    let repo_id = match &request.repo {
+        Some(repo_name) => Some(find_visible_repo(&state.pool, repo_name, user.map(|u| u.id)).await?.id),
+        None => None,
+    };
+
+    let (mentions, references) = resolve_mentions_and_references(&state.pool, &request.text, repo_id)
+        .await
+        .map_err(|e| db::error(e, "Failed to resolve references"))?;
+
+    let html = tokio::task::spawn_blocking(move || {
+        let options = RenderOptions {
+            repo: request.repo.as_deref(),
+            link_commits: request.link_commits,
+            mentions,
+            references,
+        };
+        render(&request.text, &options)
+    })
+    .await
+    .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, format!("Task failed: {}", e)))?;
+
+    Ok(([(header::CONTENT_TYPE, "text/html; charset=utf-8")], html))
+}
+
+struct Linker<'a> {
+    options: &'a RenderOptions<'a>,
+    commits: Option<git2::Repository>,
+    lookups: usize,
+}
+
+impl Linker<'_> {
+    // Splits a text run around the mentions, references and shas it can link.
+    fn link<'e>(&mut self, text: &str) -> Vec<Event<'e>> {
+        let mut events = Vec::new();
+        let mut rest = 0;
+        for token in tokens(text) {
+            let Some(html) = self.replacement(&token) else { continue };
+            if rest < token.start {
+                events.push(Event::Text(CowStr::from(text[rest..token.start].to_string())));
+            }
+            events.push(Event::InlineHtml(CowStr::from(html)));
+            rest = token.end;
+        }
+        if rest < text.len() {
+            events.push(Event::Text(CowStr::from(text[rest..].to_string())));
+        }
+        events
+    }
+
+    fn replacement(&mut self, token: &Token<'_>) -> Option<String> {
+        match token.sigil {
+            Some('@') if self.options.mentions.contains(token.word) => {
+                Some(format!(r#"<span class="user-mention">@{}</span>"#, token.word))
+            }
+            Some('#') => {
+                let number = token.word.parse::<i32>().ok()?;
+                let path = match self.options.references.get(&number)? {
+                    Reference::Issue => "issues",
+                    Reference::PullRequest => "pulls",
+                };
+                Some(format!(r##"<a class="issue-link" href="/repos/{}/{}/{}">#{}</a>"##, self.options.repo?, path, number, number))
+            }
+            None if is_sha_candidate(token.word) => {
+                let oid = self.resolve_commit(token.word)?;
+                Some(format!(
+                    r#"<a class="commit-link" href="/repos/{}/commits/{}.diff">{}</a>"#,
+                    self.options.repo?,
+                    oid,
+                    token.word,
+                ))
+            }
+            _ => None,
+        }
+    }
+
+    fn resolve_commit(&mut self, candidate: &str) -> Option<git2::Oid> {
+        let repo = self.commits.as_ref()?;
+        if self.lookups >= MAX_SHA_LOOKUPS {
+            return None;
+        }
+        self.lookups += 1;
+        repo.find_object_by_prefix(candidate, Some(git2::ObjectType::Commit)).ok().map(|object| object.id())
+    }
+}
+
+struct Token<'t> {
+    start: usize,
+    end: usize,
+    sigil: Option<char>,
+    word: &'t str,
+}
+
+fn is_word_byte(b: u8) -> bool {
+    b.is_ascii_alphanumeric() || b == b'_' || b == b'-'
+}
+
+// Standalone words, optionally prefixed with `@` or `#`. A sigil glued to a
+// preceding word (as in an email address) does not count.
+fn tokens(text: &str) -> Vec<Token<'_>> {
+    let bytes = text.as_bytes();
+    let mut tokens = Vec::new();
+    let mut i = 0;
+    while i < bytes.len() {
+        let at_boundary = i == 0 || !is_word_byte(bytes[i - 1]);
+        let sigil = matches!(bytes[i], b'@' | b'#').then(|| bytes[i] as char);
+        if !at_boundary || !(sigil.is_some() || is_word_byte(bytes[i])) {
+            i += 1;
+            continue;
+        }
+
+        let start = i;
+        if sigil.is_some() {
+            i += 1;
+        }
+        let word_start = i;
+        while i < bytes.len() && is_word_byte(bytes[i]) {
+            i += 1;
+        }
+        if i > word_start {
+            tokens.push(Token { start, end: i, sigil, word: &text[word_start..i] });
+        }
+    }
+    tokens
+}
+
+fn mention_and_reference_candidates(text: &str) -> (Vec<String>, Vec<i32>) {
+    let (mut usernames, mut numbers) = (Vec::new(), Vec::new());
+    for token in tokens(text) {
+        match token.sigil {
+            Some('@') => usernames.push(token.word.to_string()),
+            Some('#') => numbers.extend(token.word.parse::<i32>().ok()),
+            _ => {}
+        }
+    }
+    (usernames, numbers)
+}
+
+// 7-40 character hex words. All-digit words are skipped since they are far
+// more likely to be numbers than shas.
+fn is_sha_candidate(word: &str) -> bool {
+    (7..=40).contains(&word.len()) && word.bytes().all(|b| b.is_ascii_hexdigit()) && !word.bytes().all(|b| b.is_ascii_digit())
+}
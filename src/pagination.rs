@@ -0,0 +1,155 @@
+use async_trait::async_trait;
+use axum::{
+    extract::{FromRequestParts, OriginalUri, Query},
+    http::{header, request::Parts, HeaderValue},
+    response::{IntoResponse, Response},
+    Json,
+};
+use serde::{Deserialize, Serialize};
+
+use crate::urls::BaseUrl;
+use crate::validation::Validator;
+use crate::AppState;
+
+const DEFAULT_PER_PAGE: i64 = 30;
+const MAX_PER_PAGE: i64 = 100;
+// Deeper pages are refused rather than turned into an offset Postgres would
+// have to skip (or that would overflow).
+const MAX_PAGE: i64 = 100_000;
+
+#[derive(Deserialize)]
+struct PageParams {
+    page: Option<i64>,
+    per_page: Option<i64>,
+    #[serde(default)]
+    envelope: bool,
+}
+
+// Shared `?page=&per_page=&envelope=` parameters for list endpoints. Lists
+// come a page of `DEFAULT_PER_PAGE` at a time unless the client asks for
+// another size, and responses link to the neighbouring pages in a `Link`
+// header.
+pub struct Pagination {
+    page: Option<i64>,
+    per_page: Option<i64>,
+    pub envelope: bool,
+    // The request's URL with the paging parameters left out and a `?` or `&`
+    // at the end, ready for them to be appended.
+    url: String,
+}
+
+#[async_trait]
+impl FromRequestParts<AppState> for Pagination {
+    type Rejection = Response;
+
+    async fn from_request_parts(parts: &mut Parts, state: &AppState) -> Result<Self, Self::Rejection> {
+        let Query(params) = Query::<PageParams>::from_request_parts(parts, state).await.map_err(IntoResponse::into_response)?;
+        let mut validator = Validator::default();
+        validator.check(params.page.is_none_or(|page| page <= MAX_PAGE), "page", format!("must be at most {}", MAX_PAGE));
+        validator.finish().map_err(IntoResponse::into_response)?;
+
+        let uri = parts.extensions.get::<OriginalUri>().map_or(&parts.uri, |original| &original.0);
+        let mut url = BaseUrl::from_headers(&parts.headers, &state.config).join(uri.path());
+        url.push('?');
+        let kept = uri
+            .query()
+            .unwrap_or("")
+            .split('&')
+            .filter(|pair| !pair.is_empty() && !matches!(pair.split('=').next(), Some("page" | "per_page")));
+        for pair in kept {
+            url.push_str(pair);
+            url.push('&');
+        }
+
+        Ok(Pagination { page: params.page, per_page: params.per_page, envelope: params.envelope, url })
+    }
+}
+
+impl Pagination {
+    pub fn page(&self) -> i64 {
+        self.page.unwrap_or(1).max(1)
+    }
+
+    pub fn limit(&self) -> i64 {
+        self.per_page.unwrap_or(DEFAULT_PER_PAGE).clamp(1, MAX_PER_PAGE)
+    }
+
+    pub fn offset(&self) -> i64 {
+        (self.page() - 1) * self.limit()
+    }
+
+    // Whether the client named a page or a page size.
+    pub fn is_requested(&self) -> bool {
+        self.page.is_some() || self.per_page.is_some()
+    }
+
+    pub fn respond<T: Serialize>(&self, data: Vec<T>, total: i64) -> Response {
+        let mut response = if self.envelope {
+            Json(Envelope {
+                data,
+                total,
+                page: self.page(),
+                per_page: self.limit(),
+            })
+            .into_response()
+        } else {
+            Json(data).into_response()
+        };
+        if let Some(links) = self.links(total).and_then(|links| HeaderValue::from_str(&links).ok()) {
+            response.headers_mut().insert(header::LINK, links);
+        }
+        response
+    }
+
+    // `first`, `prev`, `next` and `last` links as in RFC 8288, or `None` when
+    // everything fits on the first page.
+    fn links(&self, total: i64) -> Option<String> {
+        let (page, limit) = (self.page(), self.limit());
+        let last = ((total + limit - 1) / limit).max(1);
+        if last == 1 && page == 1 {
+            return None;
+        }
+
+        let mut links = vec![(1, "first")];
+        if page > 1 {
+            links.push(((page - 1).min(last), "prev"));
+        }
+        if page < last {
+            links.push((page + 1, "next"));
+        }
+        links.push((last, "last"));
+        Some(
+            links
+                .iter()
+                .map(|(page, rel)| format!("<{}page={}&per_page={}>; rel=\"{}\"", self.url, page, limit, rel))
+                .collect::<Vec<_>>()
+                .join(", "),
+        )
+    }
+}
+
+#[derive(Serialize)]
+struct Envelope<T> {
+    data: Vec<T>,
+    total: i64,
+    page: i64,
+    per_page: i64,
+}
+
+#[derive(Deserialize, Clone, Copy, Default)]
+pub enum SortDirection {
+    #[default]
+    #[serde(rename = "asc")]
+    Asc,
+    #[serde(rename = "desc")]
+    Desc,
+}
+
+impl SortDirection {
+    pub fn as_sql(&self) -> &'static str {
+        match self {
+            SortDirection::Asc => "ASC",
+            SortDirection::Desc => "DESC",
+        }
+    }
+}
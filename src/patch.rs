@@ -0,0 +1,147 @@
+use axum::{
+    http::{header, StatusCode},
+    response::{IntoResponse, Response},
+};
+use chrono::{FixedOffset, TimeZone};
+use git2::{Commit, Diff, DiffStatsFormat, Repository};
+
+use crate::pull_requests::format_diff;
+
+#[derive(Clone, Copy)]
+pub enum PatchFormat {
+    Mbox,
+    Diff,
+}
+
+impl PatchFormat {
+    // Splits `<rev>.patch` / `<rev>.diff` into the revision and the format.
+    pub fn from_path(path: &str) -> Option<(&str, PatchFormat)> {
+        if let Some(rev) = path.strip_suffix(".patch") {
+            return Some((rev, PatchFormat::Mbox));
+        }
+        path.strip_suffix(".diff").map(|rev| (rev, PatchFormat::Diff))
+    }
+}
+
+pub fn commit_diff<'r>(repo: &'r Repository, commit: &Commit<'_>) -> Result<Diff<'r>, git2::Error> {
+    let parent_tree = match commit.parent_count() {
+        0 => None,
+        _ => Some(commit.parent(0)?.tree()?),
+    };
+    repo.diff_tree_to_tree(parent_tree.as_ref(), Some(&commit.tree()?), None)
+}
+
+// Formats a commit the way `git format-patch` does, so the output can be fed
+// straight to `git am`. `number` is the commit's place in a series, if any.
+pub fn format_mbox(repo: &Repository, commit: &Commit<'_>, number: Option<(usize, usize)>) -> Result<String, git2::Error> {
+    let author = commit.author();
+    let when = author.when();
+    let date = FixedOffset::east_opt(when.offset_minutes() * 60)
+        .and_then(|offset| offset.timestamp_opt(when.seconds(), 0).single())
+        .map(|date| date.to_rfc2822())
+        .unwrap_or_default();
+
+    // The subject is the whole first paragraph, folded onto one line.
+    let message = String::from_utf8_lossy(commit.message_bytes());
+    let message = message.trim();
+    let (subject, body) = message.split_once("\n\n").unwrap_or((message, ""));
+    let subject = subject.split_whitespace().collect::<Vec<_>>().join(" ");
+    let body = match body.trim() {
+        "" => String::new(),
+        body => format!("{}\n", body),
+    };
+    let prefix = match number {
+        Some((n, total)) if total > 1 => format!("[PATCH {}/{}]", n, total),
+        _ => "[PATCH]".to_string(),
+    };
+
+    let diff = commit_diff(repo, commit)?;
+    let stats = diff.stats()?.to_buf(DiffStatsFormat::FULL, 72)?;
+
+    Ok(format!(
+        "From {id} Mon Sep 17 00:00:00 2001\n\
+         From: {name} <{email}>\n\
+         Date: {date}\n\
+         Subject: {prefix} {subject}\n\
+         MIME-Version: 1.0\n\
+         Content-Type: text/plain; charset=UTF-8\n\
+         Content-Transfer-Encoding: 8bit\n\
+         \n\
+         {body}---\n\
+         {stats}\n\
+         {diff}",
+        id = commit.id(),
+        name = String::from_utf8_lossy(author.name_bytes()),
+        email = String::from_utf8_lossy(author.email_bytes()),
+        stats = String::from_utf8_lossy(&stats),
+        diff = format_diff(&diff)?,
+    ))
+}
+
+// Renders everything `head` adds on top of its merge base with `base`: one
+// mbox message per commit, oldest first, or a single diff.
+pub fn format_range(repo: &Repository, base: &Commit<'_>, head: &Commit<'_>, format: PatchFormat) -> Result<String, git2::Error> {
+    let merge_base = repo.find_commit(repo.merge_base(base.id(), head.id())?)?;
+
+    match format {
+        PatchFormat::Diff => {
+            let diff = repo.diff_tree_to_tree(Some(&merge_base.tree()?), Some(&head.tree()?), None)?;
+            format_diff(&diff)
+        }
+        PatchFormat::Mbox => {
+            let mut revwalk = repo.revwalk()?;
+            revwalk.set_sorting(git2::Sort::TOPOLOGICAL | git2::Sort::REVERSE)?;
+            revwalk.push(head.id())?;
+            revwalk.hide(merge_base.id())?;
+            let commits = revwalk.collect::<Result<Vec<_>, _>>()?;
+
+            let mut series = String::new();
+            for (i, oid) in commits.iter().enumerate() {
+                let commit = repo.find_commit(*oid)?;
+                series.push_str(&format_mbox(repo, &commit, Some((i + 1, commits.len())))?);
+                series.push('\n');
+            }
+            Ok(series)
+        }
+    }
+}
+
+pub fn check_size(text: String, max_bytes: usize) -> Result<String, (StatusCode, String)> {
+    if text.len() > max_bytes {
+        return Err((StatusCode::PAYLOAD_TOO_LARGE, format!("Diff is larger than the {} byte limit", max_bytes)));
+    }
+    Ok(text)
+}
+
+fn render_commit(repo_name: &str, rev: &str, format: PatchFormat, max_bytes: usize) -> Result<String, (StatusCode, String)> {
+    let repo = Repository::open(format!("./repos/{}.git", repo_name))
+        .map_err(|_| (StatusCode::NOT_FOUND, "Repository not found".to_string()))?;
+    let commit = repo
+        .revparse_single(rev)
+        .and_then(|object| object.peel_to_commit())
+        .map_err(|_| (StatusCode::NOT_FOUND, "Commit not found".to_string()))?;
+
+    let text = match format {
+        PatchFormat::Mbox => format_mbox(&repo, &commit, None),
+        PatchFormat::Diff => commit_diff(&repo, &commit).and_then(|diff| format_diff(&diff)),
+    };
+    text.map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, format!("Failed to format patch: {}", e)))
+        .and_then(|text| check_size(text, max_bytes))
+}
+
+// Runs `render` on the blocking pool and serves its output as plain text.
+pub async fn render_blocking<F>(render: F) -> Response
+where
+    F: FnOnce() -> Result<String, (StatusCode, String)> + Send + 'static,
+{
+    match tokio::task::spawn_blocking(render).await {
+        Ok(Ok(text)) => ([(header::CONTENT_TYPE, "text/plain; charset=utf-8")], text).into_response(),
+        Ok(Err(e)) => e.into_response(),
+        Err(e) => (StatusCode::INTERNAL_SERVER_ERROR, format!("Task failed: {}", e)).into_response(),
+    }
+}
+
+pub async fn commit_patch_response(repo_name: &str, rev: &str, format: PatchFormat, max_bytes: usize) -> Response {
+    let (repo_name, rev) = (repo_name.to_string(), rev.to_string());
+    render_blocking(move || render_commit(&repo_name, &rev, format, max_bytes)).await
+}
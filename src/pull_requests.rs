@@ -1,18 +1,33 @@
-use axum::{extract::{Path, State}, http::StatusCode, response::IntoResponse, Json};
+use axum::{extract::{Path, Query, State}, http::StatusCode, response::{IntoResponse, Response}, Json};
 use serde::{Deserialize, Serialize};
 use sqlx::FromRow;
 use git2::{self, DiffOptions};
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
 use tokio::task;
 
-use crate::auth::{AuthUser, PermissiveAuthUser};
+use crate::account;
+use crate::auth::{AuthUser, PermissiveAuthUser, Scope};
+use crate::codeowners;
+use crate::db;
+use crate::git_api::{default_user_ids, find_visible_repo, find_writable_repo, next_item_number, resolve_branch_commit, DefaultUsers};
+use crate::pagination::Pagination;
+use crate::patch::{self, PatchFormat};
+use crate::validation::{ValidatedJson, Validate, Validator, MAX_BODY_CHARS, MAX_TITLE_CHARS};
 use crate::AppState;
 
+pub mod base_drift;
+pub mod closes;
 pub mod comments;
+pub mod commits;
+pub mod drafts;
+pub mod requested_reviewers;
 pub mod reviews;
 
 #[derive(Serialize, FromRow, Debug)]
 pub struct PullRequest {
     pub id: i32,
+    pub number: i32,
     pub repo_id: i32,
     pub title: String,
     pub body: Option<String>,
@@ -20,16 +35,92 @@ pub struct PullRequest {
     pub head_branch: String,
     pub author_id: i32,
     pub status: String,
+    pub draft: bool,
+    // How, by whom, when and into which commit the pull request was merged;
+    // all null until it is.
+    pub merge_method: Option<String>,
+    pub merged_by: Option<i32>,
+    pub merged_at: Option<chrono::DateTime<chrono::Utc>>,
+    pub merge_commit_sha: Option<String>,
     pub created_at: chrono::DateTime<chrono::Utc>,
     pub updated_at: chrono::DateTime<chrono::Utc>,
 }
 
+#[derive(Serialize, Clone, Copy, Debug)]
+pub struct DiffStats {
+    pub files_changed: usize,
+    pub additions: usize,
+    pub deletions: usize,
+}
+
+#[derive(Serialize)]
+pub struct PullRequestWithStats {
+    #[serde(flatten)]
+    pub pull_request: PullRequest,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub stats: Option<DiffStats>,
+}
+
+#[derive(Deserialize)]
+pub struct ListPullRequestsQuery {
+    #[serde(default)]
+    pub with_stats: bool,
+    pub since: Option<chrono::DateTime<chrono::Utc>>,
+}
+
+// Diff stats keyed by repository and the base/head commit oids. A push moves
+// the branch tips, so stale entries are simply never looked up again.
+pub type DiffStatsCache = Arc<Mutex<HashMap<(String, git2::Oid, git2::Oid), DiffStats>>>;
+
+const DIFF_STATS_CACHE_CAPACITY: usize = 1024;
+// Attempts at recording a merge whose update failed after the branches were
+// merged, each a little later than the last.
+const MERGE_RECORD_ATTEMPTS: u32 = 3;
+const MERGE_RECORD_BACKOFF: std::time::Duration = std::time::Duration::from_millis(200);
+
+// Counts the reviewers whose latest verdict approves the pull request and
+// those whose latest one requests changes. Comments carry no verdict, so they
+// neither replace an earlier approval nor count as one. Only reviewers who
+// currently hold the `write` role count, and never the author.
+const LATEST_REVIEW_VERDICTS: &str = r#"
+    SELECT
+        COUNT(*) FILTER (WHERE status = 'approved'),
+        COUNT(*) FILTER (WHERE status = 'changes_requested')
+    FROM (
+        SELECT DISTINCT ON (rv.reviewer_id) rv.status
+        FROM reviews rv
+        JOIN pull_requests pr ON pr.id = rv.pull_request_id
+        JOIN repositories r ON r.id = pr.repo_id
+        WHERE rv.pull_request_id = $1
+            AND rv.status <> 'commented'
+            AND rv.reviewer_id <> pr.author_id
+            AND (
+                rv.reviewer_id = r.user_id
+                OR EXISTS (SELECT 1 FROM repo_collaborators c WHERE c.repo_id = r.id AND c.user_id = rv.reviewer_id AND c.role IN ('write', 'admin'))
+            )
+        ORDER BY rv.reviewer_id, rv.created_at DESC, rv.id DESC
+    ) latest
+"#;
+
 #[derive(Deserialize)]
 pub struct NewPullRequest {
     pub title: String,
     pub body: Option<String>,
     pub base_branch: String,
     pub head_branch: String,
+    // Drafts cannot be merged until marked ready for review.
+    #[serde(default)]
+    pub draft: bool,
+}
+
+impl Validate for NewPullRequest {
+    fn validate(&self, v: &mut Validator) {
+        v.required("title", &self.title, MAX_TITLE_CHARS)
+            .max_chars("body", self.body.as_deref(), MAX_BODY_CHARS)
+            .required("base_branch", &self.base_branch, 255)
+            .required("head_branch", &self.head_branch, 255)
+            .check(self.base_branch != self.head_branch, "head_branch", "must differ from base_branch");
+    }
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
@@ -52,47 +143,88 @@ impl std::fmt::Display for PullRequestStatus {
     }
 }
 
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum MergeMethod {
+    // A merge commit with the base and head branches as parents.
+    #[default]
+    Merge,
+    // One commit on the base branch with the combined changes.
+    Squash,
+    // Each head commit replayed onto the base branch.
+    Rebase,
+}
+
+impl MergeMethod {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            MergeMethod::Merge => "merge",
+            MergeMethod::Squash => "squash",
+            MergeMethod::Rebase => "rebase",
+        }
+    }
+}
+
 #[derive(Deserialize, Debug)]
 pub struct UpdatePullRequest {
     pub status: Option<PullRequestStatus>,
     pub title: Option<String>,
     pub body: Option<String>,
+    // Only read when `status` is `merged`.
+    pub merge_method: Option<MergeMethod>,
 }
 
+impl Validate for UpdatePullRequest {
+    fn validate(&self, v: &mut Validator) {
+        if let Some(title) = &self.title {
+            v.required("title", title, MAX_TITLE_CHARS);
+        }
+        v.max_chars("body", self.body.as_deref(), MAX_BODY_CHARS);
+    }
+}
 
 #[axum::debug_handler]
 pub async fn create_pull_request(
     State(state): State<AppState>,
     AuthUser(user): AuthUser,
     Path(repo_name): Path<String>,
-    Json(new_pull_request): Json<NewPullRequest>,
+    ValidatedJson(new_pull_request): ValidatedJson<NewPullRequest>,
 ) -> Result<impl IntoResponse, (StatusCode, String)> {
-    let mut tx = state.pool.begin().await.map_err(|e| {
-        (
-            StatusCode::INTERNAL_SERVER_ERROR,
-            format!("Failed to start transaction: {}", e),
-        )
-    })?;
-
-    let repo_info: Option<(i32, String)> = sqlx::query_as(
-        r#"SELECT r.id, r.name FROM repositories r WHERE r.name = $1 AND (r.public OR r.user_id = $2)"#,
-    )
-    .bind(&repo_name)
-    .bind(user.id)
-    .fetch_optional(&mut *tx)
-    .await
-    .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, format!("Failed to get repo: {}", e)))?;
+    user.require_scope(Scope::RepoWrite)?;
+    let repo = find_writable_repo(&state.pool, &repo_name, user.id, "open pull requests in").await?;
+    let (repo_id, repo_name_from_db) = (repo.id, repo.name);
+
+    // The base tip is recorded so base drift can later be measured against
+    // what the pull request was opened on.
+    let base_sha = {
+        let repo = git2::Repository::open(format!("./repos/{}.git", repo_name_from_db))
+            .map_err(|_| (StatusCode::NOT_FOUND, "Repository not found".to_string()))?;
+        let base_commit = resolve_branch_commit(&repo, &new_pull_request.base_branch)?;
+        resolve_branch_commit(&repo, &new_pull_request.head_branch)?;
+        base_commit.id().to_string()
+    };
 
-    let (repo_id, _repo_name_from_db) = match repo_info {
-        Some((id, name)) => (id, name),
-        None => return Err((StatusCode::FORBIDDEN, "Repository not found or you don't have permission to create a pull request here.".to_string())),
+    // Resolved before the transaction opens so the diff does not hold it.
+    let (owners_repo, owners_base, owners_head) = (repo_name_from_db, new_pull_request.base_branch.clone(), new_pull_request.head_branch.clone());
+    let code_owners = match task::spawn_blocking(move || codeowners::requested_owners(&owners_repo, &owners_base, &owners_head)).await {
+        Ok(Ok(owners)) => owners,
+        Ok(Err(e)) => {
+            tracing::warn!("Failed to resolve code owners for a pull request in {}: {}", repo_name, e);
+            Vec::new()
+        }
+        Err(e) => {
+            tracing::warn!("Code owners task failed for a pull request in {}: {}", repo_name, e);
+            Vec::new()
+        }
     };
 
+    let mut tx = state.pool.begin().await.map_err(|e| db::error(e, "Failed to start transaction"))?;
+    let number = next_item_number(&mut tx, repo_id).await?;
     let pull_request = sqlx::query_as::<_, PullRequest>(
         r#"
-        INSERT INTO pull_requests (repo_id, title, body, base_branch, head_branch, author_id)
-        VALUES ($1, $2, $3, $4, $5, $6)
-        RETURNING id, repo_id, title, body, base_branch, head_branch, author_id, status, created_at, updated_at
+        INSERT INTO pull_requests (repo_id, title, body, base_branch, head_branch, author_id, base_sha, number, draft)
+        VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9)
+        RETURNING id, number, repo_id, title, body, base_branch, head_branch, author_id, status, draft, merge_method, merged_by, merged_at, merge_commit_sha, created_at, updated_at
         "#
     )
     .bind(repo_id)
@@ -101,11 +233,45 @@ pub async fn create_pull_request(
     .bind(&new_pull_request.base_branch)
     .bind(&new_pull_request.head_branch)
     .bind(user.id)
+    .bind(&base_sha)
+    .bind(number)
+    .bind(new_pull_request.draft)
     .fetch_one(&mut *tx)
     .await
-    .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, format!("Failed to create pull request: {}", e)))?;
+    .map_err(|e| db::error(e, "Failed to create pull request"))?;
+
+    if !code_owners.is_empty() {
+        sqlx::query(
+            r#"
+            INSERT INTO pull_request_reviewers (pull_request_id, user_id)
+            SELECT $1, id FROM users WHERE username = ANY($2) AND id <> $3
+            ON CONFLICT DO NOTHING
+            "#
+        )
+        .bind(pull_request.id)
+        .bind(&code_owners)
+        .bind(user.id)
+        .execute(&mut *tx)
+        .await
+        .map_err(|e| db::error(e, "Failed to request code owner reviews"))?;
+    }
+
+    let default_reviewers = default_user_ids(&mut tx, repo_id, DefaultUsers::Reviewers).await?;
+    sqlx::query(
+        r#"
+        INSERT INTO pull_request_reviewers (pull_request_id, user_id)
+        SELECT $1, reviewer FROM unnest($2::int[]) AS reviewer WHERE reviewer <> $3
+        ON CONFLICT DO NOTHING
+        "#
+    )
+    .bind(pull_request.id)
+    .bind(&default_reviewers)
+    .bind(user.id)
+    .execute(&mut *tx)
+    .await
+    .map_err(|e| db::error(e, "Failed to request default reviews"))?;
 
-    tx.commit().await.map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, format!("Failed to commit transaction: {}", e)))?;
+    tx.commit().await.map_err(|e| db::error(e, "Failed to commit transaction"))?;
 
     Ok((StatusCode::CREATED, Json(pull_request)))
 }
@@ -115,122 +281,278 @@ pub async fn list_pull_requests(
     State(state): State<AppState>,
     PermissiveAuthUser(user): PermissiveAuthUser,
     Path(repo_name): Path<String>,
+    Query(query): Query<ListPullRequestsQuery>,
+    pagination: Pagination,
 ) -> Result<impl IntoResponse, (StatusCode, String)> {
     let user_id = user.map(|u| u.id);
-    let repo_id_option: Option<i32> = sqlx::query_scalar(
-        r#"SELECT id FROM repositories WHERE name = $1 AND (public OR user_id = $2)"#
-    )
-    .bind(repo_name)
-    .bind(user_id)
-    .fetch_optional(&state.pool)
-    .await
-    .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, format!("Failed to get repo: {}", e)))?;
+    let repo_id = find_visible_repo(&state.pool, &repo_name, user_id).await?.id;
 
-    let repo_id = match repo_id_option {
-        Some(id) => id,
-        None => return Err((StatusCode::FORBIDDEN, "Repository not found or you don't have permission to view pull requests here.".to_string())),
-    };
+    let total: i64 = sqlx::query_scalar("SELECT COUNT(*) FROM pull_requests WHERE repo_id = $1 AND ($2::timestamptz IS NULL OR updated_at >= $2)")
+        .bind(repo_id)
+        .bind(query.since)
+        .fetch_one(&state.pool)
+        .await
+        .map_err(|e| db::error(e, "Failed to count pull requests"))?;
 
     let pull_requests = sqlx::query_as::<_, PullRequest>(
-        r#"SELECT id, repo_id, title, body, base_branch, head_branch, author_id, status, created_at, updated_at FROM pull_requests WHERE repo_id = $1"#
+        r#"
+        SELECT id, number, repo_id, title, body, base_branch, head_branch, author_id, status, draft, merge_method, merged_by, merged_at, merge_commit_sha, created_at, updated_at
+        FROM pull_requests
+        WHERE repo_id = $1 AND ($4::timestamptz IS NULL OR updated_at >= $4)
+        ORDER BY CASE WHEN $4 IS NOT NULL THEN updated_at END, id
+        LIMIT $2 OFFSET $3
+        "#
     )
     .bind(repo_id)
+    .bind(pagination.limit())
+    .bind(pagination.offset())
+    .bind(query.since)
     .fetch_all(&state.pool)
     .await
-    .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, format!("Failed to fetch pull requests: {}", e)))?;
+    .map_err(|e| db::error(e, "Failed to fetch pull requests"))?;
+
+    if !query.with_stats {
+        let pull_requests: Vec<PullRequestWithStats> = pull_requests
+            .into_iter()
+            .map(|pull_request| PullRequestWithStats { pull_request, stats: None })
+            .collect();
+        return Ok(pagination.respond(pull_requests, total));
+    }
 
-    Ok(Json(pull_requests))
+    let cache = state.diff_stats_cache.clone();
+    let pull_requests = task::spawn_blocking(move || {
+        let repo = git2::Repository::open(format!("./repos/{}.git", repo_name)).ok();
+        pull_requests
+            .into_iter()
+            .map(|pull_request| {
+                let stats = repo.as_ref().and_then(|repo| {
+                    cached_diff_stats(&cache, &repo_name, repo, &pull_request.base_branch, &pull_request.head_branch)
+                });
+                PullRequestWithStats { pull_request, stats }
+            })
+            .collect::<Vec<_>>()
+    })
+    .await
+    .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, format!("Task failed: {}", e)))?;
+
+    Ok(pagination.respond(pull_requests, total))
+}
+
+fn cached_diff_stats(cache: &DiffStatsCache, repo_name: &str, repo: &git2::Repository, base_branch: &str, head_branch: &str) -> Option<DiffStats> {
+    let base_commit = repo.find_reference(&format!("refs/heads/{}", base_branch)).and_then(|r| r.peel_to_commit()).ok()?;
+    let head_commit = repo.find_reference(&format!("refs/heads/{}", head_branch)).and_then(|r| r.peel_to_commit()).ok()?;
+    let key = (repo_name.to_string(), base_commit.id(), head_commit.id());
+
+    if let Some(stats) = cache.lock().ok()?.get(&key) {
+        return Some(*stats);
+    }
+
+    let diff = repo
+        .diff_tree_to_tree(Some(&base_commit.tree().ok()?), Some(&head_commit.tree().ok()?), None)
+        .ok()?;
+    let git_stats = diff.stats().ok()?;
+    let stats = DiffStats {
+        files_changed: git_stats.files_changed(),
+        additions: git_stats.insertions(),
+        deletions: git_stats.deletions(),
+    };
+
+    let mut cache = cache.lock().ok()?;
+    if cache.len() >= DIFF_STATS_CACHE_CAPACITY {
+        cache.clear();
+    }
+    cache.insert(key, stats);
+    Some(stats)
 }
 
 #[axum::debug_handler]
 pub async fn get_pull_request(
     State(state): State<AppState>,
     PermissiveAuthUser(user): PermissiveAuthUser,
-    Path((repo_name, pull_id)): Path<(String, i32)>,
-) -> Result<impl IntoResponse, (StatusCode, String)> {
+    Path((repo_name, pull_ref)): Path<(String, String)>,
+) -> Result<Response, (StatusCode, String)> {
     let user_id = user.map(|u| u.id);
-    let repo_id_option: Option<i32> = sqlx::query_scalar(
-        r#"SELECT id FROM repositories WHERE name = $1 AND (public OR user_id = $2)"#
-    )
-    .bind(repo_name)
-    .bind(user_id)
-    .fetch_optional(&state.pool)
-    .await
-    .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, format!("Failed to get repo: {}", e)))?;
 
-    let repo_id = match repo_id_option {
-        Some(id) => id,
-        None => return Err((StatusCode::FORBIDDEN, "Repository not found or you don't have permission to view this pull request.".to_string())),
-    };
+    // `/pulls/<number>.patch` and `/pulls/<number>.diff` share this route.
+    if let Some((pull_number, format)) = PatchFormat::from_path(&pull_ref) {
+        return pull_request_patch(&state, &repo_name, parse_pull_number(pull_number)?, user_id, format).await;
+    }
+    let pull_number = parse_pull_number(&pull_ref)?;
+
+    let repo_id = find_visible_repo(&state.pool, &repo_name, user_id).await?.id;
 
     let pull_request = sqlx::query_as::<_, PullRequest>(
-        r#"SELECT id, repo_id, title, body, base_branch, head_branch, author_id, status, created_at, updated_at FROM pull_requests WHERE repo_id = $1 AND id = $2"#
+        r#"SELECT id, number, repo_id, title, body, base_branch, head_branch, author_id, status, draft, merge_method, merged_by, merged_at, merge_commit_sha, created_at, updated_at FROM pull_requests WHERE repo_id = $1 AND number = $2"#
     )
     .bind(repo_id)
-    .bind(pull_id)
+    .bind(pull_number)
     .fetch_optional(&state.pool)
     .await
-    .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, format!("Failed to fetch pull request: {}", e)))?;
+    .map_err(|e| db::error(e, "Failed to fetch pull request"))?;
 
     match pull_request {
-        Some(pr) => Ok(Json(pr)),
+        Some(pr) => Ok(Json(pr).into_response()),
         None => Err((StatusCode::NOT_FOUND, "Pull request not found.".to_string())),
     }
 }
 
+fn parse_pull_number(pull_number: &str) -> Result<i32, (StatusCode, String)> {
+    pull_number.parse().map_err(|_| (StatusCode::BAD_REQUEST, format!("Invalid pull request number '{}'", pull_number)))
+}
+
+async fn pull_request_patch(state: &AppState, repo_name: &str, pull_number: i32, user_id: Option<i32>, format: PatchFormat) -> Result<Response, (StatusCode, String)> {
+    let repo_id = find_visible_repo(&state.pool, repo_name, user_id).await?.id;
+
+    let pr = sqlx::query_as::<_, PullRequest>("SELECT * FROM pull_requests WHERE number = $1 AND repo_id = $2")
+        .bind(pull_number)
+        .bind(repo_id)
+        .fetch_optional(&state.pool)
+        .await
+        .map_err(|e| db::error(e, "Failed to fetch pull request"))?
+        .ok_or_else(|| (StatusCode::NOT_FOUND, "Pull request not found.".to_string()))?;
+
+    let (repo_name, max_bytes) = (repo_name.to_string(), state.config.max_diff_bytes);
+    Ok(patch::render_blocking(move || {
+        let repo = git2::Repository::open(format!("./repos/{}.git", repo_name))
+            .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, format!("Failed to open repository: {}", e)))?;
+        let base_commit = resolve_branch_commit(&repo, &pr.base_branch)?;
+        let head_commit = resolve_branch_commit(&repo, &pr.head_branch)?;
+
+        let text = patch::format_range(&repo, &base_commit, &head_commit, format)
+            .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, format!("Failed to format patch: {}", e)))?;
+        patch::check_size(text, max_bytes)
+    })
+    .await)
+}
+
 #[axum::debug_handler]
 pub async fn update_pull_request(
     State(state): State<AppState>,
     AuthUser(user): AuthUser,
-    Path((repo_name, pull_id)): Path<(String, i32)>,
-    Json(update_payload): Json<UpdatePullRequest>,
+    Path((repo_name, pull_number)): Path<(String, i32)>,
+    ValidatedJson(update_payload): ValidatedJson<UpdatePullRequest>,
 ) -> Result<impl IntoResponse, (StatusCode, String)> {
-    let mut tx = state.pool.begin().await.map_err(|e| {
-        (
-            StatusCode::INTERNAL_SERVER_ERROR,
-            format!("Failed to start transaction: {}", e),
-        )
-    })?;
-
-    let repo_info: Option<(i32, String)> = sqlx::query_as(
-        r#"SELECT r.id, r.name FROM repositories r WHERE r.name = $1 AND (r.public OR r.user_id = $2)"#,
-    )
-    .bind(&repo_name)
-    .bind(user.id)
-    .fetch_optional(&mut *tx)
-    .await
-    .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, format!("Failed to get repo: {}", e)))?;
+    user.require_scope(Scope::RepoWrite)?;
+    let mut tx = state.pool.begin().await.map_err(|e| db::error(e, "Failed to start transaction"))?;
 
-    let (repo_id, repo_name_from_db) = match repo_info {
-        Some((id, name)) => (id, name),
-        None => return Err((StatusCode::FORBIDDEN, "Repository not found or you don't have permission to update pull requests here.".to_string())),
-    };
+    let repo = find_visible_repo(&mut *tx, &repo_name, Some(user.id)).await?;
+    let can_merge = repo.can_write(Some(user.id));
+    let (repo_id, repo_name_from_db) = (repo.id, repo.name);
 
+    // Locked so that concurrent requests to merge the same pull request
+    // cannot both merge the branches.
     let current_pr = sqlx::query_as::<_, PullRequest>(
-        "SELECT * FROM pull_requests WHERE id = $1 AND repo_id = $2"
+        "SELECT * FROM pull_requests WHERE number = $1 AND repo_id = $2 FOR UPDATE"
     )
-    .bind(pull_id)
+    .bind(pull_number)
     .bind(repo_id)
     .fetch_optional(&mut *tx)
     .await
-    .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, format!("Failed to fetch pull request: {}", e)))?
+    .map_err(|e| db::error(e, "Failed to fetch pull request"))?
     .ok_or_else(|| (StatusCode::NOT_FOUND, "Pull request not found.".to_string()))?;
+    let pull_id = current_pr.id;
+    // Beyond those with `write`, authors may edit and close their own.
+    if current_pr.author_id != user.id && !can_merge {
+        return Err((StatusCode::FORBIDDEN, "You do not have permission to update this pull request".to_string()));
+    }
+    // The branches stay merged whatever the row says, so a merged pull
+    // request can neither be reopened (and merged again) nor closed.
+    if current_pr.status == "merged" && update_payload.status.is_some_and(|status| status != PullRequestStatus::Merged) {
+        return Err((StatusCode::CONFLICT, "A merged pull request cannot change status".to_string()));
+    }
 
     let new_status = update_payload.status.map(|s| s.to_string()).unwrap_or_else(|| current_pr.status.clone());
+    let mut merge = None;
 
     if update_payload.status == Some(PullRequestStatus::Merged) && current_pr.status != "merged" {
-        if let Err(e) = perform_git_merge(&repo_name_from_db, &current_pr.base_branch, &current_pr.head_branch, &user.username).await {
-            return Err(e);
+        if !can_merge {
+            return Err((StatusCode::FORBIDDEN, "Merging requires write access to this repository".to_string()));
         }
+        if current_pr.draft {
+            return Err((StatusCode::CONFLICT, "Draft pull requests must be marked ready for review before merging".to_string()));
+        }
+        let (require_signoff, require_linear_history, required_approvals) = sqlx::query_as::<_, (bool, bool, i32)>(
+            "SELECT require_signoff, require_linear_history, required_approvals FROM repositories WHERE id = $1"
+        )
+        .bind(repo_id)
+        .fetch_one(&mut *tx)
+        .await
+        .map_err(|e| db::error(e, "Failed to fetch repository settings"))?;
+        let method = update_payload.merge_method.unwrap_or_default();
+        if require_linear_history && method == MergeMethod::Merge {
+            return Err((StatusCode::CONFLICT, "This repository requires linear history; merge with the squash or rebase method instead".to_string()));
+        }
+        if required_approvals > 0 {
+            let (approvals, changes_requested) = sqlx::query_as::<_, (i64, i64)>(LATEST_REVIEW_VERDICTS)
+                .bind(pull_id)
+                .fetch_one(&mut *tx)
+                .await
+                .map_err(|e| db::error(e, "Failed to count approvals"))?;
+            if changes_requested > 0 {
+                return Err((StatusCode::CONFLICT, format!("{} reviewer(s) requested changes; they must approve before this pull request can be merged", changes_requested)));
+            }
+            if approvals < i64::from(required_approvals) {
+                return Err((StatusCode::CONFLICT, format!("This repository requires {} approving review(s) before merging; this pull request has {}", required_approvals, approvals)));
+            }
+        }
+        if require_signoff {
+            let (repo_name, base_branch, head_branch) = (repo_name_from_db.clone(), current_pr.base_branch.clone(), current_pr.head_branch.clone());
+            let unsigned = task::spawn_blocking(move || commits::commits_missing_signoff(&repo_name, &base_branch, &head_branch))
+                .await
+                .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, format!("Task failed: {}", e)))??;
+            if !unsigned.is_empty() {
+                return Err((StatusCode::CONFLICT, format!("This repository requires a Signed-off-by trailer on every commit; missing on {}", unsigned.join(", "))));
+            }
+        }
+
+        let (repo_name, base_branch, head_branch) = (repo_name_from_db.clone(), current_pr.base_branch.clone(), current_pr.head_branch.clone());
+        let (squash_title, username) = (format!("{} (#{})", current_pr.title, current_pr.number), user.username.clone());
+        let email = account::commit_email(&state.pool, &state.config, user.id).await?;
+        let commit = task::spawn_blocking(move || perform_git_merge(&repo_name, &base_branch, &head_branch, &squash_title, &username, &email, method))
+            .await
+            .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, format!("Task failed: {}", e)))??;
+        merge = Some(MergeRecord { method, merged_by: user.id, commit_sha: commit.to_string() });
     }
 
-    let new_title = update_payload.title.unwrap_or(current_pr.title);
-    let new_body = update_payload.body.or(current_pr.body);
+    let new_title = update_payload.title.unwrap_or_else(|| current_pr.title.clone());
+    let new_body = update_payload.body.or_else(|| current_pr.body.clone());
 
+    let updated = save_update(tx, &current_pr, &new_status, &new_title, new_body.as_deref(), merge.as_ref()).await;
+    let updated_pr = match (updated, &merge) {
+        // The branches are merged already, so the pull request must not be
+        // left open even though the rest of the update is lost.
+        (Err(e), Some(merge)) => {
+            tracing::error!("Failed to record the merge of pull request {} as {}: {}", pull_id, merge.commit_sha, e.1);
+            record_merge(&state.pool, pull_id, merge).await.map_err(|_| e)?
+        }
+        (updated, _) => updated?,
+    };
+
+    Ok(Json(updated_pr))
+}
+
+struct MergeRecord {
+    method: MergeMethod,
+    merged_by: i32,
+    commit_sha: String,
+}
+
+async fn save_update(
+    mut tx: sqlx::Transaction<'_, sqlx::Postgres>,
+    current_pr: &PullRequest,
+    new_status: &str,
+    new_title: &str,
+    new_body: Option<&str>,
+    merge: Option<&MergeRecord>,
+) -> Result<PullRequest, (StatusCode, String)> {
     let updated_pr = sqlx::query_as::<_, PullRequest>(
         r#"
         UPDATE pull_requests
-        SET status = $1, title = $2, body = $3, updated_at = now()
+        SET status = $1, title = $2, body = $3, updated_at = now(),
+            merge_method = COALESCE($6, merge_method),
+            merged_by = COALESCE($7, merged_by),
+            merged_at = CASE WHEN $6::text IS NULL THEN merged_at ELSE now() END,
+            merge_commit_sha = COALESCE($8, merge_commit_sha)
         WHERE id = $4 AND repo_id = $5
         RETURNING *
         "#,
@@ -238,90 +560,188 @@ pub async fn update_pull_request(
     .bind(new_status)
     .bind(new_title)
     .bind(new_body)
-    .bind(pull_id)
-    .bind(repo_id)
+    .bind(current_pr.id)
+    .bind(current_pr.repo_id)
+    .bind(merge.map(|merge| merge.method.as_str()))
+    .bind(merge.map(|merge| merge.merged_by))
+    .bind(merge.map(|merge| merge.commit_sha.as_str()))
     .fetch_one(&mut *tx)
     .await
-    .map_err(|e| (
-        StatusCode::INTERNAL_SERVER_ERROR,
-        format!("Failed to update pull request: {}", e),
-    ))?;
+    .map_err(|e| db::error(e, "Failed to update pull request"))?;
 
-    tx.commit().await.map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, format!("Failed to commit transaction: {}", e)))?;
+    if updated_pr.status == "merged" && current_pr.status != "merged" {
+        closes::close_referenced_issues(&mut tx, current_pr.repo_id, updated_pr.body.as_deref())
+            .await
+            .map_err(|e| db::error(e, "Failed to close referenced issues"))?;
+    }
 
-    Ok(Json(updated_pr))
+    tx.commit().await.map_err(|e| db::error(e, "Failed to commit transaction"))?;
+    Ok(updated_pr)
 }
 
+// Marks a pull request merged on its own, retrying a few times, for when the
+// update that should have recorded the merge failed.
+async fn record_merge(pool: &sqlx::PgPool, pull_id: i32, merge: &MergeRecord) -> Result<PullRequest, sqlx::Error> {
+    let mut attempt = 1;
+    loop {
+        match try_record_merge(pool, pull_id, merge).await {
+            Err(e) if attempt < MERGE_RECORD_ATTEMPTS => {
+                tracing::warn!("Retrying to record the merge of pull request {}: {}", pull_id, e);
+                tokio::time::sleep(MERGE_RECORD_BACKOFF * attempt).await;
+                attempt += 1;
+            }
+            Err(e) => {
+                tracing::error!("Gave up recording the merge of pull request {} as {}: {}", pull_id, merge.commit_sha, e);
+                return Err(e);
+            }
+            Ok(pr) => return Ok(pr),
+        }
+    }
+}
 
-async fn perform_git_merge(repo_name: &str, base_branch: &str, head_branch: &str, username: &str) -> Result<(), (StatusCode, String)> {
+async fn try_record_merge(pool: &sqlx::PgPool, pull_id: i32, merge: &MergeRecord) -> Result<PullRequest, sqlx::Error> {
+    let mut tx = pool.begin().await?;
+    let pr = sqlx::query_as::<_, PullRequest>(
+        r#"
+        UPDATE pull_requests
+        SET status = 'merged', merge_method = $2, merged_by = $3, merged_at = now(), merge_commit_sha = $4, updated_at = now()
+        WHERE id = $1
+        RETURNING *
+        "#,
+    )
+    .bind(pull_id)
+    .bind(merge.method.as_str())
+    .bind(merge.merged_by)
+    .bind(&merge.commit_sha)
+    .fetch_one(&mut *tx)
+    .await?;
+    closes::close_referenced_issues(&mut tx, pr.repo_id, pr.body.as_deref()).await?;
+    tx.commit().await?;
+    Ok(pr)
+}
+
+// Merges `head_branch` into `base_branch` with `method`, moves the base
+// branch to the result and returns it. `squash_title` heads the message of a
+// squash commit. The base branch is only moved if it still points where the
+// merge started from.
+fn perform_git_merge(repo_name: &str, base_branch: &str, head_branch: &str, squash_title: &str, username: &str, email: &str, method: MergeMethod) -> Result<git2::Oid, (StatusCode, String)> {
     let repo_path = format!("./repos/{}.git", repo_name);
     let repo = git2::Repository::open(repo_path).map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, format!("Failed to open repository: {}", e)))?;
 
     let base_ref = format!("refs/heads/{}", base_branch);
-    let head_ref = format!("refs/heads/{}", head_branch);
 
-    let base_commit = repo.find_reference(&base_ref).and_then(|r| r.peel_to_commit()).map_err(|e| (StatusCode::BAD_REQUEST, format!("Base branch not found: {}", e)))?;
-    let head_commit = repo.find_reference(&head_ref).and_then(|r| r.peel_to_commit()).map_err(|e| (StatusCode::BAD_REQUEST, format!("Head branch not found: {}", e)))?;
+    let base_commit = resolve_branch_commit(&repo, base_branch)?;
+    let head_commit = resolve_branch_commit(&repo, head_branch)?;
 
-    let mut index = repo.merge_commits(&base_commit, &head_commit, None).map_err(|e| (StatusCode::CONFLICT, format!("Merge conflict: {}", e)))?;
+    let signature = git2::Signature::now(username, email).map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, format!("Failed to create signature: {}", e)))?;
+
+    let new_tip = match method {
+        MergeMethod::Merge | MergeMethod::Squash => {
+            let tree = merged_tree(&repo, &base_commit, &head_commit)?;
+            let (message, parents) = match method {
+                MergeMethod::Merge => (format!("Merge pull request from {} into {}", head_branch, base_branch), vec![&base_commit, &head_commit]),
+                _ => (squash_message(&repo, squash_title, &base_commit, &head_commit)?, vec![&base_commit]),
+            };
+            repo.commit(None, &signature, &signature, &message, &tree, &parents)
+                .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, format!("Failed to create merge commit: {}", e)))?
+        }
+        MergeMethod::Rebase => replay_commits(&repo, &base_commit, &head_commit, &signature)?,
+    };
+
+    // libgit2 compares the ref's current target with `base_commit` while
+    // holding the ref's lock, so a push that lands mid-merge fails this
+    // with `Modified` instead of being overwritten.
+    repo.reference_matching(&base_ref, new_tip, true, base_commit.id(), &format!("{} pull request from {}", method.as_str(), head_branch))
+        .map_err(|e| match e.code() {
+            git2::ErrorCode::Modified => (StatusCode::CONFLICT, format!("'{}' changed while merging; try again", base_branch)),
+            _ => (StatusCode::INTERNAL_SERVER_ERROR, format!("Failed to update base branch: {}", e)),
+        })?;
+
+    Ok(new_tip)
+}
+
+fn merged_tree<'r>(repo: &'r git2::Repository, base: &git2::Commit<'_>, head: &git2::Commit<'_>) -> Result<git2::Tree<'r>, (StatusCode, String)> {
+    let mut index = repo.merge_commits(base, head, None).map_err(|e| (StatusCode::CONFLICT, format!("Merge conflict: {}", e)))?;
 
     if index.has_conflicts() {
         return Err((StatusCode::CONFLICT, "Merge has conflicts. Please resolve them manually.".to_string()));
     }
 
-    let oid = index.write_tree_to(&repo).map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, format!("Failed to write merge tree: {}", e)))?;
-    let tree = repo.find_tree(oid).map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, format!("Failed to find merge tree: {}", e)))?;
-
-    let signature = git2::Signature::now(username, "user@example.com").map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, format!("Failed to create signature: {}", e)))?;
-    let message = format!("Merge pull request from {} into {}", head_branch, base_branch);
+    let oid = index.write_tree_to(repo).map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, format!("Failed to write merge tree: {}", e)))?;
+    repo.find_tree(oid).map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, format!("Failed to find merge tree: {}", e)))
+}
 
-    let merge_commit_oid = repo.commit(
-        Some(&base_ref),
-        &signature,
-        &signature,
-        &message,
-        &tree,
-        &[&base_commit, &head_commit],
-    ).map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, format!("Failed to create merge commit: {}", e)))?;
+// The head commits not yet on the base branch, oldest first.
+fn unmerged_commits<'r>(repo: &'r git2::Repository, base: &git2::Commit<'_>, head: &git2::Commit<'_>) -> Result<Vec<git2::Commit<'r>>, git2::Error> {
+    let mut revwalk = repo.revwalk()?;
+    revwalk.set_sorting(git2::Sort::TOPOLOGICAL | git2::Sort::REVERSE)?;
+    revwalk.push(head.id())?;
+    revwalk.hide(base.id())?;
+    revwalk.map(|oid| repo.find_commit(oid?)).collect()
+}
 
-    let mut base_branch_ref = repo.find_reference(&base_ref).map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, format!("Failed to find base branch reference: {}", e)))?;
-    base_branch_ref.set_target(merge_commit_oid, "Fast-forward merge").map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, format!("Failed to update base branch: {}", e)))?;
+fn squash_message(repo: &git2::Repository, title: &str, base: &git2::Commit<'_>, head: &git2::Commit<'_>) -> Result<String, (StatusCode, String)> {
+    let commits = unmerged_commits(repo, base, head).map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, format!("Failed to list commits: {}", e)))?;
+    let mut message = format!("{}\n", title);
+    if !commits.is_empty() {
+        message.push('\n');
+    }
+    for commit in &commits {
+        message.push_str(&format!("* {}\n", commit.summary().unwrap_or("")));
+    }
+    Ok(message)
+}
 
-    Ok(())
+// Cherry-picks each head commit onto the base branch in order, keeping its
+// author and message, and returns the last one. Like `git rebase`, merge
+// commits are dropped and commits whose changes the base branch already has
+// are skipped.
+fn replay_commits(repo: &git2::Repository, base: &git2::Commit<'_>, head: &git2::Commit<'_>, committer: &git2::Signature<'_>) -> Result<git2::Oid, (StatusCode, String)> {
+    let commits = unmerged_commits(repo, base, head).map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, format!("Failed to list commits: {}", e)))?;
+
+    let mut onto = base.clone();
+    for commit in commits.iter().filter(|commit| commit.parent_count() == 1) {
+        let mut index = repo
+            .cherrypick_commit(commit, &onto, 0, None)
+            .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, format!("Failed to replay {}: {}", commit.id(), e)))?;
+        if index.has_conflicts() {
+            return Err((StatusCode::CONFLICT, format!("Commit {:.7} does not apply cleanly onto the base branch; it cannot be rebased", commit.id().to_string())));
+        }
+        let tree_id = index.write_tree_to(repo).map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, format!("Failed to write tree: {}", e)))?;
+        if tree_id == onto.tree_id() {
+            continue;
+        }
+        let tree = repo.find_tree(tree_id).map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, format!("Failed to find tree: {}", e)))?;
+        let message = String::from_utf8_lossy(commit.message_bytes());
+        let oid = repo
+            .commit(None, &commit.author(), committer, &message, &tree, &[&onto])
+            .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, format!("Failed to create commit: {}", e)))?;
+        onto = repo.find_commit(oid).map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, format!("Failed to find commit: {}", e)))?;
+    }
+    Ok(onto.id())
 }
 
 #[axum::debug_handler]
 pub async fn get_pull_request_diff(
     State(state): State<AppState>,
     PermissiveAuthUser(user): PermissiveAuthUser,
-    Path((repo_name, pull_id)): Path<(String, i32)>,
+    Path((repo_name, pull_number)): Path<(String, i32)>,
 ) -> Result<impl IntoResponse, (StatusCode, String)> {
     let user_id = user.map(|u| u.id);
 
-    let repo_id_option: Option<i32> = sqlx::query_scalar(
-        r#"SELECT id FROM repositories WHERE name = $1 AND (public OR user_id = $2)"#
-    )
-    .bind(&repo_name)
-    .bind(user_id)
-    .fetch_optional(&state.pool)
-    .await
-    .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, format!("Failed to get repo: {}", e)))?;
-
-    let repo_id = match repo_id_option {
-        Some(id) => id,
-        None => return Err((StatusCode::FORBIDDEN, "Repository not found or you don't have permission.".to_string())),
-    };
+    let repo_id = find_visible_repo(&state.pool, &repo_name, user_id).await?.id;
 
     let pr = sqlx::query_as::<_, PullRequest>(
-        "SELECT * FROM pull_requests WHERE id = $1 AND repo_id = $2"
+        "SELECT * FROM pull_requests WHERE number = $1 AND repo_id = $2"
     )
-    .bind(pull_id)
+    .bind(pull_number)
     .bind(repo_id)
     .fetch_optional(&state.pool)
     .await
-    .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, format!("Failed to fetch pull request: {}", e)))?
+    .map_err(|e| db::error(e, "Failed to fetch pull request"))?
     .ok_or_else(|| (StatusCode::NOT_FOUND, "Pull request not found.".to_string()))?;
 
+    let max_bytes = state.config.max_diff_bytes;
     let result = task::spawn_blocking(move || {
         let repo_path = format!("./repos/{}.git", repo_name);
         let repo = match git2::Repository::open(&repo_path) {
@@ -329,17 +749,8 @@ pub async fn get_pull_request_diff(
             Err(e) => return Err((StatusCode::INTERNAL_SERVER_ERROR, format!("Failed to open repository: {}", e))),
         };
 
-        let base_ref = format!("refs/heads/{}", pr.base_branch);
-        let head_ref = format!("refs/heads/{}", pr.head_branch);
-
-        let base_commit = match repo.find_reference(&base_ref).and_then(|r| r.peel_to_commit()) {
-            Ok(commit) => commit,
-            Err(e) => return Err((StatusCode::BAD_REQUEST, format!("Base branch not found: {}", e))),
-        };
-        let head_commit = match repo.find_reference(&head_ref).and_then(|r| r.peel_to_commit()) {
-            Ok(commit) => commit,
-            Err(e) => return Err((StatusCode::BAD_REQUEST, format!("Head branch not found: {}", e))),
-        };
+        let base_commit = resolve_branch_commit(&repo, &pr.base_branch)?;
+        let head_commit = resolve_branch_commit(&repo, &pr.head_branch)?;
 
         let base_tree = match base_commit.tree() {
             Ok(tree) => tree,
@@ -357,7 +768,7 @@ pub async fn get_pull_request_diff(
         };
 
         match format_diff(&diff) {
-            Ok(diff_text) => Ok(diff_text),
+            Ok(diff_text) => patch::check_size(diff_text, max_bytes),
             Err(e) => Err((StatusCode::INTERNAL_SERVER_ERROR, format!("Failed to format diff: {}", e))),
         }
     })
@@ -367,16 +778,159 @@ pub async fn get_pull_request_diff(
     result
 }
 
-fn format_diff(diff: &git2::Diff<'_>) -> Result<String, git2::Error> {
+// Resolves a pull request's per-repository number to its id, given the caller
+// can see the repository, or the same `404` whether the repository or the
+// pull request is missing.
+pub(crate) async fn find_visible_pull_request(state: &AppState, repo_name: &str, pull_number: i32, user_id: Option<i32>) -> Result<i32, (StatusCode, String)> {
+    let repo_id = find_visible_repo(&state.pool, repo_name, user_id).await?.id;
+    sqlx::query_scalar("SELECT id FROM pull_requests WHERE number = $1 AND repo_id = $2")
+        .bind(pull_number)
+        .bind(repo_id)
+        .fetch_optional(&state.pool)
+        .await
+        .map_err(|e| db::error(e, "Failed to fetch pull request"))?
+        .ok_or_else(|| (StatusCode::NOT_FOUND, "Pull request not found.".to_string()))
+}
+
+pub(crate) fn changed_files(repo: &git2::Repository, base_tree: &git2::Tree<'_>, head_tree: &git2::Tree<'_>) -> Result<Vec<String>, git2::Error> {
+    let diff = repo.diff_tree_to_tree(Some(base_tree), Some(head_tree), None)?;
+    Ok(diff
+        .deltas()
+        .filter_map(|delta| delta.new_file().path().or_else(|| delta.old_file().path()).map(|p| p.to_string_lossy().into_owned()))
+        .collect())
+}
+
+// File and hunk headers carry their full text in `content`, so only body lines
+// get the origin prepended; the result applies cleanly with `git apply`.
+pub(crate) fn format_diff(diff: &git2::Diff<'_>) -> Result<String, git2::Error> {
     let mut diff_text = String::new();
     diff.print(git2::DiffFormat::Patch, |_, _, line| {
-        let line_char = match line.origin() {
-            '+' | '-' | ' ' => line.origin(),
-            _ => ' ',
-        };
-        diff_text.push(line_char);
-        diff_text.push_str(std::str::from_utf8(line.content()).unwrap_or(""));
+        if let '+' | '-' | ' ' = line.origin() {
+            diff_text.push(line.origin());
+        }
+        diff_text.push_str(&String::from_utf8_lossy(line.content()));
         true
     })?;
     Ok(diff_text)
 }
+
+#[cfg(test)]
+mod tests {
+    use axum::http::StatusCode;
+    use serde_json::json;
+    use sqlx::PgPool;
+
+    use crate::test_support::{commit_file, TestApp};
+
+    // A repository with pull request #1 from `feature` opened by its owner.
+    // Returns the owner's token and the repository.
+    pub(crate) async fn open_pull_request(app: &mut TestApp) -> (String, String) {
+        let owner = app.sign_up("owner").await;
+        let repo = app.create_repo(&owner, true).await;
+        commit_file(&repo, "feature", "feature.txt", "feature\n");
+        let response = app
+            .post(&format!("/repos/{}/pulls", repo), Some(&owner), json!({ "title": "Feature", "base_branch": "main", "head_branch": "feature" }))
+            .await;
+        assert_eq!(response.status, StatusCode::CREATED, "{}", response.body);
+        (owner, repo)
+    }
+
+    async fn pull_request_needing_approval(app: &mut TestApp) -> (String, String) {
+        let (owner, repo) = open_pull_request(app).await;
+        let response = app.patch(&format!("/repos/{}", repo), Some(&owner), json!({ "required_approvals": 1 })).await;
+        assert_eq!(response.status, StatusCode::OK, "{}", response.body);
+        (owner, repo)
+    }
+
+    #[sqlx::test]
+    async fn merged_pull_requests_keep_their_status(pool: PgPool) {
+        let mut app = TestApp::new(pool);
+        let (owner, repo) = open_pull_request(&mut app).await;
+        let merge = app.patch(&format!("/repos/{}/pulls/1", repo), Some(&owner), json!({ "status": "merged" })).await;
+        assert_eq!(merge.status, StatusCode::OK, "{}", merge.body);
+
+        for status in ["open", "closed"] {
+            let response = app.patch(&format!("/repos/{}/pulls/1", repo), Some(&owner), json!({ "status": status })).await;
+            assert_eq!(response.status, StatusCode::CONFLICT);
+        }
+        let response = app.patch(&format!("/repos/{}/pulls/1", repo), Some(&owner), json!({ "title": "Renamed" })).await;
+        assert_eq!(response.status, StatusCode::OK, "{}", response.body);
+        assert_eq!(response.body["status"], "merged");
+        assert_eq!(response.body["merge_commit_sha"], merge.body["merge_commit_sha"]);
+    }
+
+    #[sqlx::test]
+    async fn unsigned_commits_block_the_merge(pool: PgPool) {
+        let mut app = TestApp::new(pool);
+        let (owner, repo) = open_pull_request(&mut app).await;
+        let response = app.patch(&format!("/repos/{}", repo), Some(&owner), json!({ "require_signoff": true })).await;
+        assert_eq!(response.status, StatusCode::OK, "{}", response.body);
+
+        let merge = app.patch(&format!("/repos/{}/pulls/1", repo), Some(&owner), json!({ "status": "merged" })).await;
+        assert_eq!(merge.status, StatusCode::CONFLICT);
+        let commits = app.get(&format!("/repos/{}/pulls/1/commits", repo), Some(&owner)).await;
+        assert!(merge.body.as_str().unwrap().contains(commits.body[0]["id"].as_str().unwrap()), "{}", merge.body);
+    }
+
+    #[sqlx::test]
+    async fn unreadable_commits_are_not_skipped(pool: PgPool) {
+        let mut app = TestApp::new(pool);
+        let (owner, repo) = open_pull_request(&mut app).await;
+        let response = app.patch(&format!("/repos/{}", repo), Some(&owner), json!({ "require_signoff": true })).await;
+        assert_eq!(response.status, StatusCode::OK, "{}", response.body);
+
+        // A signed-off tip on top of an unsigned commit that is then lost,
+        // as a corrupt repository would.
+        let git = git2::Repository::open(format!("./repos/{}.git", repo)).unwrap();
+        let unsigned = git.find_branch("feature", git2::BranchType::Local).unwrap().get().peel_to_commit().unwrap();
+        let signature = git2::Signature::now("Test", "test@example.com").unwrap();
+        git.commit(Some("refs/heads/feature"), &signature, &signature, "Sign\n\nSigned-off-by: Test <test@example.com>\n", &unsigned.tree().unwrap(), &[&unsigned])
+            .unwrap();
+        let lost = unsigned.id().to_string();
+        std::fs::remove_file(format!("./repos/{}.git/objects/{}/{}", repo, &lost[..2], &lost[2..])).unwrap();
+
+        let commits = app.get(&format!("/repos/{}/pulls/1/commits", repo), Some(&owner)).await;
+        assert_eq!(commits.status, StatusCode::INTERNAL_SERVER_ERROR);
+        let merge = app.patch(&format!("/repos/{}/pulls/1", repo), Some(&owner), json!({ "status": "merged" })).await;
+        assert_eq!(merge.status, StatusCode::INTERNAL_SERVER_ERROR);
+    }
+
+    #[sqlx::test]
+    async fn merge_waits_for_required_approvals(pool: PgPool) {
+        let mut app = TestApp::new(pool);
+        let (owner, repo) = pull_request_needing_approval(&mut app).await;
+
+        let merge = app.patch(&format!("/repos/{}/pulls/1", repo), Some(&owner), json!({ "status": "merged" })).await;
+        assert_eq!(merge.status, StatusCode::CONFLICT);
+
+        let bob = app.sign_up("bob").await;
+        app.add_collaborator(&owner, &repo, "bob", "write").await;
+        let review = app.post(&format!("/repos/{}/pulls/1/reviews", repo), Some(&bob), json!({ "status": "approved" })).await;
+        assert_eq!(review.status, StatusCode::CREATED, "{}", review.body);
+
+        let merge = app.patch(&format!("/repos/{}/pulls/1", repo), Some(&owner), json!({ "status": "merged" })).await;
+        assert_eq!(merge.status, StatusCode::OK, "{}", merge.body);
+        assert_eq!(merge.body["status"], "merged");
+    }
+
+    #[sqlx::test]
+    async fn approvals_only_count_from_reviewers_with_write_access(pool: PgPool) {
+        let mut app = TestApp::new(pool);
+        let (owner, repo) = pull_request_needing_approval(&mut app).await;
+
+        // Without a role on the repository there is no review to count.
+        let carol = app.sign_up("carol").await;
+        let review = app.post(&format!("/repos/{}/pulls/1/reviews", repo), Some(&carol), json!({ "status": "approved" })).await;
+        assert_eq!(review.status, StatusCode::FORBIDDEN);
+
+        // An approval stops counting once its reviewer loses write access.
+        let bob = app.sign_up("bob").await;
+        app.add_collaborator(&owner, &repo, "bob", "write").await;
+        let review = app.post(&format!("/repos/{}/pulls/1/reviews", repo), Some(&bob), json!({ "status": "approved" })).await;
+        assert_eq!(review.status, StatusCode::CREATED, "{}", review.body);
+        app.add_collaborator(&owner, &repo, "bob", "read").await;
+
+        let merge = app.patch(&format!("/repos/{}/pulls/1", repo), Some(&owner), json!({ "status": "merged" })).await;
+        assert_eq!(merge.status, StatusCode::CONFLICT);
+    }
+}
@@ -0,0 +1,88 @@
+use axum::{extract::{Path, State}, http::StatusCode, response::IntoResponse, Json};
+use serde::Serialize;
+use tokio::task;
+
+use crate::auth::PermissiveAuthUser;
+use crate::db;
+use crate::git_api::{find_visible_repo, resolve_branch_commit, Commit};
+use crate::AppState;
+
+const MAX_DRIFT_COMMITS: usize = 250;
+
+#[derive(Serialize)]
+pub struct BaseDrift {
+    base_branch: String,
+    original_base_sha: String,
+    current_base_sha: String,
+    // Commits that landed on the base branch since the pull request was
+    // opened, newest first and capped at `MAX_DRIFT_COMMITS`.
+    ahead_by: usize,
+    commits: Vec<Commit>,
+    // Base commits the head branch does not contain yet.
+    behind_by: usize,
+    needs_update: bool,
+}
+
+fn compute_drift(repo_name: &str, base_branch: String, head_branch: &str, original_base_sha: String) -> Result<BaseDrift, (StatusCode, String)> {
+    let repo = git2::Repository::open(format!("./repos/{}.git", repo_name))
+        .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, format!("Failed to open repository: {}", e)))?;
+    let current_base = resolve_branch_commit(&repo, &base_branch)?;
+    let head = resolve_branch_commit(&repo, head_branch)?;
+    let original_base = git2::Oid::from_str(&original_base_sha)
+        .and_then(|oid| repo.find_commit(oid))
+        .map_err(|_| (StatusCode::CONFLICT, "The base commit this pull request was opened on no longer exists".to_string()))?;
+
+    let git_error = |e: git2::Error| (StatusCode::INTERNAL_SERVER_ERROR, format!("Failed to compare commits: {}", e));
+    let mut revwalk = repo.revwalk().map_err(git_error)?;
+    revwalk.push(current_base.id()).map_err(git_error)?;
+    revwalk.hide(original_base.id()).map_err(git_error)?;
+
+    let mut ahead_by = 0;
+    let mut commits = Vec::new();
+    for oid in revwalk.flatten() {
+        ahead_by += 1;
+        if commits.len() < MAX_DRIFT_COMMITS {
+            commits.extend(repo.find_commit(oid).ok().map(|commit| Commit::from_git(&commit)));
+        }
+    }
+
+    let (_, behind_by) = repo.graph_ahead_behind(head.id(), current_base.id()).map_err(git_error)?;
+
+    Ok(BaseDrift {
+        base_branch,
+        original_base_sha,
+        current_base_sha: current_base.id().to_string(),
+        ahead_by,
+        commits,
+        behind_by,
+        needs_update: behind_by > 0,
+    })
+}
+
+#[axum::debug_handler]
+pub async fn get_base_drift(
+    State(state): State<AppState>,
+    PermissiveAuthUser(user): PermissiveAuthUser,
+    Path((repo_name, pull_number)): Path<(String, i32)>,
+) -> Result<impl IntoResponse, (StatusCode, String)> {
+    let repo = find_visible_repo(&state.pool, &repo_name, user.map(|u| u.id)).await?;
+
+    let (base_branch, head_branch, base_sha) = sqlx::query_as::<_, (String, String, Option<String>)>(
+        "SELECT base_branch, head_branch, base_sha FROM pull_requests WHERE number = $1 AND repo_id = $2"
+    )
+    .bind(pull_number)
+    .bind(repo.id)
+    .fetch_optional(&state.pool)
+    .await
+    .map_err(|e| db::error(e, "Failed to fetch pull request"))?
+    .ok_or_else(|| (StatusCode::NOT_FOUND, "Pull request not found.".to_string()))?;
+
+    let base_sha = base_sha
+        .ok_or_else(|| (StatusCode::CONFLICT, "The base commit was not recorded for this pull request".to_string()))?;
+
+    let drift = task::spawn_blocking(move || compute_drift(&repo.name, base_branch, &head_branch, base_sha))
+        .await
+        .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, format!("Task failed: {}", e)))??;
+
+    Ok(Json(drift))
+}
@@ -0,0 +1,120 @@
+use axum::{extract::{Path, State}, http::StatusCode, response::IntoResponse, Json};
+use serde::Serialize;
+use sqlx::PgConnection;
+
+use crate::auth::{AuthUser, Scope};
+use crate::db;
+use crate::git_api::find_administered_repo;
+use crate::AppState;
+
+use super::PullRequest;
+
+const CLOSING_KEYWORDS: [&str; 9] = ["close", "closes", "closed", "fix", "fixes", "fixed", "resolve", "resolves", "resolved"];
+
+#[derive(Serialize)]
+pub struct AppliedCloses {
+    referenced: Vec<i32>,
+    closed: Vec<i32>,
+}
+
+// Finds `Fixes #12`-style references in a pull request body. Each reference
+// needs its own keyword, as on GitHub: `Closes #1, closes #2`.
+pub fn closing_issue_references(text: &str) -> Vec<i32> {
+    let mut references = Vec::new();
+    let mut after_keyword = false;
+    for word in text.split_whitespace() {
+        if after_keyword {
+            let number = word
+                .strip_prefix('#')
+                .map(|n| n.trim_end_matches(|c: char| !c.is_ascii_digit()))
+                .and_then(|n| n.parse::<i32>().ok());
+            if let Some(number) = number.filter(|n| !references.contains(n)) {
+                references.push(number);
+            }
+        }
+        let keyword = word.trim_end_matches(':').to_ascii_lowercase();
+        after_keyword = CLOSING_KEYWORDS.contains(&keyword.as_str());
+    }
+    references
+}
+
+// Closes the still-open issues of `repo_id` that the body references. Safe to
+// run repeatedly: already-closed issues and unknown numbers are left alone.
+pub async fn close_referenced_issues(conn: &mut PgConnection, repo_id: i32, body: Option<&str>) -> Result<Vec<i32>, sqlx::Error> {
+    let references = closing_issue_references(body.unwrap_or(""));
+    if references.is_empty() {
+        return Ok(Vec::new());
+    }
+
+    sqlx::query_scalar(
+        "UPDATE issues SET status = 'closed', closed_at = NOW(), updated_at = NOW() WHERE repo_id = $1 AND number = ANY($2) AND status <> 'closed' RETURNING number"
+    )
+    .bind(repo_id)
+    .bind(&references)
+    .fetch_all(conn)
+    .await
+}
+
+#[axum::debug_handler]
+pub async fn apply_closes(
+    State(state): State<AppState>,
+    AuthUser(user): AuthUser,
+    Path((repo_name, pull_number)): Path<(String, i32)>,
+) -> Result<impl IntoResponse, (StatusCode, String)> {
+    user.require_scope(Scope::RepoWrite)?;
+    let mut tx = state.pool.begin().await.map_err(|e| db::error(e, "Failed to start transaction"))?;
+
+    let repo = find_administered_repo(&mut *tx, &repo_name, user.id, "re-apply closing keywords in").await?;
+
+    let pull_request = sqlx::query_as::<_, PullRequest>("SELECT * FROM pull_requests WHERE number = $1 AND repo_id = $2")
+        .bind(pull_number)
+        .bind(repo.id)
+        .fetch_optional(&mut *tx)
+        .await
+        .map_err(|e| db::error(e, "Failed to fetch pull request"))?
+        .ok_or_else(|| (StatusCode::NOT_FOUND, "Pull request not found.".to_string()))?;
+
+    if pull_request.status != "merged" {
+        return Err((StatusCode::CONFLICT, "Only merged pull requests close issues.".to_string()));
+    }
+
+    let closed = close_referenced_issues(&mut tx, repo.id, pull_request.body.as_deref())
+        .await
+        .map_err(|e| db::error(e, "Failed to close issues"))?;
+
+    tx.commit().await.map_err(|e| db::error(e, "Failed to commit transaction"))?;
+
+    Ok(Json(AppliedCloses {
+        referenced: closing_issue_references(pull_request.body.as_deref().unwrap_or("")),
+        closed,
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::http::StatusCode;
+    use serde_json::json;
+    use sqlx::PgPool;
+
+    use crate::pull_requests::tests::open_pull_request;
+    use crate::test_support::TestApp;
+
+    #[sqlx::test]
+    async fn only_admins_reapply_closing_keywords(pool: PgPool) {
+        let mut app = TestApp::new(pool);
+        let (owner, repo) = open_pull_request(&mut app).await;
+        let merge = app.patch(&format!("/repos/{}/pulls/1", repo), Some(&owner), json!({ "status": "merged" })).await;
+        assert_eq!(merge.status, StatusCode::OK, "{}", merge.body);
+
+        let uri = format!("/repos/{}/pulls/1/apply_closes", repo);
+        let writer = app.sign_up("writer").await;
+        app.add_collaborator(&owner, &repo, "writer", "write").await;
+        assert_eq!(app.post(&uri, Some(&writer), json!({})).await.status, StatusCode::FORBIDDEN);
+
+        let admin = app.sign_up("admin").await;
+        app.add_collaborator(&owner, &repo, "admin", "admin").await;
+        let response = app.post(&uri, Some(&admin), json!({})).await;
+        assert_eq!(response.status, StatusCode::OK, "{}", response.body);
+        assert_eq!(app.post(&uri, Some(&owner), json!({})).await.status, StatusCode::OK);
+    }
+}
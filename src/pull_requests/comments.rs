@@ -2,7 +2,11 @@ use axum::{extract::{Path, State}, http::StatusCode, response::IntoResponse, Jso
 use serde::{Deserialize, Serialize};
 use sqlx::FromRow;
 
-use crate::auth::{AuthUser, PermissiveAuthUser};
+use crate::auth::{AuthUser, PermissiveAuthUser, Scope};
+use crate::db;
+use crate::git_api::find_visible_repo;
+use crate::issues::DELETED_COMMENT_BODY;
+use crate::validation::{ValidatedJson, Validate, Validator, MAX_BODY_CHARS};
 use crate::AppState;
 
 #[derive(Serialize, FromRow)]
@@ -12,6 +16,10 @@ pub struct PullRequestComment {
     body: String,
     author_id: i32,
     created_at: chrono::DateTime<chrono::Utc>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    deleted_at: Option<chrono::DateTime<chrono::Utc>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    deleted_by: Option<i32>,
 }
 
 #[derive(Deserialize)]
@@ -19,93 +27,118 @@ pub struct NewComment {
     pub body: String,
 }
 
+impl Validate for NewComment {
+    fn validate(&self, v: &mut Validator) {
+        v.required("body", &self.body, MAX_BODY_CHARS);
+    }
+}
+
 #[axum::debug_handler]
 pub async fn create_comment(
     State(state): State<AppState>,
     AuthUser(user): AuthUser,
-    Path((repo_name, pull_request_id)): Path<(String, i32)>,
-    Json(new_comment): Json<NewComment>,
+    Path((repo_name, pull_number)): Path<(String, i32)>,
+    ValidatedJson(new_comment): ValidatedJson<NewComment>,
 ) -> Result<impl IntoResponse, (StatusCode, String)> {
-    let comment_result = sqlx::query_as!(
+    user.require_scope(Scope::RepoWrite)?;
+    let repo = find_visible_repo(&state.pool, &repo_name, Some(user.id)).await?;
+    let pull_request = sqlx::query!("SELECT id, author_id FROM pull_requests WHERE repo_id = $1 AND number = $2", repo.id, pull_number)
+        .fetch_optional(&state.pool)
+        .await
+        .map_err(|e| db::error(e, "Failed to fetch pull request"))?
+        .ok_or_else(|| (StatusCode::NOT_FOUND, "Pull request not found.".to_string()))?;
+    if pull_request.author_id != user.id && !repo.can_write(Some(user.id)) {
+        return Err((StatusCode::FORBIDDEN, "You do not have permission to comment on this pull request".to_string()));
+    }
+
+    let comment = sqlx::query_as!(
         PullRequestComment,
         r#"
-        WITH pull_request_repo AS (
-            SELECT repo_id FROM pull_requests WHERE id = $1
-        ), repo_access AS (
-            SELECT r.id
-            FROM repositories r
-            JOIN pull_request_repo prr ON r.id = prr.repo_id
-            WHERE r.name = $4 AND (r.public OR r.user_id = $3)
-        )
         INSERT INTO pull_request_comments (pull_request_id, body, author_id)
-        SELECT $1, $2, $3
-        FROM repo_access
-        RETURNING id, pull_request_id, body, author_id, created_at
+        VALUES ($1, $2, $3)
+        RETURNING id, pull_request_id, body, author_id, created_at, deleted_at, deleted_by
         "#,
-        pull_request_id,
+        pull_request.id,
         new_comment.body,
         user.id,
-        repo_name
     )
     .fetch_one(&state.pool)
-    .await;
-
-    match comment_result {
-        Ok(comment) => Ok((StatusCode::CREATED, Json(comment))),
-        Err(sqlx::Error::RowNotFound) => Err((
-            StatusCode::FORBIDDEN,
-            "Pull request not found, repository not found, or you don't have permission to comment.".to_string(),
-        )),
-        Err(e) => Err((
-            StatusCode::INTERNAL_SERVER_ERROR,
-            format!("Failed to create comment: {}", e),
-        )),
-    }
+    .await
+    .map_err(|e| db::error(e, "Failed to create comment"))?;
+
+    Ok((StatusCode::CREATED, Json(comment)))
 }
 
 #[axum::debug_handler]
 pub async fn list_comments(
     State(state): State<AppState>,
     PermissiveAuthUser(user): PermissiveAuthUser,
-    Path((repo_name, pull_request_id)): Path<(String, i32)>,
+    Path((repo_name, pull_number)): Path<(String, i32)>,
 ) -> Result<impl IntoResponse, (StatusCode, String)> {
     let user_id = user.map(|u| u.id);
 
-    let repo_exists_and_has_access = sqlx::query!(
-        "SELECT EXISTS(SELECT 1 FROM repositories WHERE name = $1 AND (public OR user_id = $2))",
-        repo_name,
-        user_id
-    )
-    .fetch_one(&state.pool)
-    .await
-    .map(|r| r.exists.unwrap_or(false))
-    .unwrap_or(false);
-
-    if !repo_exists_and_has_access {
-        return Err((StatusCode::NOT_FOUND, "Repository not found or you don't have permission to view it.".to_string()));
-    }
+    find_visible_repo(&state.pool, &repo_name, user_id).await?;
 
     let comments = sqlx::query_as!(
         PullRequestComment,
         r#"
-        SELECT prc.id, prc.pull_request_id, prc.body, prc.author_id, prc.created_at
+        SELECT prc.id, prc.pull_request_id, prc.body, prc.author_id, prc.created_at, prc.deleted_at, prc.deleted_by
         FROM pull_request_comments prc
         JOIN pull_requests pr ON prc.pull_request_id = pr.id
         JOIN repositories r ON pr.repo_id = r.id
-        WHERE r.name = $1 AND pr.id = $2 AND (r.public OR r.user_id = $3)
+        WHERE r.name = $1 AND pr.number = $2 AND (r.public OR r.user_id = $3 OR EXISTS (SELECT 1 FROM repo_collaborators c WHERE c.repo_id = r.id AND c.user_id = $3))
+        ORDER BY prc.id
         "#,
         repo_name,
-        pull_request_id,
+        pull_number,
         user_id,
     )
     .fetch_all(&state.pool)
     .await
-    .map_err(|e| {
-        (
-            StatusCode::INTERNAL_SERVER_ERROR,
-            format!("Failed to list comments: {}", e),
-        )
-    })?;
+    .map_err(|e| db::error(e, "Failed to list comments"))?;
 
     Ok(Json(comments))
 }
+
+// Like issue comments, deleted pull request comments are tombstoned in place.
+#[axum::debug_handler]
+pub async fn delete_comment(
+    State(state): State<AppState>,
+    AuthUser(user): AuthUser,
+    Path((repo_name, pull_number, comment_id)): Path<(String, i32, i32)>,
+) -> Result<impl IntoResponse, (StatusCode, String)> {
+    user.require_scope(Scope::RepoWrite)?;
+    let repo = find_visible_repo(&state.pool, &repo_name, Some(user.id)).await?;
+
+    let author_id = sqlx::query_scalar!(
+        r#"
+        SELECT prc.author_id
+        FROM pull_request_comments prc
+        JOIN pull_requests pr ON prc.pull_request_id = pr.id
+        WHERE prc.id = $1 AND pr.number = $2 AND pr.repo_id = $3
+        "#,
+        comment_id,
+        pull_number,
+        repo.id,
+    )
+    .fetch_optional(&state.pool)
+    .await
+    .map_err(|e| db::error(e, "Failed to fetch comment"))?
+    .ok_or_else(|| (StatusCode::NOT_FOUND, "Comment not found".to_string()))?;
+
+    if author_id != user.id && !repo.can_write(Some(user.id)) {
+        return Err((StatusCode::FORBIDDEN, "Only the comment's author or someone with write access can delete it".to_string()));
+    }
+
+    sqlx::query!(
+        "UPDATE pull_request_comments SET body = $2, deleted_at = NOW(), deleted_by = $3 WHERE id = $1 AND deleted_at IS NULL",
+        comment_id,
+        DELETED_COMMENT_BODY,
+        user.id,
+    )
+    .execute(&state.pool)
+    .await
+    .map_err(|e| db::error(e, "Failed to delete comment"))?;
+
+    Ok(StatusCode::NO_CONTENT)
+}
@@ -0,0 +1,63 @@
+use axum::{extract::{Path, State}, http::StatusCode, response::IntoResponse, Json};
+use tokio::task;
+
+use crate::auth::PermissiveAuthUser;
+use crate::db;
+use crate::git_api::{find_visible_repo, resolve_branch_commit, Commit};
+use crate::AppState;
+
+const MAX_PULL_REQUEST_COMMITS: usize = 250;
+
+// Commits on the head branch that the base branch does not have yet, oldest
+// first, up to `limit`.
+pub(crate) fn pull_request_commits(repo_name: &str, base_branch: &str, head_branch: &str, limit: usize) -> Result<Vec<Commit>, (StatusCode, String)> {
+    let repo = git2::Repository::open(format!("./repos/{}.git", repo_name))
+        .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, format!("Failed to open repository: {}", e)))?;
+    let base = resolve_branch_commit(&repo, base_branch)?;
+    let head = resolve_branch_commit(&repo, head_branch)?;
+
+    let git_error = |e: git2::Error| (StatusCode::INTERNAL_SERVER_ERROR, format!("Failed to list commits: {}", e));
+    let mut revwalk = repo.revwalk().map_err(git_error)?;
+    revwalk.set_sorting(git2::Sort::TOPOLOGICAL | git2::Sort::REVERSE).map_err(git_error)?;
+    revwalk.push(head.id()).map_err(git_error)?;
+    revwalk.hide(base.id()).map_err(git_error)?;
+
+    // A commit that cannot be read is an error rather than skipped, so the
+    // sign-off check never passes a commit it has not seen.
+    revwalk
+        .take(limit)
+        .map(|oid| repo.find_commit(oid?).map(|commit| Commit::from_git(&commit)))
+        .collect::<Result<_, _>>()
+        .map_err(git_error)
+}
+
+// Ids of the pull request's commits that carry no `Signed-off-by` trailer.
+pub(crate) fn commits_missing_signoff(repo_name: &str, base_branch: &str, head_branch: &str) -> Result<Vec<String>, (StatusCode, String)> {
+    let commits = pull_request_commits(repo_name, base_branch, head_branch, usize::MAX)?;
+    Ok(commits.iter().filter(|commit| !commit.is_signed_off()).map(|commit| commit.id().to_string()).collect())
+}
+
+#[axum::debug_handler]
+pub async fn list_commits(
+    State(state): State<AppState>,
+    PermissiveAuthUser(user): PermissiveAuthUser,
+    Path((repo_name, pull_number)): Path<(String, i32)>,
+) -> Result<impl IntoResponse, (StatusCode, String)> {
+    let repo = find_visible_repo(&state.pool, &repo_name, user.map(|u| u.id)).await?;
+
+    let (base_branch, head_branch) = sqlx::query_as::<_, (String, String)>(
+        "SELECT base_branch, head_branch FROM pull_requests WHERE number = $1 AND repo_id = $2"
+    )
+    .bind(pull_number)
+    .bind(repo.id)
+    .fetch_optional(&state.pool)
+    .await
+    .map_err(|e| db::error(e, "Failed to fetch pull request"))?
+    .ok_or_else(|| (StatusCode::NOT_FOUND, "Pull request not found.".to_string()))?;
+
+    let commits = task::spawn_blocking(move || pull_request_commits(&repo.name, &base_branch, &head_branch, MAX_PULL_REQUEST_COMMITS))
+        .await
+        .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, format!("Task failed: {}", e)))??;
+
+    Ok(Json(commits))
+}
@@ -0,0 +1,47 @@
+use axum::{extract::{Path, State}, http::StatusCode, response::IntoResponse, Json};
+
+use crate::auth::{AuthUser, Scope};
+use crate::db;
+use crate::git_api::find_visible_repo;
+use crate::AppState;
+
+use super::PullRequest;
+
+// Takes a pull request out of draft so it can be merged. Only its author or
+// someone with the `write` role may do this.
+#[axum::debug_handler]
+pub async fn mark_ready_for_review(
+    State(state): State<AppState>,
+    AuthUser(user): AuthUser,
+    Path((repo_name, pull_number)): Path<(String, i32)>,
+) -> Result<impl IntoResponse, (StatusCode, String)> {
+    user.require_scope(Scope::RepoWrite)?;
+    let mut tx = state.pool.begin().await.map_err(|e| db::error(e, "Failed to start transaction"))?;
+
+    let repo = find_visible_repo(&mut *tx, &repo_name, Some(user.id)).await?;
+
+    let pull_request = sqlx::query_as::<_, PullRequest>("SELECT * FROM pull_requests WHERE number = $1 AND repo_id = $2 FOR UPDATE")
+        .bind(pull_number)
+        .bind(repo.id)
+        .fetch_optional(&mut *tx)
+        .await
+        .map_err(|e| db::error(e, "Failed to fetch pull request"))?
+        .ok_or_else(|| (StatusCode::NOT_FOUND, "Pull request not found.".to_string()))?;
+
+    if pull_request.author_id != user.id && !repo.can_write(Some(user.id)) {
+        return Err((StatusCode::FORBIDDEN, "Only the author or someone with write access can mark this pull request ready for review".to_string()));
+    }
+    if !pull_request.draft {
+        return Err((StatusCode::CONFLICT, "Pull request is not a draft.".to_string()));
+    }
+
+    let updated = sqlx::query_as::<_, PullRequest>("UPDATE pull_requests SET draft = false, updated_at = now() WHERE id = $1 RETURNING *")
+        .bind(pull_request.id)
+        .fetch_one(&mut *tx)
+        .await
+        .map_err(|e| db::error(e, "Failed to update pull request"))?;
+
+    tx.commit().await.map_err(|e| db::error(e, "Failed to commit transaction"))?;
+
+    Ok(Json(updated))
+}
@@ -0,0 +1,129 @@
+use axum::{extract::{Path, State}, http::StatusCode, response::IntoResponse, Json};
+use sqlx::PgExecutor;
+
+use crate::auth::{AuthUser, PermissiveAuthUser, Scope};
+use crate::issues::DisplayUser;
+use crate::db;
+use crate::git_api::{find_visible_repo, user_can_read};
+use crate::AppState;
+
+use super::find_visible_pull_request;
+
+async fn fetch_requested_reviewers<'e, E: PgExecutor<'e>>(executor: E, pull_id: i32) -> Result<Vec<DisplayUser>, (StatusCode, String)> {
+    sqlx::query_as::<_, DisplayUser>(
+        r#"
+        SELECT u.id, u.username
+        FROM users u
+        JOIN pull_request_reviewers prr ON u.id = prr.user_id
+        WHERE prr.pull_request_id = $1
+        ORDER BY prr.created_at
+        "#
+    )
+    .bind(pull_id)
+    .fetch_all(executor)
+    .await
+    .map_err(|e| db::error(e, "Failed to fetch requested reviewers"))
+}
+
+#[axum::debug_handler]
+pub async fn list_requested_reviewers(
+    State(state): State<AppState>,
+    PermissiveAuthUser(user): PermissiveAuthUser,
+    Path((repo_name, pull_number)): Path<(String, i32)>,
+) -> Result<impl IntoResponse, (StatusCode, String)> {
+    let pull_id = find_visible_pull_request(&state, &repo_name, pull_number, user.map(|u| u.id)).await?;
+
+    let reviewers = fetch_requested_reviewers(&state.pool, pull_id).await?;
+    Ok(Json(reviewers))
+}
+
+// Review requests are managed by the pull request's author and anyone with
+// write access. Returns the pull request's id and author, the named user and
+// whether the named user can read the repository.
+async fn find_reviewer_to_manage(
+    conn: &mut sqlx::PgConnection,
+    repo_name: &str,
+    pull_number: i32,
+    user_id: i32,
+    username: &str,
+) -> Result<(i32, i32, DisplayUser, bool), (StatusCode, String)> {
+    let repo = find_visible_repo(&mut *conn, repo_name, Some(user_id)).await?;
+
+    let (pull_id, author_id) = sqlx::query_as::<_, (i32, i32)>("SELECT id, author_id FROM pull_requests WHERE number = $1 AND repo_id = $2")
+        .bind(pull_number)
+        .bind(repo.id)
+        .fetch_optional(&mut *conn)
+        .await
+        .map_err(|e| db::error(e, "Failed to fetch pull request"))?
+        .ok_or_else(|| (StatusCode::NOT_FOUND, "Pull request not found.".to_string()))?;
+
+    if user_id != author_id && !repo.can_write(Some(user_id)) {
+        return Err((StatusCode::FORBIDDEN, "Only the pull request author or someone with write access can manage review requests".to_string()));
+    }
+
+    let reviewer = sqlx::query_as::<_, DisplayUser>("SELECT id, username FROM users WHERE username = $1")
+        .bind(username)
+        .fetch_optional(&mut *conn)
+        .await
+        .map_err(|e| db::error(e, "Failed to fetch user"))?
+        .ok_or_else(|| (StatusCode::NOT_FOUND, "User not found".to_string()))?;
+
+    let can_read = user_can_read(&mut *conn, &repo, reviewer.id).await?;
+    Ok((pull_id, author_id, reviewer, can_read))
+}
+
+#[axum::debug_handler]
+pub async fn request_reviewer(
+    State(state): State<AppState>,
+    AuthUser(user): AuthUser,
+    Path((repo_name, pull_number, username)): Path<(String, i32, String)>,
+) -> Result<impl IntoResponse, (StatusCode, String)> {
+    user.require_scope(Scope::RepoWrite)?;
+    let mut tx = state.pool.begin().await.map_err(|e| db::error(e, "Failed to start transaction"))?;
+
+    let (pull_id, author_id, reviewer, can_read) = find_reviewer_to_manage(&mut tx, &repo_name, pull_number, user.id, &username).await?;
+    if reviewer.id == author_id {
+        return Err((StatusCode::CONFLICT, "The pull request author cannot be requested as a reviewer".to_string()));
+    }
+    if !can_read {
+        return Err((StatusCode::UNPROCESSABLE_ENTITY, format!("'{}' does not have access to this repository", reviewer.username)));
+    }
+
+    sqlx::query("INSERT INTO pull_request_reviewers (pull_request_id, user_id) VALUES ($1, $2) ON CONFLICT DO NOTHING")
+        .bind(pull_id)
+        .bind(reviewer.id)
+        .execute(&mut *tx)
+        .await
+        .map_err(|e| db::error(e, "Failed to request review"))?;
+
+    let reviewers = fetch_requested_reviewers(&mut *tx, pull_id).await?;
+
+    tx.commit().await.map_err(|e| db::error(e, "Failed to commit transaction"))?;
+
+    Ok(Json(reviewers))
+}
+
+#[axum::debug_handler]
+pub async fn remove_requested_reviewer(
+    State(state): State<AppState>,
+    AuthUser(user): AuthUser,
+    Path((repo_name, pull_number, username)): Path<(String, i32, String)>,
+) -> Result<impl IntoResponse, (StatusCode, String)> {
+    user.require_scope(Scope::RepoWrite)?;
+    let mut tx = state.pool.begin().await.map_err(|e| db::error(e, "Failed to start transaction"))?;
+
+    let (pull_id, _, reviewer, _) = find_reviewer_to_manage(&mut tx, &repo_name, pull_number, user.id, &username).await?;
+
+    sqlx::query("DELETE FROM pull_request_reviewers WHERE pull_request_id = $1 AND user_id = $2")
+        .bind(pull_id)
+        .bind(reviewer.id)
+        .execute(&mut *tx)
+        .await
+        .map_err(|e| db::error(e, "Failed to remove review request"))?;
+
+    let reviewers = fetch_requested_reviewers(&mut *tx, pull_id).await?;
+
+    tx.commit().await.map_err(|e| db::error(e, "Failed to commit transaction"))?;
+
+    Ok(Json(reviewers))
+}
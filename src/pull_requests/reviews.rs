@@ -1,10 +1,16 @@
-use axum::{extract::{Path, State}, http::StatusCode, response::IntoResponse, Json};
+use axum::{extract::{Path, Query, State}, http::StatusCode, response::IntoResponse, Json};
 use serde::{Deserialize, Serialize};
 use sqlx::FromRow;
 
-use crate::auth::{AuthUser, PermissiveAuthUser};
+use crate::auth::{AuthUser, PermissiveAuthUser, Scope};
+use crate::db;
+use crate::git_api::find_writable_repo;
+use crate::pagination::SortDirection;
+use crate::validation::{ValidatedJson, Validate, Validator, MAX_BODY_CHARS};
 use crate::AppState;
 
+use super::find_visible_pull_request;
+
 #[derive(Serialize, FromRow, Debug)]
 pub struct Review {
     pub id: i32,
@@ -28,12 +34,26 @@ pub struct UpdateReview {
     pub body: Option<String>,
 }
 
+impl Validate for NewReview {
+    fn validate(&self, v: &mut Validator) {
+        v.max_chars("body", self.body.as_deref(), MAX_BODY_CHARS);
+    }
+}
+
+impl Validate for UpdateReview {
+    fn validate(&self, v: &mut Validator) {
+        v.max_chars("body", self.body.as_deref(), MAX_BODY_CHARS);
+    }
+}
+
 #[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
 pub enum ReviewStatus {
     #[serde(rename = "approved")]
     Approved,
     #[serde(rename = "changes_requested")]
     ChangesRequested,
+    #[serde(rename = "commented")]
+    Commented,
 }
 
 impl std::fmt::Display for ReviewStatus {
@@ -41,18 +61,52 @@ impl std::fmt::Display for ReviewStatus {
         match self {
             ReviewStatus::Approved => write!(f, "approved"),
             ReviewStatus::ChangesRequested => write!(f, "changes_requested"),
+            ReviewStatus::Commented => write!(f, "commented"),
         }
     }
 }
 
+#[derive(Deserialize)]
+pub struct ReviewListQuery {
+    status: Option<ReviewStatus>,
+    reviewer: Option<String>,
+    #[serde(default)]
+    direction: SortDirection,
+    // Keep only each reviewer's most recent review.
+    #[serde(default)]
+    latest: bool,
+}
+
+// Reviewing takes the `write` role. A pull request that does not exist,
+// belongs to another repository or sits in a repository the caller cannot
+// see is a `404` alike, so reviews never land on a foreign pull request.
+// Returns the pull request's id and author.
+async fn find_reviewable_pull_request(state: &AppState, repo_name: &str, pull_number: i32, user_id: i32) -> Result<(i32, i32), (StatusCode, String)> {
+    let repo = find_writable_repo(&state.pool, repo_name, user_id, "review pull requests in").await?;
+    sqlx::query_as::<_, (i32, i32)>("SELECT id, author_id FROM pull_requests WHERE number = $1 AND repo_id = $2")
+        .bind(pull_number)
+        .bind(repo.id)
+        .fetch_optional(&state.pool)
+        .await
+        .map_err(|e| db::error(e, "Failed to fetch pull request"))?
+        .ok_or_else(|| (StatusCode::NOT_FOUND, "Pull request not found.".to_string()))
+}
 
 #[axum::debug_handler]
 pub async fn create_review(
     State(state): State<AppState>,
     AuthUser(user): AuthUser,
-    Path((_repo_name, pull_id)): Path<(String, i32)>,
-    Json(new_review): Json<NewReview>,
+    Path((repo_name, pull_number)): Path<(String, i32)>,
+    ValidatedJson(new_review): ValidatedJson<NewReview>,
 ) -> Result<impl IntoResponse, (StatusCode, String)> {
+    user.require_scope(Scope::RepoWrite)?;
+    let (pull_id, author_id) = find_reviewable_pull_request(&state, &repo_name, pull_number, user.id).await?;
+
+    // A review is someone else's judgement of the change, so authors cannot
+    // approve (or otherwise review) their own pull requests.
+    if author_id == user.id {
+        return Err((StatusCode::FORBIDDEN, "You cannot review your own pull request".to_string()));
+    }
 
     let review = sqlx::query_as::<_, Review>(
         r#"
@@ -67,7 +121,7 @@ pub async fn create_review(
     .bind(new_review.body)
     .fetch_one(&state.pool)
     .await
-    .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, format!("Failed to create review: {}", e)))?;
+    .map_err(|e| db::error(e, "Failed to create review"))?;
 
     Ok((StatusCode::CREATED, Json(review)))
 }
@@ -75,16 +129,36 @@ pub async fn create_review(
 #[axum::debug_handler]
 pub async fn list_reviews(
     State(state): State<AppState>,
-    PermissiveAuthUser(_user): PermissiveAuthUser,
-    Path((_repo_name, pull_id)): Path<(String, i32)>,
+    PermissiveAuthUser(user): PermissiveAuthUser,
+    Path((repo_name, pull_number)): Path<(String, i32)>,
+    Query(query): Query<ReviewListQuery>,
 ) -> Result<impl IntoResponse, (StatusCode, String)> {
-    let reviews = sqlx::query_as::<_, Review>(
-        "SELECT * FROM reviews WHERE pull_request_id = $1"
-    )
+    let pull_id = find_visible_pull_request(&state, &repo_name, pull_number, user.map(|u| u.id)).await?;
+
+    let distinct = if query.latest { "DISTINCT ON (r.reviewer_id)" } else { "" };
+    let sql = format!(
+        r#"
+        SELECT * FROM (
+            SELECT {distinct} r.*
+            FROM reviews r
+            JOIN users u ON r.reviewer_id = u.id
+            WHERE r.pull_request_id = $1
+              AND ($2::text IS NULL OR r.status = $2)
+              AND ($3::text IS NULL OR u.username = $3)
+            ORDER BY r.reviewer_id, r.created_at DESC, r.id DESC
+        ) filtered
+        ORDER BY created_at {direction}, id {direction}
+        "#,
+        direction = query.direction.as_sql(),
+    );
+
+    let reviews = sqlx::query_as::<_, Review>(&sql)
     .bind(pull_id)
+    .bind(query.status.map(|s| s.to_string()))
+    .bind(query.reviewer)
     .fetch_all(&state.pool)
     .await
-    .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, format!("Failed to fetch reviews: {}", e)))?;
+    .map_err(|e| db::error(e, "Failed to fetch reviews"))?;
 
     Ok(Json(reviews))
 }
@@ -92,16 +166,19 @@ pub async fn list_reviews(
 #[axum::debug_handler]
 pub async fn get_review(
     State(state): State<AppState>,
-    PermissiveAuthUser(_user): PermissiveAuthUser,
-    Path((_repo_name, _pull_id, review_id)): Path<(String, i32, i32)>,
+    PermissiveAuthUser(user): PermissiveAuthUser,
+    Path((repo_name, pull_number, review_id)): Path<(String, i32, i32)>,
 ) -> Result<impl IntoResponse, (StatusCode, String)> {
+    let pull_id = find_visible_pull_request(&state, &repo_name, pull_number, user.map(|u| u.id)).await?;
+
     let review = sqlx::query_as::<_, Review>(
-        "SELECT * FROM reviews WHERE id = $1"
+        "SELECT * FROM reviews WHERE id = $1 AND pull_request_id = $2"
     )
     .bind(review_id)
+    .bind(pull_id)
     .fetch_optional(&state.pool)
     .await
-    .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, format!("Failed to fetch review: {}", e)))?;
+    .map_err(|e| db::error(e, "Failed to fetch review"))?;
 
     match review {
         Some(review) => Ok(Json(review)),
@@ -113,17 +190,20 @@ pub async fn get_review(
 pub async fn update_review(
     State(state): State<AppState>,
     AuthUser(user): AuthUser,
-    Path((_repo_name, _pull_id, review_id)): Path<(String, i32, i32)>,
-    Json(update_review): Json<UpdateReview>,
+    Path((repo_name, pull_number, review_id)): Path<(String, i32, i32)>,
+    ValidatedJson(update_review): ValidatedJson<UpdateReview>,
 ) -> Result<impl IntoResponse, (StatusCode, String)> {
+    user.require_scope(Scope::RepoWrite)?;
+    let (pull_id, _) = find_reviewable_pull_request(&state, &repo_name, pull_number, user.id).await?;
     let current_review = sqlx::query_as::<_, Review>(
-        "SELECT * FROM reviews WHERE id = $1 AND reviewer_id = $2"
+        "SELECT * FROM reviews WHERE id = $1 AND reviewer_id = $2 AND pull_request_id = $3"
     )
     .bind(review_id)
     .bind(user.id)
+    .bind(pull_id)
     .fetch_optional(&state.pool)
     .await
-    .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, format!("Failed to fetch review: {}", e)))?;
+    .map_err(|e| db::error(e, "Failed to fetch review"))?;
 
     let current_review = match current_review {
         Some(review) => review,
@@ -146,7 +226,7 @@ pub async fn update_review(
     .bind(review_id)
     .fetch_one(&state.pool)
     .await
-    .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, format!("Failed to update review: {}", e)))?;
+    .map_err(|e| db::error(e, "Failed to update review"))?;
 
     Ok(Json(updated_review))
 }
@@ -155,16 +235,19 @@ pub async fn update_review(
 pub async fn delete_review(
     State(state): State<AppState>,
     AuthUser(user): AuthUser,
-    Path((_repo_name, _pull_id, review_id)): Path<(String, i32, i32)>,
+    Path((repo_name, pull_number, review_id)): Path<(String, i32, i32)>,
 ) -> Result<impl IntoResponse, (StatusCode, String)> {
+    user.require_scope(Scope::RepoWrite)?;
+    let (pull_id, _) = find_reviewable_pull_request(&state, &repo_name, pull_number, user.id).await?;
     let result = sqlx::query(
-        "DELETE FROM reviews WHERE id = $1 AND reviewer_id = $2"
+        "DELETE FROM reviews WHERE id = $1 AND reviewer_id = $2 AND pull_request_id = $3"
     )
     .bind(review_id)
     .bind(user.id)
+    .bind(pull_id)
     .execute(&state.pool)
     .await
-    .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, format!("Failed to delete review: {}", e)))?;
+    .map_err(|e| db::error(e, "Failed to delete review"))?;
 
     if result.rows_affected() == 0 {
         return Err((
@@ -175,3 +258,42 @@ pub async fn delete_review(
 
     Ok(StatusCode::NO_CONTENT)
 }
+
+#[cfg(test)]
+mod tests {
+    use axum::http::StatusCode;
+    use serde_json::json;
+    use sqlx::PgPool;
+
+    use crate::pull_requests::tests::open_pull_request;
+    use crate::test_support::TestApp;
+
+    #[sqlx::test]
+    async fn reviews_need_an_existing_pull_request(pool: PgPool) {
+        let mut app = TestApp::new(pool);
+        let (owner, repo) = open_pull_request(&mut app).await;
+        let reviewer = app.sign_up("reviewer").await;
+        app.add_collaborator(&owner, &repo, "reviewer", "write").await;
+
+        let response = app.post(&format!("/repos/{}/pulls/999/reviews", repo), Some(&reviewer), json!({ "status": "approved" })).await;
+        assert_eq!(response.status, StatusCode::NOT_FOUND);
+        let response = app.post("/repos/no-such-repo/pulls/1/reviews", Some(&reviewer), json!({ "status": "approved" })).await;
+        assert_eq!(response.status, StatusCode::NOT_FOUND);
+
+        let response = app.post(&format!("/repos/{}/pulls/1/reviews", repo), Some(&reviewer), json!({ "status": "approved" })).await;
+        assert_eq!(response.status, StatusCode::CREATED, "{}", response.body);
+    }
+
+    #[sqlx::test]
+    async fn authors_cannot_review_their_own_pull_request(pool: PgPool) {
+        let mut app = TestApp::new(pool);
+        let (owner, repo) = open_pull_request(&mut app).await;
+
+        for status in ["approved", "changes_requested"] {
+            let response = app.post(&format!("/repos/{}/pulls/1/reviews", repo), Some(&owner), json!({ "status": status })).await;
+            assert_eq!(response.status, StatusCode::FORBIDDEN, "{}", status);
+        }
+        let response = app.get(&format!("/repos/{}/pulls/1/reviews", repo), None).await;
+        assert_eq!(response.body, json!([]));
+    }
+}
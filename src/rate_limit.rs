@@ -0,0 +1,179 @@
+use async_trait::async_trait;
+use axum::{
+    extract::{ConnectInfo, FromRequestParts},
+    http::{header, request::Parts, HeaderMap, StatusCode},
+    response::{IntoResponse, Response},
+};
+use std::collections::HashMap;
+use std::convert::Infallible;
+use std::net::{IpAddr, SocketAddr};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use crate::AppState;
+
+const WINDOW: Duration = Duration::from_secs(60);
+// Expired windows are swept out once this many addresses are tracked.
+const PRUNE_THRESHOLD: usize = 10_000;
+
+pub struct Window {
+    started: Instant,
+    requests: u32,
+}
+
+pub type RateLimits = Arc<Mutex<HashMap<IpAddr, Window>>>;
+
+// Failed logins are counted both for the account and for the address they
+// come from, so neither guessing one account's password from many addresses
+// nor many accounts' from one address gets far.
+#[derive(PartialEq, Eq, Hash)]
+pub enum LoginKey {
+    Username(String),
+    Address(IpAddr),
+}
+
+pub type LoginFailures = Arc<Mutex<HashMap<LoginKey, Window>>>;
+
+pub struct RateLimited {
+    pub retry_after_secs: u64,
+}
+
+impl IntoResponse for RateLimited {
+    fn into_response(self) -> Response {
+        (
+            StatusCode::TOO_MANY_REQUESTS,
+            [(header::RETRY_AFTER, self.retry_after_secs.to_string())],
+            "Too many requests; sign in or retry later",
+        )
+            .into_response()
+    }
+}
+
+// Our proxy appends the address it saw to `X-Forwarded-For`; anything left of
+// that came from the client and can be forged, so only the last hop counts.
+fn forwarded_for(headers: &HeaderMap) -> Option<IpAddr> {
+    headers.get("x-forwarded-for")?.to_str().ok()?.rsplit(',').next()?.trim().parse().ok()
+}
+
+// The address the request came from: the `X-Forwarded-For` hop a trusted
+// proxy added, the peer address otherwise.
+fn client_ip(parts: &Parts, state: &AppState) -> Option<IpAddr> {
+    let forwarded = state.config.trust_proxy_headers.then(|| forwarded_for(&parts.headers)).flatten();
+    forwarded.or_else(|| parts.extensions.get::<ConnectInfo<SocketAddr>>().map(|info| info.0.ip()))
+}
+
+// The client's address, as `client_ip` determines it.
+pub struct ClientIp(pub Option<IpAddr>);
+
+#[async_trait]
+impl FromRequestParts<AppState> for ClientIp {
+    type Rejection = Infallible;
+
+    async fn from_request_parts(parts: &mut Parts, state: &AppState) -> Result<Self, Self::Rejection> {
+        Ok(ClientIp(client_ip(parts, state)))
+    }
+}
+
+// Counts an unauthenticated request against its address's per-minute budget
+// (`ANONYMOUS_RATE_LIMIT`). Signed-in users are not limited.
+pub(crate) fn check_anonymous(parts: &Parts, state: &AppState) -> Result<(), RateLimited> {
+    let limit = state.config.anonymous_rate_limit;
+    if limit == 0 {
+        return Ok(());
+    }
+    let Some(ip) = client_ip(parts, state) else {
+        return Ok(());
+    };
+
+    let now = Instant::now();
+    let Ok(mut windows) = state.rate_limits.lock() else {
+        return Ok(());
+    };
+    if windows.len() >= PRUNE_THRESHOLD {
+        windows.retain(|_, window| now.duration_since(window.started) < WINDOW);
+    }
+
+    let window = windows.entry(ip).or_insert(Window { started: now, requests: 0 });
+    if now.duration_since(window.started) >= WINDOW {
+        *window = Window { started: now, requests: 0 };
+    }
+    if window.requests >= limit {
+        let retry_after_secs = WINDOW.saturating_sub(now.duration_since(window.started)).as_secs().max(1);
+        return Err(RateLimited { retry_after_secs });
+    }
+    window.requests += 1;
+    Ok(())
+}
+
+fn login_keys(username: &str, ip: Option<IpAddr>) -> impl Iterator<Item = LoginKey> {
+    std::iter::once(LoginKey::Username(username.to_string())).chain(ip.map(LoginKey::Address))
+}
+
+// Refuses a login attempt while the account or the address has used up its
+// `LOGIN_MAX_FAILURES` within the last `LOGIN_LOCKOUT_SECS`.
+pub(crate) fn check_login(state: &AppState, username: &str, ip: Option<IpAddr>) -> Result<(), RateLimited> {
+    let (max_failures, lockout) = (state.config.login_max_failures, Duration::from_secs(state.config.login_lockout_secs));
+    if max_failures == 0 {
+        return Ok(());
+    }
+    let Ok(failures) = state.login_failures.lock() else {
+        return Ok(());
+    };
+
+    let now = Instant::now();
+    let retry_after = login_keys(username, ip)
+        .filter_map(|key| failures.get(&key))
+        .filter(|window| window.requests >= max_failures)
+        .map(|window| lockout.saturating_sub(now.duration_since(window.started)))
+        .filter(|remaining| !remaining.is_zero())
+        .max();
+    match retry_after {
+        Some(remaining) => Err(RateLimited { retry_after_secs: remaining.as_secs().max(1) }),
+        None => Ok(()),
+    }
+}
+
+pub(crate) fn record_login_failure(state: &AppState, username: &str, ip: Option<IpAddr>) {
+    let lockout = Duration::from_secs(state.config.login_lockout_secs);
+    let Ok(mut failures) = state.login_failures.lock() else {
+        return;
+    };
+
+    let now = Instant::now();
+    if failures.len() >= PRUNE_THRESHOLD {
+        failures.retain(|_, window| now.duration_since(window.started) < lockout);
+    }
+    for key in login_keys(username, ip) {
+        let window = failures.entry(key).or_insert(Window { started: now, requests: 0 });
+        if now.duration_since(window.started) >= lockout {
+            *window = Window { started: now, requests: 0 };
+        }
+        window.requests += 1;
+    }
+}
+
+pub(crate) fn clear_login_failures(state: &AppState, username: &str, ip: Option<IpAddr>) {
+    if let Ok(mut failures) = state.login_failures.lock() {
+        for key in login_keys(username, ip) {
+            failures.remove(&key);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn forged_forwarded_hops_are_ignored() {
+        let mut headers = HeaderMap::new();
+        headers.insert("x-forwarded-for", "1.2.3.4, 203.0.113.7".parse().unwrap());
+        assert_eq!(forwarded_for(&headers), "203.0.113.7".parse().ok());
+
+        headers.insert("x-forwarded-for", "203.0.113.7".parse().unwrap());
+        assert_eq!(forwarded_for(&headers), "203.0.113.7".parse().ok());
+
+        headers.insert("x-forwarded-for", "203.0.113.7, garbage".parse().unwrap());
+        assert_eq!(forwarded_for(&headers), None);
+    }
+}
@@ -0,0 +1,441 @@
+use axum::{
+    extract::{Path, State},
+    http::StatusCode,
+    response::IntoResponse,
+    Json,
+};
+use rand::distributions::Alphanumeric;
+use rand::{thread_rng, Rng};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::path::{Path as StdPath, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use crate::auth::{AuthUser, Scope};
+use crate::db;
+use crate::git_api::{find_writable_repo, validate_repo_name};
+use crate::urls::check_outbound_url;
+use crate::validation::{Validate, ValidatedJson, Validator};
+use crate::AppState;
+
+const FINISHED_JOB_RETENTION: Duration = Duration::from_secs(60 * 60);
+
+#[derive(Deserialize)]
+pub struct ImportRepoRequest {
+    pub name: String,
+    pub source_url: String,
+    pub public: Option<bool>,
+    pub mirror: Option<bool>,
+    pub credentials: Option<SourceCredentials>,
+}
+
+impl Validate for ImportRepoRequest {
+    fn validate(&self, v: &mut Validator) {
+        // Credentials are only used for the clone and never stored, so the
+        // scheduler could not sync a mirror that needs them.
+        v.check(!(self.mirror.unwrap_or(false) && self.credentials.is_some()), "credentials", "cannot be used for mirror imports");
+    }
+}
+
+#[derive(Deserialize, Clone)]
+pub struct SourceCredentials {
+    pub username: String,
+    pub password: String,
+}
+
+#[derive(Deserialize, Default)]
+pub struct SyncMirrorRequest {
+    pub credentials: Option<SourceCredentials>,
+}
+
+#[derive(Serialize)]
+pub struct MirrorSyncResult {
+    pub name: String,
+    pub source_url: String,
+    pub last_synced_at: chrono::DateTime<chrono::Utc>,
+    pub error: Option<String>,
+}
+
+#[derive(Serialize, Clone, Copy, PartialEq, Debug)]
+pub enum ImportStatus {
+    #[serde(rename = "running")]
+    Running,
+    #[serde(rename = "completed")]
+    Completed,
+    #[serde(rename = "failed")]
+    Failed,
+}
+
+#[derive(Serialize, Clone)]
+pub struct ImportJob {
+    pub id: String,
+    pub name: String,
+    pub status: ImportStatus,
+    pub received_objects: usize,
+    pub total_objects: usize,
+    pub error: Option<String>,
+    #[serde(skip)]
+    user_id: i32,
+    #[serde(skip)]
+    finished_at: Option<Instant>,
+}
+
+pub type ImportJobs = Arc<Mutex<HashMap<String, ImportJob>>>;
+
+// Only public http(s) sources are accepted, so an import can neither read
+// local paths nor reach services on the server's own network, and `git://`
+// is refused since nothing authenticates it.
+pub(crate) async fn validate_source_url(url: &str) -> Result<(), (StatusCode, String)> {
+    check_outbound_url(url).await.map_err(|message| (StatusCode::UNPROCESSABLE_ENTITY, format!("source_url {}", message)))
+}
+
+pub(crate) fn fetch_options<'a>(
+    credentials: Option<SourceCredentials>,
+    mut on_progress: impl FnMut(usize, usize) + 'a,
+) -> git2::FetchOptions<'a> {
+    let mut callbacks = git2::RemoteCallbacks::new();
+    if let Some(credentials) = credentials {
+        callbacks.credentials(move |_url, _username, _allowed| {
+            git2::Cred::userpass_plaintext(&credentials.username, &credentials.password)
+        });
+    }
+    callbacks.transfer_progress(move |progress| {
+        on_progress(progress.received_objects(), progress.total_objects());
+        true
+    });
+
+    // A redirect could lead past the address check on the source URL.
+    let mut options = git2::FetchOptions::new();
+    options.remote_callbacks(callbacks);
+    options.follow_redirects(git2::RemoteRedirect::None);
+    options
+}
+
+fn clone_mirror(
+    source_url: &str,
+    path: &StdPath,
+    credentials: Option<SourceCredentials>,
+    on_progress: impl FnMut(usize, usize),
+) -> Result<String, git2::Error> {
+    let mut builder = git2::build::RepoBuilder::new();
+    builder.bare(true);
+    builder.fetch_options(fetch_options(credentials, on_progress));
+    builder.remote_create(|repo, name, url| repo.remote_with_fetch(name, url, "+refs/*:refs/*"));
+
+    let repo = builder.clone(source_url, path)?;
+    let mut config = repo.config()?;
+    config.set_bool("remote.origin.mirror", true)?;
+    config.set_bool("http.receivepack", true)?;
+
+    // The source's HEAD comes along with the clone and names its default branch.
+    let head = repo.find_reference("HEAD")?;
+    let default_branch = head.symbolic_target().and_then(|target| target.strip_prefix("refs/heads/"));
+    Ok(default_branch.unwrap_or("main").to_string())
+}
+
+fn update_job(jobs: &ImportJobs, id: &str, f: impl FnOnce(&mut ImportJob)) {
+    if let Ok(mut jobs) = jobs.lock() {
+        if let Some(job) = jobs.get_mut(id) {
+            f(job);
+        }
+    }
+}
+
+#[axum::debug_handler]
+pub async fn import_repo_handler(
+    State(state): State<AppState>,
+    AuthUser(user): AuthUser,
+    ValidatedJson(payload): ValidatedJson<ImportRepoRequest>,
+) -> Result<impl IntoResponse, (StatusCode, String)> {
+    user.require_scope(Scope::RepoWrite)?;
+    validate_repo_name(&payload.name, &state.config)?;
+    validate_source_url(&payload.source_url).await?;
+
+    let path: PathBuf = StdPath::new("./repos").join(format!("{}.git", payload.name));
+    if path.exists() {
+        return Err((StatusCode::CONFLICT, "Repository already exists on filesystem".to_string()));
+    }
+
+    let existing_repo: Option<i32> = sqlx::query_scalar("SELECT id FROM repositories WHERE name = $1")
+        .bind(&payload.name)
+        .fetch_optional(&state.pool)
+        .await
+        .map_err(|e| db::error(e, "Failed to check repository"))?;
+    if existing_repo.is_some() {
+        return Err((StatusCode::CONFLICT, "Repository already exists in database".to_string()));
+    }
+
+    let job_id: String = thread_rng().sample_iter(&Alphanumeric).take(16).map(char::from).collect();
+    let job = ImportJob {
+        id: job_id.clone(),
+        name: payload.name.clone(),
+        status: ImportStatus::Running,
+        received_objects: 0,
+        total_objects: 0,
+        error: None,
+        user_id: user.id,
+        finished_at: None,
+    };
+
+    {
+        let mut jobs = state.import_jobs.lock().map_err(|_| (StatusCode::INTERNAL_SERVER_ERROR, "Import job registry unavailable".to_string()))?;
+        jobs.retain(|_, job| job.finished_at.is_none_or(|at| at.elapsed() < FINISHED_JOB_RETENTION));
+        jobs.insert(job_id.clone(), job.clone());
+    }
+
+    tokio::spawn(run_import(state, job_id, user.id, payload, path));
+
+    Ok((StatusCode::ACCEPTED, Json(job)))
+}
+
+// Records the repository and moves the finished clone into place. The unique
+// name in the database decides between concurrent imports, and the row is
+// only committed once the directory is there.
+async fn publish_import(
+    state: &AppState,
+    user_id: i32,
+    payload: &ImportRepoRequest,
+    default_branch: String,
+    staging: &StdPath,
+    path: &StdPath,
+) -> Result<(), String> {
+    let mut tx = state.pool.begin().await.map_err(|e| {
+        tracing::error!("Failed to record imported repository {}: {}", payload.name, e);
+        "Failed to record repository".to_string()
+    })?;
+
+    let inserted = sqlx::query("INSERT INTO repositories (name, user_id, public, source_url, is_mirror, default_branch) VALUES ($1, $2, $3, $4, $5, $6)")
+        .bind(&payload.name)
+        .bind(user_id)
+        .bind(payload.public.unwrap_or(false))
+        .bind(&payload.source_url)
+        .bind(payload.mirror.unwrap_or(false))
+        .bind(default_branch)
+        .execute(&mut *tx)
+        .await;
+    match inserted {
+        Ok(_) => {}
+        Err(sqlx::Error::Database(db_err)) if db_err.is_unique_violation() => {
+            return Err("Repository already exists in database".to_string());
+        }
+        Err(e) => {
+            tracing::error!("Failed to record imported repository {}: {}", payload.name, e);
+            return Err("Failed to record repository".to_string());
+        }
+    }
+
+    if path.exists() {
+        return Err("Repository already exists on filesystem".to_string());
+    }
+    std::fs::rename(staging, path).map_err(|e| format!("Failed to move imported repository into place: {}", e))?;
+
+    if let Err(e) = tx.commit().await {
+        tracing::error!("Failed to record imported repository {}: {}", payload.name, e);
+        // The directory was only just moved there by this job.
+        if let Err(e) = std::fs::remove_dir_all(path) {
+            tracing::error!("Failed to cleanup imported repository filesystem: {}", e);
+        }
+        return Err("Failed to record repository".to_string());
+    }
+    Ok(())
+}
+
+async fn run_import(state: AppState, job_id: String, user_id: i32, payload: ImportRepoRequest, path: PathBuf) {
+    let jobs = state.import_jobs.clone();
+    let progress_jobs = jobs.clone();
+    let progress_id = job_id.clone();
+    let source_url = payload.source_url.clone();
+    // Cloned under a name only this job uses, so failing or losing a race
+    // with another import of the same name never touches that import's files.
+    let staging = StdPath::new("./repos").join(format!(".import-{}.git", job_id));
+    let clone_path = staging.clone();
+    let credentials = payload.credentials.clone();
+
+    let cloned = tokio::task::spawn_blocking(move || {
+        clone_mirror(&source_url, &clone_path, credentials, |received, total| {
+            update_job(&progress_jobs, &progress_id, |job| {
+                job.received_objects = received;
+                job.total_objects = total;
+            });
+        })
+    })
+    .await;
+
+    let result = match cloned {
+        Ok(Ok(default_branch)) => publish_import(&state, user_id, &payload, default_branch, &staging, &path).await,
+        Ok(Err(e)) => Err(format!("Failed to clone source repository: {}", e.message())),
+        Err(e) => Err(format!("Import task failed: {}", e)),
+    };
+
+    match result {
+        Ok(()) => {
+            tracing::info!("Imported repository {} from {}", payload.name, payload.source_url);
+            update_job(&jobs, &job_id, |job| {
+                job.status = ImportStatus::Completed;
+                job.finished_at = Some(Instant::now());
+            });
+        }
+        Err(message) => {
+            tracing::error!("Import of {} failed: {}", payload.name, message);
+            if staging.exists() {
+                if let Err(e) = std::fs::remove_dir_all(&staging) {
+                    tracing::error!("Failed to cleanup imported repository filesystem: {}", e);
+                }
+            }
+            update_job(&jobs, &job_id, |job| {
+                job.status = ImportStatus::Failed;
+                job.error = Some(message);
+                job.finished_at = Some(Instant::now());
+            });
+        }
+    }
+}
+
+#[axum::debug_handler]
+pub async fn get_import_job_handler(
+    State(state): State<AppState>,
+    AuthUser(user): AuthUser,
+    Path(job_id): Path<String>,
+) -> Result<impl IntoResponse, (StatusCode, String)> {
+    let jobs = state.import_jobs.lock().map_err(|_| (StatusCode::INTERNAL_SERVER_ERROR, "Import job registry unavailable".to_string()))?;
+    match jobs.get(&job_id) {
+        Some(job) if job.user_id == user.id => Ok(Json(job.clone())),
+        _ => Err((StatusCode::NOT_FOUND, "Import job not found".to_string())),
+    }
+}
+
+fn fetch_mirror(repo_name: &str, source_url: &str, credentials: Option<SourceCredentials>) -> Result<(), git2::Error> {
+    let repo = git2::Repository::open(format!("./repos/{}.git", repo_name))?;
+    let mut remote = repo.remote_anonymous(source_url)?;
+    let mut options = fetch_options(credentials, |_, _| {});
+    options.prune(git2::FetchPrune::On);
+    remote.fetch(&["+refs/*:refs/*"], Some(&mut options), Some("mirror sync"))
+}
+
+// Fetches the mirror from its source and records when it ran and whether it
+// failed, so both the endpoint and the scheduler leave the same trail.
+async fn sync_mirror(state: &AppState, repo_name: String, source_url: String, credentials: Option<SourceCredentials>) -> Result<MirrorSyncResult, (StatusCode, String)> {
+    // The source's host may resolve somewhere else than it did at import.
+    let (fetch_name, fetch_url) = (repo_name.clone(), source_url.clone());
+    let error = match check_outbound_url(&source_url).await {
+        Err(message) => Some(format!("source_url {}", message)),
+        Ok(()) => match tokio::task::spawn_blocking(move || fetch_mirror(&fetch_name, &fetch_url, credentials)).await {
+            Ok(Ok(())) => None,
+            Ok(Err(e)) => Some(format!("Failed to fetch from source: {}", e.message())),
+            Err(e) => Some(format!("Sync task failed: {}", e)),
+        },
+    };
+
+    let last_synced_at: chrono::DateTime<chrono::Utc> = sqlx::query_scalar(
+        "UPDATE repositories SET last_synced_at = now(), last_sync_error = $1 WHERE name = $2 RETURNING last_synced_at"
+    )
+    .bind(&error)
+    .bind(&repo_name)
+    .fetch_one(&state.pool)
+    .await
+    .map_err(|e| db::error(e, "Failed to record sync result"))?;
+
+    Ok(MirrorSyncResult { name: repo_name, source_url, last_synced_at, error })
+}
+
+#[axum::debug_handler]
+pub async fn sync_mirror_handler(
+    State(state): State<AppState>,
+    AuthUser(user): AuthUser,
+    Path(repo_name): Path<String>,
+    payload: Option<Json<SyncMirrorRequest>>,
+) -> Result<impl IntoResponse, (StatusCode, String)> {
+    user.require_scope(Scope::RepoWrite)?;
+    let repo = find_writable_repo(&state.pool, &repo_name, user.id, "sync").await?;
+    let mirror: (Option<String>, bool) = sqlx::query_as("SELECT source_url, is_mirror FROM repositories WHERE id = $1")
+        .bind(repo.id)
+        .fetch_one(&state.pool)
+        .await
+        .map_err(|e| db::error(e, "Failed to get repo"))?;
+
+    let source_url = match mirror {
+        (Some(source_url), true) => source_url,
+        _ => return Err((StatusCode::CONFLICT, "Repository is not a mirror".to_string())),
+    };
+
+    let credentials = payload.and_then(|Json(p)| p.credentials);
+    let result = sync_mirror(&state, repo_name, source_url, credentials).await?;
+    let status = if result.error.is_some() { StatusCode::BAD_GATEWAY } else { StatusCode::OK };
+    Ok((status, Json(result)))
+}
+
+pub fn spawn_mirror_sync_scheduler(state: AppState) {
+    let interval = state.config.mirror_sync_interval_secs;
+    if interval == 0 {
+        return;
+    }
+
+    tokio::spawn(async move {
+        let mut ticker = tokio::time::interval(Duration::from_secs(interval));
+        ticker.tick().await;
+        loop {
+            ticker.tick().await;
+            let mirrors: Vec<(String, String)> = match sqlx::query_as(
+                "SELECT name, source_url FROM repositories WHERE is_mirror AND source_url IS NOT NULL"
+            )
+            .fetch_all(&state.pool)
+            .await
+            {
+                Ok(mirrors) => mirrors,
+                Err(e) => {
+                    tracing::error!("Failed to list mirrors for sync: {}", e);
+                    continue;
+                }
+            };
+
+            for (name, source_url) in mirrors {
+                match sync_mirror(&state, name.clone(), source_url, None).await {
+                    Ok(MirrorSyncResult { error: Some(error), .. }) => tracing::warn!("Scheduled sync of {} failed: {}", name, error),
+                    Ok(_) => tracing::debug!("Synced mirror {}", name),
+                    Err((_, message)) => tracing::error!("Scheduled sync of {} failed: {}", name, message),
+                }
+            }
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::http::StatusCode;
+    use serde_json::json;
+    use sqlx::PgPool;
+
+    use crate::test_support::TestApp;
+
+    #[sqlx::test]
+    async fn imports_from_the_servers_own_network_are_refused(pool: PgPool) {
+        let app = TestApp::new(pool);
+        let token = app.sign_up("alice").await;
+
+        for source_url in ["http://127.0.0.1:3000/other.git", "http://169.254.169.254/latest", "git://example.com/repo.git", "/etc"] {
+            let response = app.post("/repos/import", Some(&token), json!({ "name": "imported", "source_url": source_url })).await;
+            assert_eq!(response.status, StatusCode::UNPROCESSABLE_ENTITY, "{}", source_url);
+        }
+    }
+
+    #[sqlx::test]
+    async fn authenticated_sources_cannot_be_mirrored(pool: PgPool) {
+        let app = TestApp::new(pool);
+        let token = app.sign_up("alice").await;
+
+        let response = app
+            .post(
+                "/repos/import",
+                Some(&token),
+                json!({
+                    "name": "imported",
+                    "source_url": "https://93.184.216.34/private.git",
+                    "mirror": true,
+                    "credentials": { "username": "alice", "password": "secret" },
+                }),
+            )
+            .await;
+        assert_eq!(response.status, StatusCode::UNPROCESSABLE_ENTITY);
+    }
+}
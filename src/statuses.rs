@@ -0,0 +1,157 @@
+use axum::{extract::{Path, State}, http::StatusCode, response::IntoResponse, Json};
+use serde::{Deserialize, Serialize};
+use sqlx::{FromRow, PgPool};
+
+use crate::auth::{AuthUser, PermissiveAuthUser, Scope};
+use crate::db;
+use crate::git_api::{find_visible_repo, find_writable_repo};
+use crate::validation::{ValidatedJson, Validate, Validator, MAX_TITLE_CHARS};
+use crate::AppState;
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+pub enum StatusState {
+    #[serde(rename = "pending")]
+    Pending,
+    #[serde(rename = "success")]
+    Success,
+    #[serde(rename = "failure")]
+    Failure,
+    #[serde(rename = "error")]
+    Error,
+}
+
+impl std::fmt::Display for StatusState {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            StatusState::Pending => write!(f, "pending"),
+            StatusState::Success => write!(f, "success"),
+            StatusState::Failure => write!(f, "failure"),
+            StatusState::Error => write!(f, "error"),
+        }
+    }
+}
+
+#[derive(Serialize, FromRow)]
+pub struct CommitStatus {
+    id: i32,
+    sha: String,
+    state: String,
+    context: String,
+    description: Option<String>,
+    target_url: Option<String>,
+    created_at: chrono::DateTime<chrono::Utc>,
+}
+
+#[derive(Deserialize)]
+pub struct NewStatus {
+    state: StatusState,
+    context: Option<String>,
+    description: Option<String>,
+    target_url: Option<String>,
+}
+
+impl Validate for NewStatus {
+    fn validate(&self, v: &mut Validator) {
+        if let Some(context) = &self.context {
+            v.required("context", context, MAX_TITLE_CHARS);
+        }
+        v.max_chars("description", self.description.as_deref(), MAX_TITLE_CHARS).check(
+            self.target_url.as_deref().is_none_or(|url| url.starts_with("http://") || url.starts_with("https://")),
+            "target_url",
+            "must start with http:// or https://",
+        );
+    }
+}
+
+#[derive(Serialize)]
+pub struct CombinedStatus {
+    // `None` until some status has been reported for the commit.
+    pub state: Option<StatusState>,
+    pub sha: String,
+    pub statuses: Vec<CommitStatus>,
+}
+
+// Resolves a revision of the repository to the full id of the commit it names.
+pub(crate) fn resolve_commit_sha(repo_name: &str, rev: &str) -> Result<String, (StatusCode, String)> {
+    let repo = git2::Repository::open(format!("./repos/{}.git", repo_name))
+        .map_err(|_| (StatusCode::NOT_FOUND, "Repository not found on filesystem".to_string()))?;
+    repo.revparse_single(rev)
+        .and_then(|object| object.peel_to_commit())
+        .map(|commit| commit.id().to_string())
+        .map_err(|_| (StatusCode::NOT_FOUND, "Commit not found".to_string()))
+}
+
+// The latest status of every context: failing if any context failed or
+// errored, pending if any is still running, successful otherwise.
+pub(crate) async fn combined_status(pool: &PgPool, repo_id: i32, sha: String) -> Result<CombinedStatus, (StatusCode, String)> {
+    let statuses = sqlx::query_as::<_, CommitStatus>(
+        r#"
+        SELECT DISTINCT ON (context) id, sha, state, context, description, target_url, created_at
+        FROM commit_statuses
+        WHERE repo_id = $1 AND sha = $2
+        ORDER BY context, id DESC
+        "#
+    )
+    .bind(repo_id)
+    .bind(&sha)
+    .fetch_all(pool)
+    .await
+    .map_err(|e| db::error(e, "Failed to fetch commit statuses"))?;
+
+    let state = if statuses.is_empty() {
+        None
+    } else if statuses.iter().any(|s| s.state == "failure" || s.state == "error") {
+        Some(StatusState::Failure)
+    } else if statuses.iter().any(|s| s.state == "pending") {
+        Some(StatusState::Pending)
+    } else {
+        Some(StatusState::Success)
+    };
+
+    Ok(CombinedStatus { state, sha, statuses })
+}
+
+#[axum::debug_handler]
+pub async fn create_status(
+    State(state): State<AppState>,
+    AuthUser(user): AuthUser,
+    Path((repo_name, rev)): Path<(String, String)>,
+    ValidatedJson(new_status): ValidatedJson<NewStatus>,
+) -> Result<impl IntoResponse, (StatusCode, String)> {
+    user.require_scope(Scope::RepoWrite)?;
+    let repo = find_writable_repo(&state.pool, &repo_name, user.id, "set commit statuses in").await?;
+    let sha = resolve_commit_sha(&repo.name, &rev)?;
+
+    let status = sqlx::query_as::<_, CommitStatus>(
+        r#"
+        INSERT INTO commit_statuses (repo_id, sha, state, context, description, target_url, creator_id)
+        VALUES ($1, $2, $3, COALESCE($4, 'default'), $5, $6, $7)
+        RETURNING id, sha, state, context, description, target_url, created_at
+        "#
+    )
+    .bind(repo.id)
+    .bind(sha)
+    .bind(new_status.state.to_string())
+    .bind(new_status.context)
+    .bind(new_status.description)
+    .bind(new_status.target_url)
+    .bind(user.id)
+    .fetch_one(&state.pool)
+    .await
+    .map_err(|e| db::error(e, "Failed to create commit status"))?;
+
+    Ok((StatusCode::CREATED, Json(status)))
+}
+
+#[axum::debug_handler]
+pub async fn get_combined_status(
+    State(state): State<AppState>,
+    PermissiveAuthUser(user): PermissiveAuthUser,
+    Path((repo_name, rev)): Path<(String, String)>,
+) -> Result<impl IntoResponse, (StatusCode, String)> {
+    let repo = find_visible_repo(&state.pool, &repo_name, user.map(|u| u.id)).await?;
+    let sha = resolve_commit_sha(&repo.name, &rev)?;
+
+    let combined = combined_status(&state.pool, repo.id, sha).await?;
+    Ok(Json(combined))
+}
@@ -0,0 +1,121 @@
+use axum::{
+    extract::{Path, State},
+    http::StatusCode,
+    response::{IntoResponse, Response},
+    Json,
+};
+use serde::Serialize;
+
+use crate::auth::PermissiveAuthUser;
+use crate::git_api::check_repo_read_access;
+use crate::AppState;
+
+#[derive(Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum TagKind {
+    Lightweight,
+    Annotated,
+}
+
+#[derive(Serialize)]
+pub struct Tagger {
+    name: String,
+    email: String,
+    // `null` for timestamps chrono cannot represent.
+    date: Option<String>,
+}
+
+#[derive(Serialize)]
+pub struct Tag {
+    name: String,
+    #[serde(rename = "type")]
+    kind: TagKind,
+    // What the tag points at: usually a commit, but tags can name any object.
+    sha: String,
+    // The tag object itself; annotated tags only.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    tag_sha: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    tagger: Option<Tagger>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    message: Option<String>,
+}
+
+fn describe_tag(repo: &git2::Repository, name: &str) -> Result<Option<Tag>, git2::Error> {
+    let Ok(reference) = repo.find_reference(&format!("refs/tags/{}", name)) else {
+        return Ok(None);
+    };
+    let Some(oid) = reference.target() else {
+        return Ok(None);
+    };
+
+    let tag = match repo.find_tag(oid) {
+        Ok(annotated) => Tag {
+            name: name.to_string(),
+            kind: TagKind::Annotated,
+            sha: annotated.target_id().to_string(),
+            tag_sha: Some(oid.to_string()),
+            tagger: annotated.tagger().map(|tagger| Tagger {
+                name: String::from_utf8_lossy(tagger.name_bytes()).into_owned(),
+                email: String::from_utf8_lossy(tagger.email_bytes()).into_owned(),
+                date: chrono::DateTime::from_timestamp(tagger.when().seconds(), 0).map(|date| date.to_rfc3339()),
+            }),
+            message: annotated.message_bytes().map(|message| String::from_utf8_lossy(message).into_owned()),
+        },
+        Err(e) if e.code() == git2::ErrorCode::NotFound => Tag {
+            name: name.to_string(),
+            kind: TagKind::Lightweight,
+            sha: oid.to_string(),
+            tag_sha: None,
+            tagger: None,
+            message: None,
+        },
+        Err(e) => return Err(e),
+    };
+    Ok(Some(tag))
+}
+
+fn list_tags(repo_name: &str) -> Result<Vec<Tag>, git2::Error> {
+    let repo = git2::Repository::open(format!("./repos/{}.git", repo_name))?;
+    let names = repo.tag_names(None)?;
+    let mut tags = Vec::new();
+    for name in names.iter().flatten() {
+        tags.extend(describe_tag(&repo, name)?);
+    }
+    Ok(tags)
+}
+
+fn find_tag(repo_name: &str, name: &str) -> Result<Option<Tag>, git2::Error> {
+    let repo = git2::Repository::open(format!("./repos/{}.git", repo_name))?;
+    describe_tag(&repo, name)
+}
+
+#[axum::debug_handler]
+pub async fn list_tags_handler(Path(name): Path<String>, State(state): State<AppState>, user: PermissiveAuthUser) -> Response {
+    let repo_name = name.strip_suffix(".git").unwrap_or(&name).to_string();
+    if let Err(response) = check_repo_read_access(&repo_name, &state.pool, &user).await {
+        return response;
+    }
+
+    match tokio::task::spawn_blocking(move || list_tags(&repo_name)).await {
+        Ok(Ok(tags)) => Json(tags).into_response(),
+        Ok(Err(e)) => (StatusCode::INTERNAL_SERVER_ERROR, format!("Failed to list tags: {}", e)).into_response(),
+        Err(e) => (StatusCode::INTERNAL_SERVER_ERROR, format!("Task failed: {}", e)).into_response(),
+    }
+}
+
+#[axum::debug_handler]
+pub async fn get_tag_handler(Path((name, tag)): Path<(String, String)>, State(state): State<AppState>, user: PermissiveAuthUser) -> Response {
+    let repo_name = name.strip_suffix(".git").unwrap_or(&name).to_string();
+    if let Err(response) = check_repo_read_access(&repo_name, &state.pool, &user).await {
+        return response;
+    }
+
+    let tag = tag.trim_matches('/').to_string();
+    match tokio::task::spawn_blocking(move || find_tag(&repo_name, &tag)).await {
+        Ok(Ok(Some(tag))) => Json(tag).into_response(),
+        Ok(Ok(None)) => (StatusCode::NOT_FOUND, "Tag not found").into_response(),
+        Ok(Err(e)) => (StatusCode::INTERNAL_SERVER_ERROR, format!("Failed to read tag: {}", e)).into_response(),
+        Err(e) => (StatusCode::INTERNAL_SERVER_ERROR, format!("Task failed: {}", e)).into_response(),
+    }
+}
@@ -0,0 +1,101 @@
+use axum::{extract::{Path, Query}, http::StatusCode, response::IntoResponse, Json};
+use serde::{Deserialize, Serialize};
+
+use crate::auth::PermissiveAuthUser;
+use crate::AppState;
+
+// License and .gitignore files offered when initializing a new repository.
+pub struct License {
+    pub key: &'static str,
+    pub name: &'static str,
+    // Contains `[year]` and `[fullname]` placeholders.
+    pub text: &'static str,
+}
+
+pub struct Gitignore {
+    pub name: &'static str,
+    pub text: &'static str,
+}
+
+pub const LICENSES: [License; 5] = [
+    License { key: "mit", name: "MIT License", text: include_str!("../templates/licenses/mit.txt") },
+    License { key: "bsd-2-clause", name: "BSD 2-Clause \"Simplified\" License", text: include_str!("../templates/licenses/bsd-2-clause.txt") },
+    License { key: "bsd-3-clause", name: "BSD 3-Clause \"New\" or \"Revised\" License", text: include_str!("../templates/licenses/bsd-3-clause.txt") },
+    License { key: "isc", name: "ISC License", text: include_str!("../templates/licenses/isc.txt") },
+    License { key: "unlicense", name: "The Unlicense", text: include_str!("../templates/licenses/unlicense.txt") },
+];
+
+pub const GITIGNORES: [Gitignore; 5] = [
+    Gitignore { name: "Go", text: include_str!("../templates/gitignore/Go.gitignore") },
+    Gitignore { name: "Java", text: include_str!("../templates/gitignore/Java.gitignore") },
+    Gitignore { name: "Node", text: include_str!("../templates/gitignore/Node.gitignore") },
+    Gitignore { name: "Python", text: include_str!("../templates/gitignore/Python.gitignore") },
+    Gitignore { name: "Rust", text: include_str!("../templates/gitignore/Rust.gitignore") },
+];
+
+pub fn find_license(key: &str) -> Option<&'static License> {
+    LICENSES.iter().find(|license| license.key.eq_ignore_ascii_case(key))
+}
+
+pub fn find_gitignore(name: &str) -> Option<&'static Gitignore> {
+    GITIGNORES.iter().find(|gitignore| gitignore.name.eq_ignore_ascii_case(name))
+}
+
+impl License {
+    pub fn fill(&self, year: i32, fullname: &str) -> String {
+        self.text.replace("[year]", &year.to_string()).replace("[fullname]", fullname)
+    }
+}
+
+#[derive(Serialize)]
+pub struct LicenseSummary {
+    key: &'static str,
+    name: &'static str,
+}
+
+#[derive(Serialize)]
+pub struct LicenseText {
+    key: &'static str,
+    name: &'static str,
+    body: String,
+}
+
+#[derive(Serialize)]
+pub struct GitignoreText {
+    name: &'static str,
+    source: &'static str,
+}
+
+#[derive(Deserialize)]
+pub struct LicenseQuery {
+    year: Option<i32>,
+    fullname: Option<String>,
+}
+
+pub async fn list_licenses() -> impl IntoResponse {
+    Json(LICENSES.iter().map(|license| LicenseSummary { key: license.key, name: license.name }).collect::<Vec<_>>())
+}
+
+// Fills in the year (default: this year) and the copyright holder (default:
+// the caller's username). Without either, `[fullname]` is left in place.
+#[axum::debug_handler(state = AppState)]
+pub async fn get_license(
+    PermissiveAuthUser(user): PermissiveAuthUser,
+    Path(key): Path<String>,
+    Query(query): Query<LicenseQuery>,
+) -> Result<impl IntoResponse, (StatusCode, String)> {
+    let license = find_license(&key).ok_or_else(|| (StatusCode::NOT_FOUND, "License not found".to_string()))?;
+    let year = query.year.unwrap_or_else(|| chrono::Datelike::year(&chrono::Utc::now()));
+    let fullname = query.fullname.or(user.map(|u| u.username)).unwrap_or_else(|| "[fullname]".to_string());
+
+    Ok(Json(LicenseText { key: license.key, name: license.name, body: license.fill(year, &fullname) }))
+}
+
+pub async fn list_gitignores() -> impl IntoResponse {
+    Json(GITIGNORES.iter().map(|gitignore| gitignore.name).collect::<Vec<_>>())
+}
+
+pub async fn get_gitignore(Path(name): Path<String>) -> Result<impl IntoResponse, (StatusCode, String)> {
+    let gitignore = find_gitignore(&name).ok_or_else(|| (StatusCode::NOT_FOUND, "Gitignore template not found".to_string()))?;
+    Ok(Json(GitignoreText { name: gitignore.name, source: gitignore.text }))
+}
@@ -0,0 +1,181 @@
+// Drives the real router against the database `#[sqlx::test]` hands each
+// test, the way a client would.
+use axum::{
+    body::Body,
+    http::{header, HeaderMap, Method, Request, StatusCode},
+    response::Response,
+    Router,
+};
+use serde_json::Value;
+use sqlx::PgPool;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, OnceLock};
+use tower::ServiceExt;
+
+use crate::{config::Config, AppState};
+
+pub const PASSWORD: &str = "password";
+
+// Hashed at bcrypt's lowest cost, once, so creating and signing in users
+// stays fast.
+fn password_hash() -> &'static str {
+    static HASH: OnceLock<String> = OnceLock::new();
+    HASH.get_or_init(|| bcrypt::hash(PASSWORD, 4).unwrap())
+}
+
+pub struct TestApp {
+    pub pool: PgPool,
+    router: Router,
+    repos: Vec<String>,
+}
+
+pub struct TestResponse {
+    pub status: StatusCode,
+    pub headers: HeaderMap,
+    pub body: Value,
+}
+
+impl TestApp {
+    pub fn new(pool: PgPool) -> Self {
+        let state = AppState {
+            pool: pool.clone(),
+            config: Arc::new(Config::from_env()),
+            diff_stats_cache: Default::default(),
+            import_jobs: Default::default(),
+            http_client: reqwest::Client::new(),
+            push_counts: Default::default(),
+            rate_limits: Default::default(),
+            login_failures: Default::default(),
+        };
+        TestApp { pool, router: crate::app(state), repos: Vec::new() }
+    }
+
+    pub async fn request(&self, method: Method, uri: &str, token: Option<&str>, body: Option<Value>) -> TestResponse {
+        self.request_with_headers(method, uri, token, &[], body).await
+    }
+
+    pub async fn request_with_headers(
+        &self,
+        method: Method,
+        uri: &str,
+        token: Option<&str>,
+        headers: &[(&str, &str)],
+        body: Option<Value>,
+    ) -> TestResponse {
+        let mut request = Request::builder().method(method).uri(uri);
+        if let Some(token) = token {
+            request = request.header(header::AUTHORIZATION, format!("Bearer {}", token));
+        }
+        for (name, value) in headers {
+            request = request.header(*name, *value);
+        }
+        let request = match body {
+            Some(body) => request.header(header::CONTENT_TYPE, "application/json").body(Body::from(body.to_string())),
+            None => request.body(Body::empty()),
+        }
+        .unwrap();
+
+        let response = self.router.clone().oneshot(request).await.unwrap();
+        let status = response.status();
+        let headers = response.headers().clone();
+        let bytes = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        let body = serde_json::from_slice(&bytes).unwrap_or_else(|_| Value::String(String::from_utf8_lossy(&bytes).into_owned()));
+        TestResponse { status, headers, body }
+    }
+
+    // The response as the router returns it, for bodies that are streamed.
+    pub async fn get_response(&self, uri: &str) -> Response {
+        self.router.clone().oneshot(Request::get(uri).body(Body::empty()).unwrap()).await.unwrap()
+    }
+
+    pub async fn get(&self, uri: &str, token: Option<&str>) -> TestResponse {
+        self.request(Method::GET, uri, token, None).await
+    }
+
+    pub async fn post(&self, uri: &str, token: Option<&str>, body: Value) -> TestResponse {
+        self.request(Method::POST, uri, token, Some(body)).await
+    }
+
+    pub async fn patch(&self, uri: &str, token: Option<&str>, body: Value) -> TestResponse {
+        self.request(Method::PATCH, uri, token, Some(body)).await
+    }
+
+    pub async fn delete(&self, uri: &str, token: Option<&str>) -> TestResponse {
+        self.request(Method::DELETE, uri, token, None).await
+    }
+
+    // Creates a user with `PASSWORD` and returns their id.
+    pub async fn create_user(&self, username: &str) -> i32 {
+        sqlx::query_scalar("INSERT INTO users (username, password_hash) VALUES ($1, $2) RETURNING id")
+            .bind(username)
+            .bind(password_hash())
+            .fetch_one(&self.pool)
+            .await
+            .unwrap()
+    }
+
+    pub async fn login(&self, username: &str) -> String {
+        let response = self.post("/login", None, serde_json::json!({ "username": username, "password": PASSWORD })).await;
+        assert_eq!(response.status, StatusCode::OK, "{}", response.body);
+        response.body["token"].as_str().unwrap().to_string()
+    }
+
+    // Creates a user and signs them in, returning their session token.
+    pub async fn sign_up(&self, username: &str) -> String {
+        self.create_user(username).await;
+        self.login(username).await
+    }
+
+    // Creates a repository with an initial commit on `main` through the API,
+    // under a name no other test uses since they share `./repos`. It is
+    // removed from disk when the app is dropped.
+    pub async fn create_repo(&mut self, token: &str, public: bool) -> String {
+        static NEXT: AtomicUsize = AtomicUsize::new(0);
+        let name = format!("test-{}-{}", std::process::id(), NEXT.fetch_add(1, Ordering::Relaxed));
+        std::fs::create_dir_all("./repos").unwrap();
+        let response = self.post("/repos", Some(token), serde_json::json!({ "name": name, "public": public, "initialize": true })).await;
+        assert_eq!(response.status, StatusCode::CREATED, "{}", response.body);
+        self.repos.push(name.clone());
+        name
+    }
+
+    // Removes `name` from disk along with the repositories `create_repo`
+    // made, for one a test renamed.
+    pub fn remove_on_drop(&mut self, name: &str) {
+        self.repos.push(name.to_string());
+    }
+
+    pub async fn add_collaborator(&self, owner_token: &str, repo: &str, username: &str, role: &str) {
+        let response = self
+            .post(&format!("/repos/{}/collaborators", repo), Some(owner_token), serde_json::json!({ "username": username, "role": role }))
+            .await;
+        assert!(response.status.is_success(), "{}", response.body);
+    }
+}
+
+impl Drop for TestApp {
+    fn drop(&mut self) {
+        for name in &self.repos {
+            let _ = std::fs::remove_dir_all(format!("./repos/{}.git", name));
+        }
+    }
+}
+
+// Commits `path` with `content` on top of `branch` (creating the branch from
+// `main` if needed) straight in the bare repository, as a push would.
+pub fn commit_file(repo_name: &str, branch: &str, path: &str, content: &str) -> git2::Oid {
+    let repo = git2::Repository::open(format!("./repos/{}.git", repo_name)).unwrap();
+    let parent = match repo.find_branch(branch, git2::BranchType::Local) {
+        Ok(branch) => branch.get().peel_to_commit().unwrap(),
+        Err(_) => repo.find_branch("main", git2::BranchType::Local).unwrap().get().peel_to_commit().unwrap(),
+    };
+
+    let blob = repo.blob(content.as_bytes()).unwrap();
+    let mut builder = git2::build::TreeUpdateBuilder::new();
+    builder.upsert(path, blob, git2::FileMode::Blob);
+    let tree = repo.find_tree(builder.create_updated(&repo, &parent.tree().unwrap()).unwrap()).unwrap();
+
+    let signature = git2::Signature::now("Test", "test@example.com").unwrap();
+    repo.commit(Some(&format!("refs/heads/{}", branch)), &signature, &signature, &format!("Change {}", path), &tree, &[&parent])
+        .unwrap()
+}
@@ -0,0 +1,155 @@
+use async_trait::async_trait;
+use axum::{extract::FromRequestParts, http::{header, request::Parts, HeaderMap}};
+use std::convert::Infallible;
+use std::net::IpAddr;
+
+use crate::config::Config;
+use crate::AppState;
+
+// The scheme and authority every absolute URL the server hands out starts
+// with. `PUBLIC_BASE_URL` wins when set; otherwise it is taken from the
+// request, honouring `X-Forwarded-Proto`/`X-Forwarded-Host` only when
+// `TRUST_PROXY_HEADERS` says a proxy in front of us sets them.
+#[derive(Clone, Debug)]
+pub struct BaseUrl(String);
+
+impl BaseUrl {
+    pub fn from_headers(headers: &HeaderMap, config: &Config) -> Self {
+        if let Some(base_url) = &config.public_base_url {
+            return BaseUrl(base_url.clone());
+        }
+
+        let forwarded = |name: &str| {
+            config.trust_proxy_headers.then(|| last_value(headers, name)).flatten()
+        };
+        let scheme = forwarded("x-forwarded-proto")
+            .filter(|scheme| matches!(*scheme, "http" | "https"))
+            .unwrap_or("http");
+        let host = forwarded("x-forwarded-host")
+            .or_else(|| last_value(headers, header::HOST.as_str()))
+            .filter(|host| is_valid_host(host))
+            .unwrap_or("localhost:3000");
+        BaseUrl(format!("{}://{}", scheme, host))
+    }
+
+    // `path` must start with a `/`.
+    pub fn join(&self, path: &str) -> String {
+        format!("{}{}", self.0, path)
+    }
+
+    pub fn repo_url(&self, repo_name: &str) -> String {
+        self.join(&format!("/repos/{}", repo_name))
+    }
+
+    pub fn clone_url(&self, repo_name: &str) -> String {
+        self.join(&format!("/{}.git", repo_name))
+    }
+}
+
+#[async_trait]
+impl FromRequestParts<AppState> for BaseUrl {
+    type Rejection = Infallible;
+
+    async fn from_request_parts(parts: &mut Parts, state: &AppState) -> Result<Self, Self::Rejection> {
+        Ok(BaseUrl::from_headers(&parts.headers, &state.config))
+    }
+}
+
+// Proxies append to these headers, so only the last value was set by the
+// proxy in front of us; earlier ones came from the client.
+fn last_value<'h>(headers: &'h HeaderMap, name: &str) -> Option<&'h str> {
+    let value = headers.get(name)?.to_str().ok()?;
+    value.rsplit(',').next().map(str::trim).filter(|value| !value.is_empty())
+}
+
+fn is_valid_host(host: &str) -> bool {
+    host.bytes().all(|b| b.is_ascii_alphanumeric() || matches!(b, b'.' | b'-' | b':' | b'[' | b']'))
+}
+
+// Accepts `https://git.example.com` or `https://example.com/git`, without a
+// trailing slash so paths can be appended directly.
+pub(crate) fn normalize_base_url(url: &str) -> Option<String> {
+    let url = url.trim().trim_end_matches('/');
+    let rest = url.strip_prefix("https://").or_else(|| url.strip_prefix("http://"))?;
+    (!rest.is_empty() && !rest.contains(char::is_whitespace)).then(|| url.to_string())
+}
+
+// Whether a request the server makes on a user's behalf may go to `ip`: not
+// this host, the networks it sits on, or link-local addresses such as cloud
+// metadata endpoints.
+fn is_public_address(ip: IpAddr) -> bool {
+    match ip {
+        IpAddr::V4(ip) => {
+            let [a, b, ..] = ip.octets();
+            let shared = a == 100 && (64..128).contains(&b);
+            !(ip.is_loopback() || ip.is_private() || ip.is_link_local() || ip.is_unspecified() || ip.is_broadcast() || ip.is_multicast() || a == 0 || shared)
+        }
+        IpAddr::V6(ip) => match ip.to_ipv4_mapped() {
+            Some(mapped) => is_public_address(IpAddr::V4(mapped)),
+            None => {
+                let first = ip.segments()[0];
+                let unique_local = first & 0xfe00 == 0xfc00;
+                let link_local = first & 0xffc0 == 0xfe80;
+                !(ip.is_loopback() || ip.is_unspecified() || ip.is_multicast() || unique_local || link_local)
+            }
+        },
+    }
+}
+
+// Checks a URL the server is about to fetch from or post to for a user
+// (imports, webhooks): it must be `http(s)://` and every address its host
+// resolves to must be public. The error completes a sentence about the field.
+pub(crate) async fn check_outbound_url(url: &str) -> Result<(), String> {
+    let parsed = reqwest::Url::parse(url).map_err(|_| "must be a valid URL".to_string())?;
+    if !matches!(parsed.scheme(), "http" | "https") {
+        return Err("must be an http:// or https:// URL".to_string());
+    }
+    let (Some(host), Some(port)) = (parsed.host_str(), parsed.port_or_known_default()) else {
+        return Err("must name a host".to_string());
+    };
+
+    let host = host.trim_start_matches('[').trim_end_matches(']');
+    let addresses: Vec<_> = tokio::net::lookup_host((host, port))
+        .await
+        .map_err(|_| format!("host '{}' could not be resolved", host))?
+        .collect();
+    if addresses.is_empty() || !addresses.iter().all(|address| is_public_address(address.ip())) {
+        return Err("must not point at a loopback, private or link-local address".to_string());
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn forwarded_values_come_from_the_proxy() {
+        let config = Config { trust_proxy_headers: true, public_base_url: None, ..Config::from_env() };
+        let mut headers = HeaderMap::new();
+        headers.insert("x-forwarded-proto", "http, https".parse().unwrap());
+        headers.insert("x-forwarded-host", "evil.example, git.example.com".parse().unwrap());
+        assert_eq!(BaseUrl::from_headers(&headers, &config).join("/x"), "https://git.example.com/x");
+    }
+
+    #[test]
+    fn only_public_addresses_are_reachable() {
+        for ip in ["93.184.216.34", "2606:2800:220:1::1"] {
+            assert!(is_public_address(ip.parse().unwrap()), "{}", ip);
+        }
+        for ip in ["127.0.0.1", "10.1.2.3", "172.16.0.1", "192.168.1.1", "169.254.169.254", "100.64.0.1", "0.0.0.0", "::1", "fe80::1", "fd00::1", "::ffff:127.0.0.1"] {
+            assert!(!is_public_address(ip.parse().unwrap()), "{}", ip);
+        }
+    }
+
+    #[tokio::test]
+    async fn outbound_urls_must_be_public_http() {
+        assert!(check_outbound_url("git://example.com/repo.git").await.is_err());
+        assert!(check_outbound_url("file:///etc/passwd").await.is_err());
+        assert!(check_outbound_url("http://127.0.0.1:3000/repo.git").await.is_err());
+        assert!(check_outbound_url("http://localhost/repo.git").await.is_err());
+        assert!(check_outbound_url("http://[::1]/hook").await.is_err());
+        assert!(check_outbound_url("https://169.254.169.254/latest/meta-data").await.is_err());
+        assert!(check_outbound_url("https://93.184.216.34/repo.git").await.is_ok());
+    }
+}
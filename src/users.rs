@@ -0,0 +1,49 @@
+use axum::{extract::State, http::StatusCode, response::IntoResponse, Json};
+use serde::Deserialize;
+
+use crate::auth::PermissiveAuthUser;
+use crate::db;
+use crate::issues::DisplayUser;
+use crate::validation::{ValidatedJson, Validate, Validator};
+use crate::AppState;
+
+const MAX_RESOLVE_BATCH: usize = 100;
+
+#[derive(Deserialize)]
+pub struct ResolveUsers {
+    ids: Option<Vec<i32>>,
+    usernames: Option<Vec<String>>,
+}
+
+impl Validate for ResolveUsers {
+    fn validate(&self, v: &mut Validator) {
+        let batch = self.ids.as_ref().map(Vec::len).or(self.usernames.as_ref().map(Vec::len));
+        v.check(self.ids.is_some() != self.usernames.is_some(), "ids", "give either ids or usernames")
+            .check(batch.is_none_or(|len| len <= MAX_RESOLVE_BATCH), "ids", format!("at most {} users can be resolved at once", MAX_RESOLVE_BATCH));
+    }
+}
+
+// Looks up a batch of users by id or by username in one query, in the order
+// asked for. Unknown users are left out rather than failing the batch.
+#[axum::debug_handler]
+pub async fn resolve_users(
+    State(state): State<AppState>,
+    _user: PermissiveAuthUser,
+    ValidatedJson(request): ValidatedJson<ResolveUsers>,
+) -> Result<impl IntoResponse, (StatusCode, String)> {
+    let users = sqlx::query_as::<_, DisplayUser>(
+        r#"
+        SELECT id, username
+        FROM users
+        WHERE id = ANY($1) OR username = ANY($2)
+        ORDER BY array_position($1, id), array_position($2, username::text)
+        "#,
+    )
+    .bind(request.ids.unwrap_or_default())
+    .bind(request.usernames.unwrap_or_default())
+    .fetch_all(&state.pool)
+    .await
+    .map_err(|e| db::error(e, "Failed to resolve users"))?;
+
+    Ok(Json(users))
+}
@@ -0,0 +1,103 @@
+use axum::{
+    extract::{rejection::JsonRejection, FromRequest, Request},
+    http::StatusCode,
+    response::{IntoResponse, Response},
+    Json,
+};
+use async_trait::async_trait;
+use serde::{de::DeserializeOwned, Serialize};
+
+pub const MAX_TITLE_CHARS: usize = 256;
+pub const MAX_BODY_CHARS: usize = 65536;
+
+#[derive(Serialize)]
+pub struct FieldError {
+    field: &'static str,
+    message: String,
+}
+
+#[derive(Serialize)]
+pub struct ValidationError {
+    message: String,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    errors: Vec<FieldError>,
+}
+
+impl IntoResponse for ValidationError {
+    fn into_response(self) -> Response {
+        (StatusCode::UNPROCESSABLE_ENTITY, Json(self)).into_response()
+    }
+}
+
+// Collects every failed check of a request body so clients can fix all
+// fields in one go instead of one round trip per mistake.
+#[derive(Default)]
+pub struct Validator {
+    errors: Vec<FieldError>,
+}
+
+impl Validator {
+    pub fn check(&mut self, ok: bool, field: &'static str, message: impl Into<String>) -> &mut Self {
+        if !ok {
+            self.errors.push(FieldError { field, message: message.into() });
+        }
+        self
+    }
+
+    pub fn required(&mut self, field: &'static str, value: &str, max_chars: usize) -> &mut Self {
+        if value.trim().is_empty() {
+            return self.check(false, field, "must not be empty");
+        }
+        self.max_chars(field, Some(value), max_chars)
+    }
+
+    pub fn max_chars(&mut self, field: &'static str, value: Option<&str>, max_chars: usize) -> &mut Self {
+        let too_long = value.is_some_and(|v| v.chars().count() > max_chars);
+        self.check(!too_long, field, format!("must be at most {} characters", max_chars))
+    }
+
+    pub fn finish(self) -> Result<(), ValidationError> {
+        if self.errors.is_empty() {
+            return Ok(());
+        }
+        Err(ValidationError { message: "Validation failed".to_string(), errors: self.errors })
+    }
+}
+
+pub trait Validate {
+    fn validate(&self, v: &mut Validator);
+}
+
+// Drop-in replacement for `Json<T>` that answers malformed bodies with a JSON
+// error and runs `T::validate` before the handler sees the value.
+pub struct ValidatedJson<T>(pub T);
+
+#[async_trait]
+impl<T, S> FromRequest<S> for ValidatedJson<T>
+where
+    T: DeserializeOwned + Validate,
+    S: Send + Sync,
+{
+    type Rejection = Response;
+
+    async fn from_request(req: Request, state: &S) -> Result<Self, Self::Rejection> {
+        let Json(value) = Json::<T>::from_request(req, state).await.map_err(json_rejection)?;
+
+        let mut validator = Validator::default();
+        value.validate(&mut validator);
+        validator.finish().map_err(IntoResponse::into_response)?;
+
+        Ok(ValidatedJson(value))
+    }
+}
+
+fn json_rejection(rejection: JsonRejection) -> Response {
+    let status = match &rejection {
+        JsonRejection::JsonDataError(_) => StatusCode::UNPROCESSABLE_ENTITY,
+        JsonRejection::JsonSyntaxError(_) => StatusCode::BAD_REQUEST,
+        JsonRejection::MissingJsonContentType(_) => StatusCode::UNSUPPORTED_MEDIA_TYPE,
+        _ => rejection.status(),
+    };
+    let error = ValidationError { message: rejection.body_text(), errors: Vec::new() };
+    (status, Json(error)).into_response()
+}
@@ -0,0 +1,253 @@
+use axum::{extract::{Path, State}, http::StatusCode, response::IntoResponse, Json};
+use hmac::{Hmac, Mac};
+use serde::{Deserialize, Serialize};
+use sha2::Sha256;
+use sqlx::FromRow;
+
+use crate::auth::{AuthUser, Scope};
+use crate::db;
+use crate::git_api::find_administered_repo;
+use crate::urls::check_outbound_url;
+use crate::validation::{Validate, ValidatedJson, Validator};
+use crate::AppState;
+
+const SUPPORTED_EVENTS: [&str; 1] = ["push"];
+
+#[derive(Serialize, FromRow)]
+pub struct Webhook {
+    id: i32,
+    repo_id: i32,
+    url: String,
+    #[serde(skip_serializing)]
+    secret: Option<String>,
+    events: Vec<String>,
+    active: bool,
+    created_at: chrono::DateTime<chrono::Utc>,
+}
+
+#[derive(Deserialize)]
+pub struct NewWebhook {
+    url: String,
+    secret: Option<String>,
+    events: Option<Vec<String>>,
+    #[serde(default = "default_active")]
+    active: bool,
+}
+
+fn default_active() -> bool {
+    true
+}
+
+impl Validate for NewWebhook {
+    fn validate(&self, v: &mut Validator) {
+        if let Some(event) = self.events.iter().flatten().find(|e| !SUPPORTED_EVENTS.contains(&e.as_str())) {
+            v.check(false, "events", format!("unsupported event '{}'", event));
+        }
+    }
+}
+
+#[derive(Serialize)]
+pub struct PushEvent {
+    #[serde(rename = "ref")]
+    pub git_ref: String,
+    pub before: String,
+    pub after: String,
+    pub created: bool,
+    pub deleted: bool,
+    pub repository: PushRepository,
+    pub pusher: Option<PushUser>,
+    pub commits: Vec<PushCommit>,
+    pub head_commit: Option<PushCommit>,
+}
+
+#[derive(Serialize, Clone)]
+pub struct PushRepository {
+    pub name: String,
+    pub url: String,
+    pub clone_url: String,
+}
+
+#[derive(Serialize, Clone)]
+pub struct PushUser {
+    pub name: String,
+}
+
+#[derive(Serialize, Clone)]
+pub struct PushCommit {
+    pub id: String,
+    pub url: String,
+    pub message: String,
+    pub timestamp: Option<String>,
+    pub author: PushCommitAuthor,
+}
+
+#[derive(Serialize, Clone)]
+pub struct PushCommitAuthor {
+    pub name: String,
+    pub email: String,
+}
+
+// Deliveries are made from the server, so hooks are held to the same rules as
+// import sources: public http(s) addresses only. This resolves the host, so
+// it runs in the handler rather than in `validate`.
+async fn validate_webhook_url(hook: &NewWebhook) -> Result<(), (StatusCode, String)> {
+    check_outbound_url(&hook.url).await.map_err(|message| (StatusCode::UNPROCESSABLE_ENTITY, format!("Webhook URL {}", message)))
+}
+
+#[axum::debug_handler]
+pub async fn create_webhook(
+    State(state): State<AppState>,
+    AuthUser(user): AuthUser,
+    Path(repo_name): Path<String>,
+    ValidatedJson(new_hook): ValidatedJson<NewWebhook>,
+) -> Result<impl IntoResponse, (StatusCode, String)> {
+    user.require_scope(Scope::RepoWrite)?;
+    let repo = find_administered_repo(&state.pool, &repo_name, user.id, "manage webhooks for").await?;
+    validate_webhook_url(&new_hook).await?;
+
+    let events = new_hook.events.unwrap_or_else(|| SUPPORTED_EVENTS.iter().map(|e| e.to_string()).collect());
+    let hook = sqlx::query_as::<_, Webhook>(
+        "INSERT INTO webhooks (repo_id, url, secret, events, active) VALUES ($1, $2, $3, $4, $5) RETURNING *"
+    )
+    .bind(repo.id)
+    .bind(new_hook.url)
+    .bind(new_hook.secret)
+    .bind(events)
+    .bind(new_hook.active)
+    .fetch_one(&state.pool)
+    .await
+    .map_err(|e| db::error(e, "Failed to create webhook"))?;
+
+    Ok((StatusCode::CREATED, Json(hook)))
+}
+
+#[axum::debug_handler]
+pub async fn list_webhooks(
+    State(state): State<AppState>,
+    AuthUser(user): AuthUser,
+    Path(repo_name): Path<String>,
+) -> Result<impl IntoResponse, (StatusCode, String)> {
+    let repo = find_administered_repo(&state.pool, &repo_name, user.id, "manage webhooks for").await?;
+
+    let hooks = sqlx::query_as::<_, Webhook>("SELECT * FROM webhooks WHERE repo_id = $1 ORDER BY id")
+        .bind(repo.id)
+        .fetch_all(&state.pool)
+        .await
+        .map_err(|e| db::error(e, "Failed to list webhooks"))?;
+
+    Ok(Json(hooks))
+}
+
+#[axum::debug_handler]
+pub async fn delete_webhook(
+    State(state): State<AppState>,
+    AuthUser(user): AuthUser,
+    Path((repo_name, hook_id)): Path<(String, i32)>,
+) -> Result<impl IntoResponse, (StatusCode, String)> {
+    user.require_scope(Scope::RepoWrite)?;
+    let repo = find_administered_repo(&state.pool, &repo_name, user.id, "manage webhooks for").await?;
+
+    let result = sqlx::query("DELETE FROM webhooks WHERE id = $1 AND repo_id = $2")
+        .bind(hook_id)
+        .bind(repo.id)
+        .execute(&state.pool)
+        .await
+        .map_err(|e| db::error(e, "Failed to delete webhook"))?;
+
+    if result.rows_affected() == 0 {
+        return Err((StatusCode::NOT_FOUND, "Webhook not found".to_string()));
+    }
+
+    Ok(StatusCode::NO_CONTENT)
+}
+
+// Delivers `payload` to every active hook of the repository subscribed to
+// `event`. Runs in the background; delivery failures are only logged.
+pub fn dispatch<T: Serialize>(state: &AppState, repo_name: &str, event: &'static str, payload: &T) {
+    let body = match serde_json::to_vec(payload) {
+        Ok(body) => body,
+        Err(e) => {
+            tracing::error!("Failed to serialize {} webhook payload: {}", event, e);
+            return;
+        }
+    };
+    let (state, repo_name) = (state.clone(), repo_name.to_string());
+
+    tokio::spawn(async move {
+        let hooks = sqlx::query_as::<_, Webhook>(
+            r#"
+            SELECT w.* FROM webhooks w
+            JOIN repositories r ON w.repo_id = r.id
+            WHERE r.name = $1 AND w.active AND $2 = ANY(w.events)
+            "#
+        )
+        .bind(&repo_name)
+        .bind(event)
+        .fetch_all(&state.pool)
+        .await;
+
+        let hooks = match hooks {
+            Ok(hooks) => hooks,
+            Err(e) => {
+                tracing::error!("Failed to load webhooks for {}: {}", repo_name, e);
+                return;
+            }
+        };
+
+        for hook in hooks {
+            // The host may resolve somewhere else than it did when the hook
+            // was registered.
+            if let Err(message) = check_outbound_url(&hook.url).await {
+                tracing::warn!("Skipping webhook {}: URL {}", hook.id, message);
+                continue;
+            }
+            let mut request = state
+                .http_client
+                .post(&hook.url)
+                .header("Content-Type", "application/json")
+                .header("X-Git8-Event", event)
+                .header("X-GitHub-Event", event);
+            if let Some(secret) = &hook.secret {
+                request = request.header("X-Hub-Signature-256", signature(secret, &body));
+            }
+
+            match request.body(body.clone()).send().await {
+                Ok(response) if response.status().is_success() => {
+                    tracing::debug!("Delivered {} webhook {} to {}", event, hook.id, hook.url);
+                }
+                Ok(response) => tracing::warn!("Webhook {} to {} returned {}", hook.id, hook.url, response.status()),
+                Err(e) => tracing::warn!("Failed to deliver webhook {} to {}: {}", hook.id, hook.url, e),
+            }
+        }
+    });
+}
+
+fn signature(secret: &str, body: &[u8]) -> String {
+    let mut mac = Hmac::<Sha256>::new_from_slice(secret.as_bytes()).expect("HMAC accepts keys of any length");
+    mac.update(body);
+    format!("sha256={:x}", mac.finalize().into_bytes())
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::http::StatusCode;
+    use serde_json::json;
+    use sqlx::PgPool;
+
+    use crate::test_support::TestApp;
+
+    #[sqlx::test]
+    async fn hooks_cannot_target_the_servers_own_network(pool: PgPool) {
+        let mut app = TestApp::new(pool);
+        let token = app.sign_up("alice").await;
+        let repo = app.create_repo(&token, true).await;
+
+        for url in ["http://127.0.0.1:8080/hook", "http://10.0.0.5/hook", "http://169.254.169.254/latest", "http://[::1]/hook", "ftp://93.184.216.34/hook"] {
+            let response = app.post(&format!("/repos/{}/hooks", repo), Some(&token), json!({ "url": url })).await;
+            assert_eq!(response.status, StatusCode::UNPROCESSABLE_ENTITY, "{}", url);
+        }
+
+        let response = app.post(&format!("/repos/{}/hooks", repo), Some(&token), json!({ "url": "https://93.184.216.34/hook" })).await;
+        assert_eq!(response.status, StatusCode::CREATED, "{}", response.body);
+    }
+}